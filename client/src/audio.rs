@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use libgsh::shared::protocol::{audio_chunk::SampleFormat, AudioChunk};
+use sdl3::audio::{AudioFormat, AudioSpec, AudioStreamOwner};
+use std::collections::HashMap;
+
+/// Milliseconds of audio to buffer before starting playback, to absorb network jitter.
+const PREBUFFER_MS: usize = 60;
+
+/// Plays audio streams sent by the server, one SDL playback stream per `stream_id`.\
+/// Playback starts once enough audio is queued and pauses again on underrun to rebuild the buffer.
+pub struct AudioPlayer {
+    subsystem: Option<sdl3::AudioSubsystem>,
+    streams: HashMap<u32, PlaybackStream>,
+}
+
+struct PlaybackStream {
+    stream: AudioStreamOwner,
+    spec: (u32, u32, SampleFormat),
+    prebuffer_bytes: usize,
+    playing: bool,
+}
+
+impl AudioPlayer {
+    pub fn new(sdl: &sdl3::Sdl) -> Self {
+        let subsystem = sdl
+            .audio()
+            .map_err(|e| log::warn!("Audio playback unavailable: {}", e))
+            .ok();
+        Self {
+            subsystem,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Queue a chunk of audio for playback, opening or reconfiguring its stream as needed.
+    pub fn play(&mut self, chunk: AudioChunk) -> Result<()> {
+        let Some(subsystem) = &self.subsystem else {
+            return Ok(());
+        };
+        if chunk.sample_rate == 0 || chunk.channels == 0 {
+            return Err(anyhow!("Invalid audio chunk spec"));
+        }
+        let spec = (chunk.sample_rate, chunk.channels, chunk.format());
+        if self.streams.get(&chunk.stream_id).map(|s| s.spec) != Some(spec) {
+            // Samples are little-endian on the wire, SDL converts them on big-endian machines
+            let (format, sample_bytes) = match chunk.format() {
+                SampleFormat::S16 => (AudioFormat::S16LE, 2),
+                SampleFormat::F32 => (AudioFormat::F32LE, 4),
+            };
+            let audio_spec = AudioSpec {
+                freq: Some(chunk.sample_rate as i32),
+                channels: Some(chunk.channels as i32),
+                format: Some(format),
+            };
+            let stream = subsystem
+                .open_playback_device(&audio_spec)
+                .and_then(|device| device.open_device_stream(Some(&audio_spec)))
+                .map_err(|e| anyhow!(e))?;
+            log::debug!(
+                "Opened audio stream {}: {} Hz, {} channels, {:?}",
+                chunk.stream_id,
+                chunk.sample_rate,
+                chunk.channels,
+                chunk.format()
+            );
+            let bytes_per_second =
+                chunk.sample_rate as usize * chunk.channels as usize * sample_bytes;
+            self.streams.insert(
+                chunk.stream_id,
+                PlaybackStream {
+                    stream,
+                    spec,
+                    prebuffer_bytes: bytes_per_second * PREBUFFER_MS / 1000,
+                    playing: false,
+                },
+            );
+        }
+        let playback = self.streams.get_mut(&chunk.stream_id).unwrap();
+        let queued = playback.stream.queued_bytes().map_err(|e| anyhow!(e))? as usize;
+        if playback.playing && queued == 0 {
+            log::debug!("Audio stream {} underrun, rebuffering", chunk.stream_id);
+            playback.stream.pause().map_err(|e| anyhow!(e))?;
+            playback.playing = false;
+        }
+        playback
+            .stream
+            .put_data(&chunk.samples)
+            .map_err(|e| anyhow!(e))?;
+        if !playback.playing && queued + chunk.samples.len() >= playback.prebuffer_bytes {
+            playback.stream.resume().map_err(|e| anyhow!(e))?;
+            playback.playing = true;
+        }
+        Ok(())
+    }
+}
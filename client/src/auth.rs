@@ -1,5 +1,5 @@
 use crate::config::{IdFiles, KnownHosts};
-use dialoguer::{Confirm, Password};
+use dialoguer::{Confirm, Input, Password};
 use libgsh::{
     rsa::{
         pkcs1v15::{self, Signature},
@@ -7,7 +7,7 @@ use libgsh::{
         RsaPrivateKey, RsaPublicKey,
     },
     sha2::Sha256,
-    shared::auth::AuthProvider,
+    shared::{auth::AuthProvider, protocol::server_hello_ack::TotpMethod},
 };
 
 pub struct ClientAuthProvider {
@@ -80,6 +80,27 @@ impl AuthProvider for ClientAuthProvider {
         }
     }
 
+    fn totp(&mut self, host: &str, method: &TotpMethod) -> Option<String> {
+        // One-time passwords are never stored, so always prompt
+        let digits = method.digits as usize;
+        let code = Input::<String>::new()
+            .with_prompt(format!(
+                "Enter {}-digit one-time password for {}",
+                digits, host
+            ))
+            .validate_with(|code: &String| -> Result<(), String> {
+                let code = code.trim();
+                if digits > 0 && (code.len() != digits || !code.bytes().all(|b| b.is_ascii_digit()))
+                {
+                    return Err(format!("The code must be {} digits", digits));
+                }
+                Ok(())
+            })
+            .interact_text()
+            .unwrap();
+        Some(code.trim().to_string())
+    }
+
     fn signature(&mut self, host: &str, sign_message: &[u8]) -> Option<(Signature, RsaPublicKey)> {
         // Check if an ID file is provided as an override
         if let Some(id_override) = &self.id_override {
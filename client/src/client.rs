@@ -1,54 +1,146 @@
+use crate::{
+    audio::AudioPlayer,
+    download::Downloads,
+    drawlist, fidelity,
+    framebuffer::{self, FrameBuffer, Region},
+    stats::{self, WindowStats},
+    trace::ProtocolTracer,
+    upload::Uploads,
+};
 use anyhow::{anyhow, Result};
 use libgsh::{
-    client::ClientStream,
-    shared::protocol::{
-        self,
-        server_hello_ack::{self, window_settings::WindowMode, FrameFormat, WindowSettings},
-        server_message::ServerEvent,
-        status_update::{Details, StatusType},
-        user_input::{
-            self, key_event::KeyAction, mouse_event::MouseAction, window_event::WindowAction,
-            InputType,
+    client::{FrameSink, FrontendError, InputSource},
+    shared::{
+        compression,
+        protocol::{
+            self,
+            server_hello_ack::{
+                window_settings::{Icon, WindowMode},
+                Compression, FrameFormat, WindowSettings,
+            },
+            server_message::ServerEvent,
+            status_update::Details,
+            user_input::{
+                self, gamepad_event::GamepadAction, key_event::KeyAction, mouse_event::MouseAction,
+                window_event::WindowAction, InputType,
+            },
+            window_command::Action,
+            AudioChunk, ClientMessage, CursorUpdate, DestroyWindow, DrawList, FileChunk, FileOffer,
+            FileTransferEnd, Frame, SetWindowPosition, StatusUpdate, UserInput, ViewportHint,
+            WarpCursor, WindowCommand,
         },
-        Frame, StatusUpdate, UserInput,
     },
 };
 use sdl3::{
     event::{Event, WindowEvent},
-    pixels::{Color, PixelFormat},
+    keyboard::{Keycode, Mod},
+    mouse::{Cursor, MouseWheelDirection, SystemCursor},
+    pixels::PixelFormat,
     rect::Rect,
-    render::{BlendMode, Canvas},
+    render::{BlendMode, Canvas, Texture},
+    surface::Surface,
     video,
 };
 use std::{
-    collections::HashMap,
-    io::Read,
-    time::{Duration, Instant},
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 const MAX_FPS: u32 = 60;
-const FRAME_TIME: u64 = 1_000_000_000 / MAX_FPS as u64; // in nanoseconds
+pub const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / MAX_FPS as u64);
 pub type WindowID = u32;
 
 pub struct SdlWindow {
     // pub server_window_id: WindowID,
-    // pub texture_creator: sdl3::render::TextureCreator<video::WindowContext>,
-    // pub current_texture: sdl3::render::Texture<'static>,
     pub canvas: Canvas<video::Window>,
+    /// Texture of the frame buffer, kept across frames and recreated when the frame size changes
+    pub texture: Option<Texture>,
+    /// Regions of the frame buffer changed since the texture was last updated
+    pub damage: Vec<Region>,
     // pub current_frame: Option<Frame>,
+    /// Last known display scale factor, used to detect DPI changes
+    pub display_scale: f32,
+    /// Composited contents of all frames applied to this window
+    pub frame_buffer: FrameBuffer,
+    /// Last visible area reported to the server
+    pub viewport: Option<ViewportHint>,
+    /// Pixel format and compression of the frames of this window
+    pub format: FrameFormat,
+    pub compression: Option<Compression>,
+}
+
+/// Cursor requested by the server for one of its windows, applied while the mouse is over it
+pub struct WindowCursor {
+    pub visible: bool,
+    /// Custom cursor image, `None` for the system default cursor
+    pub cursor: Option<Cursor>,
+}
+
+/// Monotonic clock and its Unix epoch offset, used to timestamp user input events
+struct InputClock {
+    start: Instant,
+    epoch_ns: u64,
+}
+
+impl InputClock {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            epoch_ns: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0),
+        }
+    }
+
+    /// Nanoseconds since the Unix epoch, never going backwards even if the system clock does.
+    fn now_ns(&self) -> u64 {
+        self.epoch_ns + self.start.elapsed().as_nanos() as u64
+    }
 }
 
 pub struct Client {
     sdl: sdl3::Sdl,
     video: sdl3::VideoSubsystem,
     format: FrameFormat,
-    compression: Option<protocol::server_hello_ack::Compression>,
+    compression: Option<Compression>,
     /// Mapping from SDL window ID to SDL canvas video::Window
     windows: HashMap<WindowID, SdlWindow>,
     /// Mapping from server ID to SDL window ID
     server_window_to_sdl_window: HashMap<WindowID, WindowID>,
     sdl_window_to_server_window: HashMap<WindowID, WindowID>,
-    stream: ClientStream,
+    /// SDL window IDs of display-only windows that don't forward key and mouse input
+    input_disabled: HashSet<WindowID>,
+    event_pump: sdl3::EventPump,
+    /// Messages queued for the server, sent by the message pump after polling input
+    outbox: Vec<ClientMessage>,
+    /// Clock used to timestamp user input events
+    input_clock: InputClock,
+    /// Optional summary log of all protocol messages, enabled by `--trace-protocol`
+    tracer: Option<ProtocolTracer>,
+    /// Per-channel tolerance for reading back and verifying rendered frames, enabled by `--verify-frames`
+    verify_tolerance: Option<u8>,
+    /// Frame statistics keyed by server window ID
+    window_stats: HashMap<WindowID, WindowStats>,
+    /// Draw the frame statistics of each window on top of its contents, toggled with Ctrl+Alt+H
+    show_hud: bool,
+    /// Gamepad subsystem and opened gamepads by their SDL joystick ID, kept open to receive their events
+    gamepad_subsystem: Option<sdl3::GamepadSubsystem>,
+    gamepads: HashMap<u32, sdl3::gamepad::Gamepad>,
+    /// Playback of audio streams sent by the server
+    audio: AudioPlayer,
+    /// Frames waiting for the final frame of their group, reported to the server
+    buffered_frames: u32,
+    /// Files dropped onto windows being uploaded to the server
+    uploads: Uploads,
+    /// Cursors requested by the server keyed by SDL window ID, and the system cursor to restore elsewhere
+    cursors: HashMap<WindowID, WindowCursor>,
+    default_cursor: Option<Cursor>,
+    /// Where files sent by the server are saved, downloads are refused if unset
+    downloads: Option<Downloads>,
+    /// Largest window size the server renders, resizable windows are not resized beyond it
+    max_window_size: Option<(u32, u32)>,
 }
 
 impl Client {
@@ -56,10 +148,18 @@ impl Client {
         sdl: sdl3::Sdl,
         video: sdl3::VideoSubsystem,
         format: FrameFormat,
-        compression: Option<protocol::server_hello_ack::Compression>,
-        stream: ClientStream,
-    ) -> Self {
-        Client {
+        compression: Option<Compression>,
+    ) -> Result<Self> {
+        let event_pump = sdl.event_pump().map_err(|e| anyhow!(e))?;
+        let gamepad_subsystem = sdl
+            .gamepad()
+            .map_err(|e| log::warn!("Gamepad support unavailable: {}", e))
+            .ok();
+        let audio = AudioPlayer::new(&sdl);
+        let default_cursor = Cursor::from_system(SystemCursor::Arrow)
+            .map_err(|e| log::warn!("Failed to create the default cursor: {}", e))
+            .ok();
+        Ok(Client {
             sdl,
             video,
             format,
@@ -67,29 +167,100 @@ impl Client {
             windows: HashMap::new(),
             server_window_to_sdl_window: HashMap::new(),
             sdl_window_to_server_window: HashMap::new(),
-            stream,
-        }
+            input_disabled: HashSet::new(),
+            event_pump,
+            outbox: Vec::new(),
+            input_clock: InputClock::new(),
+            tracer: None,
+            verify_tolerance: None,
+            window_stats: HashMap::new(),
+            show_hud: false,
+            gamepad_subsystem,
+            gamepads: HashMap::new(),
+            audio,
+            buffered_frames: 0,
+            uploads: Uploads::default(),
+            cursors: HashMap::new(),
+            default_cursor,
+            downloads: None,
+            max_window_size: None,
+        })
+    }
+
+    /// Log a summary of every protocol message sent and received from now on.
+    pub fn trace_protocol(&mut self, tracer: ProtocolTracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Read back every rendered frame and compare it against the received pixel data.\
+    /// Channels may differ by up to `tolerance` to allow for lossy formats.
+    pub fn verify_frames(&mut self, tolerance: u8) {
+        self.verify_tolerance = Some(tolerance);
     }
 
-    pub fn inner_stream(&mut self) -> &mut ClientStream {
-        &mut self.stream
+    /// Show the frame statistics HUD in every window.
+    pub fn show_hud(&mut self, show: bool) {
+        self.show_hud = show;
+    }
+
+    /// Save files sent by the server to the given directory.
+    pub fn download_to(&mut self, dir: PathBuf) {
+        self.downloads = Some(Downloads::new(dir));
+    }
+
+    /// Don't let the user resize windows beyond the given size, e.g. the server's maximum frame size.
+    pub fn limit_window_size(&mut self, width: u32, height: u32) {
+        self.max_window_size = Some((width, height));
+    }
+
+    /// Queue a message for the server, sent by the message pump after polling input.
+    fn send(&mut self, message: impl Into<ClientMessage>) -> Result<()> {
+        self.outbox.push(message.into());
+        Ok(())
+    }
+
+    /// Timestamp in nanoseconds since the Unix epoch for outgoing user input events.\
+    /// Derived from a monotonic clock so consecutive events never go backwards.
+    fn input_timestamp_ns(&self) -> u64 {
+        self.input_clock.now_ns()
+    }
+
+    /// Bounds of a monitor by the `monitor_id` reported in the `ClientHello`, or `None` if it doesn't exist.
+    fn monitor_bounds(&self, window_id: u32, monitor_id: u32) -> Option<Rect> {
+        // Monitor IDs are indices into the displays, see `monitor_info` in main.rs
+        let bounds = self
+            .video
+            .displays()
+            .ok()
+            .and_then(|displays| displays.get(monitor_id as usize)?.get_bounds().ok());
+        if bounds.is_none() {
+            log::warn!(
+                "Monitor {} requested for window ID {} not found, using the primary monitor",
+                monitor_id,
+                window_id
+            );
+        }
+        bounds
     }
 
     pub fn create_window(&mut self, ws: &WindowSettings) -> Result<WindowID> {
+        let compression = ws.frame_compression(self.compression.as_ref());
+        compression::check_dictionary_size(compression.as_ref())?;
         let mut window = self.video.window(&ws.title, ws.width, ws.height);
-        if let Some(monitor_id) = ws.monitor_id {
-            // SDL3 exposes displays() which returns a Vec<Display>; use it to get bounds
-            if let Ok(displays) = self.video.displays() {
-                if let Some(display) = displays.get(monitor_id as usize) {
-                    if let Ok(bounds) = display.get_bounds() {
-                        let x = bounds.x() + ((bounds.width() as i32) - ws.width as i32) / 2;
-                        let y = bounds.y() + ((bounds.height() as i32) - ws.height as i32) / 2;
-                        window.position(x, y);
-                    }
-                }
+        match ws
+            .monitor_id
+            .and_then(|monitor_id| self.monitor_bounds(ws.window_id, monitor_id))
+        {
+            Some(bounds) => {
+                // Centered on the monitor, keeping the title bar on it if the window is larger
+                let x = bounds.x() + ((bounds.width() as i32 - ws.width as i32) / 2).max(0);
+                let y = bounds.y() + ((bounds.height() as i32 - ws.height as i32) / 2).max(0);
+                window.position(x, y);
+            }
+            // Centered on the primary monitor
+            None => {
+                window.position_centered();
             }
-        } else {
-            window.position_centered();
         }
         if ws.allow_resize {
             window.resizable();
@@ -101,47 +272,99 @@ impl Client {
         } else if ws.initial_mode == WindowMode::WindowedMaximized as i32 {
             window.maximized();
         }
-        let window = window.build().map_err(|e| anyhow!(e))?;
+        let mut window = window.build().map_err(|e| anyhow!(e))?;
         let sdl_window_id = window.id();
+        if let Some((max_width, max_height)) = self.max_window_size.filter(|_| ws.allow_resize) {
+            if let Err(err) = window.set_maximum_size(max_width, max_height) {
+                log::warn!(
+                    "Failed to limit window ID {} to {}x{}: {}",
+                    ws.window_id,
+                    max_width,
+                    max_height,
+                    err
+                );
+            }
+        }
+        if let Some(icon) = &ws.icon {
+            set_window_icon(&mut window, ws.window_id, icon)?;
+        }
+        if ws.grab_input {
+            window.set_mouse_grab(true);
+            window.set_keyboard_grab(true);
+            if !window.keyboard_grab() {
+                log::warn!(
+                    "Keyboard grab is not supported on this platform, only grabbing the mouse."
+                );
+            }
+            log::info!(
+                "Input grabbed by window ID {}, press Ctrl+Alt+G to release.",
+                ws.window_id
+            );
+        }
+        if ws.relative_mouse {
+            self.sdl.mouse().set_relative_mouse_mode(&window, true);
+            log::info!(
+                "Relative mouse mode enabled for window ID {}, press Ctrl+Alt+G to release.",
+                ws.window_id
+            );
+        }
         // SDL3's into_canvas API returns a Canvas directly
         let mut canvas = window.into_canvas();
         self.server_window_to_sdl_window
             .insert(ws.window_id, sdl_window_id);
         self.sdl_window_to_server_window
             .insert(sdl_window_id, ws.window_id);
+        if ws.accepts_input == Some(false) {
+            self.input_disabled.insert(sdl_window_id);
+        } else {
+            // Receive composed text (accented characters, IME input) in addition to raw key events
+            self.video.text_input().start(canvas.window());
+        }
         log::info!("Window ID {} created", ws.window_id);
         canvas.clear();
         canvas.present();
+        let display_scale = canvas.window().display_scale();
+        let format = ws.frame_format(self.format);
         let sdl_window = SdlWindow {
             // server_window_id: ws.window_id,
             canvas,
+            texture: None,
+            damage: Vec::new(),
+            display_scale,
+            frame_buffer: FrameBuffer::new(format),
+            viewport: None,
+            format,
+            compression,
         };
         self.windows.insert(sdl_window_id, sdl_window);
         Ok(ws.window_id)
     }
 
-    async fn destroy_window(&mut self, window_id: WindowID) -> Result<()> {
+    fn destroy_window(&mut self, window_id: WindowID) -> Result<()> {
         if let Some(mut win) = self.windows.remove(&window_id) {
             win.canvas.window_mut().hide();
+            self.input_disabled.remove(&window_id);
+            self.cursors.remove(&window_id);
             // Translate SDL window id to server window id if possible
             if let Some(server_window_id) = self.sdl_window_to_server_window.remove(&window_id) {
                 // Remove reverse mapping
                 self.server_window_to_sdl_window.remove(&server_window_id);
-                self.stream
-                    .send(protocol::UserInput {
-                        window_id: server_window_id,
-                        kind: protocol::user_input::InputType::WindowEvent as i32,
-                        input_event: Some(protocol::user_input::InputEvent::WindowEvent(
-                            user_input::WindowEvent {
-                                action: WindowAction::Close as i32,
-                                x: 0,
-                                y: 0,
-                                width: 0,
-                                height: 0,
-                            },
-                        )),
-                    })
-                    .await?;
+                self.window_stats.remove(&server_window_id);
+                self.send(protocol::UserInput {
+                    window_id: server_window_id,
+                    kind: protocol::user_input::InputType::WindowEvent as i32,
+                    input_event: Some(protocol::user_input::InputEvent::WindowEvent(
+                        user_input::WindowEvent {
+                            action: WindowAction::Close as i32,
+                            x: 0,
+                            y: 0,
+                            width: 0,
+                            height: 0,
+                            scale_factor: 0.0,
+                        },
+                    )),
+                    client_timestamp_ns: self.input_timestamp_ns(),
+                })?;
                 log::info!(
                     "Window ID {} destroyed (server id {})",
                     window_id,
@@ -149,21 +372,21 @@ impl Client {
                 );
             } else {
                 // Fallback: send to window 0 if no mapping exists
-                self.stream
-                    .send(protocol::UserInput {
-                        window_id: 0,
-                        kind: protocol::user_input::InputType::WindowEvent as i32,
-                        input_event: Some(protocol::user_input::InputEvent::WindowEvent(
-                            user_input::WindowEvent {
-                                action: WindowAction::Close as i32,
-                                x: 0,
-                                y: 0,
-                                width: 0,
-                                height: 0,
-                            },
-                        )),
-                    })
-                    .await?;
+                self.send(protocol::UserInput {
+                    window_id: 0,
+                    kind: protocol::user_input::InputType::WindowEvent as i32,
+                    input_event: Some(protocol::user_input::InputEvent::WindowEvent(
+                        user_input::WindowEvent {
+                            action: WindowAction::Close as i32,
+                            x: 0,
+                            y: 0,
+                            width: 0,
+                            height: 0,
+                            scale_factor: 0.0,
+                        },
+                    )),
+                    client_timestamp_ns: self.input_timestamp_ns(),
+                })?;
                 log::info!("Window ID {} destroyed (no server mapping)", window_id);
             }
         } else {
@@ -172,46 +395,139 @@ impl Client {
         Ok(())
     }
 
-    fn get_format(&self) -> PixelFormat {
-        match self.format {
-            FrameFormat::Rgba => PixelFormat::RGBA32,
-            FrameFormat::Rgb => PixelFormat::RGB24,
-        }
-    }
-
-    fn bytes_per_pixel(&self) -> usize {
-        match self.format {
-            FrameFormat::Rgba => 4,
-            FrameFormat::Rgb => 3,
+    /// Close a window on request of the server, which already knows so no close event is sent back.
+    fn close_server_window(&mut self, server_window_id: WindowID) {
+        let Some(sdl_window_id) = self.server_window_to_sdl_window.remove(&server_window_id) else {
+            log::warn!(
+                "Server Window ID {} not found in mapping (not destroyed)",
+                server_window_id
+            );
+            return;
+        };
+        self.sdl_window_to_server_window.remove(&sdl_window_id);
+        self.input_disabled.remove(&sdl_window_id);
+        self.cursors.remove(&sdl_window_id);
+        self.window_stats.remove(&server_window_id);
+        if let Some(mut win) = self.windows.remove(&sdl_window_id) {
+            win.canvas.window_mut().hide();
         }
+        log::info!("Window ID {} closed by the server", server_window_id);
     }
 
-    async fn key_event(
+    fn key_event(
         &mut self,
         window_id: WindowID,
         action: KeyAction,
         keycode: sdl3::keyboard::Keycode,
         keymod: sdl3::keyboard::Mod,
+        repeat: bool,
+    ) -> Result<()> {
+        if self.input_disabled.contains(&window_id) {
+            return Ok(());
+        }
+        self.send(UserInput {
+            window_id: *self
+                .sdl_window_to_server_window
+                .get(&window_id)
+                .unwrap_or(&0),
+            kind: InputType::KeyEvent as i32,
+            input_event: Some(user_input::InputEvent::KeyEvent(user_input::KeyEvent {
+                action: action as i32,
+                key_code: keycode as i32,
+                modifiers: keymod.bits() as u32,
+                repeat,
+            })),
+            client_timestamp_ns: self.input_timestamp_ns(),
+        })?;
+        Ok(())
+    }
+
+    fn text_input(&mut self, window_id: WindowID, text: String) -> Result<()> {
+        if self.input_disabled.contains(&window_id) {
+            return Ok(());
+        }
+        self.send(UserInput {
+            window_id: *self
+                .sdl_window_to_server_window
+                .get(&window_id)
+                .unwrap_or(&0),
+            kind: InputType::TextInput as i32,
+            input_event: Some(user_input::InputEvent::TextInput(user_input::TextEvent {
+                text,
+            })),
+            client_timestamp_ns: self.input_timestamp_ns(),
+        })?;
+        Ok(())
+    }
+
+    /// Upload a file dropped onto a window to the server, read and sent in chunks by `Uploads`.
+    fn upload_file(&mut self, window_id: WindowID, path: &str) -> Result<()> {
+        if self.input_disabled.contains(&window_id) {
+            return Ok(());
+        }
+        let server_window_id = *self
+            .sdl_window_to_server_window
+            .get(&window_id)
+            .unwrap_or(&0);
+        self.uploads.start(server_window_id, PathBuf::from(path));
+        Ok(())
+    }
+
+    /// Forward a gamepad event to the window with keyboard focus, gamepads are not tied to a window.
+    fn gamepad_event(
+        &mut self,
+        which: u32,
+        action: GamepadAction,
+        axis: i32,
+        value: f32,
+        button: i32,
     ) -> Result<()> {
-        self.stream
-            .send(UserInput {
-                window_id: *self
-                    .sdl_window_to_server_window
-                    .get(&window_id)
-                    .unwrap_or(&0),
-                kind: InputType::KeyEvent as i32,
-                input_event: Some(user_input::InputEvent::KeyEvent(user_input::KeyEvent {
+        let Some(window_id) = self.sdl.keyboard().focused_window_id() else {
+            return Ok(());
+        };
+        if self.input_disabled.contains(&window_id) {
+            return Ok(());
+        }
+        self.send(UserInput {
+            window_id: *self
+                .sdl_window_to_server_window
+                .get(&window_id)
+                .unwrap_or(&0),
+            kind: InputType::GamepadEvent as i32,
+            input_event: Some(user_input::InputEvent::GamepadEvent(
+                user_input::GamepadEvent {
                     action: action as i32,
-                    key_code: keycode as i32,
-                    modifiers: keymod.bits() as u32,
-                })),
-            })
-            .await?;
+                    which,
+                    axis,
+                    value,
+                    button,
+                },
+            )),
+            client_timestamp_ns: self.input_timestamp_ns(),
+        })?;
         Ok(())
     }
 
+    /// Open a newly connected gamepad so its events are received.
+    fn gamepad_added(&mut self, which: u32) -> Result<()> {
+        let Some(subsystem) = &self.gamepad_subsystem else {
+            return Ok(());
+        };
+        match subsystem.open(sdl3::sys::joystick::SDL_JoystickID(which)) {
+            Ok(gamepad) => {
+                log::info!("Gamepad {} connected", which);
+                self.gamepads.insert(which, gamepad);
+                self.gamepad_event(which, GamepadAction::Connected, 0, 0.0, 0)
+            }
+            Err(e) => {
+                log::warn!("Failed to open gamepad {}: {}", which, e);
+                Ok(())
+            }
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
-    async fn mouse_event(
+    fn mouse_event(
         &mut self,
         window_id: WindowID,
         action: MouseAction,
@@ -220,7 +536,16 @@ impl Client {
         mouse_y: i32,
         delta_x: f32,
         delta_y: f32,
+        motion_x: f32,
+        motion_y: f32,
     ) -> Result<()> {
+        if self.input_disabled.contains(&window_id) {
+            log::trace!(
+                "Suppressed mouse event for display-only window {}",
+                window_id
+            );
+            return Ok(());
+        }
         let button = match button {
             Some(sdl3::mouse::MouseButton::Left) => {
                 user_input::mouse_event::MouseButton::Left as i32
@@ -248,24 +573,25 @@ impl Client {
             delta_x,
             delta_y
         );
-        self.stream
-            .send(UserInput {
-                window_id: server_window_id,
-                kind: InputType::MouseEvent as i32,
-                input_event: Some(user_input::InputEvent::MouseEvent(user_input::MouseEvent {
-                    action: action as i32,
-                    x: mouse_x,
-                    y: mouse_y,
-                    button,
-                    delta_x,
-                    delta_y,
-                })),
-            })
-            .await?;
+        self.send(UserInput {
+            window_id: server_window_id,
+            kind: InputType::MouseEvent as i32,
+            input_event: Some(user_input::InputEvent::MouseEvent(user_input::MouseEvent {
+                action: action as i32,
+                x: mouse_x,
+                y: mouse_y,
+                button,
+                delta_x,
+                delta_y,
+                motion_x,
+                motion_y,
+            })),
+            client_timestamp_ns: self.input_timestamp_ns(),
+        })?;
         Ok(())
     }
 
-    async fn window_event(
+    fn window_event(
         &mut self,
         window_id: WindowID,
         action: WindowAction,
@@ -274,28 +600,124 @@ impl Client {
         width: u32,
         height: u32,
     ) -> Result<()> {
-        self.stream
-            .send(UserInput {
-                window_id: *self
-                    .sdl_window_to_server_window
-                    .get(&window_id)
-                    .unwrap_or(&0),
-                kind: InputType::WindowEvent as i32,
-                input_event: Some(user_input::InputEvent::WindowEvent(
-                    user_input::WindowEvent {
-                        action: action as i32,
-                        x,
-                        y,
-                        width,
-                        height,
-                    },
-                )),
-            })
-            .await?;
+        self.send(UserInput {
+            window_id: *self
+                .sdl_window_to_server_window
+                .get(&window_id)
+                .unwrap_or(&0),
+            kind: InputType::WindowEvent as i32,
+            input_event: Some(user_input::InputEvent::WindowEvent(
+                user_input::WindowEvent {
+                    action: action as i32,
+                    x,
+                    y,
+                    width,
+                    height,
+                    scale_factor: 0.0,
+                },
+            )),
+            client_timestamp_ns: self.input_timestamp_ns(),
+        })?;
         Ok(())
     }
 
-    async fn handle_window_event(&mut self, event: Event) -> Result<bool> {
+    /// Notify the server if the window moved to a display with a different scale factor.
+    fn check_display_scale(&mut self, window_id: WindowID) -> Result<()> {
+        let Some(win) = self.windows.get_mut(&window_id) else {
+            return Ok(());
+        };
+        let scale_factor = win.canvas.window().display_scale();
+        if (scale_factor - win.display_scale).abs() <= f32::EPSILON {
+            return Ok(());
+        }
+        win.display_scale = scale_factor;
+        let (width, height) = win.canvas.window().size_in_pixels();
+        log::debug!(
+            "Window {} display scale changed to {}",
+            window_id,
+            scale_factor
+        );
+        self.send(UserInput {
+            window_id: *self
+                .sdl_window_to_server_window
+                .get(&window_id)
+                .unwrap_or(&0),
+            kind: InputType::WindowEvent as i32,
+            input_event: Some(user_input::InputEvent::WindowEvent(
+                user_input::WindowEvent {
+                    action: WindowAction::DpiChanged as i32,
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                    scale_factor,
+                },
+            )),
+            client_timestamp_ns: self.input_timestamp_ns(),
+        })
+    }
+
+    /// Tell the server which part of a window is visible on the displays, if it changed since the last hint.
+    fn viewport_hint(&mut self, window_id: WindowID, hidden: bool) -> Result<()> {
+        let Some(&server_window_id) = self.sdl_window_to_server_window.get(&window_id) else {
+            return Ok(());
+        };
+        let Some(win) = self.windows.get_mut(&window_id) else {
+            return Ok(());
+        };
+        let window = win.canvas.window();
+        let (x, y) = window.position();
+        let (width, height) = window.size();
+        let bounds = Rect::new(x, y, width, height);
+        let visible = if hidden {
+            None
+        } else {
+            self.video
+                .displays()
+                .ok()
+                .into_iter()
+                .flatten()
+                .filter_map(|display| display.get_bounds().ok())
+                .filter_map(|display| display.intersection(bounds))
+                .reduce(|a, b| a.union(b))
+        };
+        let hint = ViewportHint {
+            window_id: server_window_id,
+            x: visible.map_or(0, |r| r.x() - x),
+            y: visible.map_or(0, |r| r.y() - y),
+            width: visible.map_or(0, |r| r.width()),
+            height: visible.map_or(0, |r| r.height()),
+        };
+        if win.viewport.as_ref() == Some(&hint) {
+            return Ok(());
+        }
+        log::trace!("Window {} viewport: {:?}", window_id, hint);
+        win.viewport = Some(hint);
+        self.send(hint)
+    }
+
+    /// Release the mouse and keyboard grab and relative mouse mode of a window, if it has any.\
+    /// Returns `true` if the window had grabbed input.
+    fn release_grab(&mut self, window_id: WindowID) -> bool {
+        let Some(win) = self.windows.get_mut(&window_id) else {
+            return false;
+        };
+        let window = win.canvas.window_mut();
+        let mouse = self.sdl.mouse();
+        let relative = mouse.relative_mouse_mode(window);
+        if !window.mouse_grab() && !window.keyboard_grab() && !relative {
+            return false;
+        }
+        window.set_mouse_grab(false);
+        window.set_keyboard_grab(false);
+        if relative {
+            mouse.set_relative_mouse_mode(window, false);
+        }
+        log::info!("Input grab released for window {}", window_id);
+        true
+    }
+
+    fn handle_window_event(&mut self, event: Event) -> Result<bool> {
         log::trace!("SDL event: {:?}", event);
         match event {
             Event::Quit { .. } => {
@@ -310,10 +732,9 @@ impl Client {
                 // SDL3's WindowEvent variants may differ; handle common ones and
                 // fall back to checking the debug string for Close-type events.
                 if win_event == WindowEvent::CloseRequested {
-                    self.window_event(window_id, WindowAction::Close, 0, 0, 0, 0)
-                        .await?;
+                    self.window_event(window_id, WindowAction::Close, 0, 0, 0, 0)?;
                     log::trace!("Window {} closed", window_id);
-                    self.destroy_window(window_id).await?;
+                    self.destroy_window(window_id)?;
                 } else if let WindowEvent::Resized(width, height) = win_event {
                     self.window_event(
                         window_id,
@@ -322,45 +743,124 @@ impl Client {
                         0,
                         width as u32,
                         height as u32,
-                    )
-                    .await?;
+                    )?;
                     log::trace!("Window {} resized to {}x{}", window_id, width, height);
+                    self.viewport_hint(window_id, false)?;
                 } else if let WindowEvent::Moved(x, y) = win_event {
-                    self.window_event(window_id, WindowAction::Move, x, y, 0, 0)
-                        .await?;
+                    self.window_event(window_id, WindowAction::Move, x, y, 0, 0)?;
                     log::trace!("Window {} moved to ({}, {})", window_id, x, y);
+                    self.viewport_hint(window_id, false)?;
+                } else if win_event == WindowEvent::FocusGained {
+                    self.window_event(window_id, WindowAction::Focus, 0, 0, 0, 0)?;
+                    log::trace!("Window {} gained focus", window_id);
+                } else if win_event == WindowEvent::FocusLost {
+                    self.window_event(window_id, WindowAction::Unfocus, 0, 0, 0, 0)?;
+                    log::trace!("Window {} lost focus", window_id);
+                } else if matches!(win_event, WindowEvent::Minimized | WindowEvent::Hidden) {
+                    self.viewport_hint(window_id, true)?;
+                } else if matches!(win_event, WindowEvent::Restored | WindowEvent::Shown) {
+                    self.viewport_hint(window_id, false)?;
+                } else if matches!(
+                    win_event,
+                    WindowEvent::DisplayChanged(_) | WindowEvent::PixelSizeChanged(..)
+                ) {
+                    self.check_display_scale(window_id)?;
                 } else if win_event == WindowEvent::MouseEnter {
                     // Mouse entered the window (fallback via debug string)
-                    self.mouse_event(window_id, MouseAction::Enter, None, 0, 0, 0.0, 0.0)
-                        .await?;
+                    self.apply_cursor(Some(window_id));
+                    self.mouse_event(
+                        window_id,
+                        MouseAction::Enter,
+                        None,
+                        0,
+                        0,
+                        0.0,
+                        0.0,
+                        0.0,
+                        0.0,
+                    )?;
                     log::trace!("Mouse entered window {}", window_id);
                 } else if win_event == WindowEvent::MouseLeave {
                     // Mouse left the window (fallback via debug string)
-                    self.mouse_event(window_id, MouseAction::Exit, None, 0, 0, 0.0, 0.0)
-                        .await?;
+                    self.apply_cursor(None);
+                    self.mouse_event(window_id, MouseAction::Exit, None, 0, 0, 0.0, 0.0, 0.0, 0.0)?;
                     log::trace!("Mouse left window {}", window_id);
                 }
             }
             Event::KeyDown {
-                keycode: Some(keycode),
+                keycode: Some(Keycode::G),
                 keymod,
                 window_id,
                 ..
-            } => {
-                self.key_event(window_id, KeyAction::Press, keycode, keymod)
-                    .await?
+            } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+                && keymod.intersects(Mod::LALTMOD | Mod::RALTMOD)
+                && self.release_grab(window_id) =>
+            {
+                // Escape hotkey consumed by the client, not forwarded to the server
+            }
+            Event::KeyDown {
+                keycode: Some(Keycode::H),
+                keymod,
+                ..
+            } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+                && keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) =>
+            {
+                self.show_hud = !self.show_hud;
+                log::info!(
+                    "Frame statistics HUD {}",
+                    if self.show_hud { "shown" } else { "hidden" }
+                );
             }
+            Event::KeyDown {
+                keycode: Some(keycode),
+                keymod,
+                window_id,
+                repeat,
+                ..
+            } => self.key_event(window_id, KeyAction::Press, keycode, keymod, repeat)?,
             Event::KeyUp {
                 keycode: Some(keycode),
                 keymod,
                 window_id,
                 ..
+            } => self.key_event(window_id, KeyAction::Release, keycode, keymod, false)?,
+            Event::TextInput {
+                window_id, text, ..
+            } => {
+                log::trace!("Text input in window {}: {:?}", window_id, text);
+                self.text_input(window_id, text)?
+            }
+            Event::DropFile {
+                window_id,
+                filename,
+                ..
+            } => self.upload_file(window_id, &filename)?,
+            Event::ControllerDeviceAdded { which, .. } => self.gamepad_added(which)?,
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if self.gamepads.remove(&which).is_some() {
+                    log::info!("Gamepad {} disconnected", which);
+                    self.gamepad_event(which, GamepadAction::Disconnected, 0, 0.0, 0)?;
+                }
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
             } => {
-                self.key_event(window_id, KeyAction::Release, keycode, keymod)
-                    .await?
+                let value = (value as f32 / i16::MAX as f32).clamp(-1.0, 1.0);
+                self.gamepad_event(which, GamepadAction::AxisMotion, axis as i32, value, 0)?
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.gamepad_event(which, GamepadAction::ButtonPress, 0, 0.0, button as i32)?
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.gamepad_event(which, GamepadAction::ButtonRelease, 0, 0.0, button as i32)?
             }
             Event::MouseMotion {
-                window_id, x, y, ..
+                window_id,
+                x,
+                y,
+                xrel,
+                yrel,
+                ..
             } => {
                 self.mouse_event(
                     window_id,
@@ -370,8 +870,9 @@ impl Client {
                     y as i32,
                     0.0,
                     0.0,
-                )
-                .await?;
+                    xrel,
+                    yrel,
+                )?;
                 log::trace!("Mouse moved in window {}: ({}, {})", window_id, x, y);
             }
             Event::MouseButtonDown {
@@ -389,8 +890,9 @@ impl Client {
                     y as i32,
                     0.0,
                     0.0,
-                )
-                .await?;
+                    0.0,
+                    0.0,
+                )?;
                 log::trace!(
                     "Mouse button pressed in window {}: ({}, {})",
                     window_id,
@@ -413,8 +915,9 @@ impl Client {
                     y as i32,
                     0.0,
                     0.0,
-                )
-                .await?;
+                    0.0,
+                    0.0,
+                )?;
                 log::trace!(
                     "Mouse button released in window {}: ({}, {})",
                     window_id,
@@ -423,24 +926,32 @@ impl Client {
                 );
             }
             Event::MouseWheel {
-                window_id, x, y, ..
+                window_id,
+                x,
+                y,
+                direction,
+                mouse_x,
+                mouse_y,
+                ..
             } => {
-                // SDL3 MouseWheel fields differ; use x/y as deltas. Position may not be available.
+                let (delta_x, delta_y) = normalize_scroll(x, y, direction);
                 self.mouse_event(
                     window_id,
                     MouseAction::Scroll,
                     None,
-                    0,
-                    0,
-                    x as f32,
-                    y as f32,
-                )
-                .await?;
+                    mouse_x as i32,
+                    mouse_y as i32,
+                    delta_x,
+                    delta_y,
+                    0.0,
+                    0.0,
+                )?;
                 log::trace!(
-                    "Mouse wheel scrolled in window {}: delta=({}, {})",
+                    "Mouse wheel scrolled in window {}: delta=({}, {}) ({:?})",
                     window_id,
-                    x,
-                    y
+                    delta_x,
+                    delta_y,
+                    direction
                 );
             }
             _ => {
@@ -450,113 +961,231 @@ impl Client {
         Ok(true)
     }
 
-    pub async fn main(&mut self) -> Result<()> {
-        let mut event_pump = self.sdl.event_pump().map_err(|e| anyhow!(e))?;
-        let mut last_frame_time = Instant::now();
-        'running: loop {
-            // Read messages from the server
-            match self.stream.receive().await {
-                Ok(event) => {
-                    if !self.handle_server_event(event).await? {
-                        break 'running;
-                    }
-                }
-                Err(err) => match err.kind() {
-                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => (), // No data available yet, do nothing
-                    std::io::ErrorKind::UnexpectedEof
-                    | std::io::ErrorKind::ConnectionAborted
-                    | std::io::ErrorKind::ConnectionRefused
-                    | std::io::ErrorKind::ConnectionReset
-                    | std::io::ErrorKind::NotConnected => {
-                        log::trace!("Server disconnected!");
-                        break 'running;
-                    }
-                    _ => {
-                        log::error!("Error reading message: {}", err);
-                        break 'running;
-                    }
-                },
-            };
+    /// Move a window as requested by the server, if the position is on a connected display.
+    fn move_window(&mut self, position: SetWindowPosition) {
+        let Some(win) = self
+            .server_window_to_sdl_window
+            .get(&position.window_id)
+            .and_then(|sdl_window_id| self.windows.get_mut(sdl_window_id))
+        else {
+            log::warn!(
+                "Server Window ID {} not found in mapping (not moved)",
+                position.window_id
+            );
+            return;
+        };
+        let on_display = self.video.displays().is_ok_and(|displays| {
+            displays.iter().any(|display| {
+                display
+                    .get_bounds()
+                    .is_ok_and(|bounds| bounds.contains_point((position.x, position.y)))
+            })
+        });
+        if !on_display {
+            log::warn!(
+                "Requested position ({}, {}) for window ID {} is outside of all displays, ignoring.",
+                position.x,
+                position.y,
+                position.window_id
+            );
+            return;
+        }
+        win.canvas.window_mut().set_position(
+            video::WindowPos::Positioned(position.x),
+            video::WindowPos::Positioned(position.y),
+        );
+        log::trace!(
+            "Window ID {} moved to ({}, {})",
+            position.window_id,
+            position.x,
+            position.y
+        );
+    }
+
+    /// Move the mouse pointer within a window, clamped to the window bounds.
+    fn warp_mouse(&mut self, warp: WarpCursor) {
+        let Some(win) = self
+            .server_window_to_sdl_window
+            .get(&warp.window_id)
+            .and_then(|sdl_window_id| self.windows.get(sdl_window_id))
+        else {
+            log::warn!(
+                "Server Window ID {} not found in mapping (cursor not warped)",
+                warp.window_id
+            );
+            return;
+        };
+        let (width, height) = win.canvas.window().size();
+        let (x, y) = clamp_to_window(warp.x, warp.y, width, height);
+        if (x, y) != (warp.x, warp.y) {
+            log::debug!(
+                "Warp to ({}, {}) is outside of window ID {} ({}x{}), clamped to ({}, {})",
+                warp.x,
+                warp.y,
+                warp.window_id,
+                width,
+                height,
+                x,
+                y
+            );
+        }
+        self.sdl
+            .mouse()
+            .warp_mouse_in_window(win.canvas.window(), x as f32, y as f32);
+        log::trace!(
+            "Cursor warped to ({}, {}) in window ID {}",
+            x,
+            y,
+            warp.window_id
+        );
+    }
 
-            // Events from SDL windows
-            for event in event_pump.poll_iter() {
-                if !self.handle_window_event(event).await? {
-                    break 'running;
+    /// Change the state of a window as requested by the server.\
+    /// Resulting size changes are reported back to the server by the regular window event handling.
+    fn run_window_command(&mut self, command: WindowCommand) -> Result<()> {
+        let Some(win) = self
+            .server_window_to_sdl_window
+            .get(&command.window_id)
+            .and_then(|sdl_window_id| self.windows.get_mut(sdl_window_id))
+        else {
+            log::warn!(
+                "Server Window ID {} not found in mapping (command ignored)",
+                command.window_id
+            );
+            return Ok(());
+        };
+        let Ok(action) = Action::try_from(command.action) else {
+            log::warn!(
+                "Unknown window command {} for window ID {}, ignoring.",
+                command.action,
+                command.window_id
+            );
+            return Ok(());
+        };
+        let window = win.canvas.window_mut();
+        match action {
+            Action::Minimize => {
+                if !window.minimize() {
+                    log::warn!("Failed to minimize window ID {}", command.window_id);
                 }
             }
-
-            // Sleep to maintain frame rate
-            let elapsed_time = last_frame_time.elapsed().as_nanos() as u64;
-            if elapsed_time < FRAME_TIME {
-                tokio::time::sleep(Duration::from_nanos(FRAME_TIME - elapsed_time)).await;
-            } else {
-                log::trace!(
-                    "Frame time exceeded: {} ns (max: {} ns)",
-                    elapsed_time,
-                    FRAME_TIME
-                );
+            Action::Maximize => {
+                if !window.maximize() {
+                    log::warn!("Failed to maximize window ID {}", command.window_id);
+                }
             }
-            last_frame_time = Instant::now();
-        }
-        for window_id in self.windows.keys().cloned().collect::<Vec<_>>() {
-            self.destroy_window(window_id).await?;
+            Action::Restore => {
+                window.set_fullscreen(false).map_err(|e| anyhow!(e))?;
+                if !window.restore() {
+                    log::warn!("Failed to restore window ID {}", command.window_id);
+                }
+            }
+            Action::Fullscreen => window.set_fullscreen(true).map_err(|e| anyhow!(e))?,
+            Action::SetTitle => window.set_title(&command.title)?,
         }
+        log::trace!("Window ID {} command: {:?}", command.window_id, action);
         Ok(())
     }
 
-    async fn handle_server_event(&mut self, event: ServerEvent) -> Result<bool> {
-        match event {
-            ServerEvent::StatusUpdate(status_update) => {
-                self.handle_status_update(status_update).await
-            }
-            ServerEvent::Frame(frame) => self.render_frame(frame),
-            other => {
-                log::error!("Unexpected server event: {:?}", other);
-                return Err(anyhow!("Unexpected server event"));
+    /// Store the cursor requested by the server for a window, applying it right away if the mouse is over the window.
+    fn update_cursor(&mut self, update: CursorUpdate) -> Result<()> {
+        let Some(&sdl_window_id) = self.server_window_to_sdl_window.get(&update.window_id) else {
+            log::warn!(
+                "Server Window ID {} not found in mapping (cursor not updated)",
+                update.window_id
+            );
+            return Ok(());
+        };
+        let cursor = if update.image_data.is_empty() {
+            None
+        } else {
+            let expected = FrameFormat::Rgba.data_len(update.width, update.height);
+            if update.width == 0 || update.image_data.len() != expected {
+                log::warn!(
+                    "Cursor image for window ID {} has {} bytes, expected {} for {}x{} RGBA, ignoring.",
+                    update.window_id,
+                    update.image_data.len(),
+                    expected,
+                    update.width,
+                    update.height
+                );
+                return Ok(());
             }
+            let mut data = update.image_data;
+            let surface = Surface::from_data(
+                &mut data,
+                update.width,
+                update.height,
+                FrameFormat::Rgba.row_stride(update.width as usize) as u32,
+                PixelFormat::RGBA32,
+            )
+            .map_err(|e| anyhow!(e))?;
+            Some(
+                Cursor::from_surface(&surface, update.hotspot_x, update.hotspot_y)
+                    .map_err(|e| anyhow!(e))?,
+            )
+        };
+        log::trace!(
+            "Cursor for window ID {} updated: visible={}, custom={}",
+            update.window_id,
+            update.visible,
+            cursor.is_some()
+        );
+        self.cursors.insert(
+            sdl_window_id,
+            WindowCursor {
+                visible: update.visible,
+                cursor,
+            },
+        );
+        if self.sdl.mouse().focused_window_id() == Some(sdl_window_id) {
+            self.apply_cursor(Some(sdl_window_id));
         }
+        Ok(())
     }
 
-    async fn handle_status_update(&mut self, status_update: StatusUpdate) -> Result<bool> {
-        match status_update.kind.try_into()? {
-            StatusType::Exit => {
-                log::trace!("Server gracefully disconnected!");
-                Ok(false)
-            }
-            StatusType::Info => {
-                let details = status_update.details.ok_or(anyhow!("Missing details"))?;
-                let Details::Info(info) = details else {
-                    log::warn!("Received unexpected status update message, skipping.");
-                    return Ok(true);
-                };
-                log::info!("Server info: {}", info.message);
-                Ok(true)
-            }
-            StatusType::Warning => {
-                let details = status_update.details.ok_or(anyhow!("Missing details"))?;
-                let Details::Warning(warning) = details else {
-                    log::warn!("Received unexpected status update message, skipping.");
-                    return Ok(true);
-                };
-                log::warn!("Server warning: {}", warning.message);
-                Ok(true)
-            }
-            StatusType::Error => {
-                let details = status_update.details.ok_or(anyhow!("Missing details"))?;
-                let Details::Error(error) = details else {
-                    log::warn!("Received unexpected status update message, skipping.");
-                    return Ok(true);
-                };
-                log::error!("Server error: {}", error.message);
-                Ok(true)
+    /// Show the cursor requested for the window the mouse is over, or the visible system cursor.
+    fn apply_cursor(&self, window_id: Option<WindowID>) {
+        let requested = window_id.and_then(|id| self.cursors.get(&id));
+        match requested.and_then(|c| c.cursor.as_ref()) {
+            Some(cursor) => cursor.set(),
+            None => {
+                if let Some(cursor) = &self.default_cursor {
+                    cursor.set();
+                }
             }
         }
+        self.sdl
+            .mouse()
+            .show_cursor(requested.is_none_or(|c| c.visible));
+    }
+
+    fn render_draw_list(&mut self, list: DrawList) -> Result<()> {
+        let Some(win) = self
+            .server_window_to_sdl_window
+            .get(&list.window_id)
+            .and_then(|sdl_window_id| self.windows.get_mut(sdl_window_id))
+        else {
+            log::warn!(
+                "Server Window ID {} not found in mapping (draw list not rendered)",
+                list.window_id
+            );
+            return Ok(());
+        };
+        log::trace!(
+            "Rendering draw list ({} commands) for window ID {}",
+            list.commands.len(),
+            list.window_id
+        );
+        drawlist::render_draw_list(&mut win.canvas, &list)?;
+        win.canvas.present();
+        Ok(())
     }
 
-    fn render_frame(&mut self, frame: Frame) -> Result<bool> {
+    fn render_frame(&mut self, frame: Frame) -> Result<()> {
         if frame.segments.is_empty() || frame.width == 0 || frame.height == 0 {
             log::warn!("Received empty frame, skipping rendering.");
-            return Ok(true); // Keep going
+            return Ok(());
         }
         log::debug!(
             "Received frame of size {}x{} and {} segments",
@@ -564,8 +1193,6 @@ impl Client {
             frame.height,
             frame.segments.len()
         );
-        let format = self.get_format();
-        let pixel_bytes = self.bytes_per_pixel();
         let server_window_id = frame.window_id;
         if let Some(sdl_window_id) = self.server_window_to_sdl_window.get(&server_window_id) {
             log::trace!(
@@ -574,50 +1201,134 @@ impl Client {
                 server_window_id
             );
             let win = self.windows.get_mut(sdl_window_id).unwrap();
-            let texture_creator = win.canvas.texture_creator();
-            let mut texture =
-                texture_creator.create_texture_target(format, frame.width, frame.height)?;
-            // Ensure the texture does not blend with the existing canvas contents.
-            let _ = texture.set_blend_mode(BlendMode::None);
-            // Clear the canvas first so previous frames don't persist beneath the new one.
-            win.canvas.set_draw_color(Color::BLACK);
-            win.canvas.clear();
+            let format = pixel_format(win.format);
+            let texture_pixel_bytes = texture_bytes_per_pixel(win.format);
+            let is_gray = win.format == FrameFormat::Gray8;
+            let is_yuv = win.format == FrameFormat::Yuv420p;
+            if win.frame_buffer.resize(frame.width, frame.height) || win.texture.is_none() {
+                log::debug!(
+                    "Frame buffer for window ID {} resized to {}x{}",
+                    server_window_id,
+                    frame.width,
+                    frame.height
+                );
+                let mut texture = win.canvas.texture_creator().create_texture_streaming(
+                    format,
+                    frame.width,
+                    frame.height,
+                )?;
+                // Ensure the texture does not blend with the existing canvas contents.
+                let _ = texture.set_blend_mode(BlendMode::None);
+                if let Some(old) = win.texture.replace(texture) {
+                    // SAFETY: the old texture was created by this window's renderer and is no longer used
+                    unsafe { old.destroy() };
+                }
+                win.damage = vec![win.frame_buffer.bounds()];
+                let stats = self.window_stats.entry(server_window_id).or_default();
+                stats.textures += 1;
+                log::debug!(
+                    "Allocated texture {} for window ID {}, reused until the frame size changes",
+                    stats.textures,
+                    server_window_id
+                );
+            }
+            let mut regions = Vec::new();
+            let mut applied_segments = 0;
             // Apply all segments of the frame to the window
             for segment in &frame.segments {
                 if segment.width == 0 || segment.height == 0 {
                     log::warn!("Received empty segment, skipping rendering.");
                     continue;
                 }
-                let pixel_data = if let Some(compression) = self.compression {
-                    match compression {
-                        server_hello_ack::Compression::Zstd(_zstd) => {
-                            let mut decoder =
-                                libgsh::zstd::stream::Decoder::new(&segment.data[..])?;
-                            let expected_len =
-                                segment.width as usize * segment.height as usize * pixel_bytes;
-                            let mut out = Vec::with_capacity(expected_len);
-                            decoder.read_to_end(&mut out)?;
-                            out
-                        }
-                    }
-                } else {
-                    segment.data.clone()
-                };
-                texture.update(
-                    Some(Rect::new(
-                        segment.x,
-                        segment.y,
-                        segment.width,
-                        segment.height,
-                    )),
-                    &pixel_data,
-                    segment.width as usize * pixel_bytes,
+                let pixel_data = compression::decompress(
+                    win.compression.as_ref(),
+                    &segment.data,
+                    win.format.data_len(segment.width, segment.height),
                 )?;
+                applied_segments += 1;
+                // Segment coordinates are absolute within the frame
+                win.damage.extend(win.frame_buffer.apply_segment(
+                    segment.x,
+                    segment.y,
+                    segment.width,
+                    segment.height,
+                    &pixel_data,
+                ));
+                if self.verify_tolerance.is_some() && !is_yuv {
+                    let rect = Rect::new(segment.x, segment.y, segment.width, segment.height);
+                    if is_gray {
+                        regions.push((rect, framebuffer::expand_gray8(&pixel_data)));
+                    } else {
+                        regions.push((rect, pixel_data));
+                    }
+                }
+            }
+            self.window_stats
+                .entry(server_window_id)
+                .or_default()
+                .record_frame(
+                    frame.width,
+                    frame.height,
+                    frame.segments.iter().map(|s| s.data.len()).sum(),
+                    applied_segments,
+                );
+            if frame.frame_group_id.is_some() && !frame.is_final {
+                log::trace!(
+                    "Buffered frame of group {:?} for window ID {}, waiting for the final frame",
+                    frame.frame_group_id,
+                    server_window_id
+                );
+                self.buffered_frames += 1;
+                return Ok(());
             }
+            self.buffered_frames = 0;
+            // Only upload the damaged regions, the texture keeps the rest of the previous contents
+            let texture = win.texture.as_mut().expect("texture created above");
+            for region in win.damage.drain(..) {
+                upload_region(texture, &win.frame_buffer, &region, is_gray)?;
+            }
+            // The texture covers the whole canvas, so there is no need to clear it first
             win.canvas
-                .copy(&texture, None, None)
+                .copy(texture, None, None)
                 .map_err(|e| anyhow!(e))?;
-            win.canvas.present();
+            // Verification compares RGB pixels, YUV would need converting like the renderer does
+            if let Some(tolerance) = self.verify_tolerance.filter(|_| !is_yuv) {
+                let (out_width, out_height) = win.canvas.output_size().map_err(|e| anyhow!(e))?;
+                if (out_width, out_height) != (frame.width, frame.height) {
+                    log::debug!(
+                        "Skipping frame verification, output {}x{} is scaled from {}x{}",
+                        out_width,
+                        out_height,
+                        frame.width,
+                        frame.height
+                    );
+                } else {
+                    let report = fidelity::verify_canvas(
+                        &win.canvas,
+                        format,
+                        &regions,
+                        texture_pixel_bytes,
+                        tolerance,
+                    )?;
+                    if report.is_match() {
+                        log::debug!("Frame verified: {} pixels match", report.pixels);
+                    } else {
+                        log::warn!(
+                            "Frame mismatch in window ID {}: {}/{} pixels differ (max channel diff {})",
+                            server_window_id,
+                            report.mismatched,
+                            report.pixels,
+                            report.max_diff
+                        );
+                    }
+                }
+            }
+            if self.show_hud {
+                if let Some(stats) = self.window_stats.get(&server_window_id) {
+                    stats::render_hud(&mut win.canvas, server_window_id, stats)?;
+                }
+            }
+            win.canvas.present();
             log::trace!("Updated window ID {}", server_window_id);
         } else {
             log::warn!(
@@ -625,6 +1336,1076 @@ impl Client {
                 server_window_id
             );
         }
-        Ok(true) // Keep going
+        Ok(())
+    }
+}
+
+/// Scroll deltas in the protocol's sign convention, undoing the inversion of "natural" scrolling.
+fn normalize_scroll(x: f32, y: f32, direction: MouseWheelDirection) -> (f32, f32) {
+    match direction {
+        MouseWheelDirection::Flipped => (-x, -y),
+        _ => (x, y),
+    }
+}
+
+/// Clamp a position to the pixels of a window of the given size.
+fn clamp_to_window(x: i32, y: i32, width: u32, height: u32) -> (i32, i32) {
+    let max_x = (width as i32 - 1).max(0);
+    let max_y = (height as i32 - 1).max(0);
+    (x.clamp(0, max_x), y.clamp(0, max_y))
+}
+
+/// Set the icon requested by the server, ignoring icons with invalid dimensions.
+fn set_window_icon(window: &mut video::Window, window_id: WindowID, icon: &Icon) -> Result<()> {
+    let expected = FrameFormat::Rgba.data_len(icon.width, icon.height);
+    if icon.width == 0 || icon.image_data.len() != expected {
+        log::warn!(
+            "Icon for window ID {} has {} bytes, expected {} for {}x{} RGBA, ignoring.",
+            window_id,
+            icon.image_data.len(),
+            expected,
+            icon.width,
+            icon.height
+        );
+        return Ok(());
+    }
+    let mut data = icon.image_data.clone();
+    let surface = Surface::from_data(
+        &mut data,
+        icon.width,
+        icon.height,
+        FrameFormat::Rgba.row_stride(icon.width as usize) as u32,
+        PixelFormat::RGBA32,
+    )
+    .map_err(|e| anyhow!(e))?;
+    window.set_icon(surface);
+    Ok(())
+}
+
+/// Copy a region of a window's frame buffer to its texture.
+fn upload_region(
+    texture: &mut Texture,
+    frame_buffer: &FrameBuffer,
+    region: &Region,
+    is_gray: bool,
+) -> Result<()> {
+    let rect = Rect::new(
+        region.x as i32,
+        region.y as i32,
+        region.width,
+        region.height,
+    );
+    match frame_buffer {
+        FrameBuffer::Packed(buffer) if is_gray => {
+            let expanded = framebuffer::expand_gray8(&buffer.region(region));
+            texture.update(
+                rect,
+                &expanded,
+                FrameFormat::Rgb.row_stride(region.width as usize),
+            )?;
+        }
+        FrameBuffer::Packed(buffer) => {
+            let pitch = region.width as usize * buffer.pixel_bytes();
+            texture.update(rect, &buffer.region(region), pitch)?;
+        }
+        FrameBuffer::Yuv420p(buffer) => {
+            let [y, u, v] = buffer.region(region);
+            let chroma_pitch = framebuffer::chroma_region(region).width as usize;
+            texture.update_yuv(
+                rect,
+                &y,
+                region.width as usize,
+                &u,
+                chroma_pitch,
+                &v,
+                chroma_pitch,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn pixel_format(format: FrameFormat) -> PixelFormat {
+    match format {
+        FrameFormat::Rgba => PixelFormat::RGBA32,
+        FrameFormat::Rgb => PixelFormat::RGB24,
+        FrameFormat::Bgra => PixelFormat::BGRA32,
+        FrameFormat::Bgr => PixelFormat::BGR24,
+        // Expanded to RGB before uploading, see `framebuffer::expand_gray8`
+        FrameFormat::Gray8 => PixelFormat::RGB24,
+        // Converted to RGB by the renderer
+        FrameFormat::Yuv420p => PixelFormat::IYUV,
+    }
+}
+
+/// Bytes per pixel of the uploaded texture, which differs from the wire format for Gray8.
+fn texture_bytes_per_pixel(format: FrameFormat) -> usize {
+    match format {
+        FrameFormat::Gray8 => 3,
+        _ => format.bytes_per_pixel(),
+    }
+}
+
+impl FrameSink for Client {
+    fn frame(&mut self, frame: Frame) -> std::result::Result<(), FrontendError> {
+        self.render_frame(frame).map_err(Into::into)
+    }
+
+    fn draw_list(&mut self, list: DrawList) -> std::result::Result<(), FrontendError> {
+        self.render_draw_list(list).map_err(Into::into)
+    }
+
+    fn audio(&mut self, chunk: AudioChunk) -> std::result::Result<(), FrontendError> {
+        let stream_id = chunk.stream_id;
+        if let Err(err) = self.audio.play(chunk) {
+            log::warn!("Failed to play audio stream {}: {}", stream_id, err);
+        }
+        Ok(())
+    }
+
+    fn set_window_position(
+        &mut self,
+        position: SetWindowPosition,
+    ) -> std::result::Result<(), FrontendError> {
+        self.move_window(position);
+        Ok(())
+    }
+
+    fn open_window(&mut self, settings: WindowSettings) -> std::result::Result<(), FrontendError> {
+        if self
+            .server_window_to_sdl_window
+            .contains_key(&settings.window_id)
+        {
+            log::warn!(
+                "Window ID {} already exists (not created)",
+                settings.window_id
+            );
+            return Ok(());
+        }
+        self.create_window(&settings)?;
+        Ok(())
+    }
+
+    fn close_window(&mut self, window: DestroyWindow) -> std::result::Result<(), FrontendError> {
+        self.close_server_window(window.window_id);
+        Ok(())
+    }
+
+    fn window_command(&mut self, command: WindowCommand) -> std::result::Result<(), FrontendError> {
+        self.run_window_command(command).map_err(Into::into)
+    }
+
+    fn cursor(&mut self, cursor: CursorUpdate) -> std::result::Result<(), FrontendError> {
+        self.update_cursor(cursor).map_err(Into::into)
+    }
+
+    fn warp_cursor(&mut self, warp: WarpCursor) -> std::result::Result<(), FrontendError> {
+        self.warp_mouse(warp);
+        Ok(())
+    }
+
+    fn file_offer(&mut self, offer: FileOffer) -> std::result::Result<(), FrontendError> {
+        let Some(downloads) = &mut self.downloads else {
+            log::warn!(
+                "Ignoring file {} sent by the server, no download directory set (see --download-dir)",
+                offer.name
+            );
+            return Ok(());
+        };
+        if let Err(err) = downloads.offer(&offer) {
+            log::warn!("Refused file {} from the server: {}", offer.name, err);
+        }
+        Ok(())
+    }
+
+    fn file_chunk(&mut self, chunk: FileChunk) -> std::result::Result<(), FrontendError> {
+        if let Some(downloads) = &mut self.downloads {
+            if let Err(err) = downloads.chunk(&chunk) {
+                log::warn!("Failed to receive file from the server: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    fn file_end(&mut self, end: FileTransferEnd) -> std::result::Result<(), FrontendError> {
+        if let Some(downloads) = &mut self.downloads {
+            match downloads.end(end.transfer_id) {
+                Ok(path) => log::info!("Downloaded {}", path.display()),
+                Err(err) => log::warn!("Failed to save file from the server: {}", err),
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&mut self, status: StatusUpdate) -> std::result::Result<(), FrontendError> {
+        match status.details {
+            Some(Details::Info(info)) => log::info!("Server info: {}", info.message),
+            Some(Details::Warning(warning)) => log::warn!("Server warning: {}", warning.message),
+            Some(Details::Error(error)) => log::error!("Server error: {}", error.message),
+            None => log::warn!("Received status update without details, skipping."),
+        }
+        Ok(())
+    }
+
+    fn buffered_frames(&self) -> u32 {
+        self.buffered_frames
+    }
+
+    fn frames_dropped(&mut self, window_id: u32, count: u64) {
+        self.window_stats
+            .entry(window_id)
+            .or_default()
+            .dropped_frames += count;
+    }
+
+    fn received(&mut self, event: &ServerEvent) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.received(event);
+        }
+    }
+}
+
+impl InputSource for Client {
+    fn poll_input(
+        &mut self,
+        outbox: &mut Vec<ClientMessage>,
+    ) -> std::result::Result<bool, FrontendError> {
+        // Collect first, handling an event needs the client mutably
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+        let mut keep_going = true;
+        for event in events {
+            if !self.handle_window_event(event)? {
+                keep_going = false;
+                break;
+            }
+        }
+        if !keep_going {
+            for window_id in self.windows.keys().cloned().collect::<Vec<_>>() {
+                self.destroy_window(window_id)?;
+            }
+        }
+        outbox.append(&mut self.outbox);
+        self.uploads.poll(outbox);
+        Ok(keep_going)
+    }
+
+    fn sent(&mut self, message: &ClientMessage) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.sent(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libgsh::shared::protocol::{
+        client_message::ClientEvent,
+        draw_list::{self, draw_command::Command, DrawCommand},
+        frame::Segment,
+    };
+    use std::sync::{Mutex, MutexGuard};
+
+    /// SDL allows a single event pump at a time, so tests creating a client run one after the other.
+    static SDL_LOCK: Mutex<()> = Mutex::new(());
+
+    /// A client on SDL's dummy video driver, which doesn't need a display.\
+    /// It has a single 1024x768 display at the origin and supports moving windows.
+    /// Keep the guard alive for as long as the client.
+    fn headless_client() -> (MutexGuard<'static, ()>, Client) {
+        let guard = SDL_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        sdl3::hint::set("SDL_VIDEO_DRIVER", "dummy");
+        sdl3::hint::set("SDL_AUDIO_DRIVER", "dummy");
+        let sdl = sdl3::init().unwrap();
+        let video = sdl.video().unwrap();
+        let client = Client::new(sdl, video, FrameFormat::Rgba, None).unwrap();
+        (guard, client)
+    }
+
+    /// Opens a server window on the client, returning its SDL window ID.
+    fn open_window(client: &mut Client, settings: WindowSettings) -> WindowID {
+        let window_id = client.create_window(&settings).unwrap();
+        client.server_window_to_sdl_window[&window_id]
+    }
+
+    fn window_settings(window_id: u32) -> WindowSettings {
+        WindowSettings {
+            window_id,
+            title: format!("Window {}", window_id),
+            width: 64,
+            height: 48,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn input_timestamps_increase_monotonically() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+        for i in 0..100 {
+            match i % 3 {
+                0 => client
+                    .key_event(window_id, KeyAction::Press, Keycode::A, Mod::NOMOD, false)
+                    .unwrap(),
+                1 => client
+                    .mouse_event(window_id, MouseAction::Move, None, i, i, 0.0, 0.0, 0.0, 0.0)
+                    .unwrap(),
+                _ => client
+                    .window_event(window_id, WindowAction::Move, i, i, 0, 0)
+                    .unwrap(),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(2));
+        client
+            .key_event(window_id, KeyAction::Release, Keycode::A, Mod::NOMOD, false)
+            .unwrap();
+
+        let timestamps: Vec<u64> = client
+            .outbox
+            .iter()
+            .filter_map(|message| match &message.client_event {
+                Some(ClientEvent::UserInput(input)) => Some(input.client_timestamp_ns),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(timestamps.len(), 101);
+        assert!(timestamps[0] > 0);
+        for pair in timestamps.windows(2) {
+            assert!(
+                pair[0] <= pair[1],
+                "timestamp went backwards: {} after {}",
+                pair[1],
+                pair[0]
+            );
+        }
+        assert!(timestamps[100] > timestamps[99]);
+    }
+
+    #[test]
+    fn input_on_display_only_window_is_suppressed() {
+        let (_guard, mut client) = headless_client();
+        let display_only = open_window(
+            &mut client,
+            WindowSettings {
+                accepts_input: Some(false),
+                ..window_settings(1)
+            },
+        );
+        let interactive = open_window(&mut client, window_settings(2));
+
+        for window_id in [display_only, interactive] {
+            client
+                .key_event(window_id, KeyAction::Press, Keycode::A, Mod::NOMOD, false)
+                .unwrap();
+            client
+                .mouse_event(
+                    window_id,
+                    MouseAction::Press,
+                    Some(sdl3::mouse::MouseButton::Left),
+                    10,
+                    10,
+                    0.0,
+                    0.0,
+                    0.0,
+                    0.0,
+                )
+                .unwrap();
+        }
+
+        let forwarded: Vec<u32> = client
+            .outbox
+            .iter()
+            .filter_map(|message| match &message.client_event {
+                Some(ClientEvent::UserInput(input)) => Some(input.window_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(forwarded, vec![2, 2]);
+    }
+
+    #[test]
+    fn moving_to_higher_dpi_display_emits_dpi_change() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+        // Pretend the window was on a lower-DPI display before the display changed
+        let scale_factor = client.windows[&window_id].display_scale;
+        client.windows.get_mut(&window_id).unwrap().display_scale = scale_factor / 2.0;
+
+        client.check_display_scale(window_id).unwrap();
+        // Unchanged scale factors are not reported again
+        client.check_display_scale(window_id).unwrap();
+
+        let events: Vec<user_input::WindowEvent> = client
+            .outbox
+            .iter()
+            .filter_map(|message| match &message.client_event {
+                Some(ClientEvent::UserInput(UserInput {
+                    input_event: Some(user_input::InputEvent::WindowEvent(event)),
+                    ..
+                })) => Some(*event),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].action(), WindowAction::DpiChanged);
+        assert_eq!(events[0].scale_factor, scale_factor);
+    }
+
+    /// The client's escape hotkey (Ctrl+Alt+G) pressed in a window.
+    fn escape_hotkey(window_id: WindowID) -> Event {
+        Event::KeyDown {
+            timestamp: 0,
+            window_id,
+            keycode: Some(Keycode::G),
+            scancode: None,
+            keymod: Mod::LCTRLMOD | Mod::LALTMOD,
+            repeat: false,
+            which: 0,
+            raw: 0,
+        }
+    }
+
+    #[test]
+    fn key_presses_repeats_and_releases_are_forwarded() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+        let key = |down: bool, repeat: bool| {
+            let (timestamp, keycode, scancode, keymod, which, raw) =
+                (0, Some(Keycode::W), None, Mod::NOMOD, 0, 0);
+            if down {
+                Event::KeyDown {
+                    timestamp,
+                    window_id,
+                    keycode,
+                    scancode,
+                    keymod,
+                    repeat,
+                    which,
+                    raw,
+                }
+            } else {
+                Event::KeyUp {
+                    timestamp,
+                    window_id,
+                    keycode,
+                    scancode,
+                    keymod,
+                    repeat,
+                    which,
+                    raw,
+                }
+            }
+        };
+        for event in [key(true, false), key(true, true), key(false, false)] {
+            client.handle_window_event(event).unwrap();
+        }
+
+        let keys: Vec<(KeyAction, bool)> = client
+            .outbox
+            .iter()
+            .filter_map(|message| match &message.client_event {
+                Some(ClientEvent::UserInput(UserInput {
+                    window_id: 1,
+                    input_event: Some(user_input::InputEvent::KeyEvent(event)),
+                    ..
+                })) => {
+                    assert_eq!(event.key_code, Keycode::W as i32);
+                    Some((event.action(), event.repeat))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (KeyAction::Press, false),
+                (KeyAction::Press, true),
+                (KeyAction::Release, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn focus_changes_produce_window_events() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+        for win_event in [WindowEvent::FocusLost, WindowEvent::FocusGained] {
+            client
+                .handle_window_event(Event::Window {
+                    timestamp: 0,
+                    window_id,
+                    win_event,
+                })
+                .unwrap();
+        }
+
+        let actions: Vec<WindowAction> = client
+            .outbox
+            .iter()
+            .filter_map(|message| match &message.client_event {
+                Some(ClientEvent::UserInput(UserInput {
+                    window_id: 1,
+                    input_event: Some(user_input::InputEvent::WindowEvent(event)),
+                    ..
+                })) => Some(event.action()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(actions, vec![WindowAction::Unfocus, WindowAction::Focus]);
+    }
+
+    #[test]
+    fn grabbed_window_is_released_by_escape_hotkey() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(
+            &mut client,
+            WindowSettings {
+                grab_input: true,
+                ..window_settings(1)
+            },
+        );
+        assert!(client.windows[&window_id].canvas.window().mouse_grab());
+
+        assert!(client
+            .handle_window_event(escape_hotkey(window_id))
+            .unwrap());
+
+        let window = client.windows[&window_id].canvas.window();
+        assert!(!window.mouse_grab());
+        assert!(!window.keyboard_grab());
+        // The hotkey is consumed by the client
+        assert!(client.outbox.is_empty());
+    }
+
+    #[test]
+    fn relative_mouse_window_forwards_motion_deltas() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(
+            &mut client,
+            WindowSettings {
+                relative_mouse: true,
+                ..window_settings(1)
+            },
+        );
+        let mouse = client.sdl.mouse();
+        assert!(mouse.relative_mouse_mode(client.windows[&window_id].canvas.window()));
+
+        let motion = Event::MouseMotion {
+            timestamp: 0,
+            window_id,
+            which: 0,
+            mousestate: sdl3::mouse::MouseState::from_sdl_state(0),
+            x: 32.0,
+            y: 24.0,
+            xrel: 3.0,
+            yrel: -2.0,
+        };
+        client.handle_window_event(motion).unwrap();
+        let motions: Vec<(f32, f32)> = client
+            .outbox
+            .iter()
+            .filter_map(|message| match &message.client_event {
+                Some(ClientEvent::UserInput(UserInput {
+                    input_event: Some(user_input::InputEvent::MouseEvent(event)),
+                    ..
+                })) if event.action() == MouseAction::Move => {
+                    Some((event.motion_x, event.motion_y))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(motions, vec![(3.0, -2.0)]);
+
+        // The escape hotkey leaves relative mode
+        assert!(client
+            .handle_window_event(escape_hotkey(window_id))
+            .unwrap());
+        assert!(!mouse.relative_mouse_mode(client.windows[&window_id].canvas.window()));
+    }
+
+    #[test]
+    fn window_with_icon_builds() {
+        let (_guard, mut client) = headless_client();
+        let icon = |image_data| {
+            Some(Icon {
+                width: 16,
+                height: 16,
+                image_data,
+            })
+        };
+        let with_icon = open_window(
+            &mut client,
+            WindowSettings {
+                icon: icon(vec![0xff; 16 * 16 * 4]),
+                ..window_settings(1)
+            },
+        );
+        // Icons with invalid dimensions are ignored
+        let invalid_icon = open_window(
+            &mut client,
+            WindowSettings {
+                icon: icon(vec![0xff; 15]),
+                ..window_settings(2)
+            },
+        );
+        assert!(client.windows.contains_key(&with_icon));
+        assert!(client.windows.contains_key(&invalid_icon));
+    }
+
+    #[test]
+    fn warp_coordinates_are_clamped_to_window_bounds() {
+        assert_eq!(clamp_to_window(10, 20, 64, 48), (10, 20));
+        assert_eq!(clamp_to_window(-5, 100, 64, 48), (0, 47));
+        assert_eq!(clamp_to_window(64, -1, 64, 48), (63, 0));
+        // Windows without pixels warp to their origin
+        assert_eq!(clamp_to_window(10, 10, 0, 0), (0, 0));
+    }
+
+    #[test]
+    fn flipped_scroll_is_normalized() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+        let wheel = |direction| Event::MouseWheel {
+            timestamp: 0,
+            window_id,
+            which: 0,
+            x: 1.0,
+            y: 2.0,
+            direction,
+            mouse_x: 10.0,
+            mouse_y: 20.0,
+        };
+        for direction in [MouseWheelDirection::Normal, MouseWheelDirection::Flipped] {
+            client.handle_window_event(wheel(direction)).unwrap();
+        }
+
+        let deltas: Vec<(f32, f32)> = client
+            .outbox
+            .iter()
+            .filter_map(|message| match &message.client_event {
+                Some(ClientEvent::UserInput(UserInput {
+                    window_id: 1,
+                    input_event: Some(user_input::InputEvent::MouseEvent(event)),
+                    ..
+                })) if event.action() == MouseAction::Scroll => {
+                    assert_eq!((event.x, event.y), (10, 20));
+                    Some((event.delta_x, event.delta_y))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deltas, vec![(1.0, 2.0), (-1.0, -2.0)]);
+    }
+
+    #[test]
+    fn unknown_monitor_falls_back_to_the_primary_monitor() {
+        let (_guard, mut client) = headless_client();
+        let on_monitor = |client: &mut Client, window_id, monitor_id| {
+            let settings = WindowSettings {
+                monitor_id,
+                ..window_settings(window_id)
+            };
+            let sdl_id = open_window(client, settings);
+            client.windows[&sdl_id].canvas.window().position()
+        };
+
+        // The dummy driver has a single 1024x768 display
+        let primary = on_monitor(&mut client, 1, Some(0));
+        assert_eq!(primary, ((1024 - 64) / 2, (768 - 48) / 2));
+        assert_eq!(on_monitor(&mut client, 2, Some(7)), primary);
+        assert_eq!(on_monitor(&mut client, 3, None), primary);
+    }
+
+    #[test]
+    fn set_position_moves_the_window() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+
+        client.move_window(SetWindowPosition {
+            window_id: 1,
+            x: 100,
+            y: 50,
+        });
+        assert_eq!(
+            client.windows[&window_id].canvas.window().position(),
+            (100, 50)
+        );
+
+        // Positions outside of all displays are ignored
+        client.move_window(SetWindowPosition {
+            window_id: 1,
+            x: -5000,
+            y: 50,
+        });
+        assert_eq!(
+            client.windows[&window_id].canvas.window().position(),
+            (100, 50)
+        );
+    }
+
+    /// A 64x48 RGBA frame for window 1 with a single solid segment of the given rows.
+    fn band_frame(y: i32, height: u32, value: u8) -> Frame {
+        Frame {
+            window_id: 1,
+            width: 64,
+            height: 48,
+            segments: vec![Segment {
+                x: 0,
+                y,
+                width: 64,
+                height,
+                data: vec![value; 64 * height as usize * 4],
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn frame_group_is_presented_after_final_frame() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+
+        client
+            .render_frame(Frame {
+                frame_group_id: Some(7),
+                is_final: false,
+                ..band_frame(0, 24, 0x40)
+            })
+            .unwrap();
+        // The first half is only buffered, nothing is uploaded or presented yet
+        assert_eq!(client.buffered_frames, 1);
+        assert!(!client.windows[&window_id].damage.is_empty());
+
+        client
+            .render_frame(Frame {
+                frame_group_id: Some(7),
+                is_final: true,
+                ..band_frame(24, 24, 0x80)
+            })
+            .unwrap();
+        assert_eq!(client.buffered_frames, 0);
+        assert!(client.windows[&window_id].damage.is_empty());
+        let FrameBuffer::Packed(buffer) = &client.windows[&window_id].frame_buffer else {
+            panic!("RGBA window without a packed frame buffer");
+        };
+        let pixels = buffer.region(&buffer.bounds());
+        let (top, bottom) = pixels.split_at(pixels.len() / 2);
+        assert!(top.iter().all(|&value| value == 0x40));
+        assert!(bottom.iter().all(|&value| value == 0x80));
+    }
+
+    /// Draws the persistent texture of a window again and reads back the RGBA pixels it shows, with their row pitch.
+    fn shown_pixels(client: &mut Client, window_id: WindowID) -> (Vec<u8>, usize) {
+        let win = client.windows.get_mut(&window_id).unwrap();
+        let texture = win.texture.as_ref().unwrap();
+        win.canvas.copy(texture, None, None).unwrap();
+        let surface = win
+            .canvas
+            .read_pixels(None)
+            .unwrap()
+            .convert_format(PixelFormat::RGBA32)
+            .unwrap();
+        let pitch = surface.pitch() as usize;
+        (surface.with_lock(|pixels| pixels.to_vec()), pitch)
+    }
+
+    #[test]
+    fn partial_frames_update_only_their_region() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(&mut client, window_settings(1));
+        client.render_frame(band_frame(0, 48, 0x40)).unwrap();
+        client.render_frame(band_frame(16, 16, 0x80)).unwrap();
+        assert!(client.windows[&window_id].damage.is_empty());
+
+        let (pixels, pitch) = shown_pixels(&mut client, window_id);
+        for y in 0..48 {
+            let expected = if (16..32).contains(&y) {
+                0x808080
+            } else {
+                0x404040
+            };
+            for x in [0, 31, 63] {
+                assert_eq!(pixel(&pixels, pitch, x, y) >> 8, expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn texture_is_reused_until_the_frame_size_changes() {
+        let (_guard, mut client) = headless_client();
+        open_window(&mut client, window_settings(1));
+        for value in [0x10, 0x20, 0x30] {
+            client.render_frame(band_frame(0, 48, value)).unwrap();
+        }
+        assert_eq!(client.window_stats[&1].textures, 1);
+
+        client
+            .render_frame(Frame {
+                width: 32,
+                height: 24,
+                segments: vec![Segment {
+                    x: 0,
+                    y: 0,
+                    width: 32,
+                    height: 24,
+                    data: vec![0x40; 32 * 24 * 4],
+                }],
+                ..band_frame(0, 48, 0x40)
+            })
+            .unwrap();
+        assert_eq!(client.window_stats[&1].textures, 2);
+    }
+
+    #[test]
+    fn rgb24_frames_render_their_colors() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(
+            &mut client,
+            WindowSettings {
+                format: Some(FrameFormat::Rgb as i32),
+                ..window_settings(1)
+            },
+        );
+        // Red left half, blue right half, 3 bytes per pixel
+        let row: Vec<u8> = [[0xff, 0, 0]; 32]
+            .into_iter()
+            .chain([[0, 0, 0xff]; 32])
+            .flatten()
+            .collect();
+        let data = row.repeat(48);
+        assert_eq!(data.len(), FrameFormat::Rgb.data_len(64, 48));
+        client
+            .render_frame(Frame {
+                segments: vec![Segment {
+                    x: 0,
+                    y: 0,
+                    width: 64,
+                    height: 48,
+                    data,
+                }],
+                ..band_frame(0, 48, 0)
+            })
+            .unwrap();
+
+        let (pixels, pitch) = shown_pixels(&mut client, window_id);
+        for y in [0, 24, 47] {
+            assert_eq!(pixel(&pixels, pitch, 0, y) >> 8, 0xff0000);
+            assert_eq!(pixel(&pixels, pitch, 31, y) >> 8, 0xff0000);
+            assert_eq!(pixel(&pixels, pitch, 32, y) >> 8, 0x0000ff);
+            assert_eq!(pixel(&pixels, pitch, 63, y) >> 8, 0x0000ff);
+        }
+    }
+
+    #[test]
+    fn window_created_after_startup_receives_frames() {
+        let (_guard, mut client) = headless_client();
+        let first = open_window(&mut client, window_settings(1));
+        // Frames for windows that don't exist yet are dropped
+        client
+            .render_frame(Frame {
+                window_id: 2,
+                ..band_frame(0, 48, 0x80)
+            })
+            .unwrap();
+
+        FrameSink::open_window(&mut client, window_settings(2)).unwrap();
+        let second = client.server_window_to_sdl_window[&2];
+        assert_ne!(first, second);
+        client
+            .render_frame(Frame {
+                window_id: 2,
+                ..band_frame(0, 48, 0x80)
+            })
+            .unwrap();
+        let FrameBuffer::Packed(buffer) = &client.windows[&second].frame_buffer else {
+            panic!("RGBA window without a packed frame buffer");
+        };
+        assert!(buffer
+            .region(&buffer.bounds())
+            .iter()
+            .all(|&value| value == 0x80));
+        assert!(client.windows[&first].texture.is_none());
+
+        FrameSink::close_window(&mut client, DestroyWindow { window_id: 2 }).unwrap();
+        assert!(!client.windows.contains_key(&second));
+        assert!(!client.server_window_to_sdl_window.contains_key(&2));
+    }
+
+    #[test]
+    fn windows_decode_frames_in_their_own_format() {
+        let (_guard, mut client) = headless_client();
+        let rgba = open_window(&mut client, window_settings(1));
+        let gray = open_window(
+            &mut client,
+            WindowSettings {
+                format: Some(FrameFormat::Gray8 as i32),
+                ..window_settings(2)
+            },
+        );
+        assert_eq!(client.windows[&rgba].format, FrameFormat::Rgba);
+        assert_eq!(client.windows[&gray].format, FrameFormat::Gray8);
+
+        client.render_frame(band_frame(0, 48, 0x40)).unwrap();
+        client
+            .render_frame(Frame {
+                window_id: 2,
+                segments: vec![Segment {
+                    x: 0,
+                    y: 0,
+                    width: 64,
+                    height: 48,
+                    data: vec![0x80; 64 * 48],
+                }],
+                ..band_frame(0, 48, 0x80)
+            })
+            .unwrap();
+        for (window_id, pixel_bytes, value) in [(rgba, 4, 0x40), (gray, 1, 0x80)] {
+            let FrameBuffer::Packed(buffer) = &client.windows[&window_id].frame_buffer else {
+                panic!("window without a packed frame buffer");
+            };
+            let pixels = buffer.region(&buffer.bounds());
+            assert_eq!(pixels.len(), 64 * 48 * pixel_bytes);
+            assert!(pixels.iter().all(|&pixel| pixel == value));
+        }
+    }
+
+    #[test]
+    fn small_yuv_frame_renders() {
+        let (_guard, mut client) = headless_client();
+        let window_id = open_window(
+            &mut client,
+            WindowSettings {
+                format: Some(FrameFormat::Yuv420p as i32),
+                ..window_settings(1)
+            },
+        );
+        let rgba = [0xff, 0x00, 0x00, 0xff].repeat(4 * 4);
+        let data = libgsh::shared::frame::rgba_to_yuv420p(&rgba, 4, 4);
+        assert_eq!(data.len(), FrameFormat::Yuv420p.data_len(4, 4));
+        client
+            .render_frame(Frame {
+                window_id: 1,
+                width: 4,
+                height: 4,
+                segments: vec![Segment {
+                    x: 0,
+                    y: 0,
+                    width: 4,
+                    height: 4,
+                    data: data.clone(),
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+
+        let win = &client.windows[&window_id];
+        assert!(win.texture.is_some());
+        assert!(win.damage.is_empty());
+        let FrameBuffer::Yuv420p(buffer) = &win.frame_buffer else {
+            panic!("YUV window without a planar frame buffer");
+        };
+        assert_eq!(buffer.region(&buffer.luma.bounds()).concat(), data);
+    }
+
+    const BLACK: u32 = 0x000000ff;
+    const RED: u32 = 0xff0000ff;
+
+    /// Rasterizes draw commands on a 64x48 window and reads back its RGBA pixels, with their row pitch.
+    fn rasterize(client: &mut Client, commands: Vec<Command>) -> (Vec<u8>, usize) {
+        let window_id = open_window(client, window_settings(1));
+        let list = DrawList {
+            window_id: 1,
+            width: 64,
+            height: 48,
+            commands: commands
+                .into_iter()
+                .map(|command| DrawCommand {
+                    command: Some(command),
+                })
+                .collect(),
+        };
+        let canvas = &mut client.windows.get_mut(&window_id).unwrap().canvas;
+        drawlist::render_draw_list(canvas, &list).unwrap();
+        let surface = canvas
+            .read_pixels(None)
+            .unwrap()
+            .convert_format(PixelFormat::RGBA32)
+            .unwrap();
+        let pitch = surface.pitch() as usize;
+        (surface.with_lock(|pixels| pixels.to_vec()), pitch)
+    }
+
+    fn pixel(pixels: &[u8], pitch: usize, x: usize, y: usize) -> u32 {
+        let start = y * pitch + x * 4;
+        u32::from_be_bytes(pixels[start..start + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn draw_list_rectangle_fills_its_pixels() {
+        let (_guard, mut client) = headless_client();
+        let (pixels, pitch) = rasterize(
+            &mut client,
+            vec![
+                Command::Clear(draw_list::Clear { color: BLACK }),
+                Command::Rect(draw_list::Rect {
+                    x: 8.0,
+                    y: 4.0,
+                    width: 16.0,
+                    height: 8.0,
+                    color: RED,
+                    filled: true,
+                }),
+            ],
+        );
+        for y in 0..48 {
+            for x in 0..64 {
+                let inside = (8..24).contains(&x) && (4..12).contains(&y);
+                let expected = if inside { RED } else { BLACK };
+                assert_eq!(
+                    pixel(&pixels, pitch, x, y),
+                    expected,
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn window_stats_are_tracked_per_window() {
+        let (_guard, mut client) = headless_client();
+        open_window(&mut client, window_settings(1));
+        open_window(&mut client, window_settings(2));
+
+        client.render_frame(band_frame(0, 48, 0x10)).unwrap();
+        client.render_frame(band_frame(0, 8, 0x20)).unwrap();
+        let segment = |y| Segment {
+            x: 0,
+            y,
+            width: 32,
+            height: 8,
+            data: vec![0x30; 32 * 8 * 4],
+        };
+        client
+            .render_frame(Frame {
+                window_id: 2,
+                width: 32,
+                height: 16,
+                segments: vec![segment(0), segment(8)],
+                ..Default::default()
+            })
+            .unwrap();
+
+        let first = &client.window_stats[&1];
+        assert_eq!((first.width, first.height), (64, 48));
+        assert_eq!(first.frames, 2);
+        assert_eq!(first.segments_applied, 2);
+        assert_eq!(first.last_frame_bytes, 64 * 8 * 4);
+        let second = &client.window_stats[&2];
+        assert_eq!((second.width, second.height), (32, 16));
+        assert_eq!(second.frames, 1);
+        assert_eq!(second.segments_applied, 2);
+        assert_eq!(second.last_frame_bytes, 2 * 32 * 8 * 4);
     }
 }
@@ -8,7 +8,7 @@ use std::{
     collections::HashMap,
     fs::File,
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 fn gsh_dir() -> PathBuf {
@@ -59,16 +59,26 @@ impl KnownHosts {
             std::fs::create_dir_all(gsh_dir()).expect("Failed to create .gsh directory");
             std::fs::File::create(&path).expect("Failed to create known_hosts.json file");
         }
-        // Load the file and parse it into a KnownHosts struct
-        let file = std::fs::File::open(&path).expect("Failed to open known_hosts.json file");
+        Self::load_from(&path)
+    }
+
+    /// Load known hosts from the given file, empty if it can't be read
+    pub fn load_from(path: &Path) -> Self {
+        let Ok(file) = std::fs::File::open(path) else {
+            return KnownHosts::default();
+        };
         let reader = std::io::BufReader::new(file);
         serde_json::from_reader(reader).unwrap_or_else(|_| KnownHosts::default())
     }
 
     /// Save the known hosts to a file
     pub fn save(&self) {
-        let path = gsh_dir().join("known_hosts.json");
-        let file = std::fs::File::create(&path).expect("Failed to create known_hosts.json file");
+        self.save_to(&gsh_dir().join("known_hosts.json"));
+    }
+
+    /// Save the known hosts to the given file
+    pub fn save_to(&self, path: &Path) {
+        let file = std::fs::File::create(path).expect("Failed to create known_hosts.json file");
         let writer = std::io::BufWriter::new(file);
         serde_json::to_writer_pretty(writer, self).expect("Failed to save known_hosts.json file");
     }
@@ -98,6 +108,18 @@ impl KnownHosts {
     pub fn find_host_mut(&mut self, host: &str) -> Option<&mut KnownHost> {
         self.hosts.iter_mut().find(|h| h.host == host)
     }
+
+    /// Replace the pinned fingerprints of a known host, e.g. after the user accepted a rotated certificate.\
+    /// Returns `false` if the host is not known. Call `save` to persist the change.
+    pub fn replace_fingerprints(&mut self, host: &str, fingerprints: Vec<Vec<u8>>) -> bool {
+        match self.find_host_mut(host) {
+            Some(known) => {
+                known.fingerprints = fingerprints;
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -185,3 +207,36 @@ impl IdFiles {
         path
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepted_fingerprint_replaces_stored_host_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts.json");
+        let mut known_hosts = KnownHosts::default();
+        known_hosts.hosts.push(KnownHost {
+            host: "example.com".to_string(),
+            fingerprints: vec![vec![1; 32]],
+            id_file_ref: Some("work".to_string()),
+            password: None,
+        });
+        known_hosts.save_to(&path);
+
+        let mut known_hosts = KnownHosts::load_from(&path);
+        assert!(known_hosts.replace_fingerprints("example.com", vec![vec![2; 32]]));
+        assert!(!known_hosts.replace_fingerprints("unknown.com", vec![vec![2; 32]]));
+        known_hosts.save_to(&path);
+
+        let known_hosts = KnownHosts::load_from(&path);
+        let known = known_hosts.find_host("example.com").unwrap();
+        assert_eq!(known.fingerprints, vec![vec![2; 32]]);
+        assert!(known.compare(&[vec![2; 32]]));
+        assert!(!known.compare(&[vec![1; 32]]));
+        // Only the fingerprints are replaced, the rest of the entry is kept
+        assert_eq!(known.id_file_ref(), Some(&"work".to_string()));
+        assert_eq!(known_hosts.hosts.len(), 1);
+    }
+}
@@ -1,4 +1,5 @@
 use clap::ColorChoice;
+use libgsh::shared::protocol::{server_hello_ack::TransportOffer, ServerHelloAck};
 
 const RESET: &str = "\x1b[0m";
 const BOLD: &str = "\x1b[1m";
@@ -61,6 +62,36 @@ pub fn fingerprints_summary(fingerprints: &[Vec<u8>]) -> String {
     }
 }
 
+/// One-line description of the service a client connected to, based on its `ServerHelloAck`.
+pub fn connection_summary(host: &str, port: u16, hello: &ServerHelloAck) -> String {
+    let mut summary = format!("Connected to {}:{}", host, port);
+    if let Some(info) = &hello.server_info {
+        summary.push_str(&format!(" running {}", info.name));
+        if !info.version.is_empty() {
+            summary.push_str(&format!(" v{}", info.version));
+        }
+        if !info.description.is_empty() {
+            summary.push_str(&format!(" ({})", info.description));
+        }
+    }
+    summary
+}
+
+/// Description of an alternate transport offered by the server, e.g. `QUIC at example.com:4433`.
+pub fn transport_offer(host: &str, offer: &TransportOffer) -> String {
+    let host = if offer.host.is_empty() {
+        host
+    } else {
+        &offer.host
+    };
+    format!(
+        "{} at {}:{}",
+        offer.transport().as_str_name(),
+        host,
+        offer.port
+    )
+}
+
 fn format_table_row(cells: &[TableCell], widths: &[usize]) -> String {
     let mut out = String::new();
     for (i, cell) in cells.iter().enumerate() {
@@ -0,0 +1,111 @@
+use libgsh::shared::{
+    protocol::{FileChunk, FileOffer},
+    transfer::FileAssembler,
+};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Largest file accepted from the server, as files are reassembled in memory before saving.
+const MAX_DOWNLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Saves files offered by the server to a download directory, enabled by `--download-dir`.
+pub struct Downloads {
+    dir: PathBuf,
+    assembler: FileAssembler,
+}
+
+impl Downloads {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            assembler: FileAssembler::new(MAX_DOWNLOAD_SIZE),
+        }
+    }
+
+    pub fn offer(&mut self, offer: &FileOffer) -> io::Result<()> {
+        log::info!(
+            "Downloading {} ({} bytes) from the server",
+            offer.name,
+            offer.size
+        );
+        self.assembler
+            .begin(offer.transfer_id, &offer.name, offer.size)
+    }
+
+    pub fn chunk(&mut self, chunk: &FileChunk) -> io::Result<()> {
+        let result = self.assembler.chunk(chunk);
+        if result.is_err() {
+            self.assembler.cancel(chunk.transfer_id);
+        }
+        result
+    }
+
+    /// Writes the completed file to the download directory, returning where it was saved.
+    pub fn end(&mut self, transfer_id: u64) -> io::Result<PathBuf> {
+        let file = self.assembler.end(transfer_id)?;
+        std::fs::create_dir_all(&self.dir)?;
+        let path = unique_path(&self.dir, &file.name);
+        std::fs::write(&path, &file.data)?;
+        Ok(path)
+    }
+}
+
+/// A path for `name` in `dir` that doesn't overwrite an existing file, numbering the name if needed.
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let name = if name.is_empty() { "download" } else { name };
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let name = Path::new(name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = name
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libgsh::shared::transfer::file_chunks;
+
+    const CHUNK_SIZE: usize = 1024;
+
+    #[test]
+    fn multi_chunk_offer_is_saved_to_download_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..CHUNK_SIZE * 3 + 100).map(|i| i as u8).collect();
+        let mut downloads = Downloads::new(dir.path().join("downloads"));
+        downloads
+            .offer(&FileOffer {
+                transfer_id: 1,
+                name: "result.bin".to_string(),
+                size: data.len() as u64,
+            })
+            .unwrap();
+        let chunks: Vec<FileChunk> = file_chunks(1, &data, CHUNK_SIZE).collect();
+        assert_eq!(chunks.len(), 4);
+        for chunk in &chunks {
+            downloads.chunk(chunk).unwrap();
+        }
+        let path = downloads.end(1).unwrap();
+        assert_eq!(path, dir.path().join("downloads").join("result.bin"));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), data.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn existing_files_are_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("result.bin"), b"old").unwrap();
+        assert_eq!(
+            unique_path(dir.path(), "result.bin"),
+            dir.path().join("result (1).bin")
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use libgsh::shared::protocol::{draw_list::draw_command::Command, DrawList};
+use sdl3::{
+    pixels::Color,
+    render::{Canvas, FPoint, FRect},
+    video,
+};
+use std::ffi::CString;
+
+/// Height in pixels of SDL's built-in debug font, used to rasterize text commands.
+const DEBUG_FONT_SIZE: f32 = 8.0;
+
+/// Rasterizes a draw list onto the canvas at its native output resolution.\
+/// Command coordinates are scaled from the logical draw list size to the output size,
+/// keeping lines and text sharp regardless of window size or display DPI.
+pub fn render_draw_list(canvas: &mut Canvas<video::Window>, list: &DrawList) -> Result<()> {
+    let (out_width, out_height) = canvas.output_size().map_err(|e| anyhow!(e))?;
+    let scale_x = out_width as f32 / list.width.max(1) as f32;
+    let scale_y = out_height as f32 / list.height.max(1) as f32;
+    for command in list.commands.iter().filter_map(|c| c.command.as_ref()) {
+        match command {
+            Command::Clear(clear) => {
+                canvas.set_draw_color(color(clear.color));
+                canvas.clear();
+            }
+            Command::Line(line) => {
+                canvas.set_draw_color(color(line.color));
+                canvas
+                    .draw_line(
+                        FPoint::new(line.x1 * scale_x, line.y1 * scale_y),
+                        FPoint::new(line.x2 * scale_x, line.y2 * scale_y),
+                    )
+                    .map_err(|e| anyhow!(e))?;
+            }
+            Command::Rect(rect) => {
+                canvas.set_draw_color(color(rect.color));
+                let frect = FRect::new(
+                    rect.x * scale_x,
+                    rect.y * scale_y,
+                    rect.width * scale_x,
+                    rect.height * scale_y,
+                );
+                if rect.filled {
+                    canvas.fill_rect(frect).map_err(|e| anyhow!(e))?;
+                } else {
+                    canvas.draw_rect(frect).map_err(|e| anyhow!(e))?;
+                }
+            }
+            Command::Text(text) => {
+                canvas.set_draw_color(color(text.color));
+                let glyph_scale = text.size.max(1.0) / DEBUG_FONT_SIZE;
+                let (sx, sy) = (scale_x * glyph_scale, scale_y * glyph_scale);
+                let Ok(string) = CString::new(text.text.as_str()) else {
+                    log::warn!("Draw list text contains a NUL byte, skipping.");
+                    continue;
+                };
+                canvas.set_scale(sx, sy).map_err(|e| anyhow!(e))?;
+                // SAFETY: the renderer is alive for the lifetime of the canvas and the string is NUL-terminated
+                unsafe {
+                    sdl3::sys::render::SDL_RenderDebugText(
+                        canvas.raw(),
+                        text.x * scale_x / sx,
+                        text.y * scale_y / sy,
+                        string.as_ptr(),
+                    );
+                }
+                canvas.set_scale(1.0, 1.0).map_err(|e| anyhow!(e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts a packed 0xRRGGBBAA color to an SDL color.
+fn color(rgba: u32) -> Color {
+    let [r, g, b, a] = rgba.to_be_bytes();
+    Color::RGBA(r, g, b, a)
+}
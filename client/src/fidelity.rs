@@ -0,0 +1,166 @@
+use sdl3::{pixels::PixelFormat, rect::Rect, render::Canvas, video};
+
+/// Result of comparing a rendered region against the pixel data the server sent for it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FidelityReport {
+    /// Number of pixels compared
+    pub pixels: usize,
+    /// Number of pixels where any channel differs by more than the tolerance
+    pub mismatched: usize,
+    /// Largest per-channel difference found
+    pub max_diff: u8,
+}
+
+impl FidelityReport {
+    pub fn is_match(&self) -> bool {
+        self.mismatched == 0
+    }
+
+    fn merge(&mut self, other: FidelityReport) {
+        self.pixels += other.pixels;
+        self.mismatched += other.mismatched;
+        self.max_diff = self.max_diff.max(other.max_diff);
+    }
+}
+
+/// Reads back the rendered canvas and compares each region against the pixel data that was drawn into it.\
+/// The read back pixels are converted to the negotiated `format` before comparing.
+/// Must be called **before** `present`, as the back buffer contents are undefined afterwards.
+pub fn verify_canvas(
+    canvas: &Canvas<video::Window>,
+    format: PixelFormat,
+    regions: &[(Rect, Vec<u8>)],
+    pixel_bytes: usize,
+    tolerance: u8,
+) -> anyhow::Result<FidelityReport> {
+    let surface = canvas
+        .read_pixels(None)
+        .map_err(|e| anyhow::anyhow!(e))?
+        .convert_format(format)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    let pitch = surface.pitch() as usize;
+    let mut report = FidelityReport::default();
+    surface.with_lock(|pixels| {
+        for (rect, data) in regions {
+            report.merge(compare_region(
+                pixels,
+                pitch,
+                *rect,
+                data,
+                pixel_bytes,
+                tolerance,
+            ));
+        }
+    });
+    Ok(report)
+}
+
+/// Compares a tightly packed `data` region against the same rectangle of a `pitch`-strided readback buffer.
+pub fn compare_region(
+    readback: &[u8],
+    pitch: usize,
+    rect: Rect,
+    data: &[u8],
+    pixel_bytes: usize,
+    tolerance: u8,
+) -> FidelityReport {
+    let mut report = FidelityReport::default();
+    let row_bytes = rect.width() as usize * pixel_bytes;
+    for row in 0..rect.height() as usize {
+        let src = &data[row * row_bytes..(row + 1) * row_bytes];
+        let start = (rect.y() as usize + row) * pitch + rect.x() as usize * pixel_bytes;
+        let Some(dst) = readback.get(start..start + row_bytes) else {
+            // Region falls outside of the rendered output, count it as mismatched
+            report.pixels += rect.width() as usize;
+            report.mismatched += rect.width() as usize;
+            continue;
+        };
+        for (expected, actual) in src.chunks(pixel_bytes).zip(dst.chunks(pixel_bytes)) {
+            let diff = expected
+                .iter()
+                .zip(actual)
+                .map(|(a, b)| a.abs_diff(*b))
+                .max()
+                .unwrap_or(0);
+            report.pixels += 1;
+            report.max_diff = report.max_diff.max(diff);
+            if diff > tolerance {
+                report.mismatched += 1;
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 8;
+    const HEIGHT: u32 = 4;
+    const PIXEL_BYTES: usize = 4;
+
+    /// A gradient RGBA image.
+    fn gradient() -> Vec<u8> {
+        (0..WIDTH * HEIGHT)
+            .flat_map(|i| [(i * 8) as u8, (i * 4) as u8, 255 - (i * 8) as u8, 255])
+            .collect()
+    }
+
+    #[test]
+    fn lossless_frame_round_trips_identically() {
+        let data = gradient();
+        let rect = Rect::new(0, 0, WIDTH, HEIGHT);
+        let pitch = WIDTH as usize * PIXEL_BYTES;
+        let report = compare_region(&data, pitch, rect, &data, PIXEL_BYTES, 0);
+        assert!(report.is_match());
+        assert_eq!(report.pixels, (WIDTH * HEIGHT) as usize);
+        assert_eq!(report.max_diff, 0);
+    }
+
+    #[test]
+    fn lossy_frame_is_within_tolerance() {
+        let data = gradient();
+        // Compression artifacts of a lossy codec such as JPEG, off by a few levels per channel
+        let decoded: Vec<u8> = data
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                if i % 3 == 0 {
+                    v.saturating_add(3)
+                } else {
+                    v.saturating_sub(2)
+                }
+            })
+            .collect();
+        let rect = Rect::new(0, 0, WIDTH, HEIGHT);
+        let pitch = WIDTH as usize * PIXEL_BYTES;
+
+        let report = compare_region(&decoded, pitch, rect, &data, PIXEL_BYTES, 4);
+        assert!(report.is_match());
+        assert_eq!(report.max_diff, 3);
+
+        let strict = compare_region(&decoded, pitch, rect, &data, PIXEL_BYTES, 0);
+        assert!(!strict.is_match());
+    }
+
+    #[test]
+    fn region_in_padded_readback_is_compared_at_its_position() {
+        let data = gradient();
+        // Readback rows padded to a larger pitch, with the region drawn at (2, 1)
+        let pitch = (WIDTH as usize + 4) * PIXEL_BYTES;
+        let mut readback = vec![0; pitch * (HEIGHT as usize + 2)];
+        let row_bytes = WIDTH as usize * PIXEL_BYTES;
+        for (row, src) in data.chunks(row_bytes).enumerate() {
+            let start = (row + 1) * pitch + 2 * PIXEL_BYTES;
+            readback[start..start + row_bytes].copy_from_slice(src);
+        }
+        let rect = Rect::new(2, 1, WIDTH, HEIGHT);
+        assert!(compare_region(&readback, pitch, rect, &data, PIXEL_BYTES, 0).is_match());
+
+        // A region outside the readback counts as mismatched
+        let outside = Rect::new(0, HEIGHT as i32 + 2, WIDTH, 1);
+        let report = compare_region(&readback, pitch, outside, &data, PIXEL_BYTES, 0);
+        assert_eq!(report.mismatched, WIDTH as usize);
+    }
+}
@@ -0,0 +1,361 @@
+use libgsh::shared::protocol::server_hello_ack::FrameFormat;
+
+/// Composited contents of a window, packed pixels or planar YUV depending on its frame format.
+#[derive(Debug, Clone)]
+pub enum FrameBuffer {
+    Packed(WindowFrameBuffer),
+    Yuv420p(YuvFrameBuffer),
+}
+
+impl FrameBuffer {
+    pub fn new(format: FrameFormat) -> Self {
+        match format {
+            FrameFormat::Yuv420p => Self::Yuv420p(YuvFrameBuffer::default()),
+            format => Self::Packed(WindowFrameBuffer::new(format.bytes_per_pixel())),
+        }
+    }
+
+    /// Resizes the buffer to the given frame dimensions, see `WindowFrameBuffer::resize`.
+    pub fn resize(&mut self, width: u32, height: u32) -> bool {
+        match self {
+            Self::Packed(buffer) => buffer.resize(width, height),
+            Self::Yuv420p(buffer) => buffer.resize(width, height),
+        }
+    }
+
+    /// The whole buffer as a region.
+    pub fn bounds(&self) -> Region {
+        match self {
+            Self::Packed(buffer) => buffer.bounds(),
+            Self::Yuv420p(buffer) => buffer.luma.bounds(),
+        }
+    }
+
+    /// Writes a segment in the buffer's format at `(x, y)`, returning the damaged region, if any.
+    pub fn apply_segment(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Option<Region> {
+        match self {
+            Self::Packed(buffer) => buffer.apply_segment(x, y, width, height, data),
+            Self::Yuv420p(buffer) => buffer.apply_segment(x, y, width, height, data),
+        }
+    }
+}
+
+/// Planar YUV 4:2:0 buffers of a window, with the U and V planes at half the resolution of the Y plane.
+#[derive(Debug, Clone)]
+pub struct YuvFrameBuffer {
+    pub luma: WindowFrameBuffer,
+    pub u: WindowFrameBuffer,
+    pub v: WindowFrameBuffer,
+}
+
+impl Default for YuvFrameBuffer {
+    fn default() -> Self {
+        Self {
+            luma: WindowFrameBuffer::new(1),
+            u: WindowFrameBuffer::new(1),
+            v: WindowFrameBuffer::new(1),
+        }
+    }
+}
+
+impl YuvFrameBuffer {
+    pub fn resize(&mut self, width: u32, height: u32) -> bool {
+        self.u.resize(width.div_ceil(2), height.div_ceil(2));
+        self.v.resize(width.div_ceil(2), height.div_ceil(2));
+        self.luma.resize(width, height)
+    }
+
+    /// Writes a segment of Y, U and V planes at `(x, y)`, which must be even.
+    pub fn apply_segment(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Option<Region> {
+        if x % 2 != 0 || y % 2 != 0 {
+            log::warn!("YUV segment at odd position ({}, {}), skipping.", x, y);
+            return None;
+        }
+        let luma_len = FrameFormat::Gray8.data_len(width, height);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let chroma_len = FrameFormat::Gray8.data_len(chroma_width, chroma_height);
+        if data.len() < luma_len + 2 * chroma_len {
+            log::warn!(
+                "YUV segment data too short ({} < {} bytes), skipping.",
+                data.len(),
+                luma_len + 2 * chroma_len
+            );
+            return None;
+        }
+        let (luma, chroma) = data.split_at(luma_len);
+        let region = self.luma.apply_segment(x, y, width, height, luma)?;
+        let (u, v) = chroma.split_at(chroma_len);
+        self.u
+            .apply_segment(x / 2, y / 2, chroma_width, chroma_height, u);
+        self.v
+            .apply_segment(x / 2, y / 2, chroma_width, chroma_height, &v[..chroma_len]);
+        Some(region)
+    }
+
+    /// Tightly packed Y, U and V planes of a region at even coordinates, e.g. as returned by `apply_segment`.
+    pub fn region(&self, region: &Region) -> [Vec<u8>; 3] {
+        let chroma = chroma_region(region);
+        [
+            self.luma.region(region),
+            self.u.region(&chroma),
+            self.v.region(&chroma),
+        ]
+    }
+}
+
+/// The region of the chroma planes covering a region of the Y plane at even coordinates.
+pub fn chroma_region(region: &Region) -> Region {
+    Region {
+        x: region.x / 2,
+        y: region.y / 2,
+        width: region.width.div_ceil(2),
+        height: region.height.div_ceil(2),
+    }
+}
+
+/// A persistent pixel buffer per window, accumulating the segments of every applied frame.\
+/// Partial updates are composited on top of the previous contents instead of replacing them,
+/// so only the damaged regions need to be uploaded to the window's texture after each frame.
+#[derive(Debug, Clone, Default)]
+pub struct WindowFrameBuffer {
+    width: u32,
+    height: u32,
+    pixel_bytes: usize,
+    data: Vec<u8>,
+}
+
+impl WindowFrameBuffer {
+    pub fn new(pixel_bytes: usize) -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            pixel_bytes,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn pixel_bytes(&self) -> usize {
+        self.pixel_bytes
+    }
+
+    /// Number of bytes per row of pixels.
+    pub fn pitch(&self) -> usize {
+        self.width as usize * self.pixel_bytes
+    }
+
+    /// Resizes the buffer to the given frame dimensions, clearing it if the size changed.\
+    /// Returns `true` if the buffer was reallocated.
+    pub fn resize(&mut self, width: u32, height: u32) -> bool {
+        if self.width == width && self.height == height {
+            return false;
+        }
+        self.width = width;
+        self.height = height;
+        self.data.clear();
+        self.data
+            .resize(width as usize * height as usize * self.pixel_bytes, 0);
+        true
+    }
+
+    /// Tightly packed copy of the pixels of a region within the buffer.
+    pub fn region(&self, region: &Region) -> Vec<u8> {
+        let pitch = self.pitch();
+        let row_bytes = region.width as usize * self.pixel_bytes;
+        let start = region.y as usize * pitch + region.x as usize * self.pixel_bytes;
+        let mut out = Vec::with_capacity(row_bytes * region.height as usize);
+        for row in 0..region.height as usize {
+            let row_start = start + row * pitch;
+            out.extend_from_slice(&self.data[row_start..row_start + row_bytes]);
+        }
+        out
+    }
+
+    /// The whole buffer as a region.
+    pub fn bounds(&self) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Writes a tightly packed segment of pixels into the buffer at `(x, y)`.\
+    /// Parts of the segment falling outside of the buffer are clipped.
+    /// Returns the damaged region of the buffer, if any.
+    pub fn apply_segment(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Option<Region> {
+        let src_pitch = width as usize * self.pixel_bytes;
+        if data.len() < src_pitch * height as usize {
+            log::warn!(
+                "Segment data too short ({} < {} bytes), skipping.",
+                data.len(),
+                src_pitch * height as usize
+            );
+            return None;
+        }
+        // Clip the segment rectangle to the buffer bounds
+        let x0 = x.max(0) as usize;
+        let y0 = y.max(0) as usize;
+        let x1 = ((x as i64 + width as i64).max(0) as usize).min(self.width as usize);
+        let y1 = ((y as i64 + height as i64).max(0) as usize).min(self.height as usize);
+        if x0 >= x1 || y0 >= y1 {
+            return None;
+        }
+        let dst_pitch = self.pitch();
+        let row_bytes = (x1 - x0) * self.pixel_bytes;
+        let src_x = (x0 as i64 - x as i64) as usize * self.pixel_bytes;
+        for row in y0..y1 {
+            let src_start = (row as i64 - y as i64) as usize * src_pitch + src_x;
+            let dst_start = row * dst_pitch + x0 * self.pixel_bytes;
+            self.data[dst_start..dst_start + row_bytes]
+                .copy_from_slice(&data[src_start..src_start + row_bytes]);
+        }
+        Some(Region {
+            x: x0 as u32,
+            y: y0 as u32,
+            width: (x1 - x0) as u32,
+            height: (y1 - y0) as u32,
+        })
+    }
+}
+
+/// A rectangle of pixels within a frame buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Expands 8-bit grayscale pixels to RGB24 by replicating the luma into every channel.
+pub fn expand_gray8(data: &[u8]) -> Vec<u8> {
+    data.iter().flat_map(|&y| [y, y, y]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXEL_BYTES: usize = 4;
+
+    fn solid(width: u32, height: u32, value: u8) -> Vec<u8> {
+        vec![value; width as usize * height as usize * PIXEL_BYTES]
+    }
+
+    #[test]
+    fn partial_frames_are_composited() {
+        let mut buffer = WindowFrameBuffer::new(PIXEL_BYTES);
+        buffer.resize(4, 4);
+        // First frame covers the top half, second frame the right half, overlapping the top right quarter
+        let first = buffer.apply_segment(0, 0, 4, 2, &solid(4, 2, 1));
+        let second = buffer.apply_segment(2, 0, 2, 4, &solid(2, 4, 2));
+        assert_eq!(
+            first,
+            Some(Region {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 2
+            })
+        );
+        assert_eq!(
+            second,
+            Some(Region {
+                x: 2,
+                y: 0,
+                width: 2,
+                height: 4
+            })
+        );
+
+        #[rustfmt::skip]
+        let expected: Vec<u8> = [
+            1, 1, 2, 2,
+            1, 1, 2, 2,
+            0, 0, 2, 2,
+            0, 0, 2, 2,
+        ]
+        .iter()
+        .flat_map(|&value| [value; PIXEL_BYTES])
+        .collect();
+        assert_eq!(buffer.region(&buffer.bounds()), expected);
+    }
+
+    #[test]
+    fn segments_outside_the_buffer_are_clipped() {
+        let mut buffer = WindowFrameBuffer::new(PIXEL_BYTES);
+        buffer.resize(4, 4);
+        let damage = buffer.apply_segment(-2, 3, 4, 2, &solid(4, 2, 9));
+        assert_eq!(
+            damage,
+            Some(Region {
+                x: 0,
+                y: 3,
+                width: 2,
+                height: 1
+            })
+        );
+        assert_eq!(buffer.apply_segment(4, 0, 2, 2, &solid(2, 2, 9)), None);
+    }
+
+    /// A YUV 4:2:0 segment with every sample of each plane set to the given value.
+    fn yuv(width: u32, height: u32, [y, u, v]: [u8; 3]) -> Vec<u8> {
+        let chroma = (width.div_ceil(2) * height.div_ceil(2)) as usize;
+        let mut data = vec![y; (width * height) as usize];
+        data.extend(vec![u; chroma]);
+        data.extend(vec![v; chroma]);
+        data
+    }
+
+    #[test]
+    fn yuv_segments_update_all_planes() {
+        let mut buffer = FrameBuffer::new(FrameFormat::Yuv420p);
+        buffer.resize(4, 4);
+        buffer.apply_segment(0, 0, 4, 4, &yuv(4, 4, [16, 128, 128]));
+        let damage = buffer.apply_segment(2, 2, 2, 2, &yuv(2, 2, [200, 50, 60]));
+        // Segments must start at even coordinates
+        assert_eq!(
+            buffer.apply_segment(1, 0, 2, 2, &yuv(2, 2, [0, 0, 0])),
+            None
+        );
+
+        let FrameBuffer::Yuv420p(buffer) = &buffer else {
+            panic!("YUV window without a planar frame buffer");
+        };
+        let region = damage.unwrap();
+        assert_eq!(buffer.region(&region), [vec![200; 4], vec![50], vec![60]]);
+        #[rustfmt::skip]
+        let luma = vec![
+            16, 16, 16, 16,
+            16, 16, 16, 16,
+            16, 16, 200, 200,
+            16, 16, 200, 200,
+        ];
+        assert_eq!(
+            buffer.region(&buffer.luma.bounds()),
+            [luma, vec![128, 128, 128, 50], vec![128, 128, 128, 60]]
+        );
+    }
+}
@@ -4,27 +4,44 @@ use clap::{ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand};
 use client::Client;
 use env_logger::fmt::WriteStyle;
 use libgsh::{
+    client::Disconnect,
     rsa::{pkcs1v15::VerifyingKey, signature::Verifier},
     sha2::Sha256,
     shared::{
         auth::AuthProvider,
+        cert,
         protocol::{
             client_hello::MonitorInfo,
             server_hello_ack::{
                 window_settings::{self, WindowMode},
                 FrameFormat, WindowSettings,
             },
+            server_message::ServerEvent,
         },
     },
 };
 use std::io::IsTerminal;
+use std::path::PathBuf;
 use std::process::exit;
+use std::time::Duration;
 
+mod audio;
 mod auth;
 mod client;
 mod config;
 mod display;
+mod download;
+mod drawlist;
+mod fidelity;
+mod framebuffer;
 mod network;
+mod stats;
+mod trace;
+mod upload;
+
+/// Reconnection attempts when resuming a session after the connection dropped.
+const RESUME_ATTEMPTS: u32 = 5;
+const RESUME_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -38,9 +55,31 @@ struct Args {
     /// Disable TLS server certificate verification.
     #[clap(long)]
     insecure: bool,
+    /// PEM file with a client certificate chain, for servers requiring mutual TLS.
+    #[clap(long, value_name = "FILE", requires = "client_key")]
+    client_cert: Option<String>,
+    /// PEM file with the private key of the client certificate.
+    #[clap(long, value_name = "FILE", requires = "client_cert")]
+    client_key: Option<String>,
     /// The name of the ID file to use for authentication.
     #[clap(short, long)]
     id: Option<String>,
+    /// Log a summary of every protocol message to a file, or to stderr if no file is given.
+    #[clap(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "-")]
+    trace_protocol: Option<String>,
+    /// Read back rendered frames and report pixels differing by more than the given per-channel tolerance.
+    #[clap(long, value_name = "TOLERANCE", num_args = 0..=1, default_missing_value = "0")]
+    verify_frames: Option<u8>,
+    /// Show per-window frame statistics on top of each window, toggled with Ctrl+Alt+H.
+    #[clap(long)]
+    hud: bool,
+    /// Preferred zstd compression level, lower levels are faster to decode on low-power machines.
+    /// The server picks the nearest level it allows.
+    #[clap(long, value_name = "LEVEL", allow_hyphen_values = true)]
+    zstd_level: Option<i32>,
+    /// Save files sent by the server to this directory, downloads are refused otherwise.
+    #[clap(long, value_name = "DIR")]
+    download_dir: Option<PathBuf>,
     /// Subcommand to execute.
     #[clap(subcommand)]
     command: Option<Command>,
@@ -188,29 +227,140 @@ async fn main() {
         exit(1);
     });
 
+    let mut tracer = args.trace_protocol.map(|target| {
+        trace::ProtocolTracer::new(&target).unwrap_or_else(|e| {
+            log::error!("Failed to open protocol trace {}: {}", target, e);
+            exit(1);
+        })
+    });
+
+    let load_client_cert = || {
+        args.client_cert
+            .as_ref()
+            .zip(args.client_key.as_ref())
+            .map(|(cert, key)| {
+                cert::load_pem(cert, key).unwrap_or_else(|e| {
+                    log::error!("Failed to load client certificate: {}", e);
+                    exit(1);
+                })
+            })
+    };
+    let monitors = monitor_info(&video);
+
     println!("Connecting to {}:{}...", host, args.port);
-    let (hello, messages) = network::connect_tls(
+    let (first_hello, first_messages) = network::connect_tls(
         &host,
         args.port,
         args.insecure,
-        monitor_info(&video),
+        load_client_cert(),
+        monitors.clone(),
         known_hosts,
         id_files,
-        args.id,
+        args.id.clone(),
+        None,
+        args.zstd_level,
     )
     .await
     .unwrap_or_else(|e| {
         log::error!("Failed to connect: {}", e);
         exit(1);
     });
+    println!("Successfully connected to server!");
+    if let Some(tracer) = &mut tracer {
+        tracer.received(&ServerEvent::ServerHelloAck(first_hello.clone()));
+    }
+    // Migrate to the first alternate transport the client supports, resuming the session there with its token
+    let mut endpoint = (host.clone(), args.port);
+    let (hello, mut messages) = match first_hello.migration_offer() {
+        None => {
+            for offer in &first_hello.transport_offers {
+                log::info!(
+                    "Server offers an unsupported transport: {}, staying on the current connection.",
+                    display::transport_offer(&host, offer)
+                );
+            }
+            (first_hello, first_messages)
+        }
+        Some(offer) => {
+            println!("Migrating to {}...", display::transport_offer(&host, offer));
+            let target_host = if offer.host.is_empty() {
+                host.clone()
+            } else {
+                offer.host.clone()
+            };
+            let mut first_messages = first_messages;
+            let _ = network::shutdown_tls(&mut first_messages).await;
+            // Resume on the current endpoint if the offered one can't be reached
+            let mut migrated = None;
+            for target in [(target_host, offer.port as u16), endpoint.clone()] {
+                // Give the server time to park the session of the closed connection
+                tokio::time::sleep(RESUME_RETRY_DELAY).await;
+                match network::connect_tls(
+                    &target.0,
+                    target.1,
+                    args.insecure,
+                    load_client_cert(),
+                    monitors.clone(),
+                    config::KnownHosts::load(),
+                    config::IdFiles::load(),
+                    args.id.clone(),
+                    Some(first_hello.resumption_token.clone()),
+                    args.zstd_level,
+                )
+                .await
+                {
+                    Ok(connection) => {
+                        migrated = Some((target, connection));
+                        break;
+                    }
+                    Err(e) => log::warn!(
+                        "Failed to resume the session at {}:{}: {}",
+                        target.0,
+                        target.1,
+                        e
+                    ),
+                }
+            }
+            let Some((target, (hello, messages))) = migrated else {
+                log::error!("Failed to migrate the session.");
+                exit(1);
+            };
+            if let Some(tracer) = &mut tracer {
+                tracer.received(&ServerEvent::ServerHelloAck(hello.clone()));
+            }
+            endpoint = target;
+            (hello, messages)
+        }
+    };
     let format: FrameFormat = hello.format.try_into().unwrap_or_else(|_| {
         log::error!("Failed to parse frame format: {}", hello.format);
         exit(1);
     });
-    let compression = hello.compression;
-    println!("Successfully connected to server!");
+    let compression = hello.compression.clone();
+    let mut resumption_token = hello.resumption_token.clone();
+    println!(
+        "{}",
+        display::connection_summary(&endpoint.0, endpoint.1, &hello)
+    );
 
-    let mut client = Client::new(sdl, video, format, compression, messages);
+    let mut client = Client::new(sdl, video, format, compression).unwrap_or_else(|e| {
+        log::error!("Failed to initialize client: {}", e);
+        exit(1);
+    });
+    if let Some(tracer) = tracer {
+        client.trace_protocol(tracer);
+    }
+    if let Some(tolerance) = args.verify_frames {
+        client.verify_frames(tolerance);
+    }
+    client.show_hud(args.hud);
+    if let Some(dir) = args.download_dir {
+        client.download_to(dir);
+    }
+    // Servers predating the limit announce none
+    if hello.max_frame_width > 0 && hello.max_frame_height > 0 {
+        client.limit_window_size(hello.max_frame_width, hello.max_frame_height);
+    }
 
     if hello.windows.is_empty() {
         log::warn!("No initial window settings provided, creating a default window.");
@@ -229,12 +379,56 @@ async fn main() {
             });
         }
     }
-    if let Err(e) = client.main().await {
-        log::error!("Client error: {}", e);
-        exit(1);
+    loop {
+        let disconnect = libgsh::client::run(&mut messages, &mut client, client::FRAME_TIME)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("Client error: {}", e);
+                exit(1);
+            });
+        if disconnect != Disconnect::ConnectionLost || resumption_token.is_empty() {
+            break;
+        }
+        log::warn!("Connection lost, trying to resume the session...");
+        let mut resumed = None;
+        for attempt in 1..=RESUME_ATTEMPTS {
+            tokio::time::sleep(RESUME_RETRY_DELAY).await;
+            match network::connect_tls(
+                &endpoint.0,
+                endpoint.1,
+                args.insecure,
+                load_client_cert(),
+                monitors.clone(),
+                config::KnownHosts::load(),
+                config::IdFiles::load(),
+                args.id.clone(),
+                Some(resumption_token.clone()),
+                args.zstd_level,
+            )
+            .await
+            {
+                Ok(connection) => {
+                    resumed = Some(connection);
+                    break;
+                }
+                Err(e) => log::warn!(
+                    "Resume attempt {}/{} failed: {}",
+                    attempt,
+                    RESUME_ATTEMPTS,
+                    e
+                ),
+            }
+        }
+        let Some((hello, resumed_messages)) = resumed else {
+            log::error!("Failed to resume the session.");
+            exit(1);
+        };
+        println!("Session resumed.");
+        resumption_token = hello.resumption_token;
+        messages = resumed_messages;
     }
     log::info!("Shutting down client...");
-    let _ = network::shutdown_tls(client.inner_stream()).await;
+    let _ = network::shutdown_tls(&mut messages).await;
 }
 
 fn color_choice() -> ColorChoice {
@@ -311,5 +505,11 @@ fn default_window(host: &str) -> WindowSettings {
         allow_resize: true,
         resize_frame: false,
         frame_anchor: window_settings::WindowAnchor::TopLeft as i32,
+        accepts_input: None,
+        grab_input: false,
+        relative_mouse: false,
+        format: None,
+        compression: None,
+        icon: None,
     }
 }
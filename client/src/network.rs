@@ -3,9 +3,7 @@ use dialoguer::Confirm;
 use libgsh::{
     client::ClientStream,
     sha2::{Digest, Sha256},
-    shared::protocol::{
-        client_hello::MonitorInfo, status_update::StatusType, ServerHelloAck, StatusUpdate,
-    },
+    shared::protocol::{client_hello::MonitorInfo, ServerHelloAck},
 };
 use std::sync::Arc;
 use tokio::{io::AsyncWriteExt, net::TcpStream};
@@ -19,25 +17,27 @@ use tokio_rustls::TlsConnector;
 
 pub async fn shutdown_tls(stream: &mut ClientStream) -> anyhow::Result<()> {
     log::trace!("Exiting gracefully...");
-    stream.get_inner().get_mut().1.send_close_notify();
-    stream
-        .send(StatusUpdate {
-            kind: StatusType::Exit as i32,
-            details: None,
-        })
-        .await?;
-    stream.get_inner().get_mut().0.shutdown().await?;
+    stream.close().await?;
     log::trace!("Connection closed.");
     Ok(())
 }
 
-fn tls_config(insecure: bool) -> anyhow::Result<rustls::ClientConfig> {
+/// Certificate chain and private key presented to servers requiring mutual TLS.
+pub type ClientCert = (
+    Vec<rustls::pki_types::CertificateDer<'static>>,
+    rustls::pki_types::PrivateKeyDer<'static>,
+);
+
+fn tls_config(
+    insecure: bool,
+    client_cert: Option<ClientCert>,
+) -> anyhow::Result<rustls::ClientConfig> {
     let root_store = if insecure {
         rustls::RootCertStore::empty()
     } else {
         rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
     };
-    let mut config = rustls::ClientConfig::builder_with_details(
+    let builder = rustls::ClientConfig::builder_with_details(
         CryptoProvider {
             cipher_suites: vec![provider::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256],
             kx_groups: vec![provider::kx_group::X25519],
@@ -47,8 +47,11 @@ fn tls_config(insecure: bool) -> anyhow::Result<rustls::ClientConfig> {
         Arc::new(time_provider::DefaultTimeProvider),
     )
     .with_protocol_versions(&[&rustls::version::TLS13])?
-    .with_root_certificates(root_store)
-    .with_no_client_auth();
+    .with_root_certificates(root_store);
+    let mut config = match client_cert {
+        Some((certs, private_key)) => builder.with_client_auth_cert(certs, private_key)?,
+        None => builder.with_no_client_auth(),
+    };
     if insecure {
         config
             .dangerous()
@@ -78,7 +81,25 @@ async fn verify_host(
                 known.fingerprints,
                 fingerprints
             );
-            Ok(false)
+            println!("WARNING: THE CERTIFICATE OF HOST {} HAS CHANGED!", host);
+            println!("Someone could be eavesdropping on you right now (man-in-the-middle attack),");
+            println!("or the server certificate has legitimately been rotated.");
+            println!("Only continue if you have verified the new fingerprint out of band.");
+            println!("Known fingerprints: {:X?}", known.fingerprints);
+            println!("New fingerprints:   {:X?}", fingerprints);
+            let confirmation = Confirm::new()
+                .with_prompt("Do you want to replace the known fingerprints of this host?")
+                .default(false)
+                .interact()?;
+            if confirmation {
+                known_hosts.replace_fingerprints(host, fingerprints);
+                known_hosts.save();
+                log::info!("Host {} fingerprints updated in known hosts.", host);
+                Ok(true)
+            } else {
+                log::warn!("Host {} fingerprints not updated.", host);
+                Ok(false)
+            }
         }
     } else {
         if fingerprints.is_empty() {
@@ -109,17 +130,21 @@ async fn verify_host(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn connect_tls(
     host: &str,
     port: u16,
     insecure: bool,
+    client_cert: Option<ClientCert>,
     monitors: Vec<MonitorInfo>,
     mut known_hosts: config::KnownHosts,
     id_files: config::IdFiles,
     id_override: Option<String>,
+    resumption_token: Option<Vec<u8>>,
+    zstd_level: Option<i32>,
 ) -> anyhow::Result<(ServerHelloAck, ClientStream)> {
     let server_name = host.to_string().try_into()?;
-    let tls_config = Arc::new(tls_config(insecure)?);
+    let tls_config = Arc::new(tls_config(insecure, client_cert)?);
     let tls_connector = TlsConnector::from(tls_config);
     let addr = format!("{}:{}", host, port);
     let sock = TcpStream::connect(&addr).await?;
@@ -133,12 +158,15 @@ pub async fn connect_tls(
             return Err(anyhow::anyhow!("Host verification failed."));
         }
     }
+    // The codec's default maximum message size is announced to the server during the handshake
     let mut messages = ClientStream::new(tls_stream);
     let hello = libgsh::client::handshake(
         &mut messages,
         monitors,
         ClientAuthProvider::new(known_hosts, id_files, id_override),
         host,
+        resumption_token,
+        zstd_level,
     )
     .await?;
 
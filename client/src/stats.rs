@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use sdl3::{
+    pixels::Color,
+    render::{Canvas, FRect},
+    video,
+};
+use std::{
+    ffi::CString,
+    time::{Duration, Instant},
+};
+
+/// Interval over which the frame rate is averaged.
+const FPS_INTERVAL: Duration = Duration::from_secs(1);
+/// Height in pixels of a line of SDL's built-in debug font, including spacing.
+const HUD_LINE_HEIGHT: f32 = 10.0;
+const HUD_MARGIN: f32 = 4.0;
+
+/// Frame statistics of a single server window, shown in the debug HUD.
+#[derive(Debug, Clone)]
+pub struct WindowStats {
+    pub width: u32,
+    pub height: u32,
+    /// Size in bytes of the segment data of the last frame, as received over the wire
+    pub last_frame_bytes: usize,
+    /// Total number of segments applied to the window
+    pub segments_applied: u64,
+    /// Total number of frames received for the window
+    pub frames: u64,
+    /// Total number of frames lost, detected from gaps in their sequence numbers
+    pub dropped_frames: u64,
+    /// Number of textures allocated for the window, once per frame size while the texture is reused
+    pub textures: u64,
+    /// Frames per second averaged over the last completed interval
+    pub fps: f32,
+    interval_start: Instant,
+    interval_frames: u32,
+}
+
+impl Default for WindowStats {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+            last_frame_bytes: 0,
+            segments_applied: 0,
+            frames: 0,
+            dropped_frames: 0,
+            textures: 0,
+            fps: 0.0,
+            interval_start: Instant::now(),
+            interval_frames: 0,
+        }
+    }
+}
+
+impl WindowStats {
+    /// Record a received frame and update the frame rate once per interval.
+    pub fn record_frame(&mut self, width: u32, height: u32, bytes: usize, segments: usize) {
+        self.width = width;
+        self.height = height;
+        self.last_frame_bytes = bytes;
+        self.segments_applied += segments as u64;
+        self.frames += 1;
+        self.interval_frames += 1;
+        let elapsed = self.interval_start.elapsed();
+        if elapsed >= FPS_INTERVAL {
+            self.fps = self.interval_frames as f32 / elapsed.as_secs_f32();
+            self.interval_frames = 0;
+            self.interval_start = Instant::now();
+        }
+    }
+
+    /// Lines of text describing the stats, as shown in the HUD.
+    pub fn lines(&self, window_id: u32) -> [String; 6] {
+        [
+            format!("window {}: {}x{}", window_id, self.width, self.height),
+            format!("fps: {:.1}", self.fps),
+            format!("last frame: {} bytes", self.last_frame_bytes),
+            format!(
+                "segments: {} ({} frames)",
+                self.segments_applied, self.frames
+            ),
+            format!("dropped: {} frames", self.dropped_frames),
+            format!("textures: {} allocated", self.textures),
+        ]
+    }
+}
+
+/// Draw the stats of a window as an overlay in its top-left corner.
+pub fn render_hud(
+    canvas: &mut Canvas<video::Window>,
+    window_id: u32,
+    stats: &WindowStats,
+) -> Result<()> {
+    let lines = stats.lines(window_id);
+    let width = lines.iter().map(|l| l.len()).max().unwrap_or(0) as f32 * 8.0;
+    let height = lines.len() as f32 * HUD_LINE_HEIGHT;
+    canvas.set_blend_mode(sdl3::render::BlendMode::Blend);
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    canvas
+        .fill_rect(FRect::new(
+            0.0,
+            0.0,
+            width + HUD_MARGIN * 2.0,
+            height + HUD_MARGIN * 2.0,
+        ))
+        .map_err(|e| anyhow!(e))?;
+    canvas.set_draw_color(Color::WHITE);
+    for (i, line) in lines.iter().enumerate() {
+        let string = CString::new(line.as_str())?;
+        // SAFETY: the renderer is alive for the lifetime of the canvas and the string is NUL-terminated
+        unsafe {
+            sdl3::sys::render::SDL_RenderDebugText(
+                canvas.raw(),
+                HUD_MARGIN,
+                HUD_MARGIN + i as f32 * HUD_LINE_HEIGHT,
+                string.as_ptr(),
+            );
+        }
+    }
+    canvas.set_blend_mode(sdl3::render::BlendMode::None);
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use libgsh::shared::protocol::{
+    client_message::ClientEvent, server_message::ServerEvent, ClientMessage,
+};
+use std::{fs::File, io::Write};
+
+/// Logs a one-line summary of every protocol message sent or received by the client.\
+/// Large payloads such as frame data and credentials are summarized or redacted.
+pub struct ProtocolTracer {
+    out: Box<dyn Write + Send>,
+}
+
+impl ProtocolTracer {
+    /// Creates a tracer writing to the given file path, or to stderr if the path is `-`.
+    pub fn new(target: &str) -> std::io::Result<Self> {
+        let out: Box<dyn Write + Send> = if target == "-" {
+            Box::new(std::io::stderr())
+        } else {
+            Box::new(File::create(target)?)
+        };
+        Ok(Self { out })
+    }
+
+    pub fn sent(&mut self, message: &ClientMessage) {
+        let summary = match &message.client_event {
+            Some(ClientEvent::ClientHello(hello)) => format!(
+                "ClientHello protocol_version={} os={} os_version={:?} monitors={}",
+                hello.protocol_version,
+                hello.os,
+                hello.os_version,
+                hello.monitors.len()
+            ),
+            Some(ClientEvent::ClientAuth(_)) => "ClientAuth <redacted>".to_string(),
+            Some(ClientEvent::StatusUpdate(status)) => format!("{:?}", status),
+            Some(ClientEvent::UserInput(input)) => format!("{:?}", input),
+            Some(ClientEvent::FrameAck(ack)) => format!("{:?}", ack),
+            Some(ClientEvent::NetworkStats(stats)) => format!("{:?}", stats),
+            Some(ClientEvent::ViewportHint(hint)) => format!("{:?}", hint),
+            Some(ClientEvent::RequestKeyframe(request)) => format!("{:?}", request),
+            Some(ClientEvent::FileTransferBegin(begin)) => format!("{:?}", begin),
+            Some(ClientEvent::FileChunk(chunk)) => format!(
+                "FileChunk transfer_id={} offset={} bytes={}",
+                chunk.transfer_id,
+                chunk.offset,
+                chunk.data.len()
+            ),
+            Some(ClientEvent::FileTransferEnd(end)) => format!("{:?}", end),
+            None => "<empty>".to_string(),
+        };
+        self.write("->", &summary);
+    }
+
+    pub fn received(&mut self, event: &ServerEvent) {
+        let summary = match event {
+            ServerEvent::ServerHelloAck(hello) => format!(
+                "ServerHelloAck format={} compression={:?} windows={} auth_method={}",
+                hello.format,
+                hello.compression,
+                hello.windows.len(),
+                hello.auth_method.is_some()
+            ),
+            ServerEvent::ServerAuthAck(ack) => format!("{:?}", ack),
+            ServerEvent::StatusUpdate(status) => format!("{:?}", status),
+            ServerEvent::Frame(frame) => format!(
+                "Frame window_id={} size={}x{} seq={:?} group={:?} final={} keyframe={} segments={} bytes={}",
+                frame.window_id,
+                frame.width,
+                frame.height,
+                frame.seq,
+                frame.frame_group_id,
+                frame.is_final,
+                frame.is_keyframe,
+                frame.segments.len(),
+                frame.segments.iter().map(|s| s.data.len()).sum::<usize>()
+            ),
+            ServerEvent::SetWindowPosition(position) => format!("{:?}", position),
+            ServerEvent::WindowCommand(command) => format!("{:?}", command),
+            ServerEvent::CreateWindow(settings) => format!(
+                "CreateWindow window_id={} title={:?} size={}x{}",
+                settings.window_id, settings.title, settings.width, settings.height
+            ),
+            ServerEvent::DestroyWindow(window) => format!("{:?}", window),
+            ServerEvent::FileOffer(offer) => format!("{:?}", offer),
+            ServerEvent::FileChunk(chunk) => format!(
+                "FileChunk transfer_id={} offset={} bytes={}",
+                chunk.transfer_id,
+                chunk.offset,
+                chunk.data.len()
+            ),
+            ServerEvent::FileTransferEnd(end) => format!("{:?}", end),
+            ServerEvent::Audio(chunk) => format!(
+                "AudioChunk stream_id={} rate={} channels={} format={} bytes={}",
+                chunk.stream_id,
+                chunk.sample_rate,
+                chunk.channels,
+                chunk.format,
+                chunk.samples.len()
+            ),
+            ServerEvent::WarpCursor(warp) => format!(
+                "WarpCursor window_id={} x={} y={}",
+                warp.window_id, warp.x, warp.y
+            ),
+            ServerEvent::Cursor(cursor) => format!(
+                "CursorUpdate window_id={} visible={} size={}x{} hotspot=({}, {})",
+                cursor.window_id,
+                cursor.visible,
+                cursor.width,
+                cursor.height,
+                cursor.hotspot_x,
+                cursor.hotspot_y
+            ),
+            ServerEvent::DrawList(list) => format!(
+                "DrawList window_id={} size={}x{} commands={}",
+                list.window_id,
+                list.width,
+                list.height,
+                list.commands.len()
+            ),
+        };
+        self.write("<-", &summary);
+    }
+
+    fn write(&mut self, direction: &str, summary: &str) {
+        if let Err(err) = writeln!(self.out, "{} {}", direction, summary) {
+            log::warn!("Failed to write protocol trace: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libgsh::shared::protocol::{
+        frame::Segment, ClientAuth, ClientHello, Frame, ServerHelloAck,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// A writer appending to a buffer shared with the test.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn traces_handshake_and_frame() {
+        let buffer = SharedBuffer::default();
+        let mut tracer = ProtocolTracer {
+            out: Box::new(buffer.clone()),
+        };
+        tracer.sent(&ClientMessage {
+            client_event: Some(ClientEvent::ClientHello(ClientHello {
+                protocol_version: 3,
+                ..Default::default()
+            })),
+        });
+        tracer.received(&ServerEvent::ServerHelloAck(ServerHelloAck::default()));
+        tracer.sent(&ClientMessage {
+            client_event: Some(ClientEvent::ClientAuth(ClientAuth::default())),
+        });
+        tracer.received(&ServerEvent::Frame(Frame {
+            window_id: 1,
+            width: 2,
+            height: 2,
+            segments: vec![Segment {
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 2,
+                data: vec![0; 16],
+            }],
+            seq: Some(7),
+            is_keyframe: true,
+            ..Default::default()
+        }));
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("-> ClientHello protocol_version=3 "));
+        assert!(lines[1].starts_with("<- ServerHelloAck "));
+        assert_eq!(lines[2], "-> ClientAuth <redacted>");
+        assert_eq!(
+            lines[3],
+            "<- Frame window_id=1 size=2x2 seq=Some(7) group=None final=false keyframe=true segments=1 bytes=16"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use libgsh::shared::{
+    protocol::{ClientMessage, FileChunk, FileTransferBegin, FileTransferEnd},
+    transfer::FILE_CHUNK_SIZE,
+};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+/// Chunks read ahead of the network, reader threads wait once this many are queued.
+const QUEUED_CHUNKS: usize = 8;
+/// Chunks handed to the message pump per poll, so uploads don't delay input and frame acknowledgements.
+const CHUNKS_PER_POLL: usize = 4;
+
+/// Uploads files dropped onto a window to the server.\
+/// Files are read on a background thread, one chunk at a time, and queued for the message pump.
+pub struct Uploads {
+    next_transfer_id: u64,
+    tx: SyncSender<ClientMessage>,
+    rx: Receiver<ClientMessage>,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::sync_channel(QUEUED_CHUNKS);
+        Self {
+            next_transfer_id: 1,
+            tx,
+            rx,
+        }
+    }
+}
+
+impl Uploads {
+    /// Starts uploading the file at `path` to the server window `window_id`.
+    pub fn start(&mut self, window_id: u32, path: PathBuf) {
+        let transfer_id = self.next_transfer_id;
+        self.next_transfer_id += 1;
+        let tx = self.tx.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = upload(&tx, transfer_id, window_id, &path) {
+                log::warn!("Failed to upload {}: {}", path.display(), e);
+            }
+        });
+    }
+
+    /// Moves the next queued messages of running uploads to `outbox`.
+    pub fn poll(&mut self, outbox: &mut Vec<ClientMessage>) {
+        outbox.extend(self.rx.try_iter().take(CHUNKS_PER_POLL));
+    }
+}
+
+fn upload(
+    tx: &SyncSender<ClientMessage>,
+    transfer_id: u64,
+    window_id: u32,
+    path: &Path,
+) -> io::Result<()> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    log::info!("Uploading {} ({} bytes) to the server", name, size);
+    let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "Client closed");
+    tx.send(
+        FileTransferBegin {
+            transfer_id,
+            name,
+            size,
+            window_id,
+        }
+        .into(),
+    )
+    .map_err(|_| closed())?;
+    // Never send more than announced, in case the file grows while uploading
+    let mut file = file.take(size);
+    let mut offset = 0;
+    let mut buffer = vec![0; FILE_CHUNK_SIZE];
+    let result = loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(read) => {
+                let chunk = FileChunk {
+                    transfer_id,
+                    offset,
+                    data: buffer[..read].to_vec(),
+                };
+                tx.send(chunk.into()).map_err(|_| closed())?;
+                offset += read as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e),
+        }
+    };
+    // Always end the transfer, the server drops it if it is incomplete
+    tx.send(FileTransferEnd { transfer_id }.into())
+        .map_err(|_| closed())?;
+    result
+}
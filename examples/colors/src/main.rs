@@ -4,11 +4,12 @@ use libgsh::{
     server::{GshServer, GshService, GshServiceExt, ServerStream},
     shared::{
         cert,
+        frame::full_frame_segment,
         frame::optimize_segments,
         frame::PrevFrame,
         protocol::{
             client_message::ClientEvent,
-            server_hello_ack::{window_settings, FrameFormat, WindowSettings},
+            server_hello_ack::{window_settings, FrameFormat, ServerInfo, WindowSettings},
             Frame, ServerHelloAck,
         },
     },
@@ -36,7 +37,7 @@ async fn main() {
 
 const FRAME_WIDTH: usize = 250;
 const FRAME_HEIGHT: usize = 250;
-const PIXEL_BYTES: usize = 4; // RGBA
+const PIXEL_BYTES: usize = FrameFormat::Rgba.bytes_per_pixel();
 const WINDOW_PRIMARY: u32 = 0;
 const WINDOW_SECONDARY: u32 = 1;
 
@@ -77,19 +78,29 @@ impl ColorService {
             self.cur_frame[i * PIXEL_BYTES + 2] = color.2; // Blue
             self.cur_frame[i * PIXEL_BYTES + 3] = 255;
         }
+        let keyframe = stream.keyframe_due(window_id);
+        let segments = if keyframe {
+            full_frame_segment(&self.cur_frame, FRAME_WIDTH, FRAME_HEIGHT)
+        } else {
+            optimize_segments(
+                &self.cur_frame,
+                FRAME_WIDTH,
+                FRAME_HEIGHT,
+                &self.prev_frame,
+                PIXEL_BYTES,
+            )
+        };
         stream
             .send(Frame {
                 window_id,
                 // data: frame.to_vec(),
-                segments: optimize_segments(
-                    &self.cur_frame,
-                    FRAME_WIDTH,
-                    FRAME_HEIGHT,
-                    &self.prev_frame,
-                    PIXEL_BYTES,
-                ),
+                segments,
                 width: FRAME_WIDTH as u32,
                 height: FRAME_HEIGHT as u32,
+                seq: None,
+                frame_group_id: None,
+                is_final: false,
+                is_keyframe: keyframe,
             })
             .await?;
 
@@ -143,6 +154,12 @@ impl GshService for ColorService {
                     allow_resize: false,
                     resize_frame: false,
                     frame_anchor: window_settings::WindowAnchor::Center.into(),
+                    accepts_input: None,
+                    grab_input: false,
+                    relative_mouse: false,
+                    format: None,
+                    compression: None,
+                    icon: None,
                 },
                 WindowSettings {
                     window_id: WINDOW_SECONDARY,
@@ -155,9 +172,25 @@ impl GshService for ColorService {
                     allow_resize: false,
                     resize_frame: false,
                     frame_anchor: window_settings::WindowAnchor::Center.into(),
+                    accepts_input: Some(false), // Display-only window
+                    grab_input: false,
+                    relative_mouse: false,
+                    format: None,
+                    compression: None,
+                    icon: None,
                 },
             ],
             auth_method: None,
+            server_info: Some(ServerInfo {
+                name: "Colors".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                description: "Random colors on every input".to_string(),
+            }),
+            transport_offers: vec![],
+            resumption_token: vec![],
+            protocol_version: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
         }
     }
 }
@@ -4,10 +4,11 @@ use libgsh::{
     server::{GshServer, GshService, GshServiceExt, ServerStream},
     shared::{
         cert,
-        frame::full_frame_segment,
+        draw::{Canvas, WHITE},
+        frame::{full_frame_segment, optimize_segments, PrevFrame},
         protocol::{
             client_message::ClientEvent,
-            server_hello_ack::{window_settings, FrameFormat, WindowSettings},
+            server_hello_ack::{window_settings, FrameFormat, ServerInfo, WindowSettings},
             user_input::{window_event::WindowAction, InputEvent},
             Frame, ServerHelloAck,
         },
@@ -19,7 +20,7 @@ use libgsh::{
 use std::time::Instant;
 use vek::*;
 
-const PIXEL_BYTES: usize = 4; // RGBA
+const PIXEL_BYTES: usize = FrameFormat::Rgba.bytes_per_pixel();
 const WINDOW_ID: u32 = 0;
 const INITIAL_WIDTH: usize = 300;
 const INITIAL_HEIGHT: usize = 300;
@@ -51,7 +52,8 @@ pub struct CubeService {
     start: Instant,
     width: usize,
     height: usize,
-    // prev_frame: Vec<u8>,
+    cur_frame: Vec<u8>,
+    prev_frame: PrevFrame,
 }
 
 impl Default for CubeService {
@@ -60,28 +62,51 @@ impl Default for CubeService {
             start: Instant::now(),
             width: INITIAL_WIDTH,
             height: INITIAL_HEIGHT,
-            // prev_frame: vec![0; INITIAL_WIDTH * INITIAL_HEIGHT * PIXEL_BYTES],
+            cur_frame: Vec::new(),
+            prev_frame: PrevFrame::with_capacity(INITIAL_WIDTH * INITIAL_HEIGHT * PIXEL_BYTES),
         }
     }
 }
 
 impl CubeService {
     async fn send_frame(&mut self, stream: &mut ServerStream) -> Result<()> {
-        let frame = self.draw_cube(4);
+        let mut frame = std::mem::take(&mut self.cur_frame);
+        self.draw_cube(&mut frame, 4);
+        let keyframe = stream.keyframe_due(WINDOW_ID);
+        let segments = if keyframe {
+            full_frame_segment(&frame, self.width, self.height)
+        } else {
+            optimize_segments(
+                &frame,
+                self.width,
+                self.height,
+                &self.prev_frame,
+                PIXEL_BYTES,
+            )
+        };
         stream
             .send(Frame {
                 window_id: WINDOW_ID,
-                segments: full_frame_segment(&frame, self.width, self.height),
+                segments,
                 width: self.width as u32,
                 height: self.height as u32,
+                seq: None,
+                frame_group_id: None,
+                is_final: false,
+                is_keyframe: keyframe,
             })
             .await?;
         log::trace!("Frame sent: {}x{}", self.width, self.height);
+
+        // Keep the sent frame to diff against, and reuse the older buffer for the next frame
+        self.cur_frame = self.prev_frame.update_with_frame(frame);
         Ok(())
     }
 
-    fn draw_cube(&self, stroke_width: usize) -> Vec<u8> {
-        let mut frame = vec![0u8; self.width * self.height * PIXEL_BYTES];
+    /// Draws the cube into `frame`, overwriting whatever the reused buffer contained.
+    fn draw_cube(&self, frame: &mut Vec<u8>, stroke_width: usize) {
+        frame.clear();
+        frame.resize(self.width * self.height * PIXEL_BYTES, 0);
 
         // Define cube vertices
         let size = 0.4;
@@ -131,60 +156,9 @@ impl CubeService {
             .collect();
 
         // Draw edges
+        let mut canvas = Canvas::new(frame, self.width, self.height, FrameFormat::Rgba);
         for (a, b) in edges {
-            Self::draw_line(
-                projected[a],
-                projected[b],
-                &mut frame,
-                self.width,
-                stroke_width,
-            );
-        }
-
-        frame
-    }
-
-    fn draw_line(
-        (x0, y0): (i32, i32),
-        (x1, y1): (i32, i32),
-        frame: &mut [u8],
-        width: usize,
-        stroke_width: usize,
-    ) {
-        let dx = (x1 - x0).abs();
-        let dy = -(y1 - y0).abs();
-        let sx = if x0 < x1 { 1 } else { -1 };
-        let sy = if y0 < y1 { 1 } else { -1 };
-        let mut err = dx + dy;
-        let (mut x, mut y) = (x0, y0);
-
-        loop {
-            if x >= 0 && y >= 0 {
-                for i in 0..stroke_width {
-                    for j in 0..stroke_width {
-                        let idx = ((y + i as i32) as usize * width + (x + j as i32) as usize)
-                            * PIXEL_BYTES;
-                        if idx + 3 < frame.len() {
-                            frame[idx] = 255;
-                            frame[idx + 1] = 255;
-                            frame[idx + 2] = 255;
-                            frame[idx + 3] = 255;
-                        }
-                    }
-                }
-            }
-            if x == x1 && y == y1 {
-                break;
-            }
-            let e2 = 2 * err;
-            if e2 >= dy {
-                err += dy;
-                x += sx;
-            }
-            if e2 <= dx {
-                err += dx;
-                y += sy;
-            }
+            canvas.draw_line(projected[a], projected[b], stroke_width, WHITE);
         }
     }
 }
@@ -210,8 +184,24 @@ impl GshService for CubeService {
                 allow_resize: true,
                 resize_frame: true,
                 frame_anchor: window_settings::WindowAnchor::Center.into(),
+                accepts_input: None,
+                grab_input: false,
+                relative_mouse: false,
+                format: None,
+                compression: None,
+                icon: None,
             }],
             auth_method: None,
+            server_info: Some(ServerInfo {
+                name: "Spinning Cube".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                description: "A spinning wireframe cube".to_string(),
+            }),
+            transport_offers: vec![],
+            resumption_token: vec![],
+            protocol_version: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
         }
     }
 }
@@ -231,11 +221,13 @@ impl GshServiceExt for CubeService {
     async fn on_event(&mut self, stream: &mut ServerStream, event: ClientEvent) -> Result<()> {
         log::trace!("Got event: {:?}", event);
         if let ClientEvent::UserInput(input) = &event {
-            if let InputEvent::WindowEvent(window_event) = input.input_event.unwrap() {
+            if let Some(InputEvent::WindowEvent(window_event)) = input.input_event.as_ref() {
                 if window_event.action == WindowAction::Resize as i32 {
                     if input.window_id == WINDOW_ID {
                         self.width = window_event.width as usize;
                         self.height = window_event.height as usize;
+                        // The previous frame has a different size, start over with a full frame
+                        self.prev_frame = PrevFrame::new();
                         self.send_frame(stream).await?;
                         log::info!(
                             "WindowEvent: Resize event for window {}: {}x{}",
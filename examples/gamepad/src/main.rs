@@ -0,0 +1,215 @@
+use env_logger::Env;
+use libgsh::{
+    async_trait::async_trait,
+    server::{GshServer, GshService, GshServiceExt, ServerStream},
+    shared::{
+        cert,
+        draw::{Canvas, Color},
+        frame::{full_frame_segment, optimize_segments, PrevFrame},
+        protocol::{
+            client_message::ClientEvent,
+            server_hello_ack::{window_settings, FrameFormat, ServerInfo, WindowSettings},
+            user_input::{gamepad_event::GamepadAction, InputEvent},
+            Frame, ServerHelloAck,
+        },
+    },
+    tokio, Result, ServerConfig,
+};
+use std::time::Instant;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
+        .format_line_number(true)
+        .format_file(true)
+        .format_target(false)
+        .format_timestamp(None)
+        .init();
+    let (key, private_key) = cert::self_signed(&["localhost"]).unwrap();
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![key.cert.der().clone()], private_key)
+        .unwrap();
+    let server = GshServer::new(GamepadService::default(), config);
+    server.serve().await.unwrap();
+}
+
+const FRAME_WIDTH: usize = 400;
+const FRAME_HEIGHT: usize = 300;
+const PIXEL_BYTES: usize = FrameFormat::Rgba.bytes_per_pixel();
+const WINDOW_ID: u32 = 0;
+const SQUARE_SIZE: usize = 32;
+const SPEED: f32 = 200.0; // pixels per second at full stick deflection
+const DEAD_ZONE: f32 = 0.15;
+const SDL_AXIS_LEFTX: i32 = 0;
+const SDL_AXIS_LEFTY: i32 = 1;
+const BACKGROUND: Color = [20, 20, 30, 255];
+const SQUARE: Color = [80, 200, 120, 255];
+
+#[derive(Debug, Clone)]
+pub struct GamepadService {
+    x: f32,
+    y: f32,
+    stick: (f32, f32),
+    last_tick: Instant,
+    prev_frame: PrevFrame,
+    cur_frame: Vec<u8>,
+}
+
+impl Default for GamepadService {
+    fn default() -> Self {
+        let size = FRAME_WIDTH * FRAME_HEIGHT * PIXEL_BYTES;
+        Self {
+            x: (FRAME_WIDTH - SQUARE_SIZE) as f32 / 2.0,
+            y: (FRAME_HEIGHT - SQUARE_SIZE) as f32 / 2.0,
+            stick: (0.0, 0.0),
+            last_tick: Instant::now(),
+            prev_frame: PrevFrame::with_capacity(size),
+            cur_frame: vec![0; size],
+        }
+    }
+}
+
+impl GamepadService {
+    async fn send_frame(&mut self, stream: &mut ServerStream) -> Result<()> {
+        let mut canvas = Canvas::new(
+            &mut self.cur_frame,
+            FRAME_WIDTH,
+            FRAME_HEIGHT,
+            FrameFormat::Rgba,
+        );
+        canvas.clear(BACKGROUND);
+        canvas.fill_rect(
+            self.x as i32,
+            self.y as i32,
+            SQUARE_SIZE,
+            SQUARE_SIZE,
+            SQUARE,
+        );
+        let keyframe = stream.keyframe_due(WINDOW_ID);
+        let segments = if keyframe {
+            full_frame_segment(&self.cur_frame, FRAME_WIDTH, FRAME_HEIGHT)
+        } else {
+            optimize_segments(
+                &self.cur_frame,
+                FRAME_WIDTH,
+                FRAME_HEIGHT,
+                &self.prev_frame,
+                PIXEL_BYTES,
+            )
+        };
+        if !segments.is_empty() {
+            stream
+                .send(Frame {
+                    window_id: WINDOW_ID,
+                    segments,
+                    width: FRAME_WIDTH as u32,
+                    height: FRAME_HEIGHT as u32,
+                    seq: None,
+                    frame_group_id: None,
+                    is_final: false,
+                    is_keyframe: keyframe,
+                })
+                .await?;
+            stream.flush().await?;
+        }
+        let new_frame = self
+            .prev_frame
+            .update_with_frame(std::mem::take(&mut self.cur_frame));
+        self.cur_frame = new_frame;
+        self.cur_frame
+            .resize(FRAME_WIDTH * FRAME_HEIGHT * PIXEL_BYTES, 0);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl GshService for GamepadService {
+    async fn main(self, stream: ServerStream) -> Result<()> {
+        <Self as GshServiceExt>::main(self, stream).await
+    }
+
+    fn server_hello(&self) -> ServerHelloAck {
+        ServerHelloAck {
+            format: FrameFormat::Rgba.into(),
+            compression: None,
+            windows: vec![WindowSettings {
+                window_id: WINDOW_ID,
+                monitor_id: None,
+                title: String::from("Gamepad"),
+                initial_mode: window_settings::WindowMode::Windowed.into(),
+                width: FRAME_WIDTH as u32,
+                height: FRAME_HEIGHT as u32,
+                always_on_top: false,
+                allow_resize: false,
+                resize_frame: false,
+                frame_anchor: window_settings::WindowAnchor::Center.into(),
+                accepts_input: None,
+                grab_input: false,
+                relative_mouse: false,
+                format: None,
+                compression: None,
+                icon: None,
+            }],
+            auth_method: None,
+            server_info: Some(ServerInfo {
+                name: "Gamepad".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                description: "Move a square with the left stick".to_string(),
+            }),
+            transport_offers: vec![],
+            resumption_token: vec![],
+            protocol_version: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl GshServiceExt for GamepadService {
+    async fn on_startup(&mut self, stream: &mut ServerStream) -> Result<()> {
+        self.send_frame(stream).await
+    }
+
+    async fn on_tick(&mut self, stream: &mut ServerStream) -> Result<()> {
+        let dt = self.last_tick.elapsed().as_secs_f32();
+        self.last_tick = Instant::now();
+        let (dx, dy) = self.stick;
+        self.x = (self.x + dx * SPEED * dt).clamp(0.0, (FRAME_WIDTH - SQUARE_SIZE) as f32);
+        self.y = (self.y + dy * SPEED * dt).clamp(0.0, (FRAME_HEIGHT - SQUARE_SIZE) as f32);
+        self.send_frame(stream).await
+    }
+
+    async fn on_event(&mut self, _stream: &mut ServerStream, event: ClientEvent) -> Result<()> {
+        let ClientEvent::UserInput(input) = event else {
+            return Ok(());
+        };
+        let Some(InputEvent::GamepadEvent(gamepad)) = input.input_event else {
+            return Ok(());
+        };
+        match gamepad.action() {
+            GamepadAction::AxisMotion => {
+                let value = if gamepad.value.abs() < DEAD_ZONE {
+                    0.0
+                } else {
+                    gamepad.value
+                };
+                match gamepad.axis {
+                    SDL_AXIS_LEFTX => self.stick.0 = value,
+                    SDL_AXIS_LEFTY => self.stick.1 = value,
+                    _ => {}
+                }
+            }
+            GamepadAction::Connected => log::info!("Gamepad {} connected", gamepad.which),
+            GamepadAction::Disconnected => {
+                log::info!("Gamepad {} disconnected", gamepad.which);
+                self.stick = (0.0, 0.0);
+            }
+            GamepadAction::ButtonPress | GamepadAction::ButtonRelease => {
+                log::trace!("Gamepad button {:?}: {}", gamepad.action(), gamepad.button);
+            }
+        }
+        Ok(())
+    }
+}
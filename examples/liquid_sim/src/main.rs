@@ -4,11 +4,11 @@ use libgsh::{
     async_trait::async_trait,
     server::{GshServer, GshService, GshServiceExt, ServerStream},
     shared::cert,
-    shared::frame::full_frame_segment,
+    shared::frame::compress_frame,
     shared::protocol::{
         client_message::ClientEvent,
         server_hello_ack::{
-            window_settings, Compression, FrameFormat, WindowSettings, ZstdCompression,
+            window_settings, Compression, FrameFormat, ServerInfo, WindowSettings, ZstdCompression,
         },
         user_input::{mouse_event::MouseAction, window_event::WindowAction, InputEvent},
         Frame, ServerHelloAck,
@@ -18,14 +18,21 @@ use libgsh::{
     Result, ServiceError,
 };
 use rayon::prelude::*;
+use std::ops::RangeInclusive;
 use std::time::Instant;
 
 const WINDOW_ID: u32 = 0;
 const INITIAL_WIDTH: usize = 512;
 const INITIAL_HEIGHT: usize = 512;
 const MAX_FPS: u32 = 60;
-const PIXEL_BYTES: usize = 4; // RGBA8
+const PIXEL_BYTES: usize = FrameFormat::Rgba.bytes_per_pixel();
 const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+/// Levels clients may prefer, high levels take too long to compress every frame
+const ZSTD_LEVELS: RangeInclusive<i32> = 1..=9;
+const COMPRESSION: Compression = Compression::Zstd(ZstdCompression {
+    level: ZSTD_COMPRESSION_LEVEL,
+    dictionary: Vec::new(),
+});
 
 // Particle data structure for the simulation
 #[derive(Copy, Clone, Debug)]
@@ -65,8 +72,6 @@ pub struct LiquidSimService {
     last_update: Instant,
     mouse_pos: Option<Vec2>,
     prev_mouse_pos: Option<Vec2>,
-    // Reusable output buffer for zstd compression to avoid per-frame allocations
-    compress_buf: Vec<u8>,
 }
 
 impl Default for LiquidSimService {
@@ -78,7 +83,6 @@ impl Default for LiquidSimService {
             last_update: Instant::now(),
             mouse_pos: None,
             prev_mouse_pos: None,
-            compress_buf: Vec::with_capacity(INITIAL_WIDTH * INITIAL_HEIGHT * PIXEL_BYTES),
         }
     }
 }
@@ -316,19 +320,13 @@ impl LiquidSimService {
     async fn send_frame(&mut self, stream: &mut ServerStream) -> Result<()> {
         let rgba_data = self.simulate_and_render();
 
-        // Compress the data with Zstd
-        use std::io::Write;
-        // Reuse the compression buffer across frames to reduce allocations.
-        let mut out_buf = std::mem::take(&mut self.compress_buf);
-        out_buf.clear();
-        let mut encoder = libgsh::zstd::stream::Encoder::new(out_buf, ZSTD_COMPRESSION_LEVEL)?;
-        encoder.write_all(&rgba_data)?;
-        let compressed = encoder.finish()?;
-        let compressed_len = compressed.len();
+        let compression = Compression::Zstd(ZstdCompression {
+            level: stream.zstd_level().unwrap_or(ZSTD_COMPRESSION_LEVEL),
+            dictionary: Vec::new(),
+        });
+        let segments = compress_frame(&rgba_data, self.width, self.height, Some(&compression))?;
+        let compressed_len: usize = segments.iter().map(|s| s.data.len()).sum();
         let uncompressed_len = rgba_data.len();
-        // Save compressed buffer for reuse (its capacity will be reused next frame).
-        self.compress_buf = compressed;
-
         log::debug!(
             "Frame: {}x{}, uncompressed: {} bytes, compressed: {} bytes ({:.1}% compression)",
             self.width,
@@ -341,9 +339,13 @@ impl LiquidSimService {
         stream
             .send(Frame {
                 window_id: WINDOW_ID,
-                segments: full_frame_segment(&self.compress_buf, self.width, self.height),
+                segments,
                 width: self.width as u32,
                 height: self.height as u32,
+                seq: None,
+                frame_group_id: None,
+                is_final: false,
+                is_keyframe: true,
             })
             .await?;
 
@@ -367,12 +369,14 @@ impl GshService for LiquidSimService {
         <Self as GshServiceExt>::main(self, stream).await
     }
 
+    fn zstd_levels(&self) -> Option<RangeInclusive<i32>> {
+        Some(ZSTD_LEVELS)
+    }
+
     fn server_hello(&self) -> ServerHelloAck {
         ServerHelloAck {
             format: FrameFormat::Rgba.into(),
-            compression: Some(Compression::Zstd(ZstdCompression {
-                level: ZSTD_COMPRESSION_LEVEL,
-            })),
+            compression: Some(COMPRESSION),
             windows: vec![WindowSettings {
                 window_id: WINDOW_ID,
                 monitor_id: None,
@@ -384,8 +388,24 @@ impl GshService for LiquidSimService {
                 allow_resize: true,
                 resize_frame: true,
                 frame_anchor: window_settings::WindowAnchor::Center.into(),
+                accepts_input: None,
+                grab_input: false,
+                relative_mouse: false,
+                format: None,
+                compression: None,
+                icon: None,
             }],
             auth_method: None,
+            server_info: Some(ServerInfo {
+                name: "Liquid Simulation".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                description: "Interactive particle liquid simulation".to_string(),
+            }),
+            transport_offers: vec![],
+            resumption_token: vec![],
+            protocol_version: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
         }
     }
 }
@@ -394,6 +414,11 @@ impl GshService for LiquidSimService {
 impl GshServiceExt for LiquidSimService {
     const MAX_FPS: u32 = MAX_FPS;
 
+    // No need to simulate while the window is minimized
+    fn should_tick(&self, stream: &ServerStream) -> bool {
+        !stream.all_windows_hidden()
+    }
+
     async fn on_startup(&mut self, stream: &mut ServerStream) -> Result<()> {
         log::info!("Starting liquid simulation...");
         self.send_frame(stream).await
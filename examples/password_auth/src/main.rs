@@ -45,6 +45,16 @@ impl GshService for AuthService {
             compression: None,
             windows: Vec::new(),
             auth_method: Some(server_hello_ack::AuthMethod::Password(())),
+            server_info: Some(server_hello_ack::ServerInfo {
+                name: "Password Auth".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                description: "Password authentication example".to_string(),
+            }),
+            transport_offers: vec![],
+            resumption_token: vec![],
+            protocol_version: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
         }
     }
 
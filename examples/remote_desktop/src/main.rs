@@ -1,22 +1,20 @@
 use libgsh::{
     async_trait::async_trait,
-    server::{GshServer, GshService, GshServiceExt, ServerStream},
+    server::{FrameChannel, FrameSender, GshServer, GshService, GshServiceExt, ServerStream},
     shared::cert,
-    shared::frame::full_frame_segment,
+    shared::frame::compress_frame,
     shared::protocol::{
         client_message,
-        server_hello_ack::{self, window_settings, FrameFormat, WindowSettings, ZstdCompression},
+        server_hello_ack::{
+            self, window_settings, FrameFormat, ServerInfo, WindowSettings, ZstdCompression,
+        },
         Frame, ServerHelloAck,
     },
     tokio,
     tokio_rustls::rustls::ServerConfig,
     ServiceError,
 };
-use std::{
-    io::Write,
-    sync::{mpsc::Receiver, Arc, Mutex},
-    time::Instant,
-};
+use std::{sync::mpsc::Receiver, time::Instant};
 use xcap::Monitor;
 
 #[derive(Debug, Clone)]
@@ -28,10 +26,16 @@ pub struct XCapFrame {
 
 const FRAME_FORMAT: FrameFormat = FrameFormat::Rgba;
 const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+const COMPRESSION: server_hello_ack::Compression =
+    server_hello_ack::Compression::Zstd(ZstdCompression {
+        level: ZSTD_COMPRESSION_LEVEL,
+        dictionary: Vec::new(),
+    });
 const WINDOW_ID: u32 = 0;
 const INITIAL_WIDTH: usize = 480;
 const INITIAL_HEIGHT: usize = 270;
 const MAX_FPS: u32 = 60;
+const FRAME_CHANNEL_CAPACITY: usize = 2;
 
 #[tokio::main]
 async fn main() {
@@ -64,24 +68,27 @@ async fn main() {
     // Unsafe transmute to convert video_stream Recorder to XCapFrame receiver
     // Ugly hack because `xcap::video_recorder::Frame` is not public.
     let video_stream: Receiver<XCapFrame> = unsafe { std::mem::transmute(video_stream) };
-    let recorder = Arc::new(Mutex::new(video_stream));
+
+    // Compress captured frames on a blocking thread and hand them over to the async service
+    let (sender, frames) = FrameChannel::new(FRAME_CHANNEL_CAPACITY);
+    std::thread::spawn(move || capture_frames(video_stream, sender));
 
     // Start service
-    let server = GshServer::new(RdpService::new(recorder), config);
+    let server = GshServer::new(RdpService::new(frames), config);
     server.serve().await.unwrap();
 }
 
 #[derive(Debug, Clone)]
 pub struct RdpService {
     last_frame: Instant,
-    recorder: Arc<Mutex<Receiver<XCapFrame>>>,
+    frames: FrameChannel,
 }
 
 impl RdpService {
-    fn new(recorder: Arc<Mutex<Receiver<XCapFrame>>>) -> Self {
+    fn new(frames: FrameChannel) -> Self {
         Self {
             last_frame: Instant::now(),
-            recorder,
+            frames,
         }
     }
 }
@@ -101,12 +108,26 @@ impl GshService for RdpService {
                 allow_resize: true,
                 resize_frame: false,
                 frame_anchor: window_settings::WindowAnchor::Center as i32,
+                accepts_input: None,
+                grab_input: false,
+                relative_mouse: false,
+                format: None,
+                compression: None,
+                icon: None,
             }],
             format: FRAME_FORMAT as i32,
-            compression: Some(server_hello_ack::Compression::Zstd(ZstdCompression {
-                level: ZSTD_COMPRESSION_LEVEL,
-            })),
+            compression: Some(COMPRESSION),
             auth_method: None,
+            server_info: Some(ServerInfo {
+                name: "Remote Desktop".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                description: "Stream the primary monitor".to_string(),
+            }),
+            transport_offers: vec![],
+            resumption_token: vec![],
+            protocol_version: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
         }
     }
 
@@ -128,61 +149,71 @@ impl GshServiceExt for RdpService {
 
     async fn on_tick(&mut self, stream: &mut ServerStream) -> libgsh::Result<()> {
         if self.last_frame.elapsed().as_secs_f32() >= 1.0 / MAX_FPS as f32 {
-            stream.send(self.get_frame()?).await?;
-            self.last_frame = std::time::Instant::now();
-            log::debug!("Sent frame");
+            // Frames are full and self-contained, so skipping stale ones is safe
+            for frame in self.frames.latest() {
+                stream.send(frame).await?;
+                self.last_frame = std::time::Instant::now();
+                log::debug!("Sent frame");
+            }
+            stream.flush().await?;
         }
         Ok(())
     }
 
     async fn on_startup(&mut self, stream: &mut ServerStream) -> libgsh::Result<()> {
-        stream.send(self.get_frame()?).await?;
+        let frame = self.frames.recv().await.ok_or_else(|| {
+            ServiceError::Error("Video stream closed before the first frame".to_string())
+        })?;
+        stream.send(frame).await?;
+        stream.flush().await?;
         log::debug!("Sent initial frame");
         Ok(())
     }
 }
 
-impl RdpService {
-    fn get_frame(&mut self) -> libgsh::Result<Frame> {
-        let frame = {
-            let video_stream = self.recorder.lock().unwrap();
-            video_stream.recv().map_err(|e| {
-                ServiceError::Error(format!("Failed to receive frame from video stream: {}", e))
-            })?
+/// Capture loop running on a blocking thread, compressing each captured image into a `Frame`.
+fn capture_frames(video_stream: Receiver<XCapFrame>, sender: FrameSender) {
+    while let Ok(frame) = video_stream.recv() {
+        let frame = match get_frame(frame) {
+            Ok(frame) => frame,
+            Err(err) => {
+                log::error!("Failed to encode captured frame: {}", err);
+                continue;
+            }
         };
-
-        log::debug!(
-            "Captured image of resolution {}x{} and size: {}",
-            frame.width,
-            frame.height,
-            frame.raw.len()
-        );
-        let compressed = self.compress(&frame.raw, frame.width as usize, frame.height as usize)?;
-        log::debug!(
-            "Compressed image size: {} (~{:.2}%)",
-            compressed.len(),
-            compressed.len() as f32 * 100f32 / frame.raw.len() as f32
-        );
-        Ok(Frame {
-            window_id: WINDOW_ID,
-            width: frame.width,
-            height: frame.height,
-            segments: full_frame_segment(
-                &compressed,
-                frame.width as usize,
-                frame.height as usize,
-                // &mut self.previous_frame,
-                // 4,
-            ),
-        })
+        if sender.send(frame).is_err() {
+            break;
+        }
     }
+    log::warn!("Video stream closed, stopping capture.");
+}
 
-    fn compress(&self, rgba_vec: &[u8], w: usize, h: usize) -> libgsh::Result<Vec<u8>> {
-        let mut encoder = libgsh::zstd::stream::Encoder::new(
-            Vec::with_capacity(w * h * 4),
-            ZSTD_COMPRESSION_LEVEL,
-        )?;
-        encoder.write_all(rgba_vec)?;
-        Ok(encoder.finish()?)
-    }
+fn get_frame(frame: XCapFrame) -> libgsh::Result<Frame> {
+    log::debug!(
+        "Captured image of resolution {}x{} and size: {}",
+        frame.width,
+        frame.height,
+        frame.raw.len()
+    );
+    let segments = compress_frame(
+        &frame.raw,
+        frame.width as usize,
+        frame.height as usize,
+        Some(&COMPRESSION),
+    )?;
+    log::debug!(
+        "Compressed image size: {} (~{:.2}%)",
+        segments[0].data.len(),
+        segments[0].data.len() as f32 * 100f32 / frame.raw.len() as f32
+    );
+    Ok(Frame {
+        window_id: WINDOW_ID,
+        width: frame.width,
+        height: frame.height,
+        segments,
+        seq: None,
+        frame_group_id: None,
+        is_final: false,
+        is_keyframe: true,
+    })
 }
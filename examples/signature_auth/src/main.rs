@@ -3,10 +3,10 @@ use libgsh::{
     rsa::RsaPublicKey,
     server::{GshServer, GshService, GshServiceExt, ServerStream},
     shared::{
-        auth::{AuthVerifier, SignatureVerifier},
+        auth::{AuthContext, AuthVerifier, SignatureVerifier},
         cert,
         protocol::{
-            server_hello_ack::{AuthMethod, FrameFormat, SignatureMethod},
+            server_hello_ack::{AuthMethod, FrameFormat, ServerInfo, SignatureMethod},
             ServerHelloAck,
         },
     },
@@ -28,7 +28,10 @@ async fn main() {
         .with_single_cert(vec![key.cert.der().clone()], private_key)
         .unwrap();
     let mut service = AuthService::default();
-    service.authorize_key(cert::extract_public_key(include_str!("../example.pem")).unwrap());
+    service.authorize_key(
+        "example",
+        cert::extract_public_key(include_str!("../example.pem")).unwrap(),
+    );
     let server = GshServer::new(service, config);
     server.serve().await.unwrap();
 }
@@ -36,12 +39,12 @@ async fn main() {
 #[derive(Debug, Clone, Default)]
 pub struct AuthService {
     // Any custom data you need for verification can be added here.
-    authorized_keys: Vec<RsaPublicKey>,
+    authorized_keys: Vec<(String, RsaPublicKey)>,
 }
 
 impl AuthService {
-    fn authorize_key(&mut self, key: RsaPublicKey) {
-        self.authorized_keys.push(key);
+    fn authorize_key(&mut self, user: &str, key: RsaPublicKey) {
+        self.authorized_keys.push((user.to_string(), key));
     }
 }
 
@@ -59,6 +62,16 @@ impl GshService for AuthService {
             compression: None,
             windows: Vec::new(),
             auth_method: Some(AuthMethod::Signature(SignatureMethod { sign_message })),
+            server_info: Some(ServerInfo {
+                name: "Signature Auth".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                description: "Signature authentication example".to_string(),
+            }),
+            transport_offers: vec![],
+            resumption_token: vec![],
+            protocol_version: 0,
+            max_frame_width: 0,
+            max_frame_height: 0,
         }
     }
     fn auth_verifier(&self) -> Option<AuthVerifier> {
@@ -68,22 +81,38 @@ impl GshService for AuthService {
     }
 }
 
-impl GshServiceExt for AuthService {}
+#[async_trait]
+impl GshServiceExt for AuthService {
+    async fn on_startup(&mut self, stream: &mut ServerStream) -> libgsh::Result<()> {
+        // The identity returned by the verifier is available for the whole connection
+        if let Some(context) = stream.auth_context() {
+            log::info!("User {} authenticated", context.user);
+        }
+        Ok(())
+    }
+}
 
 struct MySignatureVerifier {
     // Any custom data you need for verification can be added here.
-    authorized_keys: Vec<RsaPublicKey>,
+    authorized_keys: Vec<(String, RsaPublicKey)>,
 }
 
 impl MySignatureVerifier {
-    fn new(authorized_keys: Vec<RsaPublicKey>) -> Self {
+    fn new(authorized_keys: Vec<(String, RsaPublicKey)>) -> Self {
         Self { authorized_keys }
     }
 }
 
 impl SignatureVerifier for MySignatureVerifier {
     fn verify(&self, public_key: &RsaPublicKey) -> bool {
-        // Check if the public key is in the list of authorized keys.
-        self.authorized_keys.iter().any(|key| *key == *public_key)
+        self.identify(public_key).is_some()
+    }
+
+    fn identify(&self, public_key: &RsaPublicKey) -> Option<AuthContext> {
+        // Check if the public key is in the list of authorized keys, and map it to its user.
+        self.authorized_keys
+            .iter()
+            .find(|(_, key)| *key == *public_key)
+            .map(|(user, _)| AuthContext::user(user.clone()))
     }
 }
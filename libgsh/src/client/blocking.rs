@@ -0,0 +1,200 @@
+use super::{handshake, ClientStream};
+use crate::{
+    shared::{
+        auth::AuthProvider,
+        protocol::{
+            server_message::ServerEvent, status_update::StatusType, ClientMessage, Frame, FrameAck,
+            ServerHelloAck, StatusUpdate, UserInput,
+        },
+    },
+    Result,
+};
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use tokio::{net::TcpStream, runtime::Runtime};
+use tokio_rustls::{rustls::ClientConfig, TlsConnector};
+
+/// Headless, blocking client for scripts, bots and automated tests, without any windowing.\
+/// Runs the connection on its own single-threaded runtime, so it must not be used from within an async context.
+pub struct BlockingClient {
+    runtime: Runtime,
+    stream: ClientStream,
+    server_hello: ServerHelloAck,
+}
+
+impl BlockingClient {
+    /// Connects to a server and performs the handshake, authenticating with `auth_provider` if required.\
+    /// Server certificates are verified by the given TLS configuration, known hosts are not checked.
+    pub fn connect<A: AuthProvider>(
+        host: &str,
+        port: u16,
+        config: Arc<ClientConfig>,
+        auth_provider: A,
+    ) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let (stream, server_hello) = runtime.block_on(async {
+            let server_name = host
+                .to_string()
+                .try_into()
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+            let socket = TcpStream::connect((host, port)).await?;
+            let tls_stream = TlsConnector::from(config)
+                .connect(server_name, socket)
+                .await?;
+            let mut stream = ClientStream::new(tls_stream);
+            let server_hello =
+                handshake(&mut stream, vec![], auth_provider, host, None, None).await?;
+            Ok::<_, crate::ServiceError>((stream, server_hello))
+        })?;
+        Ok(Self {
+            runtime,
+            stream,
+            server_hello,
+        })
+    }
+
+    /// The `ServerHelloAck` received in the handshake, with the initial windows of the service.
+    pub fn server_hello(&self) -> &ServerHelloAck {
+        &self.server_hello
+    }
+
+    /// Sends a message to the server and flushes it.
+    pub fn send(&mut self, message: impl Into<ClientMessage>) -> Result<()> {
+        let message = message.into();
+        self.runtime.block_on(async {
+            self.stream.send(message).await?;
+            self.stream.flush().await
+        })?;
+        Ok(())
+    }
+
+    /// Sends user input, such as a key press or mouse click, to the server.
+    pub fn send_input(&mut self, input: UserInput) -> Result<()> {
+        self.send(input)
+    }
+
+    /// Blocks until the next server event.\
+    /// Answers pings from the server, and fails with `ConnectionAborted` when the server exits.
+    pub fn receive(&mut self) -> Result<ServerEvent> {
+        loop {
+            let event = match self.runtime.block_on(self.stream.receive()) {
+                Ok(event) => event,
+                // Reads time out to let async clients do other work, keep waiting
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            match event {
+                ServerEvent::StatusUpdate(status) if status.kind() == StatusType::Ping => {
+                    self.send(StatusUpdate::pong())?
+                }
+                ServerEvent::StatusUpdate(status) if status.kind() == StatusType::Exit => {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionAborted,
+                        status.message().unwrap_or("server exited").to_string(),
+                    )
+                    .into());
+                }
+                event => return Ok(event),
+            }
+        }
+    }
+
+    /// Blocks until the next frame, skipping other server events.\
+    /// Sequenced frames are acknowledged. Frames are returned as sent, tiles and frame groups are not reassembled.
+    pub fn recv_frame(&mut self) -> Result<Frame> {
+        loop {
+            if let ServerEvent::Frame(frame) = self.receive()? {
+                if let Some(seq) = frame.seq {
+                    self.send(FrameAck {
+                        window_id: frame.window_id,
+                        seq,
+                    })?;
+                }
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Closes the connection gracefully, see `ClientStream::close`.
+    pub fn close(mut self) -> Result<()> {
+        self.runtime.block_on(self.stream.close())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{serve_local, StaticAuth};
+    use crate::server::{GshService, GshServiceExt, ServerStream};
+    use crate::shared::protocol::{
+        client_message::ClientEvent,
+        user_input::{key_event::KeyAction, InputEvent, KeyEvent},
+    };
+    use async_trait::async_trait;
+
+    /// Answers every user input with a sequenced frame for the input's window.
+    #[derive(Clone)]
+    struct EchoService;
+
+    #[async_trait]
+    impl GshService for EchoService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for EchoService {
+        async fn on_event(&mut self, stream: &mut ServerStream, event: ClientEvent) -> Result<()> {
+            if let ClientEvent::UserInput(input) = event {
+                stream
+                    .send(Frame {
+                        window_id: input.window_id,
+                        seq: Some(7),
+                        ..Default::default()
+                    })
+                    .await?;
+                stream.flush().await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn input_is_answered_with_a_frame() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime.block_on(serve_local(EchoService)).unwrap();
+        let mut client = BlockingClient::connect(
+            "localhost",
+            server.addr.port(),
+            server.client_config.clone(),
+            StaticAuth::default(),
+        )
+        .unwrap();
+
+        client
+            .send_input(UserInput {
+                window_id: 3,
+                input_event: Some(InputEvent::KeyEvent(KeyEvent {
+                    action: KeyAction::Press as i32,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })
+            .unwrap();
+        let frame = client.recv_frame().unwrap();
+        assert_eq!((frame.window_id, frame.seq), (3, Some(7)));
+
+        client.close().unwrap();
+        runtime.block_on(server.stop()).unwrap();
+    }
+}
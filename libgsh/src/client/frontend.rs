@@ -0,0 +1,403 @@
+use super::ClientStream;
+use crate::{
+    shared::protocol::{
+        server_hello_ack::WindowSettings, server_message::ServerEvent, status_update::StatusType,
+        AudioChunk, ClientMessage, CursorUpdate, DestroyWindow, DrawList, FileChunk, FileOffer,
+        FileTransferEnd, Frame, FrameAck, NetworkStats, RequestKeyframe, SetWindowPosition,
+        StatusUpdate, WarpCursor, WindowCommand,
+    },
+    Result,
+};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+/// Interval between network condition reports, each measuring the round-trip time with a ping.
+const NETWORK_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Error type of frontend callbacks, so backends can use their own error types.
+pub type FrontendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Receives the output of a service, implemented by the rendering backend of a client.\
+/// Protocol bookkeeping (keepalive, frame acknowledgments, network reports) is handled by `run`,
+/// so implementations only present what they are given.
+pub trait FrameSink {
+    /// Apply a frame to its window.
+    fn frame(&mut self, frame: Frame) -> std::result::Result<(), FrontendError>;
+
+    /// Render a list of vector drawing commands to its window.
+    fn draw_list(&mut self, list: DrawList) -> std::result::Result<(), FrontendError>;
+
+    /// Play a chunk of audio. Ignored by default.
+    fn audio(&mut self, _chunk: AudioChunk) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Move a window as requested by the service. Ignored by default.
+    fn set_window_position(
+        &mut self,
+        _position: SetWindowPosition,
+    ) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Open a new window requested by the service after the handshake. Ignored by default.
+    fn open_window(&mut self, _settings: WindowSettings) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Close a window as requested by the service. Ignored by default.
+    fn close_window(&mut self, _window: DestroyWindow) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Change the state of a window as requested by the service. Ignored by default.
+    fn window_command(
+        &mut self,
+        _command: WindowCommand,
+    ) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Change the cursor shape or visibility over a window. Ignored by default.
+    fn cursor(&mut self, _cursor: CursorUpdate) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Move the mouse pointer within a window. Ignored by default.
+    fn warp_cursor(&mut self, _warp: WarpCursor) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Start receiving a file offered by the service. Ignored by default.
+    fn file_offer(&mut self, _offer: FileOffer) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Receive a chunk of an offered file. Ignored by default.
+    fn file_chunk(&mut self, _chunk: FileChunk) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Complete an offered file after all of its chunks. Ignored by default.
+    fn file_end(&mut self, _end: FileTransferEnd) -> std::result::Result<(), FrontendError> {
+        Ok(())
+    }
+
+    /// Show an informational, warning or error status update. Logged by default.
+    fn status(&mut self, status: StatusUpdate) -> std::result::Result<(), FrontendError> {
+        log::info!("Server status: {:?}", status);
+        Ok(())
+    }
+
+    /// Number of received frames not yet presented, reported to the service to adapt its frame rate.
+    fn buffered_frames(&self) -> u32 {
+        0
+    }
+
+    /// Called when a gap in the sequence numbers of a window's frames shows `count` frames were lost,
+    /// e.g. to report them. They are also sent to the service in the next network report.
+    fn frames_dropped(&mut self, _window_id: u32, _count: u64) {}
+
+    /// Called for every message received from the server before it is dispatched, e.g. for tracing.
+    fn received(&mut self, _event: &ServerEvent) {}
+}
+
+/// Produces the user input of a client, implemented by the input backend of a client.
+pub trait InputSource {
+    /// Poll pending user input, pushing messages for the server to `outbox`.\
+    /// Returns `false` once the user closed the client.
+    fn poll_input(
+        &mut self,
+        outbox: &mut Vec<ClientMessage>,
+    ) -> std::result::Result<bool, FrontendError>;
+
+    /// Called for every message sent to the server, e.g. for tracing.
+    fn sent(&mut self, _message: &ClientMessage) {}
+}
+
+/// Why the message pump of `run` stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disconnect {
+    /// The server ended the session gracefully.
+    Server,
+    /// The user closed the client.
+    User,
+    /// The connection dropped, the session may be resumed.
+    ConnectionLost,
+}
+
+/// Runs the message pump of a client session until it ends.\
+/// Each iteration dispatches a message from the server to the `frontend`, polls its input
+/// and sends queued messages, paced to at most one iteration per `frame_time`.
+/// Keepalive pings are answered, sequenced frames acknowledged once applied,
+/// and network conditions reported to the service periodically.
+/// A keyframe is requested when a gap in the sequence numbers of a window's frames shows a frame was lost,
+/// and the lost frames are reported to the `frontend` with `FrameSink::frames_dropped`.
+pub async fn run<F>(
+    stream: &mut ClientStream,
+    frontend: &mut F,
+    frame_time: Duration,
+) -> Result<Disconnect>
+where
+    F: FrameSink + InputSource,
+{
+    let mut outbox = Vec::new();
+    let mut last_iteration = Instant::now();
+    let mut last_network_stats = Instant::now();
+    let mut ping_sent: Option<Instant> = None;
+    let mut last_seq: HashMap<u32, u64> = HashMap::new();
+    // Frames lost since the last network report
+    let mut dropped_frames: u64 = 0;
+    loop {
+        // Read messages from the server
+        match stream.receive().await {
+            Ok(event) => {
+                frontend.received(&event);
+                match event {
+                    ServerEvent::StatusUpdate(status) => match status.kind() {
+                        StatusType::Exit => {
+                            log::trace!("Server gracefully disconnected!");
+                            return Ok(Disconnect::Server);
+                        }
+                        StatusType::Ping => outbox.push(StatusUpdate::pong().into()),
+                        StatusType::Pong => {
+                            log::trace!("Server responded to ping");
+                            if let Some(sent) = ping_sent.take() {
+                                last_network_stats = Instant::now();
+                                outbox.push(
+                                    NetworkStats {
+                                        rtt_ms: sent.elapsed().as_millis() as u32,
+                                        dropped_frames: std::mem::take(&mut dropped_frames)
+                                            .try_into()
+                                            .unwrap_or(u32::MAX),
+                                        buffer_depth: frontend.buffered_frames(),
+                                    }
+                                    .into(),
+                                );
+                            }
+                        }
+                        _ => frontend.status(status)?,
+                    },
+                    ServerEvent::Frame(frame) => {
+                        if let Some(seq) = frame.seq {
+                            match last_seq.insert(frame.window_id, seq) {
+                                Some(last) if seq > last + 1 => {
+                                    let missed = seq - last - 1;
+                                    log::warn!(
+                                        "{} frame(s) lost for window {} (seq {} after {})",
+                                        missed,
+                                        frame.window_id,
+                                        seq,
+                                        last
+                                    );
+                                    dropped_frames += missed;
+                                    frontend.frames_dropped(frame.window_id, missed);
+                                    if !frame.is_keyframe {
+                                        outbox.push(
+                                            RequestKeyframe {
+                                                window_id: frame.window_id,
+                                            }
+                                            .into(),
+                                        );
+                                    }
+                                }
+                                // E.g. a resumed session or a restarted service
+                                Some(last) if seq <= last => log::debug!(
+                                    "Frame sequence of window {} restarted at {} after {}",
+                                    frame.window_id,
+                                    seq,
+                                    last
+                                ),
+                                _ => {}
+                            }
+                        }
+                        // Acknowledge sequenced frames once fully applied, so the service can diff against them
+                        let ack = frame.seq.map(|seq| FrameAck {
+                            window_id: frame.window_id,
+                            seq,
+                        });
+                        frontend.frame(frame)?;
+                        if let Some(ack) = ack {
+                            outbox.push(ack.into());
+                        }
+                    }
+                    ServerEvent::DrawList(list) => frontend.draw_list(list)?,
+                    ServerEvent::Audio(chunk) => frontend.audio(chunk)?,
+                    ServerEvent::Cursor(cursor) => frontend.cursor(cursor)?,
+                    ServerEvent::WarpCursor(warp) => frontend.warp_cursor(warp)?,
+                    ServerEvent::WindowCommand(command) => frontend.window_command(command)?,
+                    ServerEvent::CreateWindow(settings) => frontend.open_window(settings)?,
+                    ServerEvent::DestroyWindow(window) => frontend.close_window(window)?,
+                    ServerEvent::FileOffer(offer) => frontend.file_offer(offer)?,
+                    ServerEvent::FileChunk(chunk) => frontend.file_chunk(chunk)?,
+                    ServerEvent::FileTransferEnd(end) => frontend.file_end(end)?,
+                    ServerEvent::SetWindowPosition(position) => {
+                        frontend.set_window_position(position)?
+                    }
+                    other => log::warn!("Unexpected server event, skipping: {:?}", other),
+                }
+            }
+            Err(err) => match err.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => (), // No data available yet, do nothing
+                ErrorKind::UnexpectedEof
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::NotConnected => {
+                    log::trace!("Server disconnected!");
+                    return Ok(Disconnect::ConnectionLost);
+                }
+                _ => return Err(err.into()),
+            },
+        }
+
+        // User input, sent along with the protocol responses queued above
+        let keep_going = frontend.poll_input(&mut outbox)?;
+
+        // Measure the round-trip time for the next network condition report
+        if keep_going
+            && ping_sent.is_none()
+            && last_network_stats.elapsed() >= NETWORK_STATS_INTERVAL
+        {
+            outbox.push(StatusUpdate::ping().into());
+            ping_sent = Some(Instant::now());
+        }
+
+        for message in outbox.drain(..) {
+            frontend.sent(&message);
+            stream.send(message).await?;
+        }
+        if !keep_going {
+            return Ok(Disconnect::User);
+        }
+
+        // Sleep to maintain frame rate
+        let elapsed = last_iteration.elapsed();
+        if elapsed < frame_time {
+            tokio::time::sleep(frame_time - elapsed).await;
+        } else {
+            log::trace!(
+                "Frame time exceeded: {:.2?} (max: {:.2?})",
+                elapsed,
+                frame_time
+            );
+        }
+        last_iteration = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{serve_local, StaticAuth};
+    use crate::server::{GshService, ServerStream};
+    use crate::shared::protocol::{client_message::ClientEvent, ServerHelloAck};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    /// Sends frames with the given window IDs and sequence numbers, then ends the session,
+    /// recording the windows keyframes are requested for.
+    #[derive(Clone)]
+    struct SequenceService {
+        frames: Vec<(u32, u64)>,
+        keyframe_requests: Arc<Mutex<Vec<u32>>>,
+    }
+
+    #[async_trait]
+    impl GshService for SequenceService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, mut stream: ServerStream) -> Result<()> {
+            for &(window_id, seq) in &self.frames {
+                stream
+                    .send(Frame {
+                        window_id,
+                        seq: Some(seq),
+                        ..Default::default()
+                    })
+                    .await?;
+            }
+            stream.send(StatusUpdate::exit()).await?;
+            stream.flush().await?;
+            loop {
+                match stream.receive().await {
+                    Ok(ClientEvent::RequestKeyframe(request)) => {
+                        self.keyframe_requests
+                            .lock()
+                            .unwrap()
+                            .push(request.window_id);
+                    }
+                    Ok(ClientEvent::StatusUpdate(status)) if status.kind() == StatusType::Exit => {
+                        return Ok(())
+                    }
+                    Ok(_) => {}
+                    Err(err) if err.kind() == ErrorKind::TimedOut => {}
+                    Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+    }
+
+    /// Records the frames applied and lost, without any input.
+    #[derive(Default)]
+    struct RecordingFrontend {
+        frames: Vec<(u32, Option<u64>)>,
+        dropped: Vec<(u32, u64)>,
+    }
+
+    impl FrameSink for RecordingFrontend {
+        fn frame(&mut self, frame: Frame) -> std::result::Result<(), FrontendError> {
+            self.frames.push((frame.window_id, frame.seq));
+            Ok(())
+        }
+
+        fn draw_list(&mut self, _list: DrawList) -> std::result::Result<(), FrontendError> {
+            Ok(())
+        }
+
+        fn frames_dropped(&mut self, window_id: u32, count: u64) {
+            self.dropped.push((window_id, count));
+        }
+    }
+
+    impl InputSource for RecordingFrontend {
+        fn poll_input(
+            &mut self,
+            _outbox: &mut Vec<ClientMessage>,
+        ) -> std::result::Result<bool, FrontendError> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn sequence_gaps_are_counted_per_window() {
+        let frames = vec![(1, 0), (1, 1), (1, 4), (1, 2), (1, 3), (2, 0), (2, 2)];
+        let service = SequenceService {
+            frames: frames.clone(),
+            keyframe_requests: Arc::default(),
+        };
+        let server = serve_local(service.clone()).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        let mut frontend = RecordingFrontend::default();
+        let disconnect = run(&mut stream, &mut frontend, Duration::ZERO)
+            .await
+            .unwrap();
+        assert_eq!(disconnect, Disconnect::Server);
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+
+        // Every frame is applied, late frames after a gap included
+        let applied: Vec<(u32, Option<u64>)> = frames
+            .iter()
+            .map(|&(window_id, seq)| (window_id, Some(seq)))
+            .collect();
+        assert_eq!(frontend.frames, applied);
+        // Frames arriving late restart the sequence rather than counting as lost again
+        assert_eq!(frontend.dropped, vec![(1, 2), (2, 1)]);
+        assert_eq!(*service.keyframe_requests.lock().unwrap(), vec![1, 2]);
+    }
+}
@@ -1,33 +1,42 @@
 use super::ClientStream;
 use crate::shared::{
     auth::AuthProvider,
+    compression::check_dictionary_size,
+    negotiate_protocol_version,
     protocol::{
         self, client_auth,
         client_hello::MonitorInfo,
         server_auth_ack::AuthStatus,
-        server_hello_ack::{AuthMethod, SignatureMethod},
-        ServerHelloAck,
+        server_hello_ack::{AuthMethod, SignatureMethod, TotpMethod},
+        server_message::ServerEvent,
+        status_update::StatusType,
+        ServerHelloAck, StatusUpdate,
     },
-    HandshakeError, PROTOCOL_VERSION,
+    HandshakeError, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
 use rsa::pkcs1v15::Signature;
 use rsa::signature::SignatureEncoding;
 use rsa::{pkcs1::EncodeRsaPublicKey, RsaPublicKey};
+use std::io::ErrorKind;
 
 /// Handshake function for the **client side**.
 /// It sends a `ClientHello` message and waits for a `ServerHelloAck` response.
-/// If the server version is not compatible, it sends a `StatusUpdate` message and returns an error.
+/// If the server version is not compatible, it sends a `StatusUpdate` message and returns an error.\
+/// The server chooses the protocol version, see `ClientStream::protocol_version`.\
+/// The stream's `max_message_size` is announced to the server, which must not send larger messages.\
+/// The preferred `zstd_level`, if any, is announced too, the server's choice is in the returned compression.\
+/// A `resumption_token` from the `ServerHelloAck` of a previous connection asks the server to resume that session.
 pub async fn handshake<A>(
     stream: &mut ClientStream,
     monitors: Vec<MonitorInfo>,
     mut auth_provider: A,
     host: &str,
+    resumption_token: Option<Vec<u8>>,
+    zstd_level: Option<i32>,
 ) -> Result<ServerHelloAck, HandshakeError>
 where
     A: AuthProvider,
 {
-    use crate::shared::protocol::server_message::ServerEvent;
-
     let os = match std::env::consts::OS {
         "linux" => protocol::client_hello::Os::Linux,
         "windows" => protocol::client_hello::Os::Windows,
@@ -38,70 +47,245 @@ where
     stream
         .send(protocol::ClientHello {
             protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
             os,
             os_version,
             monitors,
+            max_message_size: stream.max_message_size().unwrap_or(0) as u64,
+            resumption_token: resumption_token.unwrap_or_default(),
+            zstd_level,
         })
         .await?;
-    let ServerEvent::ServerHelloAck(server_hello) = stream.receive().await? else {
+    let server_hello = match receive(stream).await? {
+        ServerEvent::ServerHelloAck(server_hello) => server_hello,
+        ServerEvent::StatusUpdate(status) => return Err(rejection(status)),
+        _ => {
+            return Err(HandshakeError::AnyError(
+                "Expected ServerHelloAck message".into(),
+            ))
+        }
+    };
+    // Servers predating version negotiation only accept their exact version
+    let chosen = match server_hello.protocol_version {
+        0 => PROTOCOL_VERSION,
+        version => version,
+    };
+    // The server announces the single version it chose, which must be one we support
+    let Some(protocol_version) =
+        negotiate_protocol_version(&(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION), chosen, chosen)
+    else {
         return Err(HandshakeError::AnyError(
-            "Expected ServerHelloAck message".into(),
+            format!(
+                "Unsupported server protocol version: {}. Supported versions: {}..={}",
+                chosen, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            )
+            .into(),
         ));
     };
+    stream.set_protocol_version(protocol_version);
+    check_dictionary_size(server_hello.compression.as_ref())?;
+    for window in &server_hello.windows {
+        check_dictionary_size(
+            window
+                .frame_compression(server_hello.compression.as_ref())
+                .as_ref(),
+        )?;
+    }
 
     // Send ClientAuth message if auth_method is set
-    if let Some(AuthMethod::Password(_)) = server_hello.auth_method {
-        stream
-            .send(protocol::ClientAuth {
-                auth_data: Some(client_auth::AuthData::Password(client_auth::Password {
-                    password: auth_provider.password(host),
-                })),
-            })
-            .await?;
-        // Wait for ServerAuthAck message
-        let ServerEvent::ServerAuthAck(server_auth_ack) = stream.receive().await? else {
-            return Err(HandshakeError::AnyError(
-                "Expected ServerAuthAck message".into(),
-            ));
-        };
-        if server_auth_ack.status != AuthStatus::Success as i32 {
-            return Err(HandshakeError::InvalidPassword);
+    match &server_hello.auth_method {
+        Some(AuthMethod::Password(_)) => {
+            authenticate_password(stream, &mut auth_provider, host).await?
+        }
+        Some(AuthMethod::Signature(SignatureMethod { sign_message })) => {
+            let signature = auth_provider
+                .signature(host, sign_message)
+                .ok_or(HandshakeError::SignatureRequired)?;
+            authenticate_signature(stream, &mut auth_provider, signature).await?
         }
-        auth_provider.password_success_cb();
-    } else if let Some(AuthMethod::Signature(SignatureMethod { sign_message })) =
-        &server_hello.auth_method
-    {
-        let (signature, public_key): (Signature, RsaPublicKey) = auth_provider
-            .signature(host, sign_message)
-            .ok_or(HandshakeError::SignatureRequired)?;
-        let public_key_pem = public_key.to_pkcs1_pem(rsa::pkcs8::LineEnding::LF)?;
-        let public_key_pem_bytes = public_key_pem.as_bytes().to_vec();
-        let signature_bytes = signature.to_bytes().to_vec();
-        stream
-            .send(protocol::ClientAuth {
-                auth_data: Some(client_auth::AuthData::Signature(client_auth::Signature {
-                    signature: signature_bytes,
-                    public_key: public_key_pem_bytes,
-                })),
-            })
-            .await?;
-        // Wait for ServerAuthAck message
-        let ServerEvent::ServerAuthAck(server_auth_ack) = stream.receive().await? else {
+        Some(AuthMethod::Totp(method)) => {
+            authenticate_totp(stream, &mut auth_provider, host, method).await?
+        }
+        Some(AuthMethod::Any(methods)) => {
+            // Prefer a key if the provider has one, as it doesn't prompt the user
+            let signature = methods
+                .signature
+                .as_ref()
+                .and_then(|method| auth_provider.signature(host, &method.sign_message));
+            if let Some(signature) = signature {
+                authenticate_signature(stream, &mut auth_provider, signature).await?
+            } else if methods.password.is_some() {
+                authenticate_password(stream, &mut auth_provider, host).await?
+            } else if let Some(method) = &methods.totp {
+                authenticate_totp(stream, &mut auth_provider, host, method).await?
+            } else if methods.signature.is_some() {
+                return Err(HandshakeError::SignatureRequired);
+            } else {
+                return Err(HandshakeError::AnyError(
+                    "No authentication method offered by the server".into(),
+                ));
+            }
+        }
+        None => log::debug!("No authentication method required by the server."),
+    }
+
+    Ok(server_hello)
+}
+
+/// Wait for the `ServerAuthAck` of a `ClientAuth` message, returning whether authentication succeeded.
+async fn auth_succeeded(stream: &mut ClientStream) -> Result<bool, HandshakeError> {
+    let server_auth_ack = match receive(stream).await? {
+        ServerEvent::ServerAuthAck(server_auth_ack) => server_auth_ack,
+        ServerEvent::StatusUpdate(status) => return Err(rejection(status)),
+        _ => {
             return Err(HandshakeError::AnyError(
                 "Expected ServerAuthAck message".into(),
-            ));
-        };
-        if server_auth_ack.status != AuthStatus::Success as i32 {
-            return Err(HandshakeError::SignatureInvalid);
+            ))
         }
-        auth_provider.signature_success_cb();
-    } else if server_hello.auth_method.is_none() {
-        log::debug!("No authentication method required by the server.");
-    } else {
-        return Err(HandshakeError::AnyError(
-            "Unsupported authentication method".into(),
-        ));
+    };
+    if server_auth_ack.status != AuthStatus::Success as i32 {
+        log::error!(
+            "Server rejected authentication: {}",
+            server_auth_ack.message
+        );
+        return Ok(false);
     }
+    Ok(true)
+}
 
-    Ok(server_hello)
+/// Receives the next handshake message from the server.\
+/// Reads time out quickly, but the server may take a while, e.g. to verify a password, and bounds the handshake itself.
+async fn receive(stream: &mut ClientStream) -> std::io::Result<ServerEvent> {
+    loop {
+        match stream.receive().await {
+            Err(err) if err.kind() == ErrorKind::TimedOut => {}
+            result => return result,
+        }
+    }
+}
+
+/// The error for a status update received instead of the expected handshake message.
+fn rejection(status: StatusUpdate) -> HandshakeError {
+    let message = status
+        .message()
+        .unwrap_or("connection closed by the server")
+        .to_string();
+    match status.kind() {
+        StatusType::Info | StatusType::Warning | StatusType::Ping | StatusType::Pong => {
+            HandshakeError::AnyError(format!("Unexpected status update: {}", message).into())
+        }
+        // Servers predating handshake rejections send plain errors and exits
+        StatusType::Error | StatusType::Exit => {
+            HandshakeError::Rejected(StatusType::Error, message)
+        }
+        kind => HandshakeError::Rejected(kind, message),
+    }
+}
+
+async fn authenticate_password<A: AuthProvider>(
+    stream: &mut ClientStream,
+    auth_provider: &mut A,
+    host: &str,
+) -> Result<(), HandshakeError> {
+    stream
+        .send(protocol::ClientAuth {
+            auth_data: Some(client_auth::AuthData::Password(client_auth::Password {
+                password: auth_provider.password(host),
+            })),
+        })
+        .await?;
+    if !auth_succeeded(stream).await? {
+        return Err(HandshakeError::InvalidPassword);
+    }
+    auth_provider.password_success_cb();
+    Ok(())
+}
+
+async fn authenticate_signature<A: AuthProvider>(
+    stream: &mut ClientStream,
+    auth_provider: &mut A,
+    (signature, public_key): (Signature, RsaPublicKey),
+) -> Result<(), HandshakeError> {
+    let public_key_pem = public_key.to_pkcs1_pem(rsa::pkcs8::LineEnding::LF)?;
+    let public_key_pem_bytes = public_key_pem.as_bytes().to_vec();
+    let signature_bytes = signature.to_bytes().to_vec();
+    stream
+        .send(protocol::ClientAuth {
+            auth_data: Some(client_auth::AuthData::Signature(client_auth::Signature {
+                signature: signature_bytes,
+                public_key: public_key_pem_bytes,
+            })),
+        })
+        .await?;
+    if !auth_succeeded(stream).await? {
+        return Err(HandshakeError::SignatureInvalid);
+    }
+    auth_provider.signature_success_cb();
+    Ok(())
+}
+
+async fn authenticate_totp<A: AuthProvider>(
+    stream: &mut ClientStream,
+    auth_provider: &mut A,
+    host: &str,
+    method: &TotpMethod,
+) -> Result<(), HandshakeError> {
+    let code = auth_provider
+        .totp(host, method)
+        .ok_or(HandshakeError::TotpRequired)?;
+    stream
+        .send(protocol::ClientAuth {
+            auth_data: Some(client_auth::AuthData::Totp(client_auth::Totp { code })),
+        })
+        .await?;
+    if !auth_succeeded(stream).await? {
+        return Err(HandshakeError::InvalidTotp);
+    }
+    auth_provider.totp_success_cb();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        testing::{serve_local, wait_for_exit, StaticAuth},
+        GshService, ServerStream,
+    };
+    use crate::shared::protocol::server_hello_ack::ServerInfo;
+    use async_trait::async_trait;
+
+    #[derive(Clone)]
+    struct InfoService;
+
+    fn server_info() -> ServerInfo {
+        ServerInfo {
+            name: "Info".to_string(),
+            version: "1.2.3".to_string(),
+            description: "Announces its identity".to_string(),
+        }
+    }
+
+    #[async_trait]
+    impl GshService for InfoService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                server_info: Some(server_info()),
+                ..Default::default()
+            }
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn server_info_survives_the_handshake() {
+        let server = serve_local(InfoService).await.unwrap();
+        let (mut stream, server_hello) = server.handshake(StaticAuth::default()).await.unwrap();
+        assert_eq!(server_hello.server_info, Some(server_info()));
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+    }
 }
@@ -1,11 +1,19 @@
-use crate::shared::codec::GshCodec;
-use crate::shared::protocol::{server_message::ServerEvent, ClientMessage, ServerMessage};
+use crate::shared::codec::{GshCodec, CLOSE_TIMEOUT};
+use crate::shared::protocol::{
+    server_message::ServerEvent, ClientMessage, ServerMessage, StatusUpdate,
+};
 use prost::Message;
 use std::io::Result;
+use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream;
 
+pub mod blocking;
+pub mod frontend;
+
 mod handshake;
+pub use blocking::BlockingClient;
+pub use frontend::{run, Disconnect, FrameSink, FrontendError, InputSource};
 pub use handshake::handshake;
 
 /// Asynchronous message codec for the client `TlsStream` over a `TcpStream`.\
@@ -16,9 +24,118 @@ impl ClientStream {
         self.write_internal(message.into()).await
     }
 
+    /// Closes the connection gracefully.\
+    /// Sends an exit status update and a TLS `close_notify`, then waits up to `CLOSE_TIMEOUT`
+    /// for the server to close its side, so neither side sees an abrupt end of the stream.
+    pub async fn close(&mut self) -> Result<()> {
+        self.send(StatusUpdate::exit()).await?;
+        self.flush().await?;
+        // Shutting down the TLS stream writes the close_notify before closing the socket for writing
+        self.get_inner().shutdown().await?;
+        self.wait_for_peer_close(CLOSE_TIMEOUT).await;
+        Ok(())
+    }
+
+    /// Receives the next server event.\
+    /// Messages with an event unknown to this version of the protocol, e.g. from a newer server, are skipped.
     pub async fn receive(&mut self) -> Result<ServerEvent> {
-        Ok(ServerMessage::decode(self.read_internal().await?)?
-            .server_event
-            .expect("ServerEvent is required"))
+        loop {
+            match ServerMessage::decode(self.read_internal().await?)?.server_event {
+                Some(event) => return Ok(event),
+                None => log::warn!("Skipping server message with an unknown event"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{next_event, serve_local, wait_for_exit, StaticAuth};
+    use crate::server::{GshService, ServerStream};
+    use crate::shared::protocol::{
+        server_hello_ack::WindowSettings, AudioChunk, CursorUpdate, DestroyWindow, DrawList,
+        FileChunk, FileOffer, FileTransferEnd, Frame, ServerAuthAck, ServerHelloAck,
+        SetWindowPosition, WarpCursor, WindowCommand,
+    };
+    use async_trait::async_trait;
+
+    /// Sends every event once, then waits for the client to exit.
+    #[derive(Clone)]
+    struct EventService(Vec<ServerEvent>);
+
+    #[async_trait]
+    impl GshService for EventService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            for event in self.0 {
+                stream
+                    .send(ServerMessage {
+                        server_event: Some(event),
+                    })
+                    .await?;
+            }
+            stream.flush().await?;
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn every_server_event_round_trips() {
+        let events = vec![
+            ServerEvent::ServerHelloAck(ServerHelloAck::default()),
+            ServerEvent::ServerAuthAck(ServerAuthAck::default()),
+            ServerEvent::StatusUpdate(StatusUpdate::ping()),
+            ServerEvent::Frame(Frame {
+                window_id: 1,
+                width: 2,
+                height: 2,
+                ..Default::default()
+            }),
+            ServerEvent::SetWindowPosition(SetWindowPosition {
+                window_id: 1,
+                x: -10,
+                y: 20,
+            }),
+            ServerEvent::DrawList(DrawList::default()),
+            ServerEvent::Audio(AudioChunk::default()),
+            ServerEvent::Cursor(CursorUpdate {
+                window_id: 1,
+                visible: true,
+                ..Default::default()
+            }),
+            ServerEvent::WindowCommand(WindowCommand::default()),
+            ServerEvent::CreateWindow(WindowSettings {
+                window_id: 2,
+                ..Default::default()
+            }),
+            ServerEvent::DestroyWindow(DestroyWindow { window_id: 2 }),
+            ServerEvent::FileOffer(FileOffer {
+                transfer_id: 1,
+                name: "notes.txt".to_string(),
+                size: 3,
+            }),
+            ServerEvent::FileChunk(FileChunk {
+                transfer_id: 1,
+                offset: 0,
+                data: b"abc".to_vec(),
+            }),
+            ServerEvent::FileTransferEnd(FileTransferEnd { transfer_id: 1 }),
+            ServerEvent::WarpCursor(WarpCursor {
+                window_id: 1,
+                x: 5,
+                y: 6,
+            }),
+        ];
+        let server = serve_local(EventService(events.clone())).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        for event in events {
+            assert_eq!(next_event(&mut stream).await.unwrap(), event);
+        }
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
     }
 }
@@ -8,9 +8,11 @@
 // compile_error!("Features 'client' and 'server' cannot be enabled at the same time.");
 
 pub use async_trait;
+pub use lz4;
 pub use rcgen;
 pub use rsa;
 pub use sha2;
+pub use thread_priority;
 pub use tokio;
 pub use tokio_rustls::{self, rustls::ServerConfig};
 pub use zstd;
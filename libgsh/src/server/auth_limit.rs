@@ -0,0 +1,191 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Failed authentication attempts allowed per address before it is banned.
+pub const DEFAULT_MAX_FAILURES: u32 = 5;
+/// Window in which failures are counted, and the length of the first ban.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+/// Upper bound for the exponential growth of bans, as a power of two of the window.
+const MAX_BAN_DOUBLINGS: u32 = 6;
+
+/// Limits failed authentication attempts per client IP address, shared by all connections of a server.\
+/// After `max_failures` failures within `window`, the address is banned for `window`.
+/// Every further failure right after a ban doubles its length, up to 64 times the window.
+/// Banned clients are rejected right after the TLS handshake, before any credentials are checked.
+#[derive(Debug, Clone)]
+pub struct AuthRateLimit {
+    max_failures: u32,
+    window: Duration,
+    attempts: Arc<Mutex<HashMap<IpAddr, Attempts>>>,
+}
+
+#[derive(Debug)]
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl Attempts {
+    /// Whether the failures are old enough to be forgotten.
+    fn expired(&self, now: Instant, window: Duration) -> bool {
+        self.banned_until.unwrap_or(self.last_failure) + window <= now
+    }
+}
+
+impl Default for AuthRateLimit {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FAILURES, DEFAULT_WINDOW)
+    }
+}
+
+impl AuthRateLimit {
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            window,
+            attempts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the remaining ban duration if the address is currently banned.
+    pub fn banned_for(&self, ip: IpAddr) -> Option<Duration> {
+        let attempts = self.attempts.lock().unwrap();
+        let banned_until = attempts.get(&ip)?.banned_until?;
+        banned_until.checked_duration_since(Instant::now())
+    }
+
+    /// Records a failed authentication attempt, banning the address once it exceeds the limit.
+    pub fn record_failure(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, a| !a.expired(now, self.window));
+        let entry = attempts.entry(ip).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            banned_until: None,
+        });
+        entry.failures += 1;
+        entry.last_failure = now;
+        if entry.failures >= self.max_failures {
+            let doublings = (entry.failures - self.max_failures).min(MAX_BAN_DOUBLINGS);
+            let ban = self.window * 2u32.pow(doublings);
+            entry.banned_until = Some(now + ban);
+            log::warn!(
+                "Banned {} for {}s after {} failed authentication attempts",
+                ip,
+                ban.as_secs(),
+                entry.failures
+            );
+        }
+    }
+
+    /// Forgets the failures of an address after it authenticated successfully.
+    pub fn record_success(&self, ip: IpAddr) {
+        self.attempts.lock().unwrap().remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{serve_local_with, wait_for_exit, StaticAuth};
+    use crate::server::{GshService, ServerStream};
+    use crate::shared::{
+        auth::{AuthVerifier, PasswordVerifier},
+        protocol::{server_hello_ack::AuthMethod, status_update::StatusType, ServerHelloAck},
+        HandshakeError,
+    };
+    use crate::{Result, ServiceError};
+    use async_trait::async_trait;
+
+    const PASSWORD: &str = "secret";
+
+    struct FixedPassword;
+
+    impl PasswordVerifier for FixedPassword {
+        fn verify(&self, password: &str) -> bool {
+            password == PASSWORD
+        }
+    }
+
+    #[derive(Clone)]
+    struct PasswordService;
+
+    #[async_trait]
+    impl GshService for PasswordService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                auth_method: Some(AuthMethod::Password(())),
+                ..Default::default()
+            }
+        }
+
+        fn auth_verifier(&self) -> Option<AuthVerifier> {
+            Some(AuthVerifier::Password(Box::new(FixedPassword)))
+        }
+
+        async fn main(self, mut stream: ServerStream) -> Result<()> {
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[test]
+    fn bans_grow_after_repeated_failures() {
+        let limit = AuthRateLimit::new(3, DEFAULT_WINDOW);
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let other = IpAddr::from([192, 0, 2, 2]);
+        limit.record_failure(ip);
+        limit.record_failure(ip);
+        assert_eq!(limit.banned_for(ip), None);
+        limit.record_failure(ip);
+        let ban = limit.banned_for(ip).unwrap();
+        assert!(ban > DEFAULT_WINDOW / 2 && ban <= DEFAULT_WINDOW);
+        assert_eq!(limit.banned_for(other), None);
+        limit.record_failure(ip);
+        assert!(limit.banned_for(ip).unwrap() > DEFAULT_WINDOW);
+        limit.record_success(ip);
+        assert_eq!(limit.banned_for(ip), None);
+    }
+
+    #[tokio::test]
+    async fn repeated_failures_are_rejected_before_authenticating() {
+        let server = serve_local_with(PasswordService, |server| {
+            server.with_auth_rate_limit(2, DEFAULT_WINDOW)
+        })
+        .await
+        .unwrap();
+        for _ in 0..2 {
+            let err = server
+                .handshake(StaticAuth("wrong".to_string()))
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    ServiceError::HandshakeError(HandshakeError::InvalidPassword)
+                ),
+                "{:?}",
+                err
+            );
+        }
+        // Even the right password is rejected while banned
+        let err = server
+            .handshake(StaticAuth(PASSWORD.to_string()))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ServiceError::HandshakeError(HandshakeError::Rejected(StatusType::AuthFailed, _))
+            ),
+            "{:?}",
+            err
+        );
+        server.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use super::ServerStream;
+use crate::shared::protocol::ServerMessage;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::sync::{broadcast, Mutex, MutexGuard};
+
+/// State shared between all client connections of a service, with a hub for broadcasting messages to every client.\
+/// Each connection gets its own clone of the service, so collaborative services (whiteboards, multiplayer games)
+/// store a `Broadcast` in the service instead of owning the state directly. All clones share the same state.
+///
+/// # Example
+/// ```ignore
+/// // In `GshServiceExt::on_startup`, register the connection:
+/// self.receiver = Some(self.board.subscribe());
+/// // In `GshServiceExt::on_event`, update the shared state and broadcast the result:
+/// let frame = self.board.lock().await.draw(input);
+/// self.board.broadcast(frame);
+/// // In `GshServiceExt::on_tick`, forward broadcasts to this client:
+/// if let Some(receiver) = &mut self.receiver {
+///     receiver.forward(stream).await?;
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Broadcast<S> {
+    state: Arc<Mutex<S>>,
+    tx: broadcast::Sender<ServerMessage>,
+    clients: Arc<AtomicUsize>,
+}
+
+impl<S> Clone for Broadcast<S> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            tx: self.tx.clone(),
+            clients: self.clients.clone(),
+        }
+    }
+}
+
+impl<S> Broadcast<S> {
+    /// Creates a new hub around the shared `state`, buffering up to `capacity` messages per client.\
+    /// Clients falling further behind skip the oldest messages.
+    pub fn new(state: S, capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            tx,
+            clients: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Locks the shared state for reading or modification.
+    pub async fn lock(&self) -> MutexGuard<'_, S> {
+        self.state.lock().await
+    }
+
+    /// Sends a message to every subscribed client.\
+    /// Returns the number of clients the message was queued for.
+    pub fn broadcast(&self, message: impl Into<ServerMessage>) -> usize {
+        self.tx.send(message.into()).unwrap_or(0)
+    }
+
+    /// Registers a client connection to receive broadcasts.\
+    /// The client is unregistered when the returned receiver is dropped.
+    pub fn subscribe(&self) -> BroadcastReceiver {
+        self.clients.fetch_add(1, Ordering::Relaxed);
+        BroadcastReceiver {
+            rx: self.tx.subscribe(),
+            clients: self.clients.clone(),
+        }
+    }
+
+    /// Number of currently subscribed clients.
+    pub fn clients(&self) -> usize {
+        self.clients.load(Ordering::Relaxed)
+    }
+}
+
+/// Receiving half of a `Broadcast` for a single client connection.
+#[derive(Debug)]
+pub struct BroadcastReceiver {
+    rx: broadcast::Receiver<ServerMessage>,
+    clients: Arc<AtomicUsize>,
+}
+
+impl BroadcastReceiver {
+    /// Sends all pending broadcast messages to the client and flushes the stream.\
+    /// Returns the number of messages forwarded.
+    pub async fn forward(&mut self, stream: &mut ServerStream) -> std::io::Result<usize> {
+        let mut forwarded = 0;
+        loop {
+            match self.rx.try_recv() {
+                Ok(message) => {
+                    stream.send(message).await?;
+                    forwarded += 1;
+                }
+                Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                    log::warn!("Client lagged behind, skipped {} broadcasts", skipped);
+                }
+                Err(_) => break,
+            }
+        }
+        if forwarded > 0 {
+            stream.flush().await?;
+        }
+        Ok(forwarded)
+    }
+}
+
+impl Clone for BroadcastReceiver {
+    fn clone(&self) -> Self {
+        self.clients.fetch_add(1, Ordering::Relaxed);
+        Self {
+            rx: self.rx.resubscribe(),
+            clients: self.clients.clone(),
+        }
+    }
+}
+
+impl Drop for BroadcastReceiver {
+    fn drop(&mut self) {
+        self.clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{next_event, serve_local, StaticAuth};
+    use crate::server::{GshService, GshServiceExt};
+    use crate::shared::protocol::{
+        client_message::ClientEvent, server_message::ServerEvent, Frame, ServerHelloAck, UserInput,
+    };
+    use crate::Result;
+    use async_trait::async_trait;
+    use std::time::Duration;
+
+    /// Counts user input from all clients and broadcasts the count as the sequence number of a frame.
+    #[derive(Clone)]
+    struct CounterService {
+        counter: Broadcast<u64>,
+        receiver: Option<BroadcastReceiver>,
+    }
+
+    #[async_trait]
+    impl GshService for CounterService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for CounterService {
+        async fn on_startup(&mut self, _stream: &mut ServerStream) -> Result<()> {
+            self.receiver = Some(self.counter.subscribe());
+            Ok(())
+        }
+
+        async fn on_event(&mut self, _stream: &mut ServerStream, event: ClientEvent) -> Result<()> {
+            if let ClientEvent::UserInput(_) = event {
+                let mut count = self.counter.lock().await;
+                *count += 1;
+                self.counter.broadcast(Frame {
+                    window_id: 1,
+                    seq: Some(*count),
+                    ..Default::default()
+                });
+            }
+            Ok(())
+        }
+
+        async fn on_tick(&mut self, stream: &mut ServerStream) -> Result<()> {
+            if let Some(receiver) = &mut self.receiver {
+                receiver.forward(stream).await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcast_from_shared_state_reaches_every_client() {
+        let counter = Broadcast::new(0, 8);
+        let service = CounterService {
+            counter: counter.clone(),
+            receiver: None,
+        };
+        let server = serve_local(service).await.unwrap();
+        let (mut first, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        let (mut second, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        while counter.clients() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        first.send(UserInput::default()).await.unwrap();
+        first.flush().await.unwrap();
+        for stream in [&mut first, &mut second] {
+            match next_event(stream).await.unwrap() {
+                ServerEvent::Frame(frame) => assert_eq!(frame.seq, Some(1)),
+                event => panic!("expected a frame, got {:?}", event),
+            }
+        }
+        assert_eq!(*counter.lock().await, 1);
+
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+        server.stop().await.unwrap();
+        assert_eq!(counter.clients(), 0);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::shared::protocol::Frame;
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+
+/// Synchronous sending half of a `FrameChannel`.\
+/// Use it from a blocking (non-tokio) thread, such as a capture or emulation loop, to hand frames to an async service.
+#[derive(Debug, Clone)]
+pub struct FrameSender {
+    tx: broadcast::Sender<Frame>,
+}
+
+impl FrameSender {
+    /// Sends a frame to every `FrameChannel`, without blocking.\
+    /// Channels that fell `capacity` frames behind skip their oldest frames.
+    /// Returns the frame back if all channels have been dropped.
+    pub fn send(&self, frame: Frame) -> Result<(), Frame> {
+        self.tx.send(frame).map(|_| ()).map_err(|err| err.0)
+    }
+
+    /// Sends a frame to every `FrameChannel`, see `send`.\
+    /// Returns `false` if all channels have been dropped.
+    pub fn try_send(&self, frame: Frame) -> bool {
+        self.send(frame).is_ok()
+    }
+}
+
+/// A bounded bridge from synchronous frame producers to an async service.\
+/// The channel is cheap to clone so it can be stored in a `GshService`.
+/// Every clone receives every frame sent after it was cloned, so each client connection,
+/// owning its own clone of the service, gets all frames.
+///
+/// # Example
+/// ```ignore
+/// let (sender, frames) = FrameChannel::new(4);
+/// std::thread::spawn(move || loop {
+///     let frame = capture_frame();
+///     if sender.send(frame).is_err() {
+///         break;
+///     }
+/// });
+/// // In `GshServiceExt::on_tick`:
+/// for frame in self.frames.latest() {
+///     stream.send(frame).await?;
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FrameChannel {
+    rx: broadcast::Receiver<Frame>,
+}
+
+impl Clone for FrameChannel {
+    fn clone(&self) -> Self {
+        Self {
+            rx: self.rx.resubscribe(),
+        }
+    }
+}
+
+impl FrameChannel {
+    /// Creates a new channel buffering up to `capacity` frames per clone.\
+    /// Clones falling further behind skip the oldest frames.
+    pub fn new(capacity: usize) -> (FrameSender, FrameChannel) {
+        let (tx, rx) = broadcast::channel(capacity.max(1));
+        (FrameSender { tx }, FrameChannel { rx })
+    }
+
+    /// Waits for the next frame.\
+    /// Returns `None` once all senders have been dropped and the channel is empty.
+    pub async fn recv(&mut self) -> Option<Frame> {
+        loop {
+            match self.rx.recv().await {
+                Ok(frame) => return Some(frame),
+                Err(RecvError::Lagged(skipped)) => {
+                    log::debug!("Frame channel lagging behind, skipped {} frames", skipped)
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Returns the next frame if one is immediately available.
+    pub fn try_recv(&mut self) -> Option<Frame> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(frame) => return Some(frame),
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Drains all immediately available frames, keeping only the newest frame per window.\
+    /// Frames are returned in the order their windows were last updated.
+    ///
+    /// ## Note
+    /// Coalescing drops intermediate frames, so only use this when each frame is self-contained
+    /// (full-frame segments) rather than a delta against the previous one.
+    pub fn latest(&mut self) -> Vec<Frame> {
+        let mut frames: Vec<Frame> = Vec::new();
+        while let Some(frame) = self.try_recv() {
+            frames.retain(|f| f.window_id != frame.window_id);
+            frames.push(frame);
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAMES: u64 = 10;
+
+    fn frame(seq: u64) -> Frame {
+        Frame {
+            window_id: 1,
+            seq: Some(seq),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_from_std_thread_arrive_in_order() {
+        let (sender, mut frames) = FrameChannel::new(FRAMES as usize);
+        let mut second = frames.clone();
+        let producer = std::thread::spawn(move || {
+            for seq in 0..FRAMES {
+                sender.send(frame(seq)).unwrap();
+            }
+        });
+        for seq in 0..FRAMES {
+            assert_eq!(frames.recv().await.map(|f| f.seq), Some(Some(seq)));
+        }
+        producer.join().unwrap();
+        assert_eq!(frames.recv().await, None);
+        // Every clone receives every frame
+        for seq in 0..FRAMES {
+            assert_eq!(second.recv().await.map(|f| f.seq), Some(Some(seq)));
+        }
+        assert_eq!(second.recv().await, None);
+    }
+}
@@ -1,9 +1,15 @@
-use super::ServerStream;
+use super::{ServerStream, DEFAULT_MAX_FRAME_SIZE};
 use crate::shared::{
-    auth::AuthVerifier,
+    auth::{AuthContext, AuthVerifier, PasswordVerifier, SignatureVerifier, TotpVerifier},
+    negotiate_protocol_version, negotiate_zstd_level,
     protocol::{
-        self, client_auth::AuthData, client_message::ClientEvent, server_auth_ack::AuthStatus,
-        server_hello_ack::AuthMethod, status_update::StatusType, ClientHello, ServerHelloAck,
+        self,
+        client_auth::{self, AuthData},
+        client_message::ClientEvent,
+        server_auth_ack::AuthStatus,
+        server_hello_ack::{window_settings, AuthMethod, AuthMethods, Compression},
+        status_update::StatusType,
+        ClientHello, ServerHelloAck,
     },
     HandshakeError,
 };
@@ -11,153 +17,305 @@ use rsa::RsaPublicKey;
 use rsa::{pkcs1::DecodeRsaPublicKey, pkcs1v15::Signature};
 use rsa::{pkcs1v15::VerifyingKey, signature::Verifier};
 use sha2::Sha256;
+use std::io::ErrorKind;
+use std::ops::RangeInclusive;
 
 /// Handshake function for the **server side**.
 /// It reads a `ClientHello` message and sends a `ServerHelloAck` response.
-/// If the client version is not compatible, it sends a `StatusUpdate` message and returns an error.
+/// The connection uses the newest protocol version supported by both sides, announced in the `ServerHelloAck`
+/// and available from `ServerStream::protocol_version`. If the client version is not compatible, it sends a `StatusUpdate` message and returns an error.
+/// A `max_frame_width` or `max_frame_height` of 0 is replaced by `DEFAULT_MAX_FRAME_SIZE`, see `ServerStream::max_frame_size`.
+/// Offered one-time passwords are announced with the digits and time step of the verifier, see `TotpVerifier::method`.
+/// Zstd compression levels are set to the client's preferred level clamped to `zstd_levels`, see `ServerStream::zstd_level`.
+/// On success, returns the client hello and the identity returned by the verifier, if the client authenticated.
+/// Waits for the client's messages as long as it takes, bound the handshake with a timeout such as `HANDSHAKE_TIMEOUT`.
 pub async fn handshake(
     stream: &mut ServerStream,
-    supported_protocol_versions: &[u32],
-    server_hello: ServerHelloAck,
+    supported_protocol_versions: RangeInclusive<u32>,
+    mut server_hello: ServerHelloAck,
     auth_verifier: Option<AuthVerifier>,
-) -> Result<ClientHello, HandshakeError> {
+    zstd_levels: Option<RangeInclusive<i32>>,
+) -> Result<(ClientHello, Option<AuthContext>), HandshakeError> {
+    // Announce the digits and time step the verifier actually checks codes with
+    if let Some(verifier) = auth_verifier.as_ref().and_then(AuthVerifier::totp) {
+        match &mut server_hello.auth_method {
+            Some(AuthMethod::Totp(method))
+            | Some(AuthMethod::Any(AuthMethods {
+                totp: Some(method), ..
+            })) => *method = verifier.method(),
+            _ => {}
+        }
+    }
     let auth_method = server_hello.auth_method.clone();
-    let ClientEvent::ClientHello(client_hello) = stream.receive().await? else {
+    let ClientEvent::ClientHello(client_hello) = receive(stream).await? else {
         return Err(HandshakeError::AnyError(
             "Expected ClientHello message".into(),
         ));
     };
-    if !supported_protocol_versions.contains(&client_hello.protocol_version) {
+    let Some(protocol_version) = negotiate_protocol_version(
+        &supported_protocol_versions,
+        client_hello.min_protocol_version,
+        client_hello.protocol_version,
+    ) else {
         let msg = format!(
             "Unsupported client protocol version: {}. Supported versions: {:?}",
             client_hello.protocol_version, supported_protocol_versions
         );
         stream
-            .send(protocol::StatusUpdate {
-                kind: StatusType::Exit as i32,
-                details: None,
-            })
+            .send(protocol::StatusUpdate::rejected(
+                StatusType::VersionMismatch,
+                msg.clone(),
+            ))
             .await?;
         return Err(HandshakeError::AnyError(msg.into()));
+    };
+    server_hello.protocol_version = protocol_version;
+    stream.codec.set_protocol_version(protocol_version);
+    if server_hello.max_frame_width == 0 {
+        server_hello.max_frame_width = DEFAULT_MAX_FRAME_SIZE.0;
+    }
+    if server_hello.max_frame_height == 0 {
+        server_hello.max_frame_height = DEFAULT_MAX_FRAME_SIZE.1;
+    }
+    stream.max_frame_size = Some((server_hello.max_frame_width, server_hello.max_frame_height));
+    let negotiate =
+        |level| negotiate_zstd_level(level, zstd_levels.as_ref(), client_hello.zstd_level);
+    if let Some(Compression::Zstd(zstd)) = &mut server_hello.compression {
+        zstd.level = negotiate(zstd.level);
+        stream.zstd_level = Some(zstd.level);
+    }
+    for window in &mut server_hello.windows {
+        if let Some(window_settings::Compression::Zstd(zstd)) = &mut window.compression {
+            zstd.level = negotiate(zstd.level);
+        }
     }
     stream.send(server_hello).await?;
 
     // Verify ClientAuth message if auth_method is set
-    if let Some(AuthMethod::Password(_)) = auth_method {
-        let ClientEvent::ClientAuth(client_auth) = stream.receive().await? else {
-            return Err(HandshakeError::AnyError(
-                "Expected ClientAuth message".into(),
-            ));
-        };
-        let auth_verifier = auth_verifier.expect("AuthVerifier is required for server handshake");
-        let client_auth = client_auth.auth_data.expect("ClientAuth data is required");
-        let AuthVerifier::Password(password_verifier) = auth_verifier else {
-            panic!("Password verifier is required for password authentication");
-        };
-        let AuthData::Password(client_auth) = client_auth else {
-            return Err(HandshakeError::PasswordRequired);
-        };
-        if client_auth.password.is_empty() {
-            stream
-                .send(protocol::ServerAuthAck {
-                    status: AuthStatus::Failure as i32,
-                    message: "Password is required".to_string(),
-                })
-                .await?;
-            return Err(HandshakeError::PasswordRequired);
+    let Some(auth_method) = auth_method else {
+        return Ok((client_hello, None));
+    };
+    let ClientEvent::ClientAuth(client_auth) = receive(stream).await? else {
+        stream
+            .send(protocol::StatusUpdate::rejected(
+                StatusType::AuthFailed,
+                "Authentication required",
+            ))
+            .await?;
+        return Err(HandshakeError::AnyError(
+            "Expected ClientAuth message".into(),
+        ));
+    };
+    // The client authenticates with any one of the offered methods
+    let offered = match &auth_method {
+        AuthMethod::Password(()) => AuthMethods {
+            password: Some(()),
+            ..Default::default()
+        },
+        AuthMethod::Signature(method) => AuthMethods {
+            signature: Some(method.clone()),
+            ..Default::default()
+        },
+        AuthMethod::Totp(method) => AuthMethods {
+            totp: Some(*method),
+            ..Default::default()
+        },
+        AuthMethod::Any(methods) => methods.clone(),
+    };
+    // Methods offered without a verifier are rejected like methods that were not offered,
+    // the client chooses the method and must not be able to fail the connection task
+    let verifiers = auth_verifier.as_ref();
+    let signature_offered = offered.signature.is_some();
+    let password = verifiers
+        .and_then(AuthVerifier::password)
+        .filter(|_| offered.password.is_some());
+    let signature = verifiers
+        .and_then(AuthVerifier::signature)
+        .zip(offered.signature);
+    let totp = verifiers
+        .and_then(AuthVerifier::totp)
+        .filter(|_| offered.totp.is_some());
+    if offered.password.is_some() && password.is_none()
+        || signature_offered && signature.is_none()
+        || offered.totp.is_some() && totp.is_none()
+    {
+        log::error!(
+            "Authentication method offered without a verifier for it: {:?}",
+            auth_method
+        );
+    }
+    let auth_context = match (client_auth.auth_data, password, signature, totp) {
+        (Some(AuthData::Password(client_auth)), Some(verifier), _, _) => {
+            verify_password(stream, verifier, client_auth).await?
         }
-        if !password_verifier.verify(&client_auth.password) {
-            stream
-                .send(protocol::ServerAuthAck {
-                    status: AuthStatus::Failure as i32,
-                    message: "Invalid password".to_string(),
-                })
-                .await?;
-            return Err(HandshakeError::InvalidPassword);
-        } else {
-            stream
-                .send(protocol::ServerAuthAck {
-                    status: AuthStatus::Success as i32,
-                    message: "Password verified".to_string(),
-                })
-                .await?;
+        (Some(AuthData::Signature(client_auth)), _, Some((verifier, method)), _) => {
+            verify_client_signature(stream, verifier, &method.sign_message, client_auth).await?
         }
-    } else if let Some(AuthMethod::Signature(server_auth)) = auth_method {
-        let ClientEvent::ClientAuth(client_auth) = stream.receive().await? else {
-            return Err(HandshakeError::AnyError(
-                "Expected ClientAuth message".into(),
-            ));
-        };
-        let auth_verifier = auth_verifier.expect("AuthVerifier is required for server handshake");
-        let client_auth = client_auth.auth_data.expect("ClientAuth data is required");
-        let AuthVerifier::Signature(signature_verifier) = auth_verifier else {
-            panic!("Signature verifier is required for signature authentication");
-        };
-        let AuthData::Signature(client_auth) = client_auth else {
-            return Err(HandshakeError::SignatureRequired);
-        };
-        if client_auth.signature.is_empty() {
+        (Some(AuthData::Totp(client_auth)), _, _, Some(verifier)) => {
+            verify_totp(stream, verifier, client_auth).await?
+        }
+        _ => {
             stream
-                .send(protocol::ServerAuthAck {
-                    status: AuthStatus::Failure as i32,
-                    message: "Signature is required".to_string(),
-                })
+                .send(protocol::StatusUpdate::rejected(
+                    StatusType::AuthFailed,
+                    "Authentication method not offered by the server",
+                ))
                 .await?;
-            return Err(HandshakeError::SignatureRequired);
-        }
-        let public_key_pem = String::from_utf8_lossy(&client_auth.public_key);
-        let public_key = match RsaPublicKey::from_pkcs1_pem(&public_key_pem) {
-            Ok(public_key) => public_key,
-            Err(err) => {
-                stream
-                    .send(protocol::ServerAuthAck {
-                        status: AuthStatus::Failure as i32,
-                        message: format!("Invalid public key: {}", err),
-                    })
-                    .await?;
-                return Err(HandshakeError::SignatureInvalid);
-            }
-        };
-        let signature = match Signature::try_from(&client_auth.signature[..]) {
-            Ok(signature) => signature,
-            Err(err) => {
-                stream
-                    .send(protocol::ServerAuthAck {
-                        status: AuthStatus::Failure as i32,
-                        message: format!("Invalid signature: {}", err),
-                    })
-                    .await?;
-                return Err(HandshakeError::SignatureInvalid);
-            }
-        };
+            return Err(match auth_method {
+                AuthMethod::Password(_) => HandshakeError::PasswordRequired,
+                AuthMethod::Signature(_) => HandshakeError::SignatureRequired,
+                AuthMethod::Totp(_) => HandshakeError::TotpRequired,
+                AuthMethod::Any(_) => HandshakeError::AnyError(
+                    "Authentication method not offered by the server".into(),
+                ),
+            });
+        }
+    };
+
+    Ok((client_hello, Some(auth_context)))
+}
+
+async fn verify_password(
+    stream: &mut ServerStream,
+    password_verifier: &dyn PasswordVerifier,
+    client_auth: client_auth::Password,
+) -> Result<AuthContext, HandshakeError> {
+    if client_auth.password.is_empty() {
+        stream
+            .send(protocol::ServerAuthAck {
+                status: AuthStatus::Failure as i32,
+                message: "Password is required".to_string(),
+            })
+            .await?;
+        return Err(HandshakeError::PasswordRequired);
+    }
+    let Some(context) = password_verifier.identify(&client_auth.password) else {
+        stream
+            .send(protocol::ServerAuthAck {
+                status: AuthStatus::Failure as i32,
+                message: "Invalid password".to_string(),
+            })
+            .await?;
+        return Err(HandshakeError::InvalidPassword);
+    };
+    stream
+        .send(protocol::ServerAuthAck {
+            status: AuthStatus::Success as i32,
+            message: "Password verified".to_string(),
+        })
+        .await?;
+    Ok(context)
+}
 
-        if !signature_verifier.verify(&public_key) {
+async fn verify_client_signature(
+    stream: &mut ServerStream,
+    signature_verifier: &dyn SignatureVerifier,
+    sign_message: &[u8],
+    client_auth: client_auth::Signature,
+) -> Result<AuthContext, HandshakeError> {
+    if client_auth.signature.is_empty() {
+        stream
+            .send(protocol::ServerAuthAck {
+                status: AuthStatus::Failure as i32,
+                message: "Signature is required".to_string(),
+            })
+            .await?;
+        return Err(HandshakeError::SignatureRequired);
+    }
+    let public_key_pem = String::from_utf8_lossy(&client_auth.public_key);
+    let public_key = match RsaPublicKey::from_pkcs1_pem(&public_key_pem) {
+        Ok(public_key) => public_key,
+        Err(err) => {
             stream
                 .send(protocol::ServerAuthAck {
                     status: AuthStatus::Failure as i32,
-                    message: "Verification failed".to_string(),
+                    message: format!("Invalid public key: {}", err),
                 })
                 .await?;
             return Err(HandshakeError::SignatureInvalid);
         }
-        if !verify_signature(&server_auth.sign_message, signature, public_key) {
+    };
+    let signature = match Signature::try_from(&client_auth.signature[..]) {
+        Ok(signature) => signature,
+        Err(err) => {
             stream
                 .send(protocol::ServerAuthAck {
                     status: AuthStatus::Failure as i32,
-                    message: "Verification failed".to_string(),
+                    message: format!("Invalid signature: {}", err),
                 })
                 .await?;
             return Err(HandshakeError::SignatureInvalid);
         }
+    };
+
+    let Some(context) = signature_verifier.identify(&public_key) else {
         stream
             .send(protocol::ServerAuthAck {
-                status: AuthStatus::Success as i32,
-                message: "Signature verified!".to_string(),
+                status: AuthStatus::Failure as i32,
+                message: "Verification failed".to_string(),
             })
             .await?;
+        return Err(HandshakeError::SignatureInvalid);
+    };
+    if !verify_signature(sign_message, signature, public_key) {
+        stream
+            .send(protocol::ServerAuthAck {
+                status: AuthStatus::Failure as i32,
+                message: "Verification failed".to_string(),
+            })
+            .await?;
+        return Err(HandshakeError::SignatureInvalid);
     }
+    stream
+        .send(protocol::ServerAuthAck {
+            status: AuthStatus::Success as i32,
+            message: "Signature verified!".to_string(),
+        })
+        .await?;
+    Ok(context)
+}
+
+async fn verify_totp(
+    stream: &mut ServerStream,
+    totp_verifier: &dyn TotpVerifier,
+    client_auth: client_auth::Totp,
+) -> Result<AuthContext, HandshakeError> {
+    if client_auth.code.is_empty() {
+        stream
+            .send(protocol::ServerAuthAck {
+                status: AuthStatus::Failure as i32,
+                message: "One-time password is required".to_string(),
+            })
+            .await?;
+        return Err(HandshakeError::TotpRequired);
+    }
+    let Some(context) = totp_verifier.identify(&client_auth.code) else {
+        stream
+            .send(protocol::ServerAuthAck {
+                status: AuthStatus::Failure as i32,
+                message: "Invalid one-time password".to_string(),
+            })
+            .await?;
+        return Err(HandshakeError::InvalidTotp);
+    };
+    stream
+        .send(protocol::ServerAuthAck {
+            status: AuthStatus::Success as i32,
+            message: "One-time password verified".to_string(),
+        })
+        .await?;
+    Ok(context)
+}
 
-    Ok(client_hello)
+/// Receives the next handshake message from the client.\
+/// Reads time out quickly to let services do other work, but a client may take a while, e.g. while the user types a password.
+async fn receive(stream: &mut ServerStream) -> std::io::Result<ClientEvent> {
+    loop {
+        match stream.receive().await {
+            Err(err) if err.kind() == ErrorKind::TimedOut => {}
+            result => return result,
+        }
+    }
 }
 
 /// Verify the signature using the public key and the sign message from the server
@@ -165,3 +323,303 @@ fn verify_signature(sign_message: &[u8], signature: Signature, public_key: RsaPu
     let verifying_key = VerifyingKey::<Sha256>::new(public_key);
     verifying_key.verify(sign_message, &signature).is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{
+        client_hello, next_event, run_service_with_client, serve_local, wait_for_exit, LocalServer,
+        StaticAuth,
+    };
+    use crate::server::GshService;
+    use crate::shared::{
+        auth::AuthProvider,
+        protocol::{
+            server_hello_ack::{SignatureMethod, ZstdCompression},
+            server_message::ServerEvent,
+            ClientMessage, StatusUpdate,
+        },
+        PROTOCOL_VERSION,
+    };
+    use async_trait::async_trait;
+    use rsa::{pkcs1v15::SigningKey, rand_core::OsRng, signature::Signer, RsaPrivateKey};
+    use std::sync::{Arc, Mutex};
+
+    const SIGN_MESSAGE: &[u8] = b"sign me";
+
+    /// Signs with a fixed key.
+    struct KeyAuth(RsaPrivateKey);
+
+    impl AuthProvider for KeyAuth {
+        fn password(&mut self, _host: &str) -> String {
+            String::new()
+        }
+
+        fn signature(
+            &mut self,
+            _host: &str,
+            sign_message: &[u8],
+        ) -> Option<(Signature, RsaPublicKey)> {
+            let signing_key = SigningKey::<Sha256>::new(self.0.clone());
+            Some((signing_key.sign(sign_message), self.0.to_public_key()))
+        }
+    }
+
+    /// Maps a single known key to its user.
+    struct KnownKey(RsaPublicKey);
+
+    impl SignatureVerifier for KnownKey {
+        fn verify(&self, public_key: &RsaPublicKey) -> bool {
+            *public_key == self.0
+        }
+
+        fn identify(&self, public_key: &RsaPublicKey) -> Option<AuthContext> {
+            self.verify(public_key)
+                .then(|| AuthContext::user("alice").with_claim("role", "admin"))
+        }
+    }
+
+    const PASSWORD: &str = "secret";
+
+    /// Accepts a single password for its user.
+    struct FixedPassword;
+
+    impl PasswordVerifier for FixedPassword {
+        fn verify(&self, password: &str) -> bool {
+            password == PASSWORD
+        }
+
+        fn identify(&self, password: &str) -> Option<AuthContext> {
+            self.verify(password).then(|| AuthContext::user("bob"))
+        }
+    }
+
+    /// Records the identity of each client, then waits for it to exit.\
+    /// Offers signatures by the known key, and passwords as well if `with_password` is set.
+    #[derive(Clone)]
+    struct IdentityService {
+        key: RsaPublicKey,
+        with_password: bool,
+        identities: Arc<Mutex<Vec<Option<AuthContext>>>>,
+    }
+
+    impl IdentityService {
+        fn new(key: RsaPublicKey, with_password: bool) -> Self {
+            Self {
+                key,
+                with_password,
+                identities: Arc::default(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl GshService for IdentityService {
+        fn server_hello(&self) -> ServerHelloAck {
+            let signature = SignatureMethod {
+                sign_message: SIGN_MESSAGE.to_vec(),
+            };
+            let auth_method = if self.with_password {
+                AuthMethod::Any(AuthMethods {
+                    password: Some(()),
+                    signature: Some(signature),
+                    totp: None,
+                })
+            } else {
+                AuthMethod::Signature(signature)
+            };
+            ServerHelloAck {
+                auth_method: Some(auth_method),
+                ..Default::default()
+            }
+        }
+
+        fn auth_verifier(&self) -> Option<AuthVerifier> {
+            let signature = AuthVerifier::Signature(Box::new(KnownKey(self.key.clone())));
+            Some(if self.with_password {
+                AuthVerifier::Any(vec![
+                    AuthVerifier::Password(Box::new(FixedPassword)),
+                    signature,
+                ])
+            } else {
+                signature
+            })
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            self.identities
+                .lock()
+                .unwrap()
+                .push(stream.auth_context().cloned());
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn signature_verifier_identifies_user_for_service() {
+        let private_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let service = IdentityService::new(private_key.to_public_key(), false);
+        let server = serve_local(service.clone()).await.unwrap();
+        let (mut stream, _) = server.handshake(KeyAuth(private_key)).await.unwrap();
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+
+        let identities = service.identities.lock().unwrap().clone();
+        assert_eq!(
+            identities,
+            vec![Some(AuthContext::user("alice").with_claim("role", "admin"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn client_authenticates_with_either_offered_method() {
+        let private_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let service = IdentityService::new(private_key.to_public_key(), true);
+        let server = serve_local(service.clone()).await.unwrap();
+        let (mut by_password, _) = server
+            .handshake(StaticAuth(PASSWORD.to_string()))
+            .await
+            .unwrap();
+        by_password.close().await.unwrap();
+        // Providers with a key prefer signatures
+        let (mut by_key, _) = server.handshake(KeyAuth(private_key)).await.unwrap();
+        by_key.close().await.unwrap();
+        assert!(server
+            .handshake(StaticAuth("wrong".to_string()))
+            .await
+            .is_err());
+        server.stop().await.unwrap();
+
+        let identities = service.identities.lock().unwrap().clone();
+        assert_eq!(
+            identities,
+            vec![
+                Some(AuthContext::user("bob")),
+                Some(AuthContext::user("alice").with_claim("role", "admin")),
+            ]
+        );
+    }
+
+    /// Accepts clients knowing the password, then waits for them to exit.
+    #[derive(Clone)]
+    struct PasswordService;
+
+    #[async_trait]
+    impl GshService for PasswordService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                auth_method: Some(AuthMethod::Password(())),
+                ..Default::default()
+            }
+        }
+
+        fn auth_verifier(&self) -> Option<AuthVerifier> {
+            Some(AuthVerifier::Password(Box::new(FixedPassword)))
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    /// Sends a scripted handshake, returning the status update rejecting it.
+    async fn rejection(server: &LocalServer, script: Vec<ClientMessage>) -> StatusUpdate {
+        let mut stream = server.connect().await.unwrap();
+        for message in script {
+            stream.send(message).await.unwrap();
+        }
+        stream.flush().await.unwrap();
+        loop {
+            match next_event(&mut stream).await.unwrap() {
+                ServerEvent::StatusUpdate(status) => return status,
+                ServerEvent::ServerHelloAck(_) => {}
+                event => panic!("expected a status update, got {:?}", event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_failures_send_their_status() {
+        let server = serve_local(PasswordService).await.unwrap();
+        let newer = ClientHello {
+            protocol_version: PROTOCOL_VERSION + 10,
+            min_protocol_version: PROTOCOL_VERSION + 10,
+            ..client_hello()
+        };
+        let status = rejection(&server, vec![newer.into()]).await;
+        assert_eq!(status.kind(), StatusType::VersionMismatch);
+        assert!(status.message().unwrap().contains("protocol version"));
+
+        let status = rejection(
+            &server,
+            vec![client_hello().into(), StatusUpdate::ping().into()],
+        )
+        .await;
+        assert_eq!(status.kind(), StatusType::AuthFailed);
+        assert_eq!(status.message(), Some("Authentication required"));
+
+        let totp = protocol::ClientAuth {
+            auth_data: Some(AuthData::Totp(client_auth::Totp {
+                code: "123456".to_string(),
+            })),
+        };
+        let status = rejection(&server, vec![client_hello().into(), totp.into()]).await;
+        assert_eq!(status.kind(), StatusType::AuthFailed);
+        assert_eq!(
+            status.message(),
+            Some("Authentication method not offered by the server")
+        );
+        server.stop().await.unwrap();
+    }
+
+    /// Compresses with zstd level 3 by default, letting clients choose levels 1 to 9.
+    #[derive(Clone, Default)]
+    struct ZstdService {
+        levels: Arc<Mutex<Vec<Option<i32>>>>,
+    }
+
+    #[async_trait]
+    impl GshService for ZstdService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                compression: Some(Compression::Zstd(ZstdCompression {
+                    level: 3,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }
+        }
+
+        fn zstd_levels(&self) -> Option<RangeInclusive<i32>> {
+            Some(1..=9)
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            self.levels.lock().unwrap().push(stream.zstd_level());
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn preferred_zstd_level_is_clamped_to_the_server_range() {
+        for (preferred, expected) in [(None, 3), (Some(5), 5), (Some(22), 9), (Some(-7), 1)] {
+            let service = ZstdService::default();
+            let hello = ClientHello {
+                zstd_level: preferred,
+                ..client_hello()
+            };
+            let messages = run_service_with_client(service.clone(), vec![hello.into()])
+                .await
+                .unwrap();
+            let Some(ServerEvent::ServerHelloAck(ServerHelloAck {
+                compression: Some(Compression::Zstd(zstd)),
+                ..
+            })) = &messages[0].server_event
+            else {
+                panic!("expected a zstd ServerHelloAck, got {:?}", messages[0]);
+            };
+            assert_eq!(zstd.level, expected, "preferred {:?}", preferred);
+            assert_eq!(*service.levels.lock().unwrap(), vec![Some(expected)]);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::shared::protocol::Frame;
+use std::collections::{HashMap, HashSet};
+
+/// Frames between two keyframes by default, about 5 seconds at 60 FPS.
+pub const DEFAULT_KEYFRAME_INTERVAL: u32 = 300;
+
+/// Tracks when each window of a connection is due for a keyframe, a frame replacing the whole window contents.\
+/// Delta frames only update what changed, so a lost delta leaves stale pixels on the client until the next keyframe.
+/// A keyframe is due for windows that never got one, every `interval` frames, and when the client requests one.
+#[derive(Debug, Clone)]
+pub struct KeyframeSchedule {
+    interval: Option<u32>,
+    /// Frames sent per window since its last keyframe
+    since_keyframe: HashMap<u32, u32>,
+    requested: HashSet<u32>,
+}
+
+impl Default for KeyframeSchedule {
+    fn default() -> Self {
+        Self::new(Some(DEFAULT_KEYFRAME_INTERVAL))
+    }
+}
+
+impl KeyframeSchedule {
+    /// Creates a schedule with a keyframe every `interval` frames, or only on request if `None`.
+    pub fn new(interval: Option<u32>) -> Self {
+        Self {
+            interval: interval.map(|n| n.max(1)),
+            since_keyframe: HashMap::new(),
+            requested: HashSet::new(),
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Option<u32>) {
+        self.interval = interval.map(|n| n.max(1));
+    }
+
+    /// Whether the next frame of the window should be a keyframe.
+    pub fn is_due(&self, window_id: u32) -> bool {
+        if self.requested.contains(&window_id) {
+            return true;
+        }
+        match self.since_keyframe.get(&window_id) {
+            Some(&frames) => self.interval.is_some_and(|interval| frames >= interval),
+            None => true,
+        }
+    }
+
+    /// Forces a keyframe for the next frame of the window, e.g. after the client detected a lost frame.
+    pub fn request(&mut self, window_id: u32) {
+        self.requested.insert(window_id);
+    }
+
+    /// Records a sent frame, restarting the interval if it is a keyframe.
+    pub fn sent(&mut self, frame: &Frame) {
+        // Only the final frame of a group completes an update
+        if frame.frame_group_id.is_some() && !frame.is_final {
+            return;
+        }
+        if frame.is_keyframe {
+            self.since_keyframe.insert(frame.window_id, 0);
+            self.requested.remove(&frame.window_id);
+        } else if let Some(frames) = self.since_keyframe.get_mut(&frame.window_id) {
+            *frames += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(is_keyframe: bool) -> Frame {
+        Frame {
+            window_id: 1,
+            is_keyframe,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keyframe_is_due_on_request() {
+        let mut schedule = KeyframeSchedule::new(None);
+        assert!(schedule.is_due(1));
+        schedule.sent(&frame(true));
+        for _ in 0..1000 {
+            schedule.sent(&frame(false));
+            assert!(!schedule.is_due(1));
+        }
+        schedule.request(1);
+        assert!(schedule.is_due(1));
+        // Delta frames don't satisfy the request
+        schedule.sent(&frame(false));
+        assert!(schedule.is_due(1));
+        schedule.sent(&frame(true));
+        assert!(!schedule.is_due(1));
+    }
+
+    #[test]
+    fn keyframe_is_due_every_interval() {
+        let mut schedule = KeyframeSchedule::new(Some(3));
+        schedule.sent(&frame(true));
+        for _ in 0..2 {
+            schedule.sent(&frame(false));
+            assert!(!schedule.is_due(1));
+        }
+        schedule.sent(&frame(false));
+        assert!(schedule.is_due(1));
+        // Other windows are tracked independently
+        assert!(schedule.is_due(2));
+    }
+}
@@ -0,0 +1,64 @@
+use crate::shared::protocol::client_message::ClientEvent;
+use std::time::Duration;
+
+/// Hooks into the frame pipeline of a connection, e.g. to export metrics to Prometheus or statsd.\
+/// Return an implementation from `GshServiceExt::metrics` to have the default `main` loop and
+/// `ServerStream` call it. All methods default to doing nothing.
+///
+/// Hooks are called inline on the connection's task, so they should only record values and return quickly.
+pub trait Metrics: std::fmt::Debug + Send + Sync {
+    /// Called after a frame (or a tile of it) was written to the stream.\
+    /// `bytes` is the encoded size of the message and `write_time` the time spent writing it.
+    fn on_frame_sent(&self, _window_id: u32, _bytes: usize, _write_time: Duration) {}
+
+    /// Called for every event received from the client, with its kind, see `event_kind`.
+    fn on_event(&self, _kind: &'static str) {}
+
+    /// Called after every tick of the default `main` loop.\
+    /// `render_time` is the time spent rendering and encoding, `send_time` the time spent writing frames.
+    fn on_tick(&self, _render_time: Duration, _send_time: Duration) {}
+}
+
+/// Logs all metrics at debug level, to get a quick overview without an exporter.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LogMetrics;
+
+impl Metrics for LogMetrics {
+    fn on_frame_sent(&self, window_id: u32, bytes: usize, write_time: Duration) {
+        log::debug!(
+            "Frame sent to window {}: {} bytes in {:.2?}",
+            window_id,
+            bytes,
+            write_time
+        );
+    }
+
+    fn on_event(&self, kind: &'static str) {
+        log::debug!("Event received: {}", kind);
+    }
+
+    fn on_tick(&self, render_time: Duration, send_time: Duration) {
+        log::debug!(
+            "Tick: render/encode {:.2?}, send {:.2?}",
+            render_time,
+            send_time
+        );
+    }
+}
+
+/// Short name of a client event, as passed to `Metrics::on_event`.
+pub fn event_kind(event: &ClientEvent) -> &'static str {
+    match event {
+        ClientEvent::ClientHello(_) => "client_hello",
+        ClientEvent::ClientAuth(_) => "client_auth",
+        ClientEvent::StatusUpdate(_) => "status_update",
+        ClientEvent::UserInput(_) => "user_input",
+        ClientEvent::FrameAck(_) => "frame_ack",
+        ClientEvent::NetworkStats(_) => "network_stats",
+        ClientEvent::ViewportHint(_) => "viewport_hint",
+        ClientEvent::RequestKeyframe(_) => "request_keyframe",
+        ClientEvent::FileTransferBegin(_) => "file_transfer_begin",
+        ClientEvent::FileChunk(_) => "file_chunk",
+        ClientEvent::FileTransferEnd(_) => "file_transfer_end",
+    }
+}
@@ -1,29 +1,598 @@
-use crate::shared::codec::GshCodec;
-use crate::shared::protocol::{client_message::ClientEvent, ClientMessage, ServerMessage};
+use crate::shared::auth::AuthContext;
+use crate::shared::codec::{GshCodec, CLOSE_TIMEOUT};
+use crate::shared::frame::tile_frame;
+use crate::shared::protocol::{
+    client_hello::MonitorInfo,
+    client_message::ClientEvent,
+    server_message::ServerEvent,
+    user_input::{window_event::WindowAction, InputEvent},
+    ClientMessage, FileOffer, FileTransferEnd, Frame, FrameAck, ServerMessage, StatusUpdate,
+    UserInput, ViewportHint,
+};
+use crate::shared::transfer::{file_chunks, FILE_CHUNK_SIZE};
 use prost::Message;
+use std::collections::{HashMap, VecDeque};
 use std::io::Result;
-use tokio::net::TcpStream;
-use tokio_rustls::server::TlsStream;
+use std::sync::Arc;
+use std::time::Duration;
 
+pub mod auth_limit;
+pub mod broadcast;
+pub mod channel;
+pub mod keyframe;
+pub mod metrics;
 pub mod server;
 pub mod service;
+pub mod session;
+pub mod shutdown;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod transport;
 
 mod handshake;
+pub use auth_limit::AuthRateLimit;
+pub use broadcast::{Broadcast, BroadcastReceiver};
+pub use channel::{FrameChannel, FrameSender};
 pub use handshake::handshake;
-pub use server::GshServer;
+pub use keyframe::KeyframeSchedule;
+pub use metrics::{LogMetrics, Metrics};
+pub use server::{BoundServer, GshServer, HANDSHAKE_TIMEOUT};
 pub use service::{GshService, GshServiceExt};
+pub use session::SessionRegistry;
+pub use shutdown::{ShutdownHandle, ShutdownSignal};
+pub use transport::ServerTransport;
 
-/// Asynchronous message codec for the server `TlsStream` over a `TcpStream`.\
-pub type ServerStream = GshCodec<TlsStream<TcpStream>>;
+/// Asynchronous message stream of a client connection, a `GshCodec` over a `ServerTransport`
+/// together with the server-side state of the connection.
+#[derive(Debug)]
+pub struct ServerStream {
+    codec: GshCodec<ServerTransport>,
+    /// Identifier of the client, assigned by the server to each accepted connection.
+    client_id: ClientId,
+    /// Signal for the server shutting down, used by service loops to disconnect gracefully.
+    shutdown: ShutdownSignal,
+    /// Resumption token issued to the client of this connection, empty if not resumable.
+    resumption_token: Vec<u8>,
+    /// Resumption token of a previous connection presented by the client, if any.
+    resumed_token: Option<Vec<u8>>,
+    /// Identity of the client returned by the server's auth verifier, if it authenticated.
+    auth_context: Option<AuthContext>,
+    /// Zstd compression level negotiated in the handshake, if the connection uses zstd compression.
+    zstd_level: Option<i32>,
+    /// Sequenced frames sent but not yet acknowledged, as window ID and sequence number.
+    frames_in_flight: VecDeque<(u32, u64)>,
+    /// Next frame sequence number of each window, see `next_frame_seq`.
+    frame_seqs: HashMap<u32, u64>,
+    /// When each window of the connection is due for a keyframe.
+    keyframes: KeyframeSchedule,
+    /// Hooks notified of every frame sent, if any.
+    metrics: Option<Arc<dyn Metrics>>,
+    /// Largest window size announced in the `ServerHelloAck`, if any.
+    max_frame_size: Option<(u32, u32)>,
+    /// Monitors reported by the client in its `ClientHello`.
+    monitors: Vec<MonitorInfo>,
+    /// Whether each client window is visible, from its last `ViewportHint`.
+    window_visible: HashMap<u32, bool>,
+    /// Whether each client window has keyboard focus, from its last focus change.
+    window_focused: HashMap<u32, bool>,
+}
+
+/// Largest window size in pixels announced to clients by services that don't set one, 8K UHD.\
+/// Bounds the frame buffers a client can make a service allocate by resizing its windows.
+pub const DEFAULT_MAX_FRAME_SIZE: (u32, u32) = (7680, 4320);
+
+/// Unique identifier of a client connection, assigned when the connection is accepted.
+pub type ClientId = u64;
 
 impl ServerStream {
+    pub fn new(transport: ServerTransport) -> Self {
+        Self {
+            codec: GshCodec::new(transport),
+            client_id: 0,
+            shutdown: ShutdownSignal::default(),
+            resumption_token: Vec::new(),
+            resumed_token: None,
+            auth_context: None,
+            zstd_level: None,
+            frames_in_flight: VecDeque::new(),
+            frame_seqs: HashMap::new(),
+            keyframes: KeyframeSchedule::default(),
+            metrics: None,
+            max_frame_size: None,
+            monitors: Vec::new(),
+            window_visible: HashMap::new(),
+            window_focused: HashMap::new(),
+        }
+    }
+
+    /// Creates a stream for a connection with the given client identifier.
+    pub fn with_client_id(transport: ServerTransport, client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            ..Self::new(transport)
+        }
+    }
+
+    /// Identifier of the client on the other end of this stream, unique per server process (0 if not assigned).\
+    /// Use it to attribute input to a client when state is shared between connections.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// A signal completing when the server this connection belongs to shuts down.\
+    /// Never completes for connections not accepted by a `GshServer`.
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        self.shutdown.clone()
+    }
+
+    /// Token issued to the client for resuming this session after a disconnect, empty if the service is not resumable.
+    pub fn resumption_token(&self) -> &[u8] {
+        &self.resumption_token
+    }
+
+    /// Token of a previous connection presented by the client to resume its session, if any.
+    pub fn resumed_token(&self) -> Option<&[u8]> {
+        self.resumed_token.as_deref()
+    }
+
+    /// Identity of the authenticated client, as returned by the `identify` method of the service's verifier.\
+    /// `None` if the service doesn't require authentication.
+    pub fn auth_context(&self) -> Option<&AuthContext> {
+        self.auth_context.as_ref()
+    }
+
+    /// Zstd compression level negotiated in the handshake if the connection uses zstd compression,
+    /// which services should compress their frames with.
+    pub fn zstd_level(&self) -> Option<i32> {
+        self.zstd_level
+    }
+
+    /// Protocol version of the connection, see `GshCodec::protocol_version`.
+    pub fn protocol_version(&self) -> u32 {
+        self.codec.protocol_version()
+    }
+
+    /// Total time spent writing and flushing messages on this stream, see `GshCodec::write_time`.
+    pub fn write_time(&self) -> Duration {
+        self.codec.write_time()
+    }
+
+    /// Limits the size of messages read from and written to the stream, see `GshCodec::set_max_message_size`.
+    pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+        self.codec.set_max_message_size(max_message_size);
+    }
+
+    /// The largest message size allowed on this stream, if any.
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.codec.max_message_size()
+    }
+
+    pub fn get_inner(&mut self) -> &mut ServerTransport {
+        self.codec.get_inner()
+    }
+
+    /// Explicitly flush the underlying stream. Use this after sending a batch/frame.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.codec.flush().await
+    }
+
     pub async fn send(&mut self, message: impl Into<ServerMessage>) -> Result<()> {
-        self.write_internal(message.into()).await
+        self.write_message(message.into()).await
+    }
+
+    /// Closes the connection gracefully.\
+    /// Sends an exit status update and a TLS `close_notify`, then waits up to `CLOSE_TIMEOUT`
+    /// for the client to close its side, so neither side sees an abrupt end of the stream.
+    pub async fn close(&mut self) -> Result<()> {
+        // The client may already be gone after announcing its own exit
+        let _ = self.send(StatusUpdate::exit()).await;
+        let _ = self.flush().await;
+        self.get_inner().close().await?;
+        self.codec.wait_for_peer_close(CLOSE_TIMEOUT).await;
+        Ok(())
+    }
+
+    /// Sends several messages, e.g. the frames of all windows of a tick, in a single write and flushes once.\
+    /// Cuts the syscalls of sending many small messages one by one.
+    /// Messages are not tiled, use `send_frame` for frames that may exceed the `max_message_size`.
+    pub async fn send_batch<M: Into<ServerMessage>>(
+        &mut self,
+        messages: impl IntoIterator<Item = M>,
+    ) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut frames = Vec::new();
+        for message in messages {
+            let message = message.into();
+            let start = buf.len();
+            self.codec.encode_internal(&message, &mut buf)?;
+            if let Some(ServerEvent::Frame(frame)) = &message.server_event {
+                self.track_frame(frame);
+                frames.push((frame.window_id, buf.len() - start));
+            }
+        }
+        let write_time = self.write_time();
+        self.codec.write_encoded(&buf).await?;
+        let write_time = self.write_time() - write_time;
+        if let Some(metrics) = &self.metrics {
+            // Attribute the single write to the frames by their share of the bytes written
+            for (window_id, bytes) in frames {
+                let share = write_time.mul_f64(bytes as f64 / buf.len() as f64);
+                metrics.on_frame_sent(window_id, bytes, share);
+            }
+        }
+        self.flush().await
+    }
+
+    /// Sets the hooks notified of every frame sent on this stream.\
+    /// The default `GshServiceExt::main` loop sets the hooks returned by `GshServiceExt::metrics`.
+    pub fn set_metrics(&mut self, metrics: Option<Arc<dyn Metrics>>) {
+        self.metrics = metrics;
+    }
+
+    async fn write_message(&mut self, message: ServerMessage) -> Result<()> {
+        let Some(ServerEvent::Frame(frame)) = &message.server_event else {
+            return self.codec.write_internal(message).await;
+        };
+        self.track_frame(frame);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            window_id = frame.window_id,
+            seq = ?frame.seq,
+            segments = frame.segments.len(),
+            keyframe = frame.is_keyframe,
+            "frame sent"
+        );
+        let window_id = frame.window_id;
+        let Some(metrics) = self.metrics.clone() else {
+            return self.codec.write_internal(message).await;
+        };
+        let bytes = message.encoded_len();
+        let write_time = self.write_time();
+        self.codec.write_internal(message).await?;
+        metrics.on_frame_sent(window_id, bytes, self.write_time() - write_time);
+        Ok(())
+    }
+
+    /// Number of sequenced frames (frames with a `seq`) sent but not yet acknowledged by the client.
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight.len()
+    }
+
+    /// Marks the frames of a window up to the acknowledged sequence number as received.\
+    /// The default `GshServiceExt::main` loop calls this for every `FrameAck`.
+    pub fn frame_acked(&mut self, ack: &FrameAck) {
+        self.frames_in_flight
+            .retain(|&(window_id, seq)| window_id != ack.window_id || seq > ack.seq);
+    }
+
+    /// Whether the next frame of the window should be a keyframe (`is_keyframe` with full-window segments).\
+    /// Due for the first frame, every `GshServiceExt::KEYFRAME_INTERVAL` frames and when the client requests one.
+    /// Services sending delta frames should check this before diffing against the previous frame.
+    pub fn keyframe_due(&self, window_id: u32) -> bool {
+        self.keyframes.is_due(window_id)
     }
 
+    /// Forces a keyframe for the next frame of the window.\
+    /// The default `GshServiceExt::main` loop calls this for every `RequestKeyframe` from the client.
+    pub fn request_keyframe(&mut self, window_id: u32) {
+        self.keyframes.request(window_id);
+    }
+
+    /// Sets the number of frames between two keyframes, or `None` to only send keyframes on request.
+    pub fn set_keyframe_interval(&mut self, interval: Option<u32>) {
+        self.keyframes.set_interval(interval);
+    }
+
+    /// Next sequence number for a frame of the window, increasing by one per call from 0.\
+    /// Fill `Frame::seq` with it to have the client acknowledge frames and detect lost ones.
+    pub fn next_frame_seq(&mut self, window_id: u32) -> u64 {
+        let next = self.frame_seqs.entry(window_id).or_default();
+        let seq = *next;
+        *next += 1;
+        seq
+    }
+
+    /// Largest window size as width and height announced to the client in the handshake, `None` before it.\
+    /// The default `GshServiceExt::main` loop clamps resize events to it, see `GshServiceExt::on_resize`.
+    pub fn max_frame_size(&self) -> Option<(u32, u32)> {
+        self.max_frame_size
+    }
+
+    /// Monitors of the client with their geometry and refresh rate, as reported in its `ClientHello`.\
+    /// Use them to size windows to the client's displays, or to pick a `monitor_id` in `WindowSettings`.
+    pub fn monitors(&self) -> &[MonitorInfo] {
+        &self.monitors
+    }
+
+    /// Whether the client reported all of its windows as hidden, e.g. minimized.\
+    /// `false` until the client sends a `ViewportHint`, older clients never do.
+    pub fn all_windows_hidden(&self) -> bool {
+        !self.window_visible.is_empty() && !self.window_visible.values().any(|&visible| visible)
+    }
+
+    /// Whether any window of the client has keyboard focus.\
+    /// `true` until the client reports a focus change.
+    pub fn any_window_focused(&self) -> bool {
+        self.window_focused.is_empty() || self.window_focused.values().any(|&focused| focused)
+    }
+
+    /// Records the visibility of a client window.\
+    /// The default `GshServiceExt::main` loop calls this for every `ViewportHint`.
+    pub fn viewport_changed(&mut self, hint: &ViewportHint) {
+        self.window_visible
+            .insert(hint.window_id, hint.width > 0 && hint.height > 0);
+    }
+
+    /// Records focus changes and closed windows from the window events of a client.\
+    /// The default `GshServiceExt::main` loop calls this for every `UserInput`.
+    pub fn window_input(&mut self, input: &UserInput) {
+        let Some(InputEvent::WindowEvent(event)) = &input.input_event else {
+            return;
+        };
+        match event.action() {
+            WindowAction::Focus => {
+                self.window_focused.insert(input.window_id, true);
+            }
+            WindowAction::Unfocus => {
+                self.window_focused.insert(input.window_id, false);
+            }
+            WindowAction::Close => {
+                self.window_visible.remove(&input.window_id);
+                self.window_focused.remove(&input.window_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn track_frame(&mut self, frame: &Frame) {
+        self.keyframes.sent(frame);
+        // Only the final frame of a group is presented, so only count it once
+        if let Some(seq) = frame.seq {
+            if frame.frame_group_id.is_none() || frame.is_final {
+                self.frames_in_flight.push_back((frame.window_id, seq));
+            }
+        }
+    }
+
+    /// Sends a frame, splitting it into tiles if it exceeds the client's negotiated `max_message_size`.\
+    /// Tiling requires uncompressed segment data, oversized frames with compressed segments fail with `InvalidInput`,
+    /// see `frame::tile_frame`.
+    /// `pixel_bytes` is the frame format's `FrameFormat::bytes_per_pixel`.
+    pub async fn send_frame(&mut self, frame: Frame, pixel_bytes: usize) -> Result<()> {
+        match self.max_message_size() {
+            // The `ServerMessage` envelope adds a tag and length prefix of at most 6 bytes
+            Some(max) if frame.encoded_len() + 6 > max => {
+                for tile in tile_frame(frame, max, pixel_bytes)? {
+                    self.write_message(ServerMessage::from(tile)).await?;
+                }
+                Ok(())
+            }
+            _ => self.send(frame).await,
+        }
+    }
+
+    /// Offers a file for download to the client and sends its contents in chunks.\
+    /// `transfer_id` must be unique among the transfers of this connection.
+    /// Clients without a download directory discard the file.
+    pub async fn send_file(&mut self, transfer_id: u64, name: &str, data: &[u8]) -> Result<()> {
+        self.send(FileOffer {
+            transfer_id,
+            name: name.to_string(),
+            size: data.len() as u64,
+        })
+        .await?;
+        for chunk in file_chunks(transfer_id, data, FILE_CHUNK_SIZE) {
+            self.send(chunk).await?;
+        }
+        self.send(FileTransferEnd { transfer_id }).await?;
+        self.flush().await
+    }
+
+    /// Receives the next client event.\
+    /// Messages with an event unknown to this version of the protocol, e.g. from a newer client, are skipped.
     pub async fn receive(&mut self) -> Result<ClientEvent> {
-        Ok(ClientMessage::decode(self.read_internal().await?)?
-            .client_event
-            .expect("ClientEvent is required"))
+        loop {
+            match ClientMessage::decode(self.codec.read_internal().await?)?.client_event {
+                Some(event) => return Ok(event),
+                None => log::warn!("Skipping client message with an unknown event"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{
+        client_hello, next_event, run_service_with_client, serve_local, wait_for_exit, StaticAuth,
+    };
+    use crate::shared::protocol::{
+        frame::Segment, server_hello_ack::FrameFormat, status_update::StatusType, ClientHello,
+        ServerHelloAck,
+    };
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use tokio::io::AsyncReadExt;
+
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 64;
+    const PIXEL_BYTES: usize = 4;
+    const MAX_MESSAGE_SIZE: usize = 4096;
+
+    /// Sends a single full frame, far larger than `MAX_MESSAGE_SIZE`, then waits for the client to exit.
+    #[derive(Clone)]
+    struct LargeFrameService;
+
+    #[async_trait]
+    impl GshService for LargeFrameService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                format: FrameFormat::Rgba.into(),
+                ..Default::default()
+            }
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            let data = (0..WIDTH * HEIGHT * PIXEL_BYTES as u32)
+                .map(|i| i as u8)
+                .collect();
+            let frame = Frame {
+                window_id: 1,
+                width: WIDTH,
+                height: HEIGHT,
+                segments: vec![Segment {
+                    x: 0,
+                    y: 0,
+                    width: WIDTH,
+                    height: HEIGHT,
+                    data,
+                }],
+                seq: Some(stream.next_frame_seq(1)),
+                ..Default::default()
+            };
+            stream.send_frame(frame, PIXEL_BYTES).await?;
+            stream.flush().await?;
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_are_tiled_under_negotiated_max_message_size() {
+        let hello = ClientHello {
+            max_message_size: MAX_MESSAGE_SIZE as u64,
+            ..client_hello()
+        };
+        let messages = run_service_with_client(LargeFrameService, vec![hello.into()])
+            .await
+            .unwrap();
+        let tiles: Vec<Frame> = messages
+            .iter()
+            .filter_map(|message| match &message.server_event {
+                Some(ServerEvent::Frame(frame)) => Some(frame.clone()),
+                _ => None,
+            })
+            .collect();
+        assert!(tiles.len() > 1, "frame was not tiled");
+        for message in &messages {
+            assert!(message.encoded_len() <= MAX_MESSAGE_SIZE);
+        }
+        // The tiles cover the whole frame, and only the last completes the group
+        let rows: u32 = tiles
+            .iter()
+            .flat_map(|tile| &tile.segments)
+            .map(|segment| segment.height)
+            .sum();
+        assert_eq!(rows, HEIGHT);
+        let group_id = tiles[0].frame_group_id;
+        assert!(group_id.is_some());
+        assert!(tiles.iter().all(|tile| tile.frame_group_id == group_id));
+        let (last, rest) = tiles.split_last().unwrap();
+        assert!(last.is_final && last.seq.is_some());
+        assert!(rest.iter().all(|tile| !tile.is_final && tile.seq.is_none()));
+    }
+
+    /// A message from a newer protocol version, with an event this version doesn't know.
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct FutureMessage {
+        #[prost(string, tag = "999")]
+        event: String,
+    }
+
+    /// Records the events it receives until the client exits.
+    #[derive(Clone, Default)]
+    struct RecordingService {
+        events: Arc<Mutex<Vec<ClientEvent>>>,
+    }
+
+    #[async_trait]
+    impl GshService for RecordingService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            loop {
+                let event = stream.receive().await?;
+                let exit = matches!(
+                    &event,
+                    ClientEvent::StatusUpdate(status) if status.kind() == StatusType::Exit
+                );
+                self.events.lock().unwrap().push(event);
+                if exit {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_events_are_skipped() {
+        let future = FutureMessage {
+            event: "clipboard".to_string(),
+        };
+        let unknown = ClientMessage::decode(future.encode_to_vec().as_slice()).unwrap();
+        assert!(unknown.client_event.is_none());
+        let service = RecordingService::default();
+        run_service_with_client(
+            service.clone(),
+            vec![client_hello().into(), unknown, StatusUpdate::ping().into()],
+        )
+        .await
+        .unwrap();
+        let events = service.events.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            vec![
+                ClientEvent::StatusUpdate(StatusUpdate::ping()),
+                ClientEvent::StatusUpdate(StatusUpdate::exit()),
+            ]
+        );
+    }
+
+    /// Closes the connection right away, recording how long closing took.
+    #[derive(Clone, Default)]
+    struct ClosingService {
+        close_time: Arc<Mutex<Option<Duration>>>,
+    }
+
+    #[async_trait]
+    impl GshService for ClosingService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            let start = Instant::now();
+            stream.close().await?;
+            *self.close_time.lock().unwrap() = Some(start.elapsed());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn connections_close_cleanly_in_both_directions() {
+        let service = ClosingService::default();
+        let server = serve_local(service.clone()).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        match next_event(&mut stream).await.unwrap() {
+            ServerEvent::StatusUpdate(status) => assert_eq!(status.kind(), StatusType::Exit),
+            event => panic!("expected an exit status, got {:?}", event),
+        }
+        // A close_notify ends the stream, an abrupt close would be reported as an error
+        let mut buf = [0; 16];
+        assert_eq!(stream.get_inner().read(&mut buf).await.unwrap(), 0);
+
+        let start = Instant::now();
+        stream.close().await.unwrap();
+        assert!(start.elapsed() < CLOSE_TIMEOUT);
+        server.stop().await.unwrap();
+        // The server saw our close_notify instead of waiting for it to time out
+        let close_time = service.close_time.lock().unwrap().unwrap();
+        assert!(
+            close_time < CLOSE_TIMEOUT,
+            "closing took {:.2?}",
+            close_time
+        );
     }
 }
@@ -1,10 +1,39 @@
-use super::ServerStream;
-use crate::{server::service::GshService, shared::protocol::client_hello, Result};
-use std::sync::Arc;
-use tokio::net::TcpListener;
+use super::{AuthRateLimit, ClientId, ServerStream, ShutdownHandle};
+use crate::{
+    server::service::GshService,
+    shared::{
+        compression::check_dictionary_size,
+        frame::placeholder_frame,
+        protocol::{client_hello, status_update::StatusType, StatusUpdate},
+        HandshakeError,
+    },
+    Result, ServiceError,
+};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use thread_priority::ThreadPriority;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{timeout_at, Instant};
 use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
 
 const DEFAULT_PORT: u16 = 1122;
+/// Longest time a rejected connection is kept open to tell the client why.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most connections rejected at once, further connections are dropped without telling the client why.
+const MAX_PENDING_REJECTIONS: usize = 32;
+
+/// Longest time a client may take from connecting to completing the TLS and protocol handshakes.\
+/// Includes authentication, so it leaves time for users to type a password.
+/// Clients hold a connection slot while handshaking, this keeps slow or idle clients from filling them.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
 
 /// An async server that handles client connections and manages the application service implementing the `AsyncService` trait.
 /// The server listens for incoming connections and spawns a new tasks for each client connection.\
@@ -22,6 +51,10 @@ const DEFAULT_PORT: u16 = 1122;
 pub struct GshServer<ServiceT: GshService> {
     service: ServiceT,
     config: ServerConfig,
+    connection_priority: Option<ThreadPriority>,
+    shutdown: ShutdownHandle,
+    auth_rate_limit: Option<AuthRateLimit>,
+    max_connections: Option<usize>,
 }
 
 impl<ServiceT: GshService> GshServer<ServiceT>
@@ -31,7 +64,49 @@ where
     /// Creates a new `GshServer` instance with the provided server configuration.\
     /// The `ServerConfig` is used to configure the TLS settings for the server.
     pub fn new(service: ServiceT, config: ServerConfig) -> Self {
-        Self { service, config }
+        Self {
+            service,
+            config,
+            connection_priority: None,
+            shutdown: ShutdownHandle::default(),
+            auth_rate_limit: Some(AuthRateLimit::default()),
+            max_connections: None,
+        }
+    }
+
+    /// Returns a handle for gracefully shutting down the server once it is serving.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Runs each client connection on a dedicated OS thread with the given scheduling priority.\
+    /// This lets operators deprioritize background services on a shared host.
+    /// Without it, connections are spawned as tasks on the shared tokio runtime.
+    pub fn with_connection_priority(mut self, priority: ThreadPriority) -> Self {
+        self.connection_priority = Some(priority);
+        self
+    }
+
+    /// Bans client addresses for `window` after `max_failures` failed authentication attempts within it,
+    /// doubling the ban on every further failure.\
+    /// Enabled by default with 5 failures per minute.
+    pub fn with_auth_rate_limit(mut self, max_failures: u32, window: Duration) -> Self {
+        self.auth_rate_limit = Some(AuthRateLimit::new(max_failures, window));
+        self
+    }
+
+    /// Disables rate limiting of failed authentication attempts.
+    pub fn without_auth_rate_limit(mut self) -> Self {
+        self.auth_rate_limit = None;
+        self
+    }
+
+    /// Limits the number of clients connected at once.\
+    /// Further clients are rejected with a `ServerFull` status update until others disconnect.
+    /// Unlimited by default.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
     }
 
     /// Starts the server and listens for incoming connections on the default port (1122).\
@@ -40,10 +115,58 @@ where
         self.serve_port(DEFAULT_PORT).await
     }
 
-    /// Starts the server and listens for incoming connections on the specified port.\
+    /// Starts the server and listens for incoming connections on the specified port of all interfaces.\
+    /// Listens on IPv6 (and IPv4 on dual-stack hosts), falling back to IPv4 only on hosts without IPv6.
     /// This method blocks until the server is stopped or an error occurs.
     pub async fn serve_port(self, port: u16) -> Result<()> {
-        let listener = TcpListener::bind(format!("[::]:{}", port)).await?;
+        let listener = match TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)).await {
+            Ok(listener) => listener,
+            Err(e) if matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::PermissionDenied) => {
+                return Err(e.into())
+            }
+            Err(e) => {
+                log::debug!("IPv6 unavailable ({}), listening on IPv4 only", e);
+                TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).await?
+            }
+        };
+        BoundServer {
+            server: self,
+            listener,
+        }
+        .run()
+        .await
+    }
+
+    /// Starts the server and listens for incoming connections on the given address,
+    /// e.g. `127.0.0.1:1122` to only accept local clients or the address of a specific interface.\
+    /// This method blocks until the server is stopped or an error occurs.
+    pub async fn serve_on(self, addr: SocketAddr) -> Result<()> {
+        self.bind(addr).await?.run().await
+    }
+
+    /// Binds the server to the given address without accepting connections yet.\
+    /// Use port 0 to let the OS choose a free port, and `BoundServer::local_addr` to learn which,
+    /// e.g. to connect test clients to it.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let server = GshServer::new(service, config).bind(([127, 0, 0, 1], 0).into()).await?;
+    /// let addr = server.local_addr()?;
+    /// tokio::spawn(server.run());
+    /// ```
+    pub async fn bind(self, addr: SocketAddr) -> Result<BoundServer<ServiceT>> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| ServiceError::Error(format!("Failed to listen on {}: {}", addr, e)))?;
+        Ok(BoundServer {
+            server: self,
+            listener,
+        })
+    }
+
+    /// Accepts connections on the listener until the server is shut down.\
+    /// After a shutdown is requested through the `ShutdownHandle`, it waits for all clients to disconnect.
+    async fn serve_listener(self, listener: TcpListener) -> Result<()> {
         let tls_acceptor = TlsAcceptor::from(Arc::new(self.config.clone()));
         let service_fullname = std::any::type_name::<ServiceT>();
         let service_name = service_fullname
@@ -55,46 +178,795 @@ where
             service_name,
             listener.local_addr()?
         );
+        let mut shutdown = self.shutdown.signal();
+        // Every connection holds a sender, so the receiver completes once all have finished
+        let (drain_tx, mut drain_rx) = mpsc::channel::<()>(1);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let rejections = Arc::new(Semaphore::new(MAX_PENDING_REJECTIONS));
         loop {
-            let (stream, addr) = listener.accept().await?;
+            let (stream, addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = shutdown.wait() => break,
+            };
+            // Only this loop adds connections, so the count can't grow past the limit in between
+            if let Some(max) = self
+                .max_connections
+                .filter(|&max| connections.load(Ordering::Relaxed) >= max)
+            {
+                let Ok(permit) = rejections.clone().try_acquire_owned() else {
+                    log::debug!("Dropped client {}, too many pending rejections", addr);
+                    continue;
+                };
+                let tls_acceptor = tls_acceptor.clone();
+                tokio::spawn(async move {
+                    Self::reject_full(tls_acceptor, stream, addr, max).await;
+                    drop(permit);
+                });
+                continue;
+            }
+            let connection = LiveConnection::new(connections.clone());
+            let drain = drain_tx.clone();
+            let client_id: ClientId = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
             let tls_acceptor = tls_acceptor.clone();
             let service = self.service.clone();
-            tokio::spawn(async move {
-                let tls_stream = tls_acceptor.accept(stream).await.unwrap();
-                let stream = ServerStream::new(tls_stream);
-                if let Err(e) = Self::handle_client(service, stream, addr).await {
-                    log::error!("Service error {}: {}", addr, e);
+            let auth_rate_limit = self.auth_rate_limit.clone();
+            match self.connection_priority {
+                None => {
+                    let shutdown = self.shutdown.clone();
+                    tokio::spawn(async move {
+                        let _connection = connection;
+                        Self::handle_connection(
+                            service,
+                            tls_acceptor,
+                            stream,
+                            addr,
+                            client_id,
+                            shutdown,
+                            auth_rate_limit,
+                        )
+                        .await;
+                        drop(drain);
+                    });
                 }
-                println!("- Client disconnected from {}", addr);
-            });
+                Some(priority) => {
+                    // Re-register the socket with the dedicated runtime of the connection thread
+                    let stream = stream.into_std()?;
+                    let shutdown = self.shutdown.clone();
+                    std::thread::spawn(move || {
+                        let _drain = drain;
+                        let _connection = connection;
+                        if let Err(e) = thread_priority::set_current_thread_priority(priority) {
+                            log::warn!(
+                                "Failed to set connection thread priority {:?}: {:?}",
+                                priority,
+                                e
+                            );
+                        }
+                        let runtime = match tokio::runtime::Builder::new_current_thread()
+                            .enable_all()
+                            .build()
+                        {
+                            Ok(runtime) => runtime,
+                            Err(e) => {
+                                log::error!("Failed to start connection runtime {}: {}", addr, e);
+                                return;
+                            }
+                        };
+                        runtime.block_on(async move {
+                            match TcpStream::from_std(stream) {
+                                Ok(stream) => {
+                                    Self::handle_connection(
+                                        service,
+                                        tls_acceptor,
+                                        stream,
+                                        addr,
+                                        client_id,
+                                        shutdown,
+                                        auth_rate_limit,
+                                    )
+                                    .await
+                                }
+                                Err(e) => log::error!("Failed to register client {}: {}", addr, e),
+                            }
+                        });
+                    });
+                }
+            }
+        }
+        drop(drain_tx);
+        println!("Server shutting down, waiting for clients to disconnect...");
+        let _ = drain_rx.recv().await;
+        Ok(())
+    }
+
+    /// Tells a client exceeding the connection limit that the server is full, then closes the connection.
+    async fn reject_full(
+        tls_acceptor: TlsAcceptor,
+        stream: TcpStream,
+        addr: std::net::SocketAddr,
+        max_connections: usize,
+    ) {
+        log::warn!(
+            "Rejected client {}, server full ({} connections)",
+            addr,
+            max_connections
+        );
+        let reject = async {
+            let tls_stream = tls_acceptor.accept(stream).await?;
+            let mut stream = ServerStream::new(tls_stream.into());
+            stream
+                .send(StatusUpdate::rejected(
+                    StatusType::ServerFull,
+                    "Too many clients connected, try again later",
+                ))
+                .await?;
+            stream.flush().await?;
+            stream.get_inner().close().await?;
+            stream.codec.wait_for_peer_close(REJECT_TIMEOUT).await;
+            Ok::<(), ServiceError>(())
+        };
+        match tokio::time::timeout(REJECT_TIMEOUT, reject).await {
+            Ok(Err(e)) => log::debug!("Failed to reject client {}: {}", addr, e),
+            Err(_) => log::debug!("Timed out rejecting client {}", addr),
+            Ok(Ok(())) => {}
         }
     }
 
+    /// Accepts the TLS connection and runs the service until the client disconnects.
+    async fn handle_connection(
+        service: ServiceT,
+        tls_acceptor: TlsAcceptor,
+        stream: TcpStream,
+        addr: std::net::SocketAddr,
+        client_id: ClientId,
+        shutdown: ShutdownHandle,
+        auth_rate_limit: Option<AuthRateLimit>,
+    ) {
+        let handshake_deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        // Failed TLS handshakes are routine with mutual TLS, e.g. clients without a trusted certificate
+        let tls_stream = match timeout_at(handshake_deadline, tls_acceptor.accept(stream)).await {
+            Ok(Ok(tls_stream)) => tls_stream,
+            Err(_) => {
+                log::warn!(
+                    "TLS handshake with {} (client {}) timed out",
+                    addr,
+                    client_id
+                );
+                return;
+            }
+            Ok(Err(err)) => {
+                log::warn!(
+                    "TLS handshake with {} (client {}) failed: {}",
+                    addr,
+                    client_id,
+                    err
+                );
+                return;
+            }
+        };
+        let mut stream = ServerStream::with_client_id(tls_stream.into(), client_id);
+        stream.shutdown = shutdown.signal();
+        if let Some(ban) = auth_rate_limit
+            .as_ref()
+            .and_then(|limit| limit.banned_for(addr.ip()))
+        {
+            log::warn!("Rejected banned client {} (client {})", addr, client_id);
+            let message = format!(
+                "Too many failed authentication attempts, try again in {}s",
+                ban.as_secs() + 1
+            );
+            let _ = stream
+                .send(StatusUpdate::rejected(StatusType::AuthFailed, message))
+                .await;
+            let _ = stream.flush().await;
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "client",
+            client_id,
+            %addr,
+            service = %service.server_hello().server_info.map(|info| info.name).unwrap_or_default(),
+            os = tracing::field::Empty,
+        );
+        let session =
+            Self::handle_client(service, stream, addr, auth_rate_limit, handshake_deadline);
+        #[cfg(feature = "tracing")]
+        let session = tracing::Instrument::instrument(session, span);
+        if let Err(e) = session.await {
+            log::error!("Service error {} (client {}): {}", addr, client_id, e);
+        }
+        println!("- Client {} disconnected from {}", client_id, addr);
+    }
+
     /// Handles a client connection.\
-    /// This function performs the TLS handshake and starts the service's main event loop.\
-    async fn handle_client(
+    /// This function performs the protocol handshake, failing if it isn't done by `handshake_deadline`,
+    /// and starts the service's main event loop.
+    pub(crate) async fn handle_client(
         service: ServiceT,
         mut stream: ServerStream,
         addr: std::net::SocketAddr,
+        auth_rate_limit: Option<AuthRateLimit>,
+        handshake_deadline: Instant,
     ) -> Result<()> {
-        let client = super::handshake::handshake(
+        let mut server_hello = service.server_hello();
+        check_dictionary_size(server_hello.compression.as_ref())?;
+        for window in &server_hello.windows {
+            check_dictionary_size(
+                window
+                    .frame_compression(server_hello.compression.as_ref())
+                    .as_ref(),
+            )?;
+        }
+        if service.resumable() {
+            server_hello.resumption_token = super::session::resumption_token();
+        }
+        let handshake = super::handshake::handshake(
             &mut stream,
-            &[crate::shared::PROTOCOL_VERSION],
-            service.server_hello(),
+            crate::shared::MIN_PROTOCOL_VERSION..=crate::shared::PROTOCOL_VERSION,
+            server_hello.clone(),
             service.auth_verifier(),
-        )
-        .await?;
+            service.zstd_levels(),
+        );
+        let handshake = timeout_at(handshake_deadline, handshake)
+            .await
+            .unwrap_or_else(|_| {
+                Err(std::io::Error::new(ErrorKind::TimedOut, "Handshake timed out").into())
+            });
+        if let Some(limit) = &auth_rate_limit {
+            match &handshake {
+                Ok(_) => limit.record_success(addr.ip()),
+                Err(
+                    HandshakeError::InvalidPassword
+                    | HandshakeError::SignatureInvalid
+                    | HandshakeError::InvalidTotp,
+                ) => limit.record_failure(addr.ip()),
+                Err(_) => {}
+            }
+        }
+        let (client, auth_context) = handshake?;
+        stream.auth_context = auth_context;
+        stream.monitors = client.monitors.clone();
+        let resumed =
+            (!client.resumption_token.is_empty()).then(|| client.resumption_token.clone());
+        stream.resumption_token = server_hello.resumption_token.clone();
+        stream.resumed_token = resumed;
+        if client.max_message_size > 0 {
+            // Never raise the limit above our own, the client's size is untrusted input
+            let max = client.max_message_size as usize;
+            let max = stream
+                .max_message_size()
+                .map_or(max, |limit| limit.min(max));
+            stream.set_max_message_size(Some(max));
+        }
         let os: client_hello::Os = client.os.try_into().unwrap_or(client_hello::Os::Unknown);
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("os", tracing::field::debug(os));
         let monitors = client.monitors.len();
         log::info!(
-            "+ Client connected running {:?} {} with {} monitor(s) on {}",
+            "+ Client {} connected running {:?} {} with {} monitor(s) on {}",
+            stream.client_id(),
             os,
             client.os_version,
             monitors,
             addr.port()
         );
+        if let Err(err) = service.on_connect(&stream, &client).await {
+            log::info!(
+                "Client {} rejected by the service: {}",
+                stream.client_id(),
+                err
+            );
+            stream
+                .send(StatusUpdate::rejected(StatusType::Error, err.to_string()))
+                .await?;
+            stream.close().await?;
+            return Ok(());
+        }
+
+        // Cold start fast-path: fill the windows before the service renders its first frame
+        if let Some(color) = service.placeholder_color() {
+            for window in &server_hello.windows {
+                let frame = placeholder_frame(
+                    window.window_id,
+                    color,
+                    window.frame_format(server_hello.format()),
+                    window
+                        .frame_compression(server_hello.compression.as_ref())
+                        .as_ref(),
+                )?;
+                stream.send(frame).await?;
+            }
+            stream.flush().await?;
+        }
 
         service.main(stream).await?;
         Ok(())
     }
 }
+
+/// A server bound to its address, returned by `GshServer::bind`.\
+/// Connections queue up in the OS until `run` starts accepting them.
+#[derive(Debug)]
+pub struct BoundServer<ServiceT: GshService> {
+    server: GshServer<ServiceT>,
+    listener: TcpListener,
+}
+
+impl<ServiceT: GshService> BoundServer<ServiceT>
+where
+    ServiceT: Send + Sync + 'static,
+{
+    /// The address the server is listening on, with the port chosen by the OS if bound to port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Returns a handle for gracefully shutting down the server once it is running.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.server.shutdown_handle()
+    }
+
+    /// Accepts connections until the server is shut down, see `GshServer::serve_port`.
+    pub async fn run(self) -> Result<()> {
+        self.server.serve_listener(self.listener).await
+    }
+}
+
+/// A connection counted towards `GshServer::with_max_connections` until dropped.
+struct LiveConnection(Arc<AtomicUsize>);
+
+impl LiveConnection {
+    fn new(connections: Arc<AtomicUsize>) -> Self {
+        connections.fetch_add(1, Ordering::Relaxed);
+        Self(connections)
+    }
+}
+
+impl Drop for LiveConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{
+        client_hello, next_event, run_service_with_client, serve_local, serve_local_with,
+        wait_for_exit, StaticAuth,
+    };
+    use crate::server::GshServiceExt;
+    use crate::shared::protocol::{
+        client_hello::{MonitorInfo, Os},
+        frame::Segment,
+        server_hello_ack::{FrameFormat, WindowSettings},
+        server_message::ServerEvent,
+        ClientHello, Frame, ServerHelloAck,
+    };
+    use async_trait::async_trait;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Mutex;
+
+    /// Records the ID and thread niceness of each connection, then waits for the client to exit.
+    #[derive(Clone, Default)]
+    struct TestService {
+        client_ids: Arc<Mutex<Vec<ClientId>>>,
+        niceness: Arc<Mutex<Vec<i32>>>,
+    }
+
+    #[async_trait]
+    impl GshService for TestService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, mut stream: ServerStream) -> Result<()> {
+            self.client_ids.lock().unwrap().push(stream.client_id());
+            if let Some(niceness) = thread_niceness() {
+                self.niceness.lock().unwrap().push(niceness);
+            }
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn connections_get_distinct_client_ids() {
+        let service = TestService::default();
+        let server = serve_local(service.clone()).await.unwrap();
+        let (mut first, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        let (mut second, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+        server.stop().await.unwrap();
+        let client_ids = service.client_ids.lock().unwrap().clone();
+        assert_eq!(client_ids.len(), 2);
+        assert_ne!(client_ids[0], client_ids[1]);
+    }
+
+    #[tokio::test]
+    async fn loopback_ephemeral_port_is_reachable() {
+        let (key, private_key) = crate::shared::cert::self_signed(&["localhost"]).unwrap();
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![key.cert.der().clone()], private_key)
+            .unwrap();
+        let server = GshServer::new(TestService::default(), config)
+            .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        assert_eq!(addr.ip(), Ipv4Addr::LOCALHOST);
+        assert_ne!(addr.port(), 0);
+        assert!(TcpStream::connect(addr).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn client_connects_to_ephemeral_port_server() {
+        // Binds port 0 and runs the server on the address it reports
+        let service = TestService::default();
+        let server = serve_local(service.clone()).await.unwrap();
+        assert_ne!(server.addr.port(), 0);
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+        assert_eq!(service.client_ids.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn clients_over_the_limit_are_rejected_as_full() {
+        let server = serve_local_with(TestService::default(), |server| {
+            server.with_max_connections(1)
+        })
+        .await
+        .unwrap();
+        let (mut first, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        match server.handshake(StaticAuth::default()).await {
+            Err(ServiceError::HandshakeError(HandshakeError::Rejected(kind, _))) => {
+                assert_eq!(kind, StatusType::ServerFull)
+            }
+            result => panic!("expected a server full rejection, got {:?}", result.err()),
+        }
+        first.close().await.unwrap();
+
+        // The slot frees up once the first client has disconnected
+        let mut attempts = 0;
+        let mut second = loop {
+            match server.handshake(StaticAuth::default()).await {
+                Ok((stream, _)) => break stream,
+                Err(_) if attempts < 50 => attempts += 1,
+                Err(err) => panic!("connection slot not freed: {}", err),
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        second.close().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    /// Runs the default service loop, recording when it exits.
+    #[derive(Clone, Default)]
+    struct LoopService {
+        exited: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl GshService for LoopService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for LoopService {
+        async fn on_exit(&mut self, _stream: &mut ServerStream) -> Result<()> {
+            self.exited.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_disconnects_clients_and_returns_accept_loop() {
+        let service = LoopService::default();
+        let server = serve_local(service.clone()).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        server.shutdown.shutdown();
+        match next_event(&mut stream).await.unwrap() {
+            ServerEvent::StatusUpdate(status) => assert_eq!(status.kind(), StatusType::Exit),
+            event => panic!("expected an exit status, got {:?}", event),
+        }
+        stream.close().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server.task)
+            .await
+            .expect("accept loop did not return")
+            .unwrap()
+            .unwrap();
+        assert!(service.exited.load(Ordering::SeqCst));
+    }
+
+    /// Records the fields of all `client` spans.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct CaptureSpans {
+        spans: Arc<Mutex<Vec<&'static tracing::Metadata<'static>>>>,
+        fields: Arc<Mutex<Vec<(&'static str, String)>>>,
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for CaptureSpans {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.fields
+                .lock()
+                .unwrap()
+                .push((field.name(), format!("{:?}", value)));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for CaptureSpans {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            if span.metadata().name() == "client" {
+                spans.push(span.metadata());
+                span.record(&mut self.clone());
+            }
+            tracing::span::Id::from_u64(spans.len() as u64 + 1)
+        }
+
+        fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, _event: &tracing::Event<'_>) {}
+
+        fn enter(&self, _span: &tracing::span::Id) {}
+
+        fn exit(&self, _span: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn connection_span_captures_client_fields() {
+        let spans = CaptureSpans::default();
+        let _guard = tracing::subscriber::set_default(spans.clone());
+        let server = serve_local(TestService::default()).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        let addr = stream.get_inner().get_ref().0.local_addr().unwrap();
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+
+        let client_spans = spans.spans.lock().unwrap().clone();
+        assert_eq!(client_spans.len(), 1);
+        // The OS is only known after the handshake, it is recorded into the entered span then
+        assert!(client_spans[0].fields().field("os").is_some());
+        let fields = spans.fields.lock().unwrap().clone();
+        let field = |name| {
+            fields
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_else(|| panic!("no {} field in {:?}", name, fields))
+        };
+        assert!(field("client_id").parse::<ClientId>().is_ok());
+        assert_eq!(field("addr"), addr.to_string());
+        assert_eq!(field("service"), "");
+    }
+
+    /// Niceness of the current thread, from `/proc` on Linux.
+    fn thread_niceness() -> Option<i32> {
+        let stat = std::fs::read_to_string("/proc/thread-self/stat").ok()?;
+        // Fields after the parenthesized command name start at the third field, the niceness is the 19th
+        let (_, fields) = stat.rsplit_once(')')?;
+        fields.split_whitespace().nth(16)?.parse().ok()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn low_priority_connections_run_with_requested_niceness() {
+        let service = TestService::default();
+        let server = serve_local_with(service.clone(), |server| {
+            server.with_connection_priority(ThreadPriority::Min)
+        })
+        .await
+        .unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+        let niceness = service.niceness.lock().unwrap().clone();
+        assert_eq!(niceness, vec![thread_priority::unix::NICENESS_MIN as i32]);
+    }
+
+    /// Sends a single 2x2 frame to its window, then waits for the client to exit.
+    #[derive(Clone)]
+    struct PlaceholderService;
+
+    #[async_trait]
+    impl GshService for PlaceholderService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                format: FrameFormat::Rgba.into(),
+                windows: vec![WindowSettings {
+                    window_id: 1,
+                    width: 2,
+                    height: 2,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }
+        }
+
+        fn placeholder_color(&self) -> Option<[u8; 3]> {
+            Some([10, 20, 30])
+        }
+
+        async fn main(self, mut stream: ServerStream) -> Result<()> {
+            let frame = Frame {
+                window_id: 1,
+                width: 2,
+                height: 2,
+                segments: vec![Segment {
+                    x: 0,
+                    y: 0,
+                    width: 2,
+                    height: 2,
+                    data: vec![0xff; 16],
+                }],
+                ..Default::default()
+            };
+            stream.send(frame).await?;
+            stream.flush().await?;
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn placeholder_frame_precedes_first_frame() {
+        let messages = run_service_with_client(PlaceholderService, vec![client_hello().into()])
+            .await
+            .unwrap();
+        let events: Vec<ServerEvent> = messages
+            .into_iter()
+            .filter_map(|message| message.server_event)
+            .collect();
+        let [ServerEvent::ServerHelloAck(_), ServerEvent::Frame(placeholder), ServerEvent::Frame(frame)] =
+            events.as_slice()
+        else {
+            panic!("unexpected messages: {:?}", events);
+        };
+        assert_eq!((placeholder.width, placeholder.height), (1, 1));
+        assert_eq!(placeholder.segments[0].data, vec![10, 20, 30, 255]);
+        assert_eq!((frame.width, frame.height), (2, 2));
+    }
+
+    /// Only accepts Linux clients, sending them a single frame.
+    #[derive(Clone)]
+    struct LinuxOnlyService;
+
+    #[async_trait]
+    impl GshService for LinuxOnlyService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn on_connect(&self, _stream: &ServerStream, client: &ClientHello) -> Result<()> {
+            match client.os() {
+                Os::Linux => Ok(()),
+                os => Err(ServiceError::Error(format!(
+                    "{} clients are not supported",
+                    os.as_str_name()
+                ))),
+            }
+        }
+
+        async fn main(self, mut stream: ServerStream) -> Result<()> {
+            stream
+                .send(Frame {
+                    window_id: 1,
+                    ..Default::default()
+                })
+                .await?;
+            stream.flush().await?;
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn on_connect_rejects_clients_by_os() {
+        let connect = |os: Os| {
+            let hello = ClientHello {
+                os: os as i32,
+                ..client_hello()
+            };
+            run_service_with_client(LinuxOnlyService, vec![hello.into()])
+        };
+
+        let events: Vec<ServerEvent> = connect(Os::Windows)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|message| message.server_event)
+            .collect();
+        let [ServerEvent::ServerHelloAck(_), ServerEvent::StatusUpdate(status), rest @ ..] =
+            events.as_slice()
+        else {
+            panic!("unexpected messages: {:?}", events);
+        };
+        assert_eq!(status.kind(), StatusType::Error);
+        assert!(status.message().unwrap().contains("WINDOWS"));
+        // The service never runs for rejected clients
+        assert!(!rest
+            .iter()
+            .any(|event| matches!(event, ServerEvent::Frame(_))));
+
+        let events: Vec<ServerEvent> = connect(Os::Linux)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|message| message.server_event)
+            .collect();
+        assert!(
+            matches!(
+                events.as_slice(),
+                [ServerEvent::ServerHelloAck(_), ServerEvent::Frame(_), ..]
+            ),
+            "unexpected messages: {:?}",
+            events
+        );
+    }
+
+    /// Records the monitors reported by the client, then waits for it to exit.
+    #[derive(Clone, Default)]
+    struct MonitorService {
+        monitors: Arc<Mutex<Vec<MonitorInfo>>>,
+    }
+
+    #[async_trait]
+    impl GshService for MonitorService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, mut stream: ServerStream) -> Result<()> {
+            *self.monitors.lock().unwrap() = stream.monitors().to_vec();
+            wait_for_exit(&mut stream).await
+        }
+    }
+
+    #[tokio::test]
+    async fn service_reads_the_client_monitors() {
+        let monitors = vec![
+            MonitorInfo {
+                monitor_id: 1,
+                x: 0,
+                y: 0,
+                width: 2560,
+                height: 1440,
+                refresh_hz: 144,
+            },
+            MonitorInfo {
+                monitor_id: 2,
+                x: 2560,
+                y: 0,
+                width: 1920,
+                height: 1080,
+                refresh_hz: 60,
+            },
+        ];
+        let service = MonitorService::default();
+        let hello = ClientHello {
+            monitors: monitors.clone(),
+            ..client_hello()
+        };
+        run_service_with_client(service.clone(), vec![hello.into()])
+            .await
+            .unwrap();
+        assert_eq!(*service.monitors.lock().unwrap(), monitors);
+    }
+}
@@ -1,14 +1,28 @@
-use super::ServerStream;
+use super::{
+    keyframe::DEFAULT_KEYFRAME_INTERVAL,
+    metrics::{event_kind, Metrics},
+    ServerStream,
+};
 use crate::{
     shared::{
         auth::AuthVerifier,
-        protocol::{client_message::ClientEvent, status_update::StatusType, ServerHelloAck},
+        protocol::{
+            client_message::ClientEvent,
+            status_update::StatusType,
+            user_input::{window_event::WindowAction, InputEvent},
+            ClientHello, NetworkStats, ServerHelloAck, StatusUpdate, ViewportHint,
+        },
     },
     Result,
 };
 use async_trait::async_trait;
 use std::io::ErrorKind;
-use tokio::io::AsyncWriteExt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Frames allowed in flight per network round trip before the adaptive frame rate is lowered.
+const FRAMES_PER_RTT: u32 = 4;
 
 /// A trait for an async service that can be run in a separate thread.
 /// The service is responsible for handling client events and sending frames to the client.
@@ -26,6 +40,36 @@ pub trait GshService: Clone + Send + Sync + 'static {
         None
     }
 
+    /// Placeholder color for the cold start fast-path.\
+    /// If set, a tiny solid-color frame is sent to every initial window right after the handshake,
+    /// so windows are not left black while the service renders its first real frame.
+    fn placeholder_color(&self) -> Option<[u8; 3]> {
+        None
+    }
+
+    /// Whether clients are issued a resumption token to rejoin their session after a disconnect.\
+    /// Resumable services keep the state of disconnected clients in a `SessionRegistry`,
+    /// and should send full frames after resuming, as the client may have missed some.
+    fn resumable(&self) -> bool {
+        false
+    }
+
+    /// Zstd compression levels clients may choose from by announcing a preferred level in their `ClientHello`.\
+    /// Clients without a preference, or all clients if `None`, get the level of the `server_hello` compression.
+    /// The negotiated level is sent in the `ServerHelloAck`, compress frames with `ServerStream::zstd_level`.
+    fn zstd_levels(&self) -> Option<RangeInclusive<i32>> {
+        None
+    }
+
+    /// Called for every client after a successful handshake, before `main`.\
+    /// Use it for custom post-authentication logic, e.g. logging analytics or checking a license server.
+    /// The client's identity is available from `stream.auth_context()`.
+    /// Returning an error rejects the connection: the client is sent the error message and disconnected.
+    #[allow(unused_variables)]
+    async fn on_connect(&self, stream: &ServerStream, client: &ClientHello) -> Result<()> {
+        Ok(())
+    }
+
     /// Main event loop for the service.\
     /// This is running in a separate thread, handling client events and sending frames back to the client.
     async fn main(self, stream: ServerStream) -> Result<()>
@@ -42,6 +86,55 @@ pub trait GshService: Clone + Send + Sync + 'static {
 pub trait GshServiceExt: GshService {
     const MAX_FPS: u32 = 60;
     const FRAME_TIME_NS: u64 = 1_000_000_000 / Self::MAX_FPS as u64; // in nanoseconds
+    /// Lowest frame rate the adaptive pacing slows down to on poor network conditions.
+    const MIN_FPS: u32 = 10;
+    /// Whether to log a warning when a tick (render, encode and send) exceeds the frame budget.
+    const LOG_SLOW_FRAMES: bool = true;
+    /// Minimum interval between two slow frame warnings, further slow frames are only counted.
+    const SLOW_FRAME_LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+    /// Opt-in keepalive for detecting dead connections.\
+    /// If no message is received from the client for this long, a ping is sent.
+    /// If the client still doesn't respond within the same interval, the connection is considered dead and `on_exit` is called.
+    const IDLE_TIMEOUT: Option<std::time::Duration> = None;
+    /// Opt-in flow control for sequenced frames (frames with a `seq`, acknowledged by the client with a `FrameAck`).\
+    /// While this many sequenced frames are unacknowledged, `on_tick` is skipped so frames don't pile up in network buffers.
+    /// A low limit bounds the latency a slow client accumulates, but lowers throughput on high-latency links,
+    /// where the connection idles while waiting for acks. Allow at least a round trip worth of frames there.
+    const MAX_FRAMES_IN_FLIGHT: Option<usize> = None;
+    /// Frames per window between two keyframes, see `ServerStream::keyframe_due`.\
+    /// Clients also request keyframes when they detect lost sequenced frames. `None` to only send keyframes on request.
+    const KEYFRAME_INTERVAL: Option<u32> = Some(DEFAULT_KEYFRAME_INTERVAL);
+    /// Target time between two ticks of the default `main` event loop.\
+    /// Starts at `FRAME_TIME_NS` and is re-evaluated whenever the client reports its network conditions,
+    /// slowing down towards `MIN_FPS` on high latency or when frames pile up on the client.
+    /// Override to implement a custom pacing policy.
+    fn target_frame_time(&self, network: Option<&NetworkStats>) -> Duration {
+        let frame_time = Duration::from_nanos(Self::FRAME_TIME_NS);
+        match network {
+            Some(stats) => adaptive_frame_time(
+                frame_time,
+                Duration::from_secs(1) / Self::MIN_FPS.max(1),
+                stats,
+            ),
+            None => frame_time,
+        }
+    }
+
+    /// Whether the default `main` loop should call `on_tick`, checked before every tick.\
+    /// Always `true` by default. Override to save server CPU while the user can't see the service,
+    /// e.g. `!stream.all_windows_hidden()` to pause while the client is minimized, or `stream.any_window_focused()`.
+    /// Events are still handled while paused.
+    #[allow(unused_variables)]
+    fn should_tick(&self, stream: &ServerStream) -> bool {
+        true
+    }
+
+    /// Hooks notified of frames sent, events received and ticks of the default `main` loop, e.g. `LogMetrics`.\
+    /// Called once when the loop starts, `None` by default.
+    fn metrics(&self) -> Option<Arc<dyn Metrics>> {
+        None
+    }
+
     /// Start up function for the service.\
     /// This is called when the service is started and can be used to perform any necessary initialization.
     async fn on_startup(&mut self, _stream: &mut ServerStream) -> Result<()> {
@@ -62,6 +155,29 @@ pub trait GshServiceExt: GshService {
         Ok(())
     }
 
+    /// Size of a window resized by the client, before the resize event is passed to `on_event`.\
+    /// Clamps to `ServerStream::max_frame_size` by default, so a client can't make the service allocate huge frame buffers.
+    /// Override to apply another policy, e.g. keeping an aspect ratio.
+    fn on_resize(&self, stream: &ServerStream, width: u32, height: u32) -> (u32, u32) {
+        match stream.max_frame_size() {
+            Some((max_width, max_height)) => (width.min(max_width), height.min(max_height)),
+            None => (width, height),
+        }
+    }
+
+    /// Handle a change of the visible area of a client window.\
+    /// The hint is advisory, expensive services can use it to skip rendering what the user can't see,
+    /// but must still send frames of the full window size.
+    #[allow(unused_variables)]
+    async fn on_viewport_change(
+        &mut self,
+        stream: &mut ServerStream,
+        hint: ViewportHint,
+    ) -> Result<()> {
+        log::trace!("Viewport changed: {:?}", hint);
+        Ok(())
+    }
+
     /// Graceful exit of the service.\
     /// This is called when the service receives a `StatusUpdate` event with `Exit` status.
     async fn on_exit(&mut self, _stream: &mut ServerStream) -> Result<()> {
@@ -75,30 +191,97 @@ pub trait GshServiceExt: GshService {
     where
         Self: Sized,
     {
+        stream.set_keyframe_interval(Self::KEYFRAME_INTERVAL);
+        let metrics = self.metrics();
+        stream.set_metrics(metrics.clone());
         self.on_startup(&mut stream).await?;
 
         log::trace!("Starting service main loop...");
         // Use a tokio interval for precise pacing and natural yielding.
-        let mut tick = tokio::time::interval(std::time::Duration::from_nanos(Self::FRAME_TIME_NS));
+        let mut frame_time = self.target_frame_time(None);
+        let mut tick = tokio::time::interval(frame_time);
+        let mut slow_frames = SlowFrameLog::default();
+        let mut last_received = Instant::now();
+        let mut ping_sent: Option<Instant> = None;
+        let mut shutdown = stream.shutdown_signal();
         'running: loop {
             tokio::select! {
+                _ = shutdown.wait() => {
+                    log::trace!("Server shutting down, disconnecting client...");
+                    let _ = stream.close().await;
+                    self.on_exit(&mut stream).await?;
+                    break 'running;
+                }
                 res = stream.receive() => {
+                    if let Ok(event) = &res {
+                        last_received = Instant::now();
+                        ping_sent = None;
+                        if let Some(metrics) = &metrics {
+                            metrics.on_event(event_kind(event));
+                        }
+                    }
                     match res {
+                        Ok(ClientEvent::StatusUpdate(status_update)) if status_update.kind == StatusType::Ping as i32 => {
+                            stream.send(StatusUpdate::pong()).await?;
+                            stream.flush().await?;
+                        }
+                        Ok(ClientEvent::StatusUpdate(status_update)) if status_update.kind == StatusType::Pong as i32 => {
+                            log::trace!("Client responded to ping");
+                        }
                         Ok(ClientEvent::StatusUpdate(status_update)) => {
                             if status_update.kind == StatusType::Exit as i32 {
                                 log::trace!("Client gracefully disconnected!");
-                                stream.get_inner().get_mut().1.send_close_notify();
-                                let _ = stream.get_inner().get_mut().0.flush().await;
-                                let _ = stream.get_inner().get_mut().0.shutdown().await;
+                                let _ = stream.close().await;
                                 self.on_exit(&mut stream).await?;
                                 drop(stream);
                                 break 'running;
                             }
                             self.on_event(&mut stream, ClientEvent::StatusUpdate(status_update)).await?;
                         }
-                        Ok(ClientEvent::UserInput(user_input)) => {
+                        Ok(ClientEvent::UserInput(mut user_input)) => {
+                            stream.window_input(&user_input);
+                            if let Some(InputEvent::WindowEvent(event)) = &mut user_input.input_event {
+                                if event.action() == WindowAction::Resize {
+                                    let (width, height) = self.on_resize(&stream, event.width, event.height);
+                                    if (width, height) != (event.width, event.height) {
+                                        log::debug!("Clamped resize of window {} from {}x{} to {}x{}", user_input.window_id, event.width, event.height, width, height);
+                                    }
+                                    event.width = width;
+                                    event.height = height;
+                                }
+                            }
                             self.on_event(&mut stream, ClientEvent::UserInput(user_input)).await?;
                         }
+                        Ok(ClientEvent::FrameAck(frame_ack)) => {
+                            stream.frame_acked(&frame_ack);
+                            self.on_event(&mut stream, ClientEvent::FrameAck(frame_ack)).await?;
+                        }
+                        Ok(ClientEvent::NetworkStats(stats)) => {
+                            let target = self.target_frame_time(Some(&stats));
+                            if target != frame_time {
+                                log::debug!("Adjusting frame time to {:.2?} for {:?}", target, stats);
+                                frame_time = target;
+                                tick = tokio::time::interval_at(tokio::time::Instant::now() + frame_time, frame_time);
+                            }
+                            self.on_event(&mut stream, ClientEvent::NetworkStats(stats)).await?;
+                        }
+                        Ok(ClientEvent::RequestKeyframe(request)) => {
+                            log::trace!("Client requested a keyframe for window {}", request.window_id);
+                            stream.request_keyframe(request.window_id);
+                            self.on_event(&mut stream, ClientEvent::RequestKeyframe(request)).await?;
+                        }
+                        Ok(
+                            event @ (ClientEvent::FileTransferBegin(_)
+                            | ClientEvent::FileChunk(_)
+                            | ClientEvent::FileTransferEnd(_)),
+                        ) => {
+                            // Reassembled by the service, see `transfer::FileAssembler`
+                            self.on_event(&mut stream, event).await?;
+                        }
+                        Ok(ClientEvent::ViewportHint(hint)) => {
+                            stream.viewport_changed(&hint);
+                            self.on_viewport_change(&mut stream, hint).await?;
+                        }
                         Ok(other) => {
                             log::trace!("Received data: {:?}", &other);
                             log::trace!("Unknown message type, ignoring...");
@@ -125,8 +308,47 @@ pub trait GshServiceExt: GshService {
                     }
                 }
                 _ = tick.tick() => {
+                    if let Some(idle_timeout) = Self::IDLE_TIMEOUT {
+                        match ping_sent {
+                            Some(sent) if sent.elapsed() >= idle_timeout => {
+                                log::warn!("Client did not respond to ping within {:?}, disconnecting.", idle_timeout);
+                                self.on_exit(&mut stream).await?;
+                                break 'running;
+                            }
+                            None if last_received.elapsed() >= idle_timeout => {
+                                log::trace!("No message from client in {:?}, sending ping", idle_timeout);
+                                stream.send(StatusUpdate::ping()).await?;
+                                stream.flush().await?;
+                                ping_sent = Some(Instant::now());
+                            }
+                            _ => {}
+                        }
+                    }
+                    if Self::MAX_FRAMES_IN_FLIGHT.is_some_and(|max| stream.frames_in_flight() >= max) {
+                        log::trace!("{} frames awaiting acknowledgment, skipping tick", stream.frames_in_flight());
+                        continue 'running;
+                    }
+                    if !self.should_tick(&stream) {
+                        log::trace!("Service paused, skipping tick");
+                        continue 'running;
+                    }
                     // Periodic tick; call on_tick which may render and send frames.
+                    let tick_start = Instant::now();
+                    let write_time = stream.write_time();
                     self.on_tick(&mut stream).await?;
+                    let tick_time = tick_start.elapsed();
+                    let send_time = stream.write_time() - write_time;
+                    if let Some(metrics) = &metrics {
+                        metrics.on_tick(tick_time.saturating_sub(send_time), send_time);
+                    }
+                    if Self::LOG_SLOW_FRAMES {
+                        slow_frames.record(
+                            tick_time,
+                            send_time,
+                            frame_time,
+                            Self::SLOW_FRAME_LOG_INTERVAL,
+                        );
+                    }
                 }
             }
         }
@@ -134,3 +356,682 @@ pub trait GshServiceExt: GshService {
         Ok(())
     }
 }
+
+/// Frame time adapted to the network conditions reported by a client, between `min_frame_time` and `max_frame_time`.\
+/// Allows `FRAMES_PER_RTT` frames per round trip, and backs off further for every frame the client is behind on or missed.
+pub fn adaptive_frame_time(
+    min_frame_time: Duration,
+    max_frame_time: Duration,
+    stats: &NetworkStats,
+) -> Duration {
+    let rtt = Duration::from_millis(stats.rtt_ms as u64);
+    let backlog = stats
+        .buffer_depth
+        .saturating_add(stats.dropped_frames)
+        .min(8);
+    let frame_time = min_frame_time.max(rtt / FRAMES_PER_RTT) * (1 + backlog);
+    frame_time.clamp(min_frame_time, max_frame_time.max(min_frame_time))
+}
+
+/// Rate-limited warnings for ticks exceeding the frame budget.
+#[derive(Debug, Default)]
+struct SlowFrameLog {
+    last_logged: Option<Instant>,
+    suppressed: usize,
+}
+
+impl SlowFrameLog {
+    /// Records a tick taking `total` time, of which `send` was spent writing to the stream.
+    fn record(&mut self, total: Duration, send: Duration, budget: Duration, interval: Duration) {
+        if total <= budget {
+            return;
+        }
+        if self
+            .last_logged
+            .is_some_and(|last| last.elapsed() < interval)
+        {
+            self.suppressed += 1;
+            return;
+        }
+        log::warn!(
+            "Slow frame: tick took {:.2?} (budget {:.2?}): render/encode {:.2?}, send {:.2?}{}",
+            total,
+            budget,
+            total.saturating_sub(send),
+            send,
+            if self.suppressed > 0 {
+                format!(" ({} more slow frames since last warning)", self.suppressed)
+            } else {
+                String::new()
+            }
+        );
+        self.last_logged = Some(Instant::now());
+        self.suppressed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::{
+        client_hello, next_event, run_service_with_client, serve_local, StaticAuth,
+    };
+    use crate::shared::protocol::{
+        server_message::ServerEvent, user_input::WindowEvent, ClientMessage, Frame,
+        RequestKeyframe, UserInput,
+    };
+    use crate::ServiceError;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Warnings logged by all tests of this binary, as `log` allows a single logger per process.
+    struct CaptureLog(Mutex<Vec<String>>);
+
+    impl log::Log for CaptureLog {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURED: CaptureLog = CaptureLog(Mutex::new(Vec::new()));
+
+    /// Starts capturing warnings, returning the ones captured so far.
+    fn captured_warnings() -> Vec<String> {
+        let _ = log::set_logger(&CAPTURED);
+        log::set_max_level(log::LevelFilter::Warn);
+        CAPTURED.0.lock().unwrap().clone()
+    }
+
+    /// Service taking three frame budgets for every tick.
+    #[derive(Clone)]
+    struct SlowService;
+
+    #[async_trait]
+    impl GshService for SlowService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for SlowService {
+        async fn on_tick(&mut self, _stream: &mut ServerStream) -> Result<()> {
+            tokio::time::sleep(Duration::from_nanos(Self::FRAME_TIME_NS * 3)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_tick_is_logged_with_phase_timings() {
+        captured_warnings();
+        let server = serve_local(SlowService).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+
+        let warnings = captured_warnings();
+        let slow_frames: Vec<&String> = warnings
+            .iter()
+            .filter(|warning| warning.starts_with("Slow frame"))
+            .collect();
+        assert!(
+            !slow_frames.is_empty(),
+            "no slow frame logged: {:?}",
+            warnings
+        );
+        assert!(slow_frames
+            .iter()
+            .all(|warning| warning.contains("render/encode") && warning.contains("send")));
+    }
+
+    /// Service with a short idle timeout, recording when it exits.
+    #[derive(Clone, Default)]
+    struct IdleService {
+        exited: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl GshService for IdleService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for IdleService {
+        const IDLE_TIMEOUT: Option<Duration> = Some(Duration::from_millis(100));
+
+        async fn on_exit(&mut self, _stream: &mut ServerStream) -> Result<()> {
+            self.exited.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn silent_client_is_pinged_then_disconnected() {
+        let service = IdleService::default();
+        let exited = service.exited.clone();
+        let server = serve_local(service).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+
+        // The client never answers the ping
+        match next_event(&mut stream).await.unwrap() {
+            ServerEvent::StatusUpdate(status) => assert_eq!(status.kind(), StatusType::Ping),
+            event => panic!("expected a ping, got {:?}", event),
+        }
+        match next_event(&mut stream).await {
+            Err(ServiceError::IoError(err)) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof)
+            }
+            result => panic!("expected the connection to close, got {:?}", result),
+        }
+        assert!(exited.load(Ordering::SeqCst));
+        server.stop().await.unwrap();
+    }
+
+    #[test]
+    fn high_rtt_lengthens_frame_time() {
+        let frame_time = Duration::from_nanos(SlowService::FRAME_TIME_NS);
+        let stats = |rtt_ms, dropped_frames| NetworkStats {
+            rtt_ms,
+            dropped_frames,
+            buffer_depth: 0,
+        };
+        assert_eq!(SlowService.target_frame_time(None), frame_time);
+        assert_eq!(
+            SlowService.target_frame_time(Some(&stats(10, 0))),
+            frame_time
+        );
+        // Four frames per round trip
+        assert_eq!(
+            SlowService.target_frame_time(Some(&stats(200, 0))),
+            Duration::from_millis(50)
+        );
+        assert_eq!(
+            SlowService.target_frame_time(Some(&stats(10, 1))),
+            frame_time * 2
+        );
+        // Never slower than `MIN_FPS`
+        assert_eq!(
+            SlowService.target_frame_time(Some(&stats(2000, 0))),
+            Duration::from_millis(100)
+        );
+    }
+
+    /// Sends a sequenced frame every tick, allowing three unacknowledged frames.
+    #[derive(Clone, Default)]
+    struct SequencedService {
+        sent: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl GshService for SequencedService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for SequencedService {
+        const MAX_FRAMES_IN_FLIGHT: Option<usize> = Some(3);
+
+        async fn on_tick(&mut self, stream: &mut ServerStream) -> Result<()> {
+            let seq = self.sent.fetch_add(1, Ordering::SeqCst);
+            stream
+                .send(Frame {
+                    window_id: 1,
+                    seq: Some(seq),
+                    ..Default::default()
+                })
+                .await?;
+            stream.flush().await?;
+            Ok(())
+        }
+    }
+
+    /// Receives the next frame, returning its sequence number.
+    async fn next_seq(stream: &mut crate::client::ClientStream) -> Option<u64> {
+        match next_event(stream).await.unwrap() {
+            ServerEvent::Frame(frame) => frame.seq,
+            event => panic!("expected a frame, got {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn never_acking_client_throttles_ticks() {
+        let service = SequencedService::default();
+        let sent = service.sent.clone();
+        let server = serve_local(service).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        for seq in 0..3 {
+            assert_eq!(next_seq(&mut stream).await, Some(seq));
+        }
+        // Ten frame budgets pass without a single frame more
+        tokio::time::sleep(Duration::from_nanos(SequencedService::FRAME_TIME_NS * 10)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), 3);
+
+        // Acknowledging the first frame lets exactly one more through
+        stream.send(ClientMessage::frame_ack(1, 0)).await.unwrap();
+        stream.flush().await.unwrap();
+        assert_eq!(next_seq(&mut stream).await, Some(3));
+        tokio::time::sleep(Duration::from_nanos(SequencedService::FRAME_TIME_NS * 10)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), 4);
+
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    /// Records the viewport hints it receives.
+    #[derive(Clone, Default)]
+    struct ViewportService {
+        hints: Arc<Mutex<Vec<ViewportHint>>>,
+    }
+
+    #[async_trait]
+    impl GshService for ViewportService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for ViewportService {
+        async fn on_viewport_change(
+            &mut self,
+            _stream: &mut ServerStream,
+            hint: ViewportHint,
+        ) -> Result<()> {
+            self.hints.lock().unwrap().push(hint);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn viewport_hint_is_delivered_to_service() {
+        let service = ViewportService::default();
+        let hint = ViewportHint {
+            window_id: 1,
+            x: 10,
+            y: 20,
+            width: 300,
+            height: 200,
+        };
+        run_service_with_client(service.clone(), vec![client_hello().into(), hint.into()])
+            .await
+            .unwrap();
+        assert_eq!(*service.hints.lock().unwrap(), vec![hint]);
+    }
+
+    /// Sends a frame every tick, a keyframe only when one is due.
+    #[derive(Clone)]
+    struct KeyframeService;
+
+    #[async_trait]
+    impl GshService for KeyframeService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for KeyframeService {
+        const KEYFRAME_INTERVAL: Option<u32> = None;
+
+        async fn on_tick(&mut self, stream: &mut ServerStream) -> Result<()> {
+            let is_keyframe = stream.keyframe_due(1);
+            stream
+                .send(Frame {
+                    window_id: 1,
+                    is_keyframe,
+                    ..Default::default()
+                })
+                .await?;
+            stream.flush().await?;
+            Ok(())
+        }
+    }
+
+    /// Receives the next frame, returning whether it is a keyframe.
+    async fn next_is_keyframe(stream: &mut crate::client::ClientStream) -> bool {
+        match next_event(stream).await.unwrap() {
+            ServerEvent::Frame(frame) => frame.is_keyframe,
+            event => panic!("expected a frame, got {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn keyframe_is_sent_on_request() {
+        let server = serve_local(KeyframeService).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        assert!(next_is_keyframe(&mut stream).await);
+        for _ in 0..3 {
+            assert!(!next_is_keyframe(&mut stream).await);
+        }
+
+        stream.send(RequestKeyframe { window_id: 1 }).await.unwrap();
+        stream.flush().await.unwrap();
+        // Frames already sent before the request arrived are deltas
+        let mut deltas = 0;
+        while !next_is_keyframe(&mut stream).await {
+            deltas += 1;
+            assert!(deltas < 60, "no keyframe sent after the request");
+        }
+        assert!(!next_is_keyframe(&mut stream).await);
+
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    /// Records every hook call.
+    #[derive(Debug, Default)]
+    struct RecordingMetrics {
+        frames: Mutex<Vec<(u32, usize, Duration)>>,
+        events: Mutex<Vec<&'static str>>,
+        ticks: Mutex<Vec<(Duration, Duration)>>,
+    }
+
+    impl Metrics for RecordingMetrics {
+        fn on_frame_sent(&self, window_id: u32, bytes: usize, write_time: Duration) {
+            self.frames
+                .lock()
+                .unwrap()
+                .push((window_id, bytes, write_time));
+        }
+
+        fn on_event(&self, kind: &'static str) {
+            self.events.lock().unwrap().push(kind);
+        }
+
+        fn on_tick(&self, render_time: Duration, send_time: Duration) {
+            self.ticks.lock().unwrap().push((render_time, send_time));
+        }
+    }
+
+    /// Sends a frame every tick, reporting to `RecordingMetrics`.
+    #[derive(Clone, Default)]
+    struct MetricsService {
+        metrics: Arc<RecordingMetrics>,
+    }
+
+    #[async_trait]
+    impl GshService for MetricsService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for MetricsService {
+        fn metrics(&self) -> Option<Arc<dyn Metrics>> {
+            Some(self.metrics.clone())
+        }
+
+        async fn on_tick(&mut self, stream: &mut ServerStream) -> Result<()> {
+            stream
+                .send(Frame {
+                    window_id: 1,
+                    segments: vec![Default::default()],
+                    ..Default::default()
+                })
+                .await?;
+            stream.flush().await?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_hooks_fire_with_plausible_values() {
+        let service = MetricsService::default();
+        let metrics = service.metrics.clone();
+        let server = serve_local(service).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        for _ in 0..3 {
+            next_seq(&mut stream).await;
+        }
+        stream.send(ClientMessage::frame_ack(1, 0)).await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_nanos(MetricsService::FRAME_TIME_NS * 3)).await;
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+
+        let frames = metrics.frames.lock().unwrap();
+        assert!(frames.len() >= 3);
+        assert!(frames.iter().all(|&(window_id, bytes, write_time)| {
+            window_id == 1 && bytes > 0 && write_time < Duration::from_secs(1)
+        }));
+        assert!(metrics.events.lock().unwrap().contains(&"frame_ack"));
+        let ticks = metrics.ticks.lock().unwrap();
+        assert!(ticks.len() >= 3);
+        assert!(ticks
+            .iter()
+            .all(|&(render_time, send_time)| render_time + send_time < Duration::from_secs(1)));
+    }
+
+    /// Counts its ticks.
+    #[derive(Clone, Default)]
+    struct CountingService {
+        ticks: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl GshService for CountingService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for CountingService {
+        async fn on_tick(&mut self, _stream: &mut ServerStream) -> Result<()> {
+            self.ticks.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn loop_ticks_at_max_fps() {
+        let service = CountingService::default();
+        let ticks = service.ticks.clone();
+        let server = serve_local(service).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        let start = ticks.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let ticks = ticks.load(Ordering::SeqCst) - start;
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+
+        // A loop sleeping twice per frame would only reach half of `MAX_FPS`
+        let expected = CountingService::MAX_FPS as u64 / 2;
+        assert!(
+            ticks >= expected * 3 / 4 && ticks <= expected + 2,
+            "{} ticks in 500ms, expected about {}",
+            ticks,
+            expected
+        );
+    }
+
+    /// Counts its ticks, pausing while all client windows are hidden.
+    #[derive(Clone, Default)]
+    struct PausingService {
+        ticks: Arc<AtomicU64>,
+    }
+
+    #[async_trait]
+    impl GshService for PausingService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck::default()
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for PausingService {
+        fn should_tick(&self, stream: &ServerStream) -> bool {
+            !stream.all_windows_hidden()
+        }
+
+        async fn on_tick(&mut self, _stream: &mut ServerStream) -> Result<()> {
+            self.ticks.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn minimized_client_suppresses_ticks_until_restored() {
+        let service = PausingService::default();
+        let ticks = service.ticks.clone();
+        let server = serve_local(service).await.unwrap();
+        let (mut stream, _) = server.handshake(StaticAuth::default()).await.unwrap();
+        let frames = |count| Duration::from_nanos(PausingService::FRAME_TIME_NS * count);
+        let viewport = |width, height| ViewportHint {
+            window_id: 1,
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        tokio::time::sleep(frames(3)).await;
+        assert!(ticks.load(Ordering::SeqCst) > 0);
+
+        // Minimized windows have an empty viewport
+        stream.send(viewport(0, 0)).await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(frames(3)).await;
+        let paused = ticks.load(Ordering::SeqCst);
+        tokio::time::sleep(frames(10)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), paused);
+
+        stream.send(viewport(640, 480)).await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(frames(5)).await;
+        assert!(ticks.load(Ordering::SeqCst) > paused);
+
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    /// Records the size of every resize event it handles, rendering at most 640x480.
+    #[derive(Clone, Default)]
+    struct ResizeService {
+        sizes: Arc<Mutex<Vec<(u32, u32)>>>,
+    }
+
+    #[async_trait]
+    impl GshService for ResizeService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                max_frame_width: 640,
+                max_frame_height: 480,
+                ..Default::default()
+            }
+        }
+
+        async fn main(self, stream: ServerStream) -> Result<()> {
+            <Self as GshServiceExt>::main(self, stream).await
+        }
+    }
+
+    #[async_trait]
+    impl GshServiceExt for ResizeService {
+        async fn on_event(&mut self, _stream: &mut ServerStream, event: ClientEvent) -> Result<()> {
+            if let ClientEvent::UserInput(UserInput {
+                input_event: Some(InputEvent::WindowEvent(event)),
+                ..
+            }) = event
+            {
+                self.sizes.lock().unwrap().push((event.width, event.height));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_resizes_are_clamped() {
+        let resize = |width, height| -> ClientMessage {
+            UserInput {
+                window_id: 1,
+                input_event: Some(InputEvent::WindowEvent(WindowEvent {
+                    action: WindowAction::Resize.into(),
+                    width,
+                    height,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            }
+            .into()
+        };
+        let service = ResizeService::default();
+        let messages = run_service_with_client(
+            service.clone(),
+            vec![
+                client_hello().into(),
+                resize(100_000, 100_000),
+                resize(320, 240),
+                resize(800, 200),
+            ],
+        )
+        .await
+        .unwrap();
+        let Some(ServerEvent::ServerHelloAck(hello)) = &messages[0].server_event else {
+            panic!("expected a ServerHelloAck, got {:?}", messages[0]);
+        };
+        assert_eq!((hello.max_frame_width, hello.max_frame_height), (640, 480));
+        assert_eq!(
+            *service.sizes.lock().unwrap(),
+            vec![(640, 480), (320, 240), (640, 200)]
+        );
+    }
+
+    #[test]
+    fn slow_frame_warnings_are_rate_limited() {
+        let budget = Duration::from_millis(16);
+        let mut log = SlowFrameLog::default();
+        log.record(budget / 2, Duration::ZERO, budget, Duration::from_secs(60));
+        assert!(log.last_logged.is_none());
+        log.record(budget * 2, budget, budget, Duration::from_secs(60));
+        assert!(log.last_logged.is_some());
+        log.record(budget * 2, budget, budget, Duration::from_secs(60));
+        log.record(budget * 3, budget, budget, Duration::from_secs(60));
+        assert_eq!(log.suppressed, 2);
+    }
+}
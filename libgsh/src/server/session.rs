@@ -0,0 +1,266 @@
+use rsa::rand_core::{OsRng, RngCore};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Size in bytes of the random resumption tokens issued to clients.
+pub const RESUMPTION_TOKEN_SIZE: usize = 16;
+
+/// Generates a random opaque resumption token.
+pub fn resumption_token() -> Vec<u8> {
+    let mut token = vec![0; RESUMPTION_TOKEN_SIZE];
+    OsRng.fill_bytes(&mut token);
+    token
+}
+
+/// Keeps the state of disconnected clients alive for a grace period, so they can resume their session after reconnecting.\
+/// Services enabling `GshService::resumable` store one registry shared by all connections (all clones share the same sessions).
+/// Tokens are random and rotated on every connection, so a token can be used to resume a session only once.
+///
+/// # Example
+/// ```ignore
+/// // In `GshServiceExt::on_startup`, restore the state of a previous connection:
+/// if let Some(state) = stream.resumed_token().and_then(|token| self.sessions.resume(token)) {
+///     self.state = state;
+/// }
+/// // In `GshServiceExt::on_exit`, park the state under the token issued to this connection:
+/// self.sessions.park(stream.resumption_token(), self.state.clone());
+/// ```
+#[derive(Debug)]
+pub struct SessionRegistry<S> {
+    grace: Duration,
+    sessions: Arc<Mutex<HashMap<Vec<u8>, ParkedSession<S>>>>,
+}
+
+/// State of a disconnected client and when it was parked.
+#[derive(Debug)]
+struct ParkedSession<S> {
+    state: S,
+    parked: Instant,
+}
+
+impl<S> Clone for SessionRegistry<S> {
+    fn clone(&self) -> Self {
+        Self {
+            grace: self.grace,
+            sessions: self.sessions.clone(),
+        }
+    }
+}
+
+impl<S> SessionRegistry<S> {
+    /// Creates a registry keeping parked sessions for `grace` after the client disconnected.
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Parks the state of a disconnected client under its resumption token.\
+    /// Expired sessions are dropped, and state already parked under the same token is replaced.
+    pub fn park(&self, token: &[u8], state: S) {
+        if token.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| now.duration_since(session.parked) < self.grace);
+        let session = ParkedSession { state, parked: now };
+        if sessions.insert(token.to_vec(), session).is_some() {
+            log::warn!("Replaced a session parked under the same resumption token");
+        }
+    }
+
+    /// Takes the state parked under a token, unless it expired.
+    pub fn resume(&self, token: &[u8]) -> Option<S> {
+        let session = self.sessions.lock().unwrap().remove(token)?;
+        (session.parked.elapsed() < self.grace).then_some(session.state)
+    }
+
+    /// Number of sessions currently parked, including expired ones not yet dropped.
+    pub fn parked(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{handshake, ClientStream};
+    use crate::server::{
+        testing::{next_event, serve_local, wait_for_exit, LocalServer, StaticAuth},
+        GshService, ServerStream,
+    };
+    use crate::shared::protocol::{
+        server_hello_ack::{transport_offer::Transport, TransportOffer},
+        server_message::ServerEvent,
+        ServerHelloAck,
+    };
+    use async_trait::async_trait;
+
+    /// Counts the connections of a session, telling the client the count in an info status update.\
+    /// The count is parked when the client disconnects, and resumed when it reconnects with its token.
+    #[derive(Clone)]
+    struct CounterService {
+        sessions: SessionRegistry<u32>,
+        offers: Vec<TransportOffer>,
+    }
+
+    #[async_trait]
+    impl GshService for CounterService {
+        fn server_hello(&self) -> ServerHelloAck {
+            ServerHelloAck {
+                transport_offers: self.offers.clone(),
+                ..Default::default()
+            }
+        }
+
+        fn resumable(&self) -> bool {
+            true
+        }
+
+        async fn main(self, mut stream: ServerStream) -> crate::Result<()> {
+            let resumed = stream
+                .resumed_token()
+                .and_then(|token| self.sessions.resume(token));
+            let count = resumed.unwrap_or(0) + 1;
+            stream
+                .send(crate::shared::protocol::StatusUpdate::info(
+                    count.to_string(),
+                ))
+                .await?;
+            stream.flush().await?;
+            let result = wait_for_exit(&mut stream).await;
+            self.sessions.park(stream.resumption_token(), count);
+            result
+        }
+    }
+
+    /// Connects with an optional resumption token, returning the handshake and the session's connection count.
+    async fn connect(
+        server: &LocalServer,
+        token: Option<Vec<u8>>,
+    ) -> (ClientStream, ServerHelloAck, u32) {
+        let mut stream = server.connect().await.unwrap();
+        let server_hello = handshake(
+            &mut stream,
+            vec![],
+            StaticAuth::default(),
+            "localhost",
+            token,
+            None,
+        )
+        .await
+        .unwrap();
+        let ServerEvent::StatusUpdate(status) = next_event(&mut stream).await.unwrap() else {
+            panic!("expected the connection count");
+        };
+        let count = status.message().unwrap().parse().unwrap();
+        (stream, server_hello, count)
+    }
+
+    #[tokio::test]
+    async fn reconnecting_within_grace_window_resumes_state_once() {
+        let sessions = SessionRegistry::new(Duration::from_secs(60));
+        let server = serve_local(CounterService {
+            sessions: sessions.clone(),
+            offers: vec![],
+        })
+        .await
+        .unwrap();
+        let (mut stream, server_hello, count) = connect(&server, None).await;
+        assert_eq!(count, 1);
+        stream.close().await.unwrap();
+        while sessions.parked() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let token = server_hello.resumption_token;
+        let (mut stream, _, count) = connect(&server, Some(token.clone())).await;
+        assert_eq!(count, 2);
+        assert_eq!(sessions.parked(), 0);
+        // The state was taken, so the same token starts a fresh session
+        let (mut fresh, _, count) = connect(&server, Some(token)).await;
+        assert_eq!(count, 1);
+        stream.close().await.unwrap();
+        fresh.close().await.unwrap();
+        server.stop().await.unwrap();
+    }
+
+    #[test]
+    fn expired_sessions_are_not_resumed() {
+        let sessions = SessionRegistry::new(Duration::from_millis(20));
+        sessions.park(b"token", 1);
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(sessions.resume(b"token"), None);
+
+        // Parking drops expired sessions
+        sessions.park(b"old", 1);
+        std::thread::sleep(Duration::from_millis(40));
+        sessions.park(b"new", 2);
+        assert_eq!(sessions.parked(), 1);
+    }
+
+    #[test]
+    fn parking_under_same_token_replaces_state() {
+        let sessions = SessionRegistry::new(Duration::from_secs(60));
+        sessions.park(b"token", 1);
+        sessions.park(b"token", 2);
+        assert_eq!(sessions.parked(), 1);
+        assert_eq!(sessions.resume(b"token"), Some(2));
+        assert_eq!(sessions.resume(b"token"), None);
+        // Connections without a token are never parked
+        sessions.park(b"", 3);
+        assert_eq!(sessions.parked(), 0);
+    }
+
+    #[tokio::test]
+    async fn client_offered_quic_migrates_to_the_tls_endpoint_and_resumes() {
+        let sessions = SessionRegistry::new(Duration::from_secs(60));
+        // The offered endpoint shares the sessions of the server the client first connects to
+        let endpoint = serve_local(CounterService {
+            sessions: sessions.clone(),
+            offers: vec![],
+        })
+        .await
+        .unwrap();
+        let offer = |transport: Transport, port| TransportOffer {
+            transport: transport.into(),
+            host: String::new(),
+            port,
+        };
+        let server = serve_local(CounterService {
+            sessions: sessions.clone(),
+            offers: vec![
+                offer(Transport::Quic, 4433),
+                offer(Transport::Tls, endpoint.addr.port().into()),
+            ],
+        })
+        .await
+        .unwrap();
+        let (mut stream, server_hello, count) = connect(&server, None).await;
+        assert_eq!(count, 1);
+        // QUIC is not implemented by the client, so it migrates to the TLS endpoint
+        let migration = server_hello.migration_offer().unwrap();
+        assert_eq!(migration.port, u32::from(endpoint.addr.port()));
+
+        stream.close().await.unwrap();
+        while sessions.parked() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let (mut stream, resumed_hello, count) =
+            connect(&endpoint, Some(server_hello.resumption_token.clone())).await;
+        assert_eq!(count, 2);
+        // The token is rotated, so the old one can't resume the session again
+        assert_ne!(
+            resumed_hello.resumption_token,
+            server_hello.resumption_token
+        );
+        stream.close().await.unwrap();
+        server.stop().await.unwrap();
+        endpoint.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// A handle for gracefully shutting down a running `GshServer` from outside, e.g. on Ctrl-C.\
+/// Shutting down stops accepting new connections and signals every service loop to disconnect its client and run `on_exit`.
+/// `serve` returns once all clients have disconnected.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self {
+            tx: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl ShutdownHandle {
+    /// Requests the server to shut down.
+    pub fn shutdown(&self) {
+        self.tx.send_replace(true);
+    }
+
+    /// Whether a shutdown has been requested.
+    pub fn is_shutdown(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// A signal that completes once a shutdown is requested.
+    pub fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            rx: Some(self.tx.subscribe()),
+        }
+    }
+}
+
+/// The waiting side of a `ShutdownHandle`.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    rx: Option<watch::Receiver<bool>>,
+}
+
+impl ShutdownSignal {
+    /// Waits until a shutdown is requested.\
+    /// Never completes for a default signal, or if the handle is dropped without shutting down.
+    pub async fn wait(&mut self) {
+        if let Some(rx) = &mut self.rx {
+            if rx.wait_for(|shutdown| *shutdown).await.is_ok() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
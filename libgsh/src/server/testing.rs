@@ -0,0 +1,251 @@
+//! Headless harnesses for testing services end-to-end,
+//! either over an in-memory connection or a loopback TLS connection.\
+//! Available with the `test-util` feature.
+use super::{
+    transport::ServerTransport, GshServer, GshService, ServerStream, ShutdownHandle,
+    HANDSHAKE_TIMEOUT,
+};
+use crate::client::{handshake, ClientStream};
+use crate::shared::{
+    auth::AuthProvider,
+    cert,
+    codec::GshCodec,
+    protocol::{
+        client_hello::Os, client_message::ClientEvent, server_message::ServerEvent,
+        status_update::StatusType, ClientHello, ClientMessage, ServerHelloAck, ServerMessage,
+        StatusUpdate,
+    },
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::{Result, ServerConfig, ServiceError};
+use prost::Message;
+use rsa::{pkcs1v15::Signature, RsaPublicKey};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+
+/// Size of the in-memory pipe in each direction.
+const PIPE_SIZE: usize = 16 * 1024 * 1024;
+/// Longest time to wait for the service to end the session after the script was sent.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `ClientHello` for the current protocol version, to start a test script with.
+pub fn client_hello() -> ClientHello {
+    ClientHello {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        os: Os::Unknown as i32,
+        os_version: String::new(),
+        monitors: vec![],
+        max_message_size: 0,
+        resumption_token: vec![],
+        zstd_level: None,
+    }
+}
+
+/// Runs a service over an in-memory connection, sending it a scripted client session.\
+/// The `script` starts with the handshake (see `client_hello`), followed by client events.
+/// After the script, an exit status update and closing the connection end the session, and all messages sent by the service
+/// are returned in order, starting with the `ServerHelloAck`.
+///
+/// # Example
+/// ```ignore
+/// let messages = run_service_with_client(
+///     ColorService::default(),
+///     vec![client_hello().into(), key_press.into()],
+/// )
+/// .await?;
+/// ```
+pub async fn run_service_with_client<S>(
+    service: S,
+    script: Vec<ClientMessage>,
+) -> Result<Vec<ServerMessage>>
+where
+    S: GshService + Send + Sync + 'static,
+{
+    let (client_io, server_io) = tokio::io::duplex(PIPE_SIZE);
+    let stream = ServerStream::new(ServerTransport::Memory(server_io));
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let server = tokio::spawn(GshServer::handle_client(
+        service, stream, addr, None, deadline,
+    ));
+
+    let mut client = GshCodec::new(client_io);
+    for message in script {
+        client.write_internal(message).await?;
+    }
+    client
+        .write_internal(ClientMessage::from(StatusUpdate::exit()))
+        .await?;
+    client.flush().await?;
+    // Close our side like a real client, so the service doesn't wait for it when closing
+    client.get_inner().shutdown().await?;
+
+    let deadline = Instant::now() + SESSION_TIMEOUT;
+    let mut messages = Vec::new();
+    loop {
+        match client.read_internal().await {
+            Ok(bytes) => messages.push(
+                ServerMessage::decode(bytes).map_err(|err| ServiceError::AnyError(err.into()))?,
+            ),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) if err.kind() == ErrorKind::TimedOut => {
+                if Instant::now() >= deadline {
+                    return Err(ServiceError::Error(
+                        "Service did not end the session".to_string(),
+                    ));
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    server
+        .await
+        .map_err(|err| ServiceError::AnyError(err.into()))??;
+    Ok(messages)
+}
+
+/// A server running on a loopback port with a fresh self-signed certificate, see `serve_local`.
+#[derive(Debug)]
+pub struct LocalServer {
+    /// The address the server is listening on.
+    pub addr: SocketAddr,
+    /// A client TLS configuration trusting the server certificate, issued for `localhost`.
+    pub client_config: Arc<ClientConfig>,
+    /// Handle for shutting down the server.
+    pub shutdown: ShutdownHandle,
+    /// The task accepting connections, completed once the server has shut down.
+    pub task: JoinHandle<Result<()>>,
+}
+
+impl LocalServer {
+    /// Opens a TLS connection to the server, ready for the client `handshake`.
+    pub async fn connect(&self) -> Result<ClientStream> {
+        let socket = TcpStream::connect(self.addr).await?;
+        let tls_stream = TlsConnector::from(self.client_config.clone())
+            .connect(ServerName::try_from("localhost").unwrap(), socket)
+            .await?;
+        Ok(ClientStream::new(tls_stream))
+    }
+
+    /// Opens a TLS connection and performs the client handshake without monitors, authenticating with `auth_provider`.
+    pub async fn handshake<A: AuthProvider>(
+        &self,
+        auth_provider: A,
+    ) -> Result<(ClientStream, ServerHelloAck)> {
+        let mut stream = self.connect().await?;
+        let server_hello =
+            handshake(&mut stream, vec![], auth_provider, "localhost", None, None).await?;
+        Ok((stream, server_hello))
+    }
+
+    /// Shuts down the server and waits for all clients to disconnect.
+    pub async fn stop(self) -> Result<()> {
+        self.shutdown.shutdown();
+        self.task
+            .await
+            .map_err(|err| ServiceError::AnyError(err.into()))?
+    }
+}
+
+/// Receives the next server event on a client stream, waiting up to `SESSION_TIMEOUT` for it.\
+/// Unlike `ClientStream::receive`, reads timing out before a message arrived are retried.
+pub async fn next_event(stream: &mut ClientStream) -> Result<ServerEvent> {
+    let deadline = Instant::now() + SESSION_TIMEOUT;
+    loop {
+        match stream.receive().await {
+            Ok(event) => return Ok(event),
+            Err(err) if err.kind() == ErrorKind::TimedOut && Instant::now() < deadline => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Waits until the client exits or disconnects, for test services that only send messages.\
+/// Reads timing out are retried and other client events are ignored.
+pub async fn wait_for_exit(stream: &mut ServerStream) -> Result<()> {
+    loop {
+        match stream.receive().await {
+            Ok(ClientEvent::StatusUpdate(status)) if status.kind() == StatusType::Exit => {
+                return Ok(())
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::TimedOut => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Runs a service on a loopback port chosen by the OS, over TLS with a self-signed certificate.
+///
+/// # Example
+/// ```ignore
+/// let server = serve_local(ColorService::default()).await?;
+/// let mut stream = server.connect().await?;
+/// let server_hello = handshake(&mut stream, vec![], auth, "localhost", None, None).await?;
+/// ```
+pub async fn serve_local<S>(service: S) -> Result<LocalServer>
+where
+    S: GshService + Send + Sync + 'static,
+{
+    serve_local_with(service, |server| server).await
+}
+
+/// Runs a service on a loopback port like `serve_local`, with the server adjusted by `configure`,
+/// e.g. to set connection limits.
+pub async fn serve_local_with<S>(
+    service: S,
+    configure: impl FnOnce(GshServer<S>) -> GshServer<S>,
+) -> Result<LocalServer>
+where
+    S: GshService + Send + Sync + 'static,
+{
+    let (key, private_key) = cert::self_signed(&["localhost"])
+        .map_err(|err| ServiceError::Error(format!("Failed to generate certificate: {}", err)))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![key.cert.der().clone()], private_key.clone_key())?;
+    let mut roots = RootCertStore::empty();
+    roots.add(key.cert.der().clone())?;
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server = configure(GshServer::new(service, config))
+        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await?;
+    Ok(LocalServer {
+        addr: server.local_addr()?,
+        client_config: Arc::new(client_config),
+        shutdown: server.shutdown_handle(),
+        task: tokio::spawn(server.run()),
+    })
+}
+
+/// Client `AuthProvider` answering every password prompt with a fixed password, without signatures.
+#[derive(Debug, Clone, Default)]
+pub struct StaticAuth(pub String);
+
+impl AuthProvider for StaticAuth {
+    fn password(&mut self, _host: &str) -> String {
+        self.0.clone()
+    }
+
+    fn signature(
+        &mut self,
+        _host: &str,
+        _sign_message: &[u8],
+    ) -> Option<(Signature, RsaPublicKey)> {
+        None
+    }
+}
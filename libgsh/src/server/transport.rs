@@ -0,0 +1,90 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+
+/// The transport a `ServerStream` communicates over.\
+/// Clients connect over TLS, the in-memory transport is only used by the `testing` harness.
+#[derive(Debug)]
+pub enum ServerTransport {
+    Tls(Box<TlsStream<TcpStream>>),
+    #[cfg(any(test, feature = "test-util"))]
+    Memory(tokio::io::DuplexStream),
+}
+
+impl From<TlsStream<TcpStream>> for ServerTransport {
+    fn from(stream: TlsStream<TcpStream>) -> Self {
+        ServerTransport::Tls(Box::new(stream))
+    }
+}
+
+impl ServerTransport {
+    /// The TLS stream of the connection, e.g. to inspect client certificates.\
+    /// `None` for in-memory connections.
+    pub fn tls(&self) -> Option<&TlsStream<TcpStream>> {
+        match self {
+            ServerTransport::Tls(stream) => Some(stream),
+            #[cfg(any(test, feature = "test-util"))]
+            ServerTransport::Memory(_) => None,
+        }
+    }
+
+    /// Closes our side of the connection, notifying TLS clients with a `close_notify` alert.\
+    /// See `ServerStream::close` to also tell the client and wait for it to close its side.
+    pub async fn close(&mut self) -> io::Result<()> {
+        match self {
+            // Shutting down the TLS stream writes the close_notify before closing the socket for writing
+            ServerTransport::Tls(stream) => stream.shutdown().await,
+            #[cfg(any(test, feature = "test-util"))]
+            ServerTransport::Memory(stream) => stream.shutdown().await,
+        }
+    }
+}
+
+impl AsyncRead for ServerTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerTransport::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+            #[cfg(any(test, feature = "test-util"))]
+            ServerTransport::Memory(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ServerTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ServerTransport::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+            #[cfg(any(test, feature = "test-util"))]
+            ServerTransport::Memory(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerTransport::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+            #[cfg(any(test, feature = "test-util"))]
+            ServerTransport::Memory(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerTransport::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+            #[cfg(any(test, feature = "test-util"))]
+            ServerTransport::Memory(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
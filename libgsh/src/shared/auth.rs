@@ -1,9 +1,23 @@
 //! This module provides the `AuthProvider` trait, which is used to define authentication providers.
+use super::protocol::server_hello_ack::TotpMethod;
+use hmac::{Hmac, Mac};
 use rsa::{pkcs1v15::Signature, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+/// Default number of digits of a TOTP code.
+pub const TOTP_DIGITS: u32 = 6;
+/// Default number of seconds each TOTP code is valid for, as used by common authenticator apps.
+pub const TOTP_DEFAULT_TIME_STEP: u64 = 30;
 
 /// The `AuthProvider` trait defines the interface for client authentication providers.\
 /// It requires implementing the `password` and `signature` methods to retrieve the password and signature for authentication.
 /// This trait is used in the `handshake_client` function to send authentication information to the server.
+/// When the server offers several methods, a signature is used if `signature` returns one, otherwise a password or one-time password.
 pub trait AuthProvider: Send + Sync + 'static {
     fn password(&mut self, host: &str) -> String;
     fn password_success_cb(&mut self) {
@@ -13,10 +27,48 @@ pub trait AuthProvider: Send + Sync + 'static {
     fn signature_success_cb(&mut self) {
         log::debug!("Signature authentication successful.");
     }
+    /// The one-time password for a server offering `method`, or `None` if this provider doesn't support them.\
+    /// Defaults to `None`, so providers written before one-time passwords keep working.
+    #[allow(unused_variables)]
+    fn totp(&mut self, host: &str, method: &TotpMethod) -> Option<String> {
+        None
+    }
+    fn totp_success_cb(&mut self) {
+        log::debug!("One-time password authentication successful.");
+    }
+}
+
+/// Identity of an authenticated client, returned by the verifier and kept for the whole connection.\
+/// Services read it from `ServerStream::auth_context`, e.g. to authorize per user on a multi-tenant server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthContext {
+    /// Name of the user the credentials belong to, empty if the verifier doesn't know
+    pub user: String,
+    /// Additional service-defined claims, such as roles or a tenant
+    pub claims: HashMap<String, String>,
+}
+
+impl AuthContext {
+    pub fn user(user: impl Into<String>) -> Self {
+        Self {
+            user: user.into(),
+            claims: HashMap::new(),
+        }
+    }
+
+    pub fn with_claim(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.claims.insert(key.into(), value.into());
+        self
+    }
 }
 
 pub trait PasswordVerifier: Send + Sync + 'static {
     fn verify(&self, password: &str) -> bool;
+    /// Verify the password and return who it belongs to, or `None` to reject it.\
+    /// Defaults to an empty context if `verify` accepts the password.
+    fn identify(&self, password: &str) -> Option<AuthContext> {
+        self.verify(password).then(AuthContext::default)
+    }
 }
 
 /// The `SignatureVerifier` trait defines the interface for additional signature verification.\
@@ -26,13 +78,158 @@ pub trait PasswordVerifier: Send + Sync + 'static {
 /// This function **should not** verify the signature, but allows the user to define their own verification logic.
 pub trait SignatureVerifier: Send + Sync + 'static {
     fn verify(&self, public_key: &RsaPublicKey) -> bool;
+    /// Check the public key and return who it belongs to, or `None` to reject it.\
+    /// Defaults to an empty context if `verify` accepts the key. Same as `verify`, this is called before checking the signature.
+    fn identify(&self, public_key: &RsaPublicKey) -> Option<AuthContext> {
+        self.verify(public_key).then(AuthContext::default)
+    }
+}
+
+/// The `TotpVerifier` trait defines the interface for time-based one-time password verification (RFC 6238).\
+/// Implementors provide the shared secret, which is also enrolled in the user's authenticator app.
+pub trait TotpVerifier: Send + Sync + 'static {
+    /// The raw shared secret (decoded, if authenticator apps are given it as base32).
+    fn secret(&self) -> &[u8];
+    /// Seconds each code is valid for.
+    fn time_step(&self) -> u64 {
+        TOTP_DEFAULT_TIME_STEP
+    }
+    /// Number of digits of each code.
+    fn digits(&self) -> u32 {
+        TOTP_DIGITS
+    }
+    /// Number of time steps before and after the current one that are also accepted, to tolerate clock drift.
+    fn skew(&self) -> u64 {
+        1
+    }
+    /// Verify the code, accepting each code only once.\
+    /// Codes of the time step of the last accepted code, or of earlier ones, are rejected as replays.
+    fn verify(&self, code: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let secret = self.secret();
+        verify_totp(
+            secret,
+            code,
+            now,
+            self.time_step(),
+            self.digits(),
+            self.skew(),
+        )
+        .is_some_and(|step| accept_totp_step(secret, step))
+    }
+    /// The method announced to clients, with the digits and time step of this verifier.
+    fn method(&self) -> TotpMethod {
+        TotpMethod {
+            digits: self.digits(),
+            time_step: self.time_step(),
+        }
+    }
+    /// Verify the code and return who the secret belongs to, or `None` to reject it.\
+    /// Defaults to an empty context if `verify` accepts the code.
+    fn identify(&self, code: &str) -> Option<AuthContext> {
+        self.verify(code).then(AuthContext::default)
+    }
+}
+
+/// Compute a HOTP code (RFC 4226) with HMAC-SHA1 for the given counter.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    binary % 10u32.pow(digits)
+}
+
+/// Compute the TOTP code (RFC 6238) for a unix timestamp in seconds, zero-padded to `digits` digits (at most 9).
+pub fn totp(secret: &[u8], unix_time: u64, time_step: u64, digits: u32) -> String {
+    let digits = digits.clamp(1, 9);
+    let code = hotp(secret, unix_time / time_step.max(1), digits);
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+/// Verify a TOTP code for a unix timestamp in seconds, accepting codes up to `skew` time steps away.\
+/// Returns the time step the code belongs to, codes are compared in constant time.
+pub fn verify_totp(
+    secret: &[u8],
+    code: &str,
+    unix_time: u64,
+    time_step: u64,
+    digits: u32,
+    skew: u64,
+) -> Option<u64> {
+    let time_step = time_step.max(1);
+    let counter = unix_time / time_step;
+    let code = code.trim().as_bytes();
+    (counter.saturating_sub(skew)..=counter.saturating_add(skew)).find(|&c| {
+        let expected = totp(secret, c * time_step, time_step, digits);
+        bool::from(expected.as_bytes().ct_eq(code))
+    })
+}
+
+/// Last accepted time step per TOTP secret, shared by all connections to reject replayed codes.\
+/// Secrets are keyed by their hash, so the map holds no copies of them.
+static ACCEPTED_TOTP_STEPS: LazyLock<Mutex<HashMap<[u8; 32], u64>>> =
+    LazyLock::new(Default::default);
+
+/// Records `step` as used for `secret`, returning `false` if it or a later step was already accepted.
+fn accept_totp_step(secret: &[u8], step: u64) -> bool {
+    let key: [u8; 32] = Sha256::digest(secret).into();
+    let mut accepted = ACCEPTED_TOTP_STEPS.lock().unwrap();
+    match accepted.get(&key) {
+        Some(&last) if step <= last => false,
+        _ => {
+            accepted.insert(key, step);
+            true
+        }
+    }
 }
 
 /// The `AuthVerifier` enum defines the authentication verification methods.\
-/// It can be either a password verifier or a signature verifier.\
+/// It can be either a password, signature or one-time password verifier.\
+/// Servers offering several methods with `AuthMethod::Any` return `Any` with a verifier for each of them.
 pub enum AuthVerifier {
     Password(Box<dyn PasswordVerifier>),
     Signature(Box<dyn SignatureVerifier>),
+    Totp(Box<dyn TotpVerifier>),
+    Any(Vec<AuthVerifier>),
+}
+
+impl AuthVerifier {
+    /// The password verifier, if any.
+    pub fn password(&self) -> Option<&dyn PasswordVerifier> {
+        match self {
+            AuthVerifier::Password(verifier) => Some(verifier.as_ref()),
+            AuthVerifier::Any(verifiers) => verifiers.iter().find_map(AuthVerifier::password),
+            _ => None,
+        }
+    }
+
+    /// The signature verifier, if any.
+    pub fn signature(&self) -> Option<&dyn SignatureVerifier> {
+        match self {
+            AuthVerifier::Signature(verifier) => Some(verifier.as_ref()),
+            AuthVerifier::Any(verifiers) => verifiers.iter().find_map(AuthVerifier::signature),
+            _ => None,
+        }
+    }
+
+    /// The one-time password verifier, if any.
+    pub fn totp(&self) -> Option<&dyn TotpVerifier> {
+        match self {
+            AuthVerifier::Totp(verifier) => Some(verifier.as_ref()),
+            AuthVerifier::Any(verifiers) => verifiers.iter().find_map(AuthVerifier::totp),
+            _ => None,
+        }
+    }
 }
 
 impl From<Box<dyn PasswordVerifier>> for AuthVerifier {
@@ -46,3 +243,58 @@ impl From<Box<dyn SignatureVerifier>> for AuthVerifier {
         AuthVerifier::Signature(verifier)
     }
 }
+
+impl From<Box<dyn TotpVerifier>> for AuthVerifier {
+    fn from(verifier: Box<dyn TotpVerifier>) -> Self {
+        AuthVerifier::Totp(verifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The shared secret of the RFC 4226 and RFC 6238 test vectors.
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn known_codes_match_rfc_test_vectors() {
+        assert_eq!(hotp(SECRET, 0, 6), 755224);
+        assert_eq!(hotp(SECRET, 1, 6), 287082);
+        assert_eq!(totp(SECRET, 59, 30, 8), "94287082");
+        assert_eq!(totp(SECRET, 1111111109, 30, 8), "07081804");
+        assert_eq!(totp(SECRET, 1111111109, 30, 6), "081804");
+        assert_eq!(totp(SECRET, 2000000000, 30, 8), "69279037");
+    }
+
+    #[test]
+    fn codes_are_accepted_within_skew() {
+        let now = 1111111109;
+        let previous = totp(SECRET, now - 30, 30, 6);
+        assert_eq!(
+            verify_totp(SECRET, &previous, now, 30, 6, 1),
+            Some(now / 30 - 1)
+        );
+        assert_eq!(verify_totp(SECRET, &previous, now, 30, 6, 0), None);
+        assert_eq!(verify_totp(SECRET, "000000", now, 30, 6, 1), None);
+    }
+
+    struct TestTotp;
+
+    impl TotpVerifier for TestTotp {
+        fn secret(&self) -> &[u8] {
+            b"totp verifier test secret"
+        }
+    }
+
+    #[test]
+    fn verifier_rejects_replayed_code() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let code = totp(TestTotp.secret(), now, TOTP_DEFAULT_TIME_STEP, TOTP_DIGITS);
+        assert!(TestTotp.verify(&code));
+        assert!(!TestTotp.verify(&code));
+    }
+}
@@ -3,7 +3,25 @@ use rsa::{
     pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPrivateKey, EncodeRsaPublicKey},
     RsaPrivateKey, RsaPublicKey,
 };
-use tokio_rustls::rustls::pki_types::{pem::PemObject, PrivateKeyDer};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio_rustls::rustls::{
+    self,
+    crypto::{ring as provider, CryptoProvider, SupportedKxGroup},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion,
+};
+
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::ServiceError;
+
+/// Remaining validity below which loading a certificate logs a warning, see `check_expiry`.
+pub const EXPIRY_WARNING: Duration = Duration::from_secs(30 * 24 * 60 * 60);
 
 // Generate a self-signed certificate
 pub fn self_signed<T: AsRef<str>>(
@@ -19,6 +37,228 @@ pub fn self_signed<T: AsRef<str>>(
     Ok((cert_key, private_key))
 }
 
+/// Load a certificate and private key from a PEM file, or generate a self-signed pair and persist it there.\
+/// This gives a server a stable identity (and certificate fingerprint) across restarts,
+/// so clients that trust it on first use don't see a fingerprint mismatch on the next run.
+pub fn load_or_create<P: AsRef<Path>, T: AsRef<str>>(
+    path: P,
+    alt_names: &[T],
+) -> crate::Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let path = path.as_ref();
+    if path.exists() {
+        let pem = std::fs::read(path)?;
+        let cert = CertificateDer::from_pem_slice(&pem).map_err(|err| {
+            ServiceError::Error(format!(
+                "Failed to parse certificate PEM {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        let private_key = PrivateKeyDer::from_pem_slice(&pem).map_err(|err| {
+            ServiceError::Error(format!(
+                "Failed to parse private key PEM {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        log::debug!("Loaded server certificate from {}", path.display());
+        return Ok((cert, private_key));
+    }
+
+    let (cert_key, private_key) =
+        self_signed(alt_names).map_err(|err| ServiceError::AnyError(err.into()))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(
+        path,
+        format!(
+            "{}{}",
+            cert_key.cert.pem(),
+            cert_key.key_pair.serialize_pem()
+        ),
+    )?;
+    log::info!("Generated new server certificate at {}", path.display());
+    Ok((cert_key.cert.der().clone(), private_key.clone_key()))
+}
+
+/// Load a certificate chain and private key from PEM files, e.g. as issued by Let's Encrypt.\
+/// The certificate file may contain the full chain, leaf first.
+/// The private key may be encoded as PKCS#1, PKCS#8 or SEC1.
+pub fn load_pem<P: AsRef<Path>, K: AsRef<Path>>(
+    cert_path: P,
+    key_path: K,
+) -> crate::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| {
+            ServiceError::Error(format!(
+                "Failed to parse certificate PEM {}: {}",
+                cert_path.display(),
+                err
+            ))
+        })?;
+    if certs.is_empty() {
+        return Err(ServiceError::Error(format!(
+            "No certificates found in {}",
+            cert_path.display()
+        )));
+    }
+    let private_key = PrivateKeyDer::from_pem_file(key_path).map_err(|err| {
+        ServiceError::Error(format!(
+            "Failed to parse private key PEM {}: {}",
+            key_path.display(),
+            err
+        ))
+    })?;
+    log::debug!(
+        "Loaded {} certificate(s) from {}",
+        certs.len(),
+        cert_path.display()
+    );
+    if let Err(err) = check_expiry(&certs[0], EXPIRY_WARNING) {
+        log::error!("{} ({})", err, cert_path.display());
+    }
+    Ok((certs, private_key))
+}
+
+/// Check the validity period of a certificate, e.g. the leaf of the chain passed to `with_single_cert`.\
+/// Fails if it is expired or not yet valid, and logs a warning if it expires within `warn_within`.
+/// Clients fail the TLS handshake on expired certificates, with errors that are hard to trace back to the server.
+pub fn check_expiry(cert: &CertificateDer<'_>, warn_within: Duration) -> crate::Result<()> {
+    let (_, cert) = X509Certificate::from_der(cert)
+        .map_err(|err| ServiceError::Error(format!("Failed to parse certificate: {}", err)))?;
+    let validity = cert.validity();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let (not_before, not_after) = (
+        validity.not_before.timestamp(),
+        validity.not_after.timestamp(),
+    );
+    if now < not_before {
+        return Err(ServiceError::Error(format!(
+            "Certificate for {} is not valid before {}",
+            cert.subject(),
+            validity.not_before
+        )));
+    }
+    if now > not_after {
+        return Err(ServiceError::Error(format!(
+            "Certificate for {} expired on {}",
+            cert.subject(),
+            validity.not_after
+        )));
+    }
+    let remaining = Duration::from_secs((not_after - now) as u64);
+    if remaining < warn_within {
+        log::warn!(
+            "Certificate for {} expires on {}, in {} days",
+            cert.subject(),
+            validity.not_after,
+            remaining.as_secs() / (24 * 60 * 60)
+        );
+    }
+    Ok(())
+}
+
+/// Build a `ServerConfig` without client authentication from a certificate chain and private key in PEM files.
+pub fn server_config_from_pem<P: AsRef<Path>, K: AsRef<Path>>(
+    cert_path: P,
+    key_path: K,
+) -> crate::Result<ServerConfig> {
+    let (certs, private_key) = load_pem(cert_path, key_path)?;
+    Ok(ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)?)
+}
+
+/// Build a `ServerConfig` requiring clients to present a certificate signed by one of `client_ca_certs` (mutual TLS).\
+/// Client certificates are verified during the TLS handshake, before the `ClientHello` is sent,
+/// so clients without a trusted certificate never reach the service.
+/// This is independent of the `AuthMethod` announced in the `ServerHelloAck`, and both can be combined.
+/// Services can inspect the verified chain with `stream.get_inner().tls()` and `peer_certificates()`.
+pub fn server_config_with_client_auth(
+    certs: Vec<CertificateDer<'static>>,
+    private_key: PrivateKeyDer<'static>,
+    client_ca_certs: Vec<CertificateDer<'static>>,
+) -> crate::Result<ServerConfig> {
+    let mut roots = RootCertStore::empty();
+    for ca_cert in client_ca_certs {
+        roots.add(ca_cert)?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(|err| ServiceError::Error(format!("Failed to build client verifier: {}", err)))?;
+    Ok(ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, private_key)?)
+}
+
+/// TLS protocol versions, cipher suites and key exchange groups a server accepts.\
+/// The default allows everything supported by rustls, see `TlsPolicy::hardened` for a restricted set.
+#[derive(Debug, Clone)]
+pub struct TlsPolicy {
+    pub versions: Vec<&'static SupportedProtocolVersion>,
+    pub cipher_suites: Vec<SupportedCipherSuite>,
+    pub kx_groups: Vec<&'static dyn SupportedKxGroup>,
+}
+
+impl Default for TlsPolicy {
+    fn default() -> Self {
+        let provider = provider::default_provider();
+        Self {
+            versions: rustls::ALL_VERSIONS.to_vec(),
+            cipher_suites: provider.cipher_suites,
+            kx_groups: provider.kx_groups,
+        }
+    }
+}
+
+impl TlsPolicy {
+    /// TLS 1.3 only, with AEAD cipher suites and X25519 or P-384 key exchange.\
+    /// Includes the ChaCha20 suite and X25519 group pinned by the `gsh` client.
+    pub fn hardened() -> Self {
+        Self {
+            versions: vec![&rustls::version::TLS13],
+            cipher_suites: vec![
+                provider::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+                provider::cipher_suite::TLS13_AES_256_GCM_SHA384,
+                provider::cipher_suite::TLS13_AES_128_GCM_SHA256,
+            ],
+            kx_groups: vec![provider::kx_group::X25519, provider::kx_group::SECP384R1],
+        }
+    }
+}
+
+/// Build a `ServerConfig` without client authentication, accepting only the versions and algorithms of `policy`.\
+/// Clients offering none of them fail the TLS handshake.
+pub fn server_config_with_policy(
+    certs: Vec<CertificateDer<'static>>,
+    private_key: PrivateKeyDer<'static>,
+    policy: &TlsPolicy,
+) -> crate::Result<ServerConfig> {
+    let provider = CryptoProvider {
+        cipher_suites: policy.cipher_suites.clone(),
+        kx_groups: policy.kx_groups.clone(),
+        ..provider::default_provider()
+    };
+    Ok(ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&policy.versions)?
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)?)
+}
+
+/// Build a `ServerConfig` without client authentication restricted to `TlsPolicy::hardened`, e.g. for compliance environments.
+pub fn hardened_server_config(
+    certs: Vec<CertificateDer<'static>>,
+    private_key: PrivateKeyDer<'static>,
+) -> crate::Result<ServerConfig> {
+    server_config_with_policy(certs, private_key, &TlsPolicy::hardened())
+}
+
 /// Extract the public key from the signature
 pub fn extract_public_key(pem: &str) -> Option<RsaPublicKey> {
     const PEM_PUBLIC_KEY_HEADER: &str = "-----BEGIN RSA PUBLIC KEY-----";
@@ -71,3 +311,171 @@ pub fn keys_to_pem(private_key: &RsaPrivateKey, public_key: &RsaPublicKey) -> St
         .expect("Failed to encode public key");
     format!("{}\n{}", *private_key_pem, public_key_pem)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig},
+        TlsAcceptor, TlsConnector,
+    };
+
+    #[test]
+    fn load_or_create_persists_the_generated_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("certs").join("server.pem");
+        let (cert, key) = load_or_create(&path, &["localhost"]).unwrap();
+        let written = std::fs::read(&path).unwrap();
+
+        let (loaded_cert, loaded_key) = load_or_create(&path, &["localhost"]).unwrap();
+        assert_eq!(cert, loaded_cert);
+        assert_eq!(key.secret_der(), loaded_key.secret_der());
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+    }
+
+    #[test]
+    fn pem_chain_and_key_are_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let (leaf, _) = self_signed(&["localhost"]).unwrap();
+        let (intermediate, _) = self_signed(&["ca.localhost"]).unwrap();
+        let cert_path = dir.path().join("fullchain.pem");
+        let key_path = dir.path().join("privkey.pem");
+        std::fs::write(
+            &cert_path,
+            format!("{}{}", leaf.cert.pem(), intermediate.cert.pem()),
+        )
+        .unwrap();
+        std::fs::write(&key_path, leaf.key_pair.serialize_pem()).unwrap();
+
+        let (certs, key) = load_pem(&cert_path, &key_path).unwrap();
+        assert_eq!(
+            certs,
+            vec![leaf.cert.der().clone(), intermediate.cert.der().clone()]
+        );
+        assert!(matches!(key, PrivateKeyDer::Pkcs8(_)));
+        assert_eq!(key.secret_der(), leaf.key_pair.serialized_der());
+        server_config_from_pem(&cert_path, &key_path).unwrap();
+
+        // PKCS#1 keys, as written by `openssl genrsa`, are accepted as well
+        let rsa_key = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 512).unwrap();
+        let pkcs1 = rsa_key.to_pkcs1_pem(rsa::pkcs8::LineEnding::LF).unwrap();
+        std::fs::write(&key_path, pkcs1.as_bytes()).unwrap();
+        let (_, key) = load_pem(&cert_path, &key_path).unwrap();
+        assert!(matches!(key, PrivateKeyDer::Pkcs1(_)));
+
+        // A file without certificates is rejected
+        std::fs::write(&cert_path, "").unwrap();
+        assert!(load_pem(&cert_path, &key_path).is_err());
+    }
+
+    /// Issues a certificate for `name` signed by the CA, for the given usage.
+    fn issue(
+        name: &str,
+        usage: ExtendedKeyUsagePurpose,
+        ca: &Certificate,
+        ca_key: &KeyPair,
+    ) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.extended_key_usages = vec![usage];
+        let cert = params.signed_by(&key, ca, ca_key).unwrap();
+        let key = PrivateKeyDer::Pkcs8(key.serialize_der().into());
+        (cert.der().clone(), key)
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_handshake_verifies_client_certificate() {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let (server_cert, server_key) = issue(
+            "localhost",
+            ExtendedKeyUsagePurpose::ServerAuth,
+            &ca,
+            &ca_key,
+        );
+        let (client_cert, client_key) =
+            issue("client", ExtendedKeyUsagePurpose::ClientAuth, &ca, &ca_key);
+        let server_config =
+            server_config_with_client_auth(vec![server_cert], server_key, vec![ca.der().clone()])
+                .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots.clone())
+            .with_client_auth_cert(vec![client_cert.clone()], client_key)
+            .unwrap();
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (server, client) = tokio::join!(
+            acceptor.accept(server_io),
+            TlsConnector::from(Arc::new(client_config)).connect(server_name.clone(), client_io)
+        );
+        client.unwrap();
+        let server = server.unwrap();
+        let (_, connection) = server.get_ref();
+        assert_eq!(connection.peer_certificates(), Some(&[client_cert][..]));
+
+        // Clients without a certificate are rejected during the TLS handshake
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (server, _) = tokio::join!(
+            acceptor.accept(server_io),
+            TlsConnector::from(Arc::new(client_config)).connect(server_name, client_io)
+        );
+        assert!(server.is_err());
+    }
+
+    #[tokio::test]
+    async fn hardened_server_rejects_tls12_clients() {
+        let (cert, key) = self_signed(&["localhost"]).unwrap();
+        let server_cert = cert.cert.der().clone();
+        let server_config =
+            hardened_server_config(vec![server_cert.clone()], key.clone_key()).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let mut roots = RootCertStore::empty();
+        roots.add(server_cert).unwrap();
+        let connect = |versions: &[&'static SupportedProtocolVersion]| {
+            let config = ClientConfig::builder_with_protocol_versions(versions)
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth();
+            let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+            let server_name = ServerName::try_from("localhost").unwrap();
+            let client = TlsConnector::from(Arc::new(config)).connect(server_name, client_io);
+            (acceptor.accept(server_io), client)
+        };
+
+        let (server, client) = connect(&[&rustls::version::TLS13]);
+        let (server, client) = tokio::join!(server, client);
+        server.unwrap();
+        client.unwrap();
+
+        let (server, client) = connect(&[&rustls::version::TLS12]);
+        let (server, client) = tokio::join!(server, client);
+        assert!(server.is_err());
+        assert!(client.is_err());
+    }
+
+    #[test]
+    fn expired_certificates_are_rejected() {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+        let expired = params.self_signed(&key).unwrap();
+        let err = check_expiry(expired.der(), EXPIRY_WARNING).unwrap_err();
+        assert!(err.to_string().contains("expired"), "{}", err);
+
+        // Certificates valid for longer than the warning window pass
+        let (valid, _) = self_signed(&["localhost"]).unwrap();
+        check_expiry(valid.cert.der(), Duration::ZERO).unwrap();
+    }
+}
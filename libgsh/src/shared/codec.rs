@@ -2,81 +2,287 @@
 use crate::shared::protocol::{
     client_message::ClientEvent, server_message::ServerEvent, ClientMessage, ServerMessage,
 };
+use crate::shared::PROTOCOL_VERSION;
 use prost::Message;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::time::{timeout, Duration};
+use tokio::time::{timeout, timeout_at, Duration, Instant};
 
 type LengthType = u32;
 const LENGTH_SIZE: usize = std::mem::size_of::<LengthType>();
 
+/// Longest time a graceful close waits for the peer to close its side of the connection.
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Default largest message size, guarding against peers announcing huge lengths to exhaust memory.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
 /// A codec for reading and writing length-value encoded messages.
 #[derive(Debug)]
 pub struct GshCodec<S: AsyncRead + AsyncWrite + Send + Unpin> {
     /// The underlying reader and writer stream.
     stream: S,
+    /// The length prefix of the message being read.
+    header: [u8; LENGTH_SIZE],
     /// The buffer to store the read data.
     buf: Vec<u8>,
     /// The length of the message to be read.
     length: usize,
-    partial_read: bool,
+    /// Bytes of the current message read so far, including the length prefix.\
+    /// Kept across timed out reads so a message arriving over several reads is resumed where it stopped.
+    filled: usize,
+    /// The largest message size allowed to be read or written, if any.\
+    /// Defaults to `DEFAULT_MAX_MESSAGE_SIZE`.
+    max_message_size: Option<usize>,
+    /// Total time spent writing and flushing messages.
+    write_time: Duration,
+    /// Protocol version negotiated in the handshake.
+    protocol_version: u32,
 }
 
 impl<S: AsyncRead + AsyncWrite + Send + Unpin> GshCodec<S> {
     pub fn new(stream: S) -> Self {
         Self {
             stream,
+            header: [0; LENGTH_SIZE],
             buf: Vec::new(),
             length: 0,
-            partial_read: false,
+            filled: 0,
+            max_message_size: Some(DEFAULT_MAX_MESSAGE_SIZE),
+            write_time: Duration::ZERO,
+            protocol_version: PROTOCOL_VERSION,
         }
     }
 
+    pub(crate) fn set_protocol_version(&mut self, version: u32) {
+        self.protocol_version = version;
+    }
+
+    /// Protocol version of the connection, negotiated in the handshake (`PROTOCOL_VERSION` before that).\
+    /// Newer peers fall back to an older version with reduced features, check it before using features added later.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// Total time spent writing and flushing messages on this stream.\
+    /// Compare snapshots before and after a tick to measure how long sending took.
+    pub fn write_time(&self) -> Duration {
+        self.write_time
+    }
+
+    /// Limits the size of messages read from and written to the stream.\
+    /// Reading a larger message fails with `InvalidData` before allocating its buffer,
+    /// writing one fails with `InvalidInput`. Pass `None` to disable the limit for trusted peers.
+    pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// The largest message size allowed on this stream, if any.
+    pub fn max_message_size(&self) -> Option<usize> {
+        self.max_message_size
+    }
+
     pub fn get_inner(&mut self) -> &mut S {
         &mut self.stream
     }
 
     /// Reads a whole length-value encoded message from the underlying reader.
     /// Returns the message bytes as a `Vec<u8>`.
+    ///
+    /// Fails with `TimedOut` if no complete message arrives within a short timeout.
+    /// The bytes read so far are kept, so the next call continues the same message.
     pub(crate) async fn read_internal(&mut self) -> std::io::Result<prost::bytes::Bytes> {
-        let read_timeout = Duration::from_millis(10); // Set a 10-second timeout
+        let deadline = Instant::now() + Duration::from_millis(10);
 
-        if !self.partial_read {
-            let mut length_buf = [0; LENGTH_SIZE];
-            timeout(read_timeout, self.stream.read_exact(&mut length_buf)).await??;
-            self.length = LengthType::from_be_bytes(length_buf) as usize;
+        // Only `read` is cancel safe, `read_exact` would lose the bytes it read when timing out
+        while self.filled < LENGTH_SIZE {
+            let read =
+                timeout_at(deadline, self.stream.read(&mut self.header[self.filled..])).await??;
+            if read == 0 {
+                return Err(self.unexpected_eof());
+            }
+            self.filled += read;
+        }
+        if self.filled == LENGTH_SIZE {
+            self.length = LengthType::from_be_bytes(self.header) as usize;
+            if let Some(max) = self.max_message_size {
+                if self.length > max {
+                    self.filled = 0;
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Message of {} bytes exceeds the maximum of {} bytes",
+                            self.length, max
+                        ),
+                    ));
+                }
+            }
             self.buf.resize(self.length, 0);
         }
-        self.partial_read = true;
-        timeout(read_timeout, self.stream.read_exact(&mut self.buf)).await??;
+        while self.filled < LENGTH_SIZE + self.length {
+            let offset = self.filled - LENGTH_SIZE;
+            let read = timeout_at(deadline, self.stream.read(&mut self.buf[offset..])).await??;
+            if read == 0 {
+                return Err(self.unexpected_eof());
+            }
+            self.filled += read;
+        }
+        // If we managed to get here, we have a complete message.
+        self.filled = 0;
         // Convert the Vec<u8> to Bytes for better performance
         // and to avoid unnecessary allocations.
-        let bytes = prost::bytes::Bytes::from(std::mem::replace(
-            &mut self.buf,
-            Vec::with_capacity(self.length),
-        ));
-        // If we managed to get here, no exception was thrown and we have a complete message.
-        self.partial_read = false;
-        Ok(bytes)
+        Ok(prost::bytes::Bytes::from(std::mem::take(&mut self.buf)))
+    }
+
+    /// The error for the peer closing the connection, noting if it did so in the middle of a message.
+    fn unexpected_eof(&self) -> std::io::Error {
+        if self.filled == 0 {
+            return std::io::ErrorKind::UnexpectedEof.into();
+        }
+        std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("Connection closed after {} bytes of a message", self.filled),
+        )
     }
 
     /// Writes a length-value encoded message to the underlying writer.
     #[inline]
     pub(crate) async fn write_internal<T: Message>(&mut self, message: T) -> std::io::Result<()> {
-        let message: Vec<u8> = message.encode_to_vec();
-        let mut buf: Vec<u8> = Vec::new(); // with_capacity(LENGTH_SIZE + message.len());
-        let length = message.len() as LengthType;
-        let length_buf = length.to_be_bytes();
-        assert_eq!(length_buf.len(), LENGTH_SIZE);
-        buf.extend_from_slice(&length_buf);
-        buf.extend_from_slice(&message);
-        self.stream.write_all(&buf).await?;
+        let mut buf = Vec::new();
+        self.encode_internal(&message, &mut buf)?;
+        self.write_encoded(&buf).await
+    }
+
+    /// Appends a length-value encoded message to `buf`, to write several messages at once with `write_encoded`.
+    pub(crate) fn encode_internal<T: Message>(
+        &self,
+        message: &T,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        let length = message.encoded_len();
+        if let Some(max) = self.max_message_size {
+            if length > max {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Message of {} bytes exceeds the negotiated maximum of {} bytes",
+                        length, max
+                    ),
+                ));
+            }
+        }
+        buf.reserve(LENGTH_SIZE + length);
+        buf.extend_from_slice(&(length as LengthType).to_be_bytes());
+        message.encode(buf).map_err(std::io::Error::other)
+    }
+
+    /// Writes already encoded messages to the underlying writer in a single write.
+    pub(crate) async fn write_encoded(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        let start = Instant::now();
+        let result = self.stream.write_all(buf).await;
+        self.write_time += start.elapsed();
         // NOTE: do not flush on every message — callers should flush once per batch/frame
         // to avoid syscall overhead and reduce p99 latency.
-        Ok(())
+        result
+    }
+
+    /// Reads and discards data until the peer closes its side of the connection or `limit` elapses.\
+    /// Called after closing our side, so the peer's TLS `close_notify` is read instead of the socket
+    /// being dropped under it, which the peer would see as a truncated stream.
+    pub(crate) async fn wait_for_peer_close(&mut self, limit: Duration) {
+        let mut buf = [0; 1024];
+        let drain = async {
+            while let Ok(n) = self.stream.read(&mut buf).await {
+                if n == 0 {
+                    break;
+                }
+            }
+        };
+        if timeout(limit, drain).await.is_err() {
+            log::debug!("Peer did not close the connection within {:.2?}", limit);
+        }
     }
 
     /// Explicitly flush the underlying stream. Use this after sending a batch/frame.
     pub async fn flush(&mut self) -> std::io::Result<()> {
-        self.stream.flush().await
+        let start = Instant::now();
+        let result = self.stream.flush().await;
+        self.write_time += start.elapsed();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn oversized_length_prefix_is_rejected_before_allocating() {
+        let (mut peer, io) = tokio::io::duplex(64);
+        let mut codec = GshCodec::new(io);
+        peer.write_all(&LengthType::MAX.to_be_bytes())
+            .await
+            .unwrap();
+        let err = codec.read_internal().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(codec.buf.capacity(), 0);
+
+        // A limit of its own applies the same way
+        codec.set_max_message_size(Some(16));
+        peer.write_all(&17u32.to_be_bytes()).await.unwrap();
+        let err = codec.read_internal().await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(codec.buf.capacity(), 0);
+    }
+
+    /// A frame message of about `size` bytes with a recognizable byte pattern.
+    fn large_message(size: usize, seed: u8) -> ServerMessage {
+        ServerMessage::frame(crate::shared::protocol::Frame {
+            window_id: seed as u32,
+            segments: vec![crate::shared::protocol::frame::Segment {
+                data: (0..size)
+                    .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+                    .collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn message_split_across_read_timeouts_is_reassembled() {
+        let messages = [large_message(64 * 1024, 1), large_message(1000, 2)];
+        let (mut peer, io) = tokio::io::duplex(4096);
+        let mut codec = GshCodec::new(io);
+        let mut encoded = Vec::new();
+        for message in &messages {
+            codec.encode_internal(message, &mut encoded).unwrap();
+        }
+        let writer = tokio::spawn(async move {
+            // Split the length prefix of the first message, then trickle the rest in small chunks,
+            // pausing longer than the read timeout in between
+            let (header, rest) = encoded.split_at(2);
+            peer.write_all(header).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(15)).await;
+            for chunk in rest.chunks(4000) {
+                peer.write_all(chunk).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(15)).await;
+            }
+            peer
+        });
+
+        let mut timeouts = 0;
+        for message in &messages {
+            let bytes = loop {
+                match codec.read_internal().await {
+                    Ok(bytes) => break bytes,
+                    Err(err) if err.kind() == std::io::ErrorKind::TimedOut => timeouts += 1,
+                    Err(err) => panic!("read failed: {}", err),
+                }
+            };
+            assert_eq!(&ServerMessage::decode(bytes).unwrap(), message);
+        }
+        assert!(timeouts > 10, "only {} read timeouts", timeouts);
+        assert_eq!(codec.filled, 0);
+        drop(writer.await.unwrap());
     }
 }
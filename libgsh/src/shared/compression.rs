@@ -0,0 +1,200 @@
+use crate::shared::{frame::rle, protocol::server_hello_ack::Compression};
+use lz4::block::CompressionMode;
+use std::io::{self, Read, Write};
+
+/// Largest zstd dictionary accepted in a `ServerHelloAck`.\
+/// The dictionary is sent with the handshake and kept in memory on both sides for the whole session.
+pub const MAX_ZSTD_DICTIONARY_SIZE: usize = 128 * 1024;
+
+/// Trains a zstd dictionary of at most `max_size` bytes on representative uncompressed frames or segments.\
+/// Dictionaries pay off for many small, similar frames such as dashboards, where each frame alone
+/// is too small to build up compression context. Large or noisy frames gain little.
+/// Training needs a fair number of samples, typically hundreds, and fails on too few.
+pub fn train_zstd_dictionary(samples: &[Vec<u8>], max_size: usize) -> io::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size.min(MAX_ZSTD_DICTIONARY_SIZE))
+}
+
+/// Rejects zstd dictionaries larger than `MAX_ZSTD_DICTIONARY_SIZE`, checked for the connection and every window.
+pub fn check_dictionary_size(compression: Option<&Compression>) -> io::Result<()> {
+    match compression {
+        Some(Compression::Zstd(zstd)) if zstd.dictionary.len() > MAX_ZSTD_DICTIONARY_SIZE => {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Zstd dictionary of {} bytes exceeds the limit of {} bytes",
+                    zstd.dictionary.len(),
+                    MAX_ZSTD_DICTIONARY_SIZE
+                ),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Compresses frame data with the compression negotiated in `ServerHelloAck`.\
+/// Data is returned as is if no compression is used.
+pub fn compress(compression: Option<&Compression>, data: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        None => Ok(data.to_vec()),
+        Some(Compression::Zstd(zstd)) if zstd.dictionary.is_empty() => {
+            zstd::stream::encode_all(data, zstd.level)
+        }
+        Some(Compression::Zstd(zstd)) => {
+            let mut encoder =
+                zstd::stream::Encoder::with_dictionary(Vec::new(), zstd.level, &zstd.dictionary)?;
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        Some(Compression::Lz4(lz4)) => {
+            let mode = match lz4.level {
+                0 => CompressionMode::DEFAULT,
+                level if level > 0 => CompressionMode::HIGHCOMPRESSION(level),
+                level => CompressionMode::FAST(-level),
+            };
+            lz4::block::compress(data, Some(mode), true)
+        }
+        Some(Compression::Rle(settings)) => rle::encode(data, settings.pixel_bytes as usize),
+    }
+}
+
+/// Decompresses frame data with the compression negotiated in `ServerHelloAck`.\
+/// `expected_len` is the size of the uncompressed data, used to preallocate the output
+/// and to reject LZ4 and RLE data that would exceed it.
+pub fn decompress(
+    compression: Option<&Compression>,
+    data: &[u8],
+    expected_len: usize,
+) -> io::Result<Vec<u8>> {
+    match compression {
+        None => Ok(data.to_vec()),
+        Some(Compression::Zstd(zstd)) => {
+            let mut out = Vec::with_capacity(expected_len);
+            if zstd.dictionary.is_empty() {
+                zstd::stream::Decoder::new(data)?.read_to_end(&mut out)?;
+            } else {
+                zstd::stream::Decoder::with_dictionary(data, &zstd.dictionary)?
+                    .read_to_end(&mut out)?;
+            }
+            Ok(out)
+        }
+        // The uncompressed size is prepended to the block by `compress`, checked before allocating
+        Some(Compression::Lz4(_)) => {
+            let Some((prefix, block)) = data.split_first_chunk::<4>() else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Truncated LZ4 size prefix",
+                ));
+            };
+            let size = i32::from_le_bytes(*prefix);
+            if !usize::try_from(size).is_ok_and(|size| size <= expected_len) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "LZ4 size prefix of {} bytes exceeds the expected {} bytes",
+                        size, expected_len
+                    ),
+                ));
+            }
+            lz4::block::decompress(block, Some(size))
+        }
+        Some(Compression::Rle(settings)) => {
+            rle::decode(data, settings.pixel_bytes as usize, expected_len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::protocol::server_hello_ack::{Lz4Compression, ZstdCompression};
+
+    /// A 640x480 RGBA frame with a horizontal red and vertical green gradient.
+    fn vga_gradient() -> Vec<u8> {
+        let (width, height) = (640, 480);
+        let mut frame = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                frame.extend_from_slice(&[
+                    (x * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    0x80,
+                    0xff,
+                ]);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn lz4_round_trips_vga_gradient() {
+        let frame = vga_gradient();
+        for level in [0, 9, -4] {
+            let compression = Compression::Lz4(Lz4Compression { level });
+            let compressed = compress(Some(&compression), &frame).unwrap();
+            assert!(compressed.len() < frame.len());
+            let decompressed = decompress(Some(&compression), &compressed, frame.len()).unwrap();
+            assert_eq!(decompressed, frame);
+        }
+    }
+
+    #[test]
+    fn lz4_size_prefix_beyond_the_expected_size_is_rejected() {
+        let frame = vga_gradient();
+        let compression = Compression::Lz4(Lz4Compression { level: 0 });
+        let compressed = compress(Some(&compression), &frame).unwrap();
+        let err = decompress(Some(&compression), &compressed, frame.len() - 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A forged prefix must not allocate the claimed size
+        let mut forged = i32::MAX.to_le_bytes().to_vec();
+        forged.extend_from_slice(&compressed[4..]);
+        let err = decompress(Some(&compression), &forged, frame.len()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = decompress(Some(&compression), &compressed[..3], frame.len()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// A small 32x8 RGBA dashboard tile showing a bar of the given length.
+    fn dashboard_tile(value: usize) -> Vec<u8> {
+        let mut tile = vec![0x20; 32 * 8 * 4];
+        for y in 2..6 {
+            for x in 0..value % 32 {
+                tile[(y * 32 + x) * 4..][..4].copy_from_slice(&[0x30, 0xc0, 0x60, 0xff]);
+            }
+        }
+        tile
+    }
+
+    #[test]
+    fn zstd_round_trips_with_trained_dictionary() {
+        let samples: Vec<Vec<u8>> = (0..500).map(dashboard_tile).collect();
+        let dictionary = train_zstd_dictionary(&samples, 4 * 1024).unwrap();
+        assert!(!dictionary.is_empty() && dictionary.len() <= 4 * 1024);
+        let compression = Compression::Zstd(ZstdCompression {
+            level: 3,
+            dictionary,
+        });
+        check_dictionary_size(Some(&compression)).unwrap();
+
+        let frame = dashboard_tile(17);
+        let compressed = compress(Some(&compression), &frame).unwrap();
+        let decompressed = decompress(Some(&compression), &compressed, frame.len()).unwrap();
+        assert_eq!(decompressed, frame);
+        // Decoding needs the same dictionary
+        let without = Compression::Zstd(ZstdCompression {
+            level: 3,
+            ..Default::default()
+        });
+        assert!(decompress(Some(&without), &compressed, frame.len()).is_err());
+    }
+
+    #[test]
+    fn oversized_dictionary_is_rejected() {
+        let compression = Compression::Zstd(ZstdCompression {
+            level: 3,
+            dictionary: vec![0; MAX_ZSTD_DICTIONARY_SIZE + 1],
+        });
+        let err = check_dictionary_size(Some(&compression)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
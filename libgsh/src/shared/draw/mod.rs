@@ -0,0 +1,291 @@
+//! Simple 2D drawing primitives for rendering into raw frame buffers.
+//!
+//! ```ignore
+//! let mut buffer = vec![0u8; width * height * 4];
+//! let mut canvas = Canvas::new(&mut buffer, width, height, FrameFormat::Rgba);
+//! canvas.clear(BLACK);
+//! canvas.draw_line((0, 0), (width as i32 - 1, height as i32 - 1), 1, WHITE);
+//! ```
+use crate::shared::protocol::server_hello_ack::FrameFormat;
+
+pub mod text;
+
+/// An RGBA color, converted to the canvas format when drawn.
+pub type Color = [u8; 4];
+
+pub const BLACK: Color = [0, 0, 0, 255];
+pub const WHITE: Color = [255, 255, 255, 255];
+pub const TRANSPARENT: Color = [0, 0, 0, 0];
+
+/// A drawing surface over a tightly packed frame buffer in any `FrameFormat`.\
+/// All drawing operations are clipped to the frame edges.
+#[derive(Debug)]
+pub struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    format: FrameFormat,
+    pixel_bytes: usize,
+}
+
+impl<'a> Canvas<'a> {
+    /// Wraps a frame buffer of `width * height` pixels in the given format.
+    ///
+    /// # Panics
+    /// If the buffer is too small for the given dimensions and format, or the format is planar (`Yuv420p`).
+    pub fn new(data: &'a mut [u8], width: usize, height: usize, format: FrameFormat) -> Self {
+        assert!(
+            format != FrameFormat::Yuv420p,
+            "Drawing on planar YUV frames is not supported, draw in RGBA and convert with `frame::rgba_to_yuv420p`"
+        );
+        let pixel_bytes = format.bytes_per_pixel();
+        assert!(
+            data.len() >= format.row_stride(width) * height,
+            "Frame buffer of {} bytes is too small for {}x{} {:?} pixels",
+            data.len(),
+            width,
+            height,
+            format
+        );
+        Self {
+            data,
+            width,
+            height,
+            format,
+            pixel_bytes,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> FrameFormat {
+        self.format
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data
+    }
+
+    /// Fills the whole canvas with a color.
+    pub fn clear(&mut self, color: Color) {
+        let pixel = encode(color, self.format);
+        let len = self.width * self.height * self.pixel_bytes;
+        for chunk in self.data[..len].chunks_exact_mut(self.pixel_bytes) {
+            chunk.copy_from_slice(&pixel[..self.pixel_bytes]);
+        }
+    }
+
+    /// Sets a single pixel, ignoring coordinates outside of the canvas.
+    #[inline]
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let pixel = encode(color, self.format);
+        let start = (y as usize * self.width + x as usize) * self.pixel_bytes;
+        self.data[start..start + self.pixel_bytes].copy_from_slice(&pixel[..self.pixel_bytes]);
+    }
+
+    /// Draws a line between two points using Bresenham's algorithm.\
+    /// Each point of the line is drawn as a `stroke_width` sized square.
+    pub fn draw_line(
+        &mut self,
+        (x0, y0): (i32, i32),
+        (x1, y1): (i32, i32),
+        stroke_width: usize,
+        color: Color,
+    ) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+        loop {
+            if stroke_width <= 1 {
+                self.set_pixel(x, y, color);
+            } else {
+                self.fill_rect(x, y, stroke_width, stroke_width, color);
+            }
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Fills a rectangle with its top-left corner at `(x, y)`.
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: usize, height: usize, color: Color) {
+        let Some((x0, y0, x1, y1)) = self.clip(x, y, width, height) else {
+            return;
+        };
+        let pixel = encode(color, self.format);
+        for row in y0..y1 {
+            let start = (row * self.width + x0) * self.pixel_bytes;
+            let end = (row * self.width + x1) * self.pixel_bytes;
+            for chunk in self.data[start..end].chunks_exact_mut(self.pixel_bytes) {
+                chunk.copy_from_slice(&pixel[..self.pixel_bytes]);
+            }
+        }
+    }
+
+    /// Draws the outline of a circle using the midpoint circle algorithm.
+    pub fn draw_circle(&mut self, cx: i32, cy: i32, radius: i32, color: Color) {
+        let (mut x, mut y) = (radius, 0);
+        let mut err = 1 - radius;
+        while x >= y {
+            for (px, py) in [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ] {
+                self.set_pixel(cx + px, cy + py, color);
+            }
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    /// Copies a tightly packed image in the canvas format with its top-left corner at `(x, y)`.
+    pub fn blit(&mut self, x: i32, y: i32, src_width: usize, src_height: usize, src: &[u8]) {
+        let src_pitch = src_width * self.pixel_bytes;
+        if src.len() < src_pitch * src_height {
+            log::warn!(
+                "Blit source of {} bytes is too small for {}x{} pixels, skipping.",
+                src.len(),
+                src_width,
+                src_height
+            );
+            return;
+        }
+        let Some((x0, y0, x1, y1)) = self.clip(x, y, src_width, src_height) else {
+            return;
+        };
+        let row_bytes = (x1 - x0) * self.pixel_bytes;
+        let src_x = (x0 as i64 - x as i64) as usize * self.pixel_bytes;
+        for row in y0..y1 {
+            let src_start = (row as i64 - y as i64) as usize * src_pitch + src_x;
+            let dst_start = (row * self.width + x0) * self.pixel_bytes;
+            self.data[dst_start..dst_start + row_bytes]
+                .copy_from_slice(&src[src_start..src_start + row_bytes]);
+        }
+    }
+
+    /// Clips a rectangle to the canvas, returning its `(x0, y0, x1, y1)` bounds if any part is visible.
+    fn clip(
+        &self,
+        x: i32,
+        y: i32,
+        width: usize,
+        height: usize,
+    ) -> Option<(usize, usize, usize, usize)> {
+        let x0 = x.max(0) as usize;
+        let y0 = y.max(0) as usize;
+        let x1 = ((x as i64 + width as i64).max(0) as usize).min(self.width);
+        let y1 = ((y as i64 + height as i64).max(0) as usize).min(self.height);
+        (x0 < x1 && y0 < y1).then_some((x0, y0, x1, y1))
+    }
+}
+
+/// Converts an RGBA color to the byte layout of a frame format, padded to 4 bytes.
+fn encode([r, g, b, a]: Color, format: FrameFormat) -> [u8; 4] {
+    match format {
+        FrameFormat::Rgba => [r, g, b, a],
+        FrameFormat::Rgb => [r, g, b, 0],
+        FrameFormat::Bgra => [b, g, r, a],
+        FrameFormat::Bgr => [b, g, r, 0],
+        FrameFormat::Gray8 => {
+            let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+            [luma as u8, 0, 0, 0]
+        }
+        FrameFormat::Yuv420p => unreachable!("planar format"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 4;
+    const RED: Color = [255, 0, 0, 255];
+
+    /// Coordinates of all pixels that are not black, in row order.
+    fn set_pixels(canvas: &Canvas) -> Vec<(usize, usize)> {
+        let pixel_bytes = canvas.format().bytes_per_pixel();
+        canvas
+            .data()
+            .chunks_exact(pixel_bytes)
+            .enumerate()
+            .filter(|(_, pixel)| pixel.iter().any(|&b| b != 0))
+            .map(|(i, _)| (i % canvas.width(), i / canvas.width()))
+            .collect()
+    }
+
+    #[test]
+    fn line_sets_bresenham_pixels() {
+        let mut buffer = vec![0; WIDTH * HEIGHT * 3];
+        let mut canvas = Canvas::new(&mut buffer, WIDTH, HEIGHT, FrameFormat::Rgb);
+        canvas.draw_line((0, 0), (7, 3), 1, RED);
+        assert_eq!(
+            set_pixels(&canvas),
+            vec![
+                (0, 0),
+                (1, 0),
+                (2, 1),
+                (3, 1),
+                (4, 2),
+                (5, 2),
+                (6, 3),
+                (7, 3)
+            ]
+        );
+        assert_eq!(&canvas.data()[..3], &[255, 0, 0]);
+
+        // The same line in BGRA writes the bytes of that format
+        let mut buffer = vec![0; WIDTH * HEIGHT * 4];
+        let mut canvas = Canvas::new(&mut buffer, WIDTH, HEIGHT, FrameFormat::Bgra);
+        canvas.draw_line((7, 3), (0, 0), 1, RED);
+        assert_eq!(set_pixels(&canvas).len(), 8);
+        assert_eq!(&canvas.data()[..4], &[0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn line_is_clipped_at_frame_edges() {
+        let mut buffer = vec![0; WIDTH * HEIGHT * 4];
+        let mut canvas = Canvas::new(&mut buffer, WIDTH, HEIGHT, FrameFormat::Rgba);
+        canvas.draw_line((-4, 1), (20, 1), 1, RED);
+        assert_eq!(
+            set_pixels(&canvas),
+            (0..WIDTH).map(|x| (x, 1)).collect::<Vec<_>>()
+        );
+        canvas.draw_line((-10, -10), (-1, -1), 3, RED);
+        // Only the corner of the last stroke square reaches into the canvas
+        assert_eq!(set_pixels(&canvas)[..3], [(0, 0), (1, 0), (0, 1)]);
+        assert_eq!(set_pixels(&canvas).len(), WIDTH + 2);
+    }
+}
@@ -1,5 +1,21 @@
-use crate::shared::protocol::frame::Segment;
+use crate::shared::compression::compress;
+use crate::shared::protocol::{
+    frame::Segment,
+    server_hello_ack::{Compression, FrameFormat},
+    Frame,
+};
+use std::collections::VecDeque;
 use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub mod rle;
+
+static NEXT_FRAME_GROUP_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returns a new process-wide unique ID for a group of frames forming one logical update.
+pub fn next_frame_group_id() -> u64 {
+    NEXT_FRAME_GROUP_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 /// A ping-pong previous-frame buffer to avoid full-frame copies between frames.
 ///
@@ -11,6 +27,10 @@ use std::mem;
 ///   which moves ownership of `cur_frame` into the prev-frame storage and returns the
 ///   old previous-frame Vec for reuse as the next `cur_frame`. This avoids copying the
 ///   entire frame buffer every tick.
+///
+/// The order matters: diff first, rotate after sending. The returned buffer holds a stale
+/// (or, on the first rotations, empty) frame, so it must be fully redrawn before the next diff.
+/// When the frame size changes, replace the `PrevFrame` with a new one so the next diff sends a full frame.
 #[derive(Debug, Clone)]
 pub struct PrevFrame {
     buf0: Vec<u8>,
@@ -61,6 +81,78 @@ impl PrevFrame {
     }
 }
 
+/// A small ring of recently sent frames of one window, used to diff against the last frame the client acknowledged.
+///
+/// Usage pattern:
+/// - Diff the new frame with `optimize_segments_against(&cur_frame, ..., history.acked(), pixel_bytes)`.
+/// - Take the window's next sequence number from `ServerStream::next_frame_seq`, store the frame with
+///   `history.push(seq, cur_frame)` and send it with `seq: Some(seq)`.
+/// - Call `history.ack(seq)` for each `FrameAck` of the window received from the client.
+///
+/// If a frame is dropped before it is applied, it is never acknowledged and the next diff is
+/// still computed against the last frame the client actually has.
+/// Until the first acknowledgement (or if the acked frame was evicted) `acked` is empty, which yields a full frame.
+#[derive(Debug, Clone)]
+pub struct FrameHistory {
+    frames: VecDeque<(u64, Vec<u8>)>,
+    capacity: usize,
+    acked_seq: Option<u64>,
+}
+
+impl FrameHistory {
+    /// Creates a new history keeping at most `capacity` unacknowledged frames besides the acked one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity + 1),
+            capacity: capacity.max(1),
+            acked_seq: None,
+        }
+    }
+
+    /// Stores a frame sent with sequence number `seq`.\
+    /// Sequence numbers must increase, as the ones returned by `ServerStream::next_frame_seq` for the window.
+    pub fn push(&mut self, seq: u64, frame: Vec<u8>) {
+        self.frames.push_back((seq, frame));
+        // Evict the oldest unacknowledged frames, but never the acked one
+        while self.frames.len() > self.capacity + 1 {
+            let oldest = self
+                .frames
+                .iter()
+                .position(|(s, _)| Some(*s) != self.acked_seq)
+                .unwrap_or(0);
+            self.frames.remove(oldest);
+        }
+    }
+
+    /// Marks the frame with sequence number `seq` as fully applied by the client.\
+    /// Older frames are dropped since they will never be diffed against again.
+    pub fn ack(&mut self, seq: u64) {
+        if self.acked_seq.is_some_and(|acked| acked >= seq) {
+            return;
+        }
+        if !self.frames.iter().any(|(s, _)| *s == seq) {
+            log::trace!("Acknowledged frame {} is no longer in history", seq);
+            return;
+        }
+        self.frames.retain(|(s, _)| *s >= seq);
+        self.acked_seq = Some(seq);
+    }
+
+    /// Sequence number of the last acknowledged frame, if any.
+    pub fn acked_seq(&self) -> Option<u64> {
+        self.acked_seq
+    }
+
+    /// Returns the last acknowledged frame, or an empty slice if there is none.
+    pub fn acked(&self) -> &[u8] {
+        self.frames
+            .iter()
+            .find(|(s, _)| Some(*s) == self.acked_seq)
+            .map(|(_, frame)| frame.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
 pub fn full_frame_segment(
     full_frame_data: &[u8],
     frame_width: usize,
@@ -75,14 +167,214 @@ pub fn full_frame_segment(
     }]
 }
 
+/// Compresses a full frame with the compression negotiated in `ServerHelloAck`,
+/// returning a single full-frame segment ready to be sent.\
+/// Using this ensures the data sent always matches the advertised compression.
+pub fn compress_frame(
+    full_frame_data: &[u8],
+    frame_width: usize,
+    frame_height: usize,
+    compression: Option<&Compression>,
+) -> std::io::Result<Vec<Segment>> {
+    let data = compress(compression, full_frame_data)?;
+    Ok(vec![Segment {
+        x: 0,
+        y: 0,
+        width: frame_width as u32,
+        height: frame_height as u32,
+        data,
+    }])
+}
+
+/// A single-pixel solid-color frame, which the client stretches to fill the window.\
+/// Used as a cheap placeholder while the first real frame is being rendered.
+pub fn placeholder_frame(
+    window_id: u32,
+    color: [u8; 3],
+    format: FrameFormat,
+    compression: Option<&Compression>,
+) -> std::io::Result<Frame> {
+    let pixel = match format {
+        FrameFormat::Rgb => color.to_vec(),
+        FrameFormat::Rgba => vec![color[0], color[1], color[2], 255],
+        FrameFormat::Bgr => vec![color[2], color[1], color[0]],
+        FrameFormat::Bgra => vec![color[2], color[1], color[0], 255],
+        FrameFormat::Gray8 => {
+            let [r, g, b] = color.map(u32::from);
+            vec![((r * 299 + g * 587 + b * 114) / 1000) as u8]
+        }
+        FrameFormat::Yuv420p => rgb_to_yuv(color).to_vec(),
+    };
+    let data = compress(compression, &pixel)?;
+    Ok(Frame {
+        window_id,
+        width: 1,
+        height: 1,
+        segments: full_frame_segment(&data, 1, 1),
+        seq: None,
+        frame_group_id: None,
+        is_final: false,
+        is_keyframe: false,
+    })
+}
+
+/// Converts a tightly packed RGBA frame to planar YUV 4:2:0 for `FrameFormat::Yuv420p`.\
+/// Chroma is averaged over blocks of 2x2 pixels. The alpha channel is dropped.
+pub fn rgba_to_yuv420p(rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let mut out = vec![0; width * height + 2 * chroma_width * chroma_height];
+    let (luma, chroma) = out.split_at_mut(width * height);
+    let (u_plane, v_plane) = chroma.split_at_mut(chroma_width * chroma_height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) * 4;
+            luma[y * width + x] = rgb_to_yuv([rgba[i], rgba[i + 1], rgba[i + 2]])[0];
+        }
+    }
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+            for y in (cy * 2)..(cy * 2 + 2).min(height) {
+                for x in (cx * 2)..(cx * 2 + 2).min(width) {
+                    let i = (y * width + x) * 4;
+                    for channel in 0..3 {
+                        sum[channel] += rgba[i + channel] as u32;
+                    }
+                    count += 1;
+                }
+            }
+            let [_, u, v] = rgb_to_yuv(sum.map(|c| (c / count) as u8));
+            u_plane[cy * chroma_width + cx] = u;
+            v_plane[cy * chroma_width + cx] = v;
+        }
+    }
+    out
+}
+
+/// Converts an RGB color to BT.601 limited range YUV.
+fn rgb_to_yuv([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let [r, g, b] = [r, g, b].map(i32::from);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+/// Bytes reserved for the protobuf encoding of a frame message and each of its segments when tiling.
+const TILE_FRAME_OVERHEAD: usize = 64;
+const TILE_SEGMENT_OVERHEAD: usize = 32;
+
+/// Splits a frame into multiple frames that each encode to at most `max_message_size` bytes.\
+/// Segments are split along rows, so this only works on **uncompressed** segment data:
+/// tile frames before compressing their segments.
+/// Fails with `InvalidInput` if a segment too large for a single tile is not `width * height * pixel_bytes` bytes,
+/// e.g. because it was compressed, as splitting it would corrupt the frame.
+/// If the frame has a sequence number, it is only set on the last tile so it is acknowledged once complete.
+/// Multiple tiles share a frame group ID so the client presents them at once.
+pub fn tile_frame(
+    frame: Frame,
+    max_message_size: usize,
+    pixel_bytes: usize,
+) -> std::io::Result<Vec<Frame>> {
+    let budget = max_message_size.saturating_sub(TILE_FRAME_OVERHEAD);
+    let mut pieces = Vec::new();
+    for segment in frame.segments {
+        let row_bytes = segment.width as usize * pixel_bytes;
+        if segment.data.len() + TILE_SEGMENT_OVERHEAD <= budget {
+            pieces.push(segment);
+            continue;
+        }
+        if row_bytes == 0 || segment.data.len() != row_bytes * segment.height as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Cannot tile a {}x{} segment of {} bytes, only uncompressed segments of {} byte pixels can be split",
+                    segment.width,
+                    segment.height,
+                    segment.data.len(),
+                    pixel_bytes
+                ),
+            ));
+        }
+        let rows_per_tile = (budget.saturating_sub(TILE_SEGMENT_OVERHEAD) / row_bytes).max(1);
+        for (i, rows) in segment.data.chunks(rows_per_tile * row_bytes).enumerate() {
+            pieces.push(Segment {
+                x: segment.x,
+                y: segment.y + (i * rows_per_tile) as i32,
+                width: segment.width,
+                height: (rows.len() / row_bytes) as u32,
+                data: rows.to_vec(),
+            });
+        }
+    }
+
+    let mut tiles: Vec<Frame> = Vec::new();
+    let mut tile_size = 0;
+    for piece in pieces {
+        let piece_size = piece.data.len() + TILE_SEGMENT_OVERHEAD;
+        if tiles.is_empty() || tile_size + piece_size > budget {
+            tiles.push(Frame {
+                window_id: frame.window_id,
+                width: frame.width,
+                height: frame.height,
+                segments: Vec::new(),
+                seq: None,
+                frame_group_id: None,
+                is_final: false,
+                is_keyframe: frame.is_keyframe,
+            });
+            tile_size = 0;
+        }
+        tile_size += piece_size;
+        tiles.last_mut().unwrap().segments.push(piece);
+    }
+    // Group the tiles so the client presents them atomically
+    if tiles.len() > 1 || frame.frame_group_id.is_some() {
+        let group_id = frame.frame_group_id.unwrap_or_else(next_frame_group_id);
+        for tile in &mut tiles {
+            tile.frame_group_id = Some(group_id);
+        }
+    }
+    if let Some(last) = tiles.last_mut() {
+        last.seq = frame.seq;
+        last.is_final = frame.frame_group_id.is_none() || frame.is_final;
+    }
+    Ok(tiles)
+}
+
 /// A function to optimize a frame segments for transmission.
 /// Identifying what partial (rectangle-area) updates are needed to be sent to the client compared to the previous frame.
+/// Changed rows are grouped into bands, then each band is cropped to its changed columns,
+/// so a small change only sends the pixels around it rather than whole rows.
+///
+/// Frames are expected to be tightly packed, with a row stride of `frame_width * pixel_bytes`.
+/// `pixel_bytes` must match the negotiated format, see `FrameFormat::bytes_per_pixel`. Planar formats are not supported.
+/// A mismatch shifts every row, producing garbage segments rather than an error.
 pub fn optimize_segments(
     full_frame_data: &[u8],
     frame_width: usize,
     frame_height: usize,
     prev_frame: &PrevFrame,
     pixel_bytes: usize,
+) -> Vec<Segment> {
+    optimize_segments_against(
+        full_frame_data,
+        frame_width,
+        frame_height,
+        prev_frame.current(),
+        pixel_bytes,
+    )
+}
+
+/// Same as `optimize_segments`, but diffs against an arbitrary previous frame buffer,
+/// such as the last client-acknowledged frame from `FrameHistory::acked`.
+pub fn optimize_segments_against(
+    full_frame_data: &[u8],
+    frame_width: usize,
+    frame_height: usize,
+    prev_frame: &[u8],
+    pixel_bytes: usize,
 ) -> Vec<Segment> {
     const MIN_SEGMENT_ROWS: usize = 4; // Minimum segment size in rows
     const MAX_SEGMENT_COUNT: usize = 50; // Maximum number of segments to send
@@ -93,7 +385,7 @@ pub fn optimize_segments(
     for y in 0..frame_height {
         let start = y * frame_width * pixel_bytes;
         let end = start + frame_width * pixel_bytes;
-        if let Some(prev_row) = prev_frame.current().get(start..end) {
+        if let Some(prev_row) = prev_frame.get(start..end) {
             if *prev_row != full_frame_data[start..end] {
                 let segment_data = full_frame_data[start..end].to_vec();
                 if let Some(ref mut segment) = current_segment {
@@ -174,6 +466,17 @@ pub fn optimize_segments(
         optimized_segments.push(segment);
     }
 
+    // Second pass: crop each band of changed rows to its changed columns
+    for segment in &mut optimized_segments {
+        crop_segment_columns(
+            segment,
+            full_frame_data,
+            frame_width,
+            prev_frame,
+            pixel_bytes,
+        );
+    }
+
     // Note: we do not mutate or copy into the previous-frame buffer here.
     // The caller should call `PrevFrame::update_with_frame(cur_frame_vec)` after
     // sending the frame to rotate buffers and avoid an O(N) copy.
@@ -181,6 +484,97 @@ pub fn optimize_segments(
     optimized_segments
 }
 
+/// Crops a full-width band of rows horizontally to the leftmost and rightmost changed pixel in any of its rows.\
+/// Rows missing from the previous frame count as changed across the whole width.
+/// The segment data is rebuilt with a row stride of the cropped width.
+fn crop_segment_columns(
+    segment: &mut Segment,
+    full_frame_data: &[u8],
+    frame_width: usize,
+    prev_frame: &[u8],
+    pixel_bytes: usize,
+) {
+    let stride = frame_width * pixel_bytes;
+    let rows = segment.y as usize..segment.y as usize + segment.height as usize;
+    let mut min_x = frame_width;
+    let mut max_x = 0;
+    for y in rows.clone() {
+        let row = &full_frame_data[y * stride..(y + 1) * stride];
+        let Some(prev_row) = prev_frame.get(y * stride..(y + 1) * stride) else {
+            min_x = 0;
+            max_x = frame_width - 1;
+            break;
+        };
+        let pixels = || row.chunks(pixel_bytes).zip(prev_row.chunks(pixel_bytes));
+        if let Some(first) = pixels().position(|(a, b)| a != b) {
+            let last = pixels().rposition(|(a, b)| a != b).unwrap_or(first);
+            min_x = min_x.min(first);
+            max_x = max_x.max(last);
+        }
+    }
+    if min_x > max_x || (min_x == 0 && max_x + 1 == frame_width) {
+        return; // Nothing to crop
+    }
+
+    let width = max_x + 1 - min_x;
+    let mut data = Vec::with_capacity(width * pixel_bytes * rows.len());
+    for y in rows {
+        let start = y * stride + min_x * pixel_bytes;
+        data.extend_from_slice(&full_frame_data[start..start + width * pixel_bytes]);
+    }
+    segment.x = min_x as i32;
+    segment.width = width as u32;
+    segment.data = data;
+}
+
+/// Default tile size in pixels for `optimize_segments_tiled`.
+pub const DEFAULT_TILE_SIZE: usize = 32;
+
+/// An alternative to `optimize_segments` for content with localized changes, like a moving cursor or sidebar.
+/// Divides the frame into `tile_size`x`tile_size` tiles (smaller at the right and bottom edges),
+/// compares each tile against the previous frame and emits only the dirty tiles as segments.
+/// If the previous frame is missing or has a different size, the whole frame is sent as one segment.
+pub fn optimize_segments_tiled(
+    full_frame_data: &[u8],
+    frame_width: usize,
+    frame_height: usize,
+    prev_frame: &[u8],
+    pixel_bytes: usize,
+    tile_size: usize,
+) -> Vec<Segment> {
+    if prev_frame.len() != full_frame_data.len() || tile_size == 0 {
+        return full_frame_segment(full_frame_data, frame_width, frame_height);
+    }
+    let stride = frame_width * pixel_bytes;
+    let mut segments = Vec::new();
+    for tile_y in (0..frame_height).step_by(tile_size) {
+        let tile_height = tile_size.min(frame_height - tile_y);
+        for tile_x in (0..frame_width).step_by(tile_size) {
+            let tile_width = tile_size.min(frame_width - tile_x);
+            let row_bytes = tile_width * pixel_bytes;
+            let rows = (tile_y..tile_y + tile_height).map(|y| y * stride + tile_x * pixel_bytes);
+            let dirty = rows.clone().any(|start| {
+                full_frame_data[start..start + row_bytes] != prev_frame[start..start + row_bytes]
+            });
+            if !dirty {
+                continue;
+            }
+            let mut data = Vec::with_capacity(row_bytes * tile_height);
+            for start in rows {
+                data.extend_from_slice(&full_frame_data[start..start + row_bytes]);
+            }
+            segments.push(Segment {
+                x: tile_x as i32,
+                y: tile_y as i32,
+                width: tile_width as u32,
+                height: tile_height as u32,
+                data,
+            });
+        }
+    }
+    segments
+}
+
 // fn find_diffs(
 //     full_frame_data: &[u8],
 //     prev_frame: &[u8],
@@ -230,3 +624,227 @@ pub fn optimize_segments(
 
 //     groups
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::protocol::server_hello_ack::ZstdCompression;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 64;
+    const PIXEL_BYTES: usize = 4;
+
+    /// A black frame with the given bands of rows, as first and end row, set to `value` across the whole width.
+    fn frame_with_rows(rows: &[(usize, usize)], value: u8) -> Vec<u8> {
+        let stride = WIDTH * PIXEL_BYTES;
+        let mut frame = vec![0; stride * HEIGHT];
+        for &(start, end) in rows {
+            frame[start * stride..end * stride].fill(value);
+        }
+        frame
+    }
+
+    fn segment_rows(segments: &[Segment]) -> Vec<(i32, u32)> {
+        segments.iter().map(|s| (s.y, s.height)).collect()
+    }
+
+    #[test]
+    fn diff_after_dropped_frame_is_against_acked_frame() {
+        let mut history = FrameHistory::new(4);
+        let acked = frame_with_rows(&[], 0);
+        history.push(0, acked.clone());
+        history.ack(0);
+
+        // Frame 1 is sent but never acknowledged, the client dropped it
+        let dropped = frame_with_rows(&[(10, 20)], 0xff);
+        history.push(1, dropped.clone());
+
+        let next = frame_with_rows(&[(10, 20), (30, 40)], 0xff);
+        let segments =
+            optimize_segments_against(&next, WIDTH, HEIGHT, history.acked(), PIXEL_BYTES);
+        assert_eq!(history.acked_seq(), Some(0));
+        assert_eq!(history.acked(), acked.as_slice());
+        // The rows of the dropped frame are sent again
+        assert_eq!(segment_rows(&segments), vec![(10, 10), (30, 10)]);
+
+        // Diffing against the unacknowledged frame would have lost them
+        let against_dropped =
+            optimize_segments_against(&next, WIDTH, HEIGHT, &dropped, PIXEL_BYTES);
+        assert_eq!(segment_rows(&against_dropped), vec![(30, 10)]);
+    }
+
+    #[test]
+    fn acked_frame_is_never_evicted() {
+        let mut history = FrameHistory::new(2);
+        assert!(history.acked().is_empty());
+        history.push(0, vec![0]);
+        history.ack(0);
+        for seq in 1..10 {
+            history.push(seq, vec![seq as u8]);
+        }
+        assert_eq!(history.acked(), &[0]);
+        history.ack(9);
+        assert_eq!(history.acked(), &[9]);
+        // Stale and unknown acknowledgements are ignored
+        history.ack(5);
+        history.ack(42);
+        assert_eq!(history.acked_seq(), Some(9));
+    }
+
+    /// Writes segments onto a copy of `prev`, as the client does when rendering them.
+    fn apply_segments(
+        prev: &[u8],
+        segments: &[Segment],
+        width: usize,
+        pixel_bytes: usize,
+    ) -> Vec<u8> {
+        let mut frame = prev.to_vec();
+        let stride = width * pixel_bytes;
+        for segment in segments {
+            let row_bytes = segment.width as usize * pixel_bytes;
+            for (row, data) in segment.data.chunks_exact(row_bytes).enumerate() {
+                let start = (segment.y as usize + row) * stride + segment.x as usize * pixel_bytes;
+                frame[start..start + row_bytes].copy_from_slice(data);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn compressed_frame_decodes_to_the_original() {
+        let frame = frame_with_rows(&[(10, 20)], 0xff);
+        let none = compress_frame(&frame, WIDTH, HEIGHT, None).unwrap();
+        assert_eq!(none.len(), 1);
+        assert_eq!(none[0].data, frame);
+
+        let zstd = Compression::Zstd(ZstdCompression {
+            level: 3,
+            ..Default::default()
+        });
+        let segments = compress_frame(&frame, WIDTH, HEIGHT, Some(&zstd)).unwrap();
+        assert_eq!(segments.len(), 1);
+        let segment = &segments[0];
+        assert_eq!((segment.x, segment.y), (0, 0));
+        assert_eq!(
+            (segment.width, segment.height),
+            (WIDTH as u32, HEIGHT as u32)
+        );
+        assert!(segment.data.len() < frame.len());
+        // Decoded as the client does
+        let decoded =
+            crate::shared::compression::decompress(Some(&zstd), &segment.data, frame.len())
+                .unwrap();
+        assert_eq!(decoded, frame);
+    }
+
+    #[test]
+    fn gray8_frame_survives_segment_round_trip() {
+        const SIZE: usize = 256;
+        let pixel_bytes = FrameFormat::Gray8.bytes_per_pixel();
+        assert_eq!(pixel_bytes, 1);
+        let first: Vec<u8> = (0..SIZE * SIZE).map(|i| (i % SIZE) as u8).collect();
+
+        // Without a previous frame, the whole frame is sent at one byte per pixel
+        let segments = optimize_segments_against(&first, SIZE, SIZE, &[], pixel_bytes);
+        let bytes: usize = segments.iter().map(|s| s.data.len()).sum();
+        assert_eq!(bytes, SIZE * SIZE);
+        assert_eq!(
+            apply_segments(&vec![0; SIZE * SIZE], &segments, SIZE, pixel_bytes),
+            first
+        );
+
+        // A changed block is sent as a cropped segment of one byte per pixel
+        let mut second = first.clone();
+        for y in 100..120 {
+            second[y * SIZE + 40..y * SIZE + 60].fill(0xff);
+        }
+        let segments = optimize_segments_against(&second, SIZE, SIZE, &first, pixel_bytes);
+        for segment in &segments {
+            assert_eq!(
+                segment.data.len(),
+                (segment.width * segment.height) as usize
+            );
+        }
+        assert_eq!(apply_segments(&first, &segments, SIZE, pixel_bytes), second);
+    }
+
+    #[test]
+    fn single_dirty_tile_is_sent_alone() {
+        const SIZE: usize = 128;
+        let prev = vec![0; SIZE * SIZE * PIXEL_BYTES];
+        let mut next = prev.clone();
+        // One pixel inside the tile at (64, 32)
+        let start = (40 * SIZE + 70) * PIXEL_BYTES;
+        next[start..start + PIXEL_BYTES].fill(0xff);
+
+        let segments =
+            optimize_segments_tiled(&next, SIZE, SIZE, &prev, PIXEL_BYTES, DEFAULT_TILE_SIZE);
+        assert_eq!(segments.len(), 1);
+        let tile = &segments[0];
+        assert_eq!((tile.x, tile.y, tile.width, tile.height), (64, 32, 32, 32));
+        assert_eq!(
+            tile.data.len(),
+            DEFAULT_TILE_SIZE * DEFAULT_TILE_SIZE * PIXEL_BYTES
+        );
+        assert_eq!(apply_segments(&prev, &segments, SIZE, PIXEL_BYTES), next);
+
+        // An unchanged frame sends nothing
+        assert!(
+            optimize_segments_tiled(&prev, SIZE, SIZE, &prev, PIXEL_BYTES, DEFAULT_TILE_SIZE)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn centered_dirty_rect_is_cropped_to_its_columns() {
+        const SIZE: usize = 64;
+        let stride = SIZE * PIXEL_BYTES;
+        let prev = vec![0; stride * SIZE];
+        let mut next = prev.clone();
+        for y in 28..36 {
+            next[y * stride + 28 * PIXEL_BYTES..y * stride + 36 * PIXEL_BYTES].fill(0xff);
+        }
+
+        let segments = optimize_segments_against(&next, SIZE, SIZE, &prev, PIXEL_BYTES);
+        assert_eq!(segments.len(), 1);
+        let segment = &segments[0];
+        assert_eq!(
+            (segment.x, segment.y, segment.width, segment.height),
+            (28, 28, 8, 8)
+        );
+        // Rows are packed with the stride of the segment width, not the frame width
+        assert_eq!(segment.data.len(), 8 * 8 * PIXEL_BYTES);
+        assert!(segment.data.iter().all(|&b| b == 0xff));
+        assert_eq!(apply_segments(&prev, &segments, SIZE, PIXEL_BYTES), next);
+
+        // Changes at different columns of the same band are cropped to their union
+        let mut next = prev.clone();
+        for (y, x) in [(30, 10), (31, 20), (32, 20), (33, 50)] {
+            next[y * stride + x * PIXEL_BYTES] = 1;
+        }
+        let segments = optimize_segments_against(&next, SIZE, SIZE, &prev, PIXEL_BYTES);
+        assert_eq!(segments.len(), 1);
+        assert_eq!((segments[0].x, segments[0].width), (10, 41));
+        assert_eq!(apply_segments(&prev, &segments, SIZE, PIXEL_BYTES), next);
+    }
+
+    #[test]
+    fn tiling_rejects_compressed_segments() {
+        let frame = Frame {
+            window_id: 1,
+            width: WIDTH as u32,
+            height: HEIGHT as u32,
+            segments: vec![Segment {
+                x: 0,
+                y: 0,
+                width: WIDTH as u32,
+                height: HEIGHT as u32,
+                // Fewer bytes than the segment's pixels, as after compression
+                data: vec![0; WIDTH * HEIGHT * PIXEL_BYTES / 2],
+            }],
+            ..Default::default()
+        };
+        let err = tile_frame(frame, 1024, PIXEL_BYTES).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,133 @@
+//! Run-length encoding of pixels, for frames with large areas of a single color such as flat UIs.
+//!
+//! The encoded data is a sequence of runs, each a varint count followed by one pixel of `pixel_bytes` bytes.
+//! A solid segment encodes to a few bytes, while noisy data grows by at most one byte per pixel.
+
+use prost::encoding::{decode_varint, encode_varint};
+use std::io::{self, ErrorKind};
+
+/// Encodes `data`, tightly packed pixels of `pixel_bytes` bytes each, as runs of identical pixels.
+pub fn encode(data: &[u8], pixel_bytes: usize) -> io::Result<Vec<u8>> {
+    check_layout(data.len(), pixel_bytes, ErrorKind::InvalidInput)?;
+    let mut out = Vec::new();
+    let mut pixels = data.chunks_exact(pixel_bytes).peekable();
+    while let Some(pixel) = pixels.next() {
+        let mut count = 1u64;
+        while pixels.next_if_eq(&pixel).is_some() {
+            count += 1;
+        }
+        encode_varint(count, &mut out);
+        out.extend_from_slice(pixel);
+    }
+    Ok(out)
+}
+
+/// Decodes runs produced by `encode`, failing if the output would exceed `max_len` bytes.
+pub fn decode(mut data: &[u8], pixel_bytes: usize, max_len: usize) -> io::Result<Vec<u8>> {
+    check_layout(0, pixel_bytes, ErrorKind::InvalidData)?;
+    let mut out = Vec::with_capacity(max_len);
+    while !data.is_empty() {
+        let count =
+            decode_varint(&mut data).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        if data.len() < pixel_bytes {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Truncated RLE run"));
+        }
+        let (pixel, rest) = data.split_at(pixel_bytes);
+        data = rest;
+        usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(pixel_bytes))
+            .filter(|&len| {
+                out.len()
+                    .checked_add(len)
+                    .is_some_and(|total| total <= max_len)
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("RLE data exceeds the expected {} bytes", max_len),
+                )
+            })?;
+        for _ in 0..count {
+            out.extend_from_slice(pixel);
+        }
+    }
+    Ok(out)
+}
+
+fn check_layout(len: usize, pixel_bytes: usize, kind: ErrorKind) -> io::Result<()> {
+    if pixel_bytes == 0 || !len.is_multiple_of(pixel_bytes) {
+        return Err(io::Error::new(
+            kind,
+            format!(
+                "{} bytes are not a whole number of {} byte pixels",
+                len, pixel_bytes
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXEL_BYTES: usize = 4;
+    const PIXELS: usize = 64 * 48;
+
+    /// Pixels of pseudo-random noise, where neighbours almost never repeat.
+    fn noise() -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..PIXELS * PIXEL_BYTES)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn solid_frames_encode_smaller_than_noisy_frames() {
+        let solid = [0x20, 0x40, 0x80, 0xff].repeat(PIXELS);
+        let noisy = noise();
+
+        let solid_rle = encode(&solid, PIXEL_BYTES).unwrap();
+        let noisy_rle = encode(&noisy, PIXEL_BYTES).unwrap();
+        // A single run, a varint count and one pixel
+        assert!(solid_rle.len() <= 3 + PIXEL_BYTES);
+        // Noise grows by at most one count byte per pixel
+        assert!(noisy_rle.len() > noisy.len());
+        assert!(noisy_rle.len() <= noisy.len() + PIXELS);
+
+        assert_eq!(decode(&solid_rle, PIXEL_BYTES, solid.len()).unwrap(), solid);
+        assert_eq!(decode(&noisy_rle, PIXEL_BYTES, noisy.len()).unwrap(), noisy);
+    }
+
+    #[test]
+    fn runs_beyond_the_expected_size_are_rejected() {
+        let solid = vec![7; PIXELS * PIXEL_BYTES];
+        let encoded = encode(&solid, PIXEL_BYTES).unwrap();
+        let err = decode(&encoded, PIXEL_BYTES, solid.len() - PIXEL_BYTES).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huge_run_counts_are_rejected() {
+        // A one pixel run followed by a run so long the total length overflows
+        let mut encoded = Vec::new();
+        encode_varint(1, &mut encoded);
+        encoded.push(7);
+        encode_varint(u64::MAX, &mut encoded);
+        encoded.push(7);
+        let err = decode(&encoded, 1, PIXELS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn partial_pixels_are_rejected() {
+        let err = encode(&[1, 2, 3], PIXEL_BYTES).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = decode(&[1, 2, 3], PIXEL_BYTES, PIXEL_BYTES).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
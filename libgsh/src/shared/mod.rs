@@ -1,13 +1,46 @@
 pub mod auth;
 pub mod cert;
 pub mod codec;
+pub mod compression;
+pub mod draw;
 pub mod frame;
+pub mod transfer;
 
 pub mod protocol {
     include!(concat!(env!("OUT_DIR"), "/protocol.rs"));
 }
 
+/// Newest protocol version implemented by this library.
 pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version this library can still talk, with reduced features.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Chooses the protocol version of a connection, the newest version within both the local and the peer's range.\
+/// Returns `None` if the ranges don't overlap. Peers announcing no minimum (0) only support their newest version.
+pub fn negotiate_protocol_version(
+    supported: &std::ops::RangeInclusive<u32>,
+    peer_min: u32,
+    peer_max: u32,
+) -> Option<u32> {
+    let peer_min = if peer_min == 0 { peer_max } else { peer_min };
+    let version = peer_max.min(*supported.end());
+    (version >= peer_min.max(*supported.start())).then_some(version)
+}
+
+/// Chooses the zstd compression level of a connection, the client's `preferred` level clamped to the `allowed` range.\
+/// The server's own `level` is used if the client has no preference or the server allows no other levels.
+pub fn negotiate_zstd_level(
+    level: i32,
+    allowed: Option<&std::ops::RangeInclusive<i32>>,
+    preferred: Option<i32>,
+) -> i32 {
+    match (allowed, preferred) {
+        (Some(allowed), Some(preferred)) if !allowed.is_empty() => {
+            preferred.clamp(*allowed.start(), *allowed.end())
+        }
+        _ => level,
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum HandshakeError {
@@ -18,6 +51,10 @@ pub enum HandshakeError {
     InvalidPassword,
     SignatureRequired,
     SignatureInvalid,
+    TotpRequired,
+    InvalidTotp,
+    /// The server rejected the client with a status update, see `StatusUpdate::rejected`.
+    Rejected(protocol::status_update::StatusType, String),
     AnyError(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
@@ -30,12 +67,272 @@ impl std::fmt::Display for HandshakeError {
             HandshakeError::InvalidPassword => write!(f, "Invalid password"),
             HandshakeError::SignatureRequired => write!(f, "Signature required"),
             HandshakeError::SignatureInvalid => write!(f, "Signature invalid"),
+            HandshakeError::TotpRequired => write!(f, "One-time password required"),
+            HandshakeError::InvalidTotp => write!(f, "Invalid one-time password"),
+            HandshakeError::Rejected(kind, message) => {
+                use protocol::status_update::StatusType;
+                match kind {
+                    StatusType::AuthFailed => write!(f, "Authentication failed: {}", message),
+                    StatusType::VersionMismatch => {
+                        write!(f, "Incompatible protocol version: {}", message)
+                    }
+                    StatusType::ServerFull => write!(f, "Server full: {}", message),
+                    _ => write!(f, "Rejected by the server: {}", message),
+                }
+            }
             HandshakeError::ProstDecodeError(err) => write!(f, "Prost decode error: {}", err),
             HandshakeError::AnyError(err) => write!(f, "{}", err),
         }
     }
 }
 
+impl protocol::server_hello_ack::FrameFormat {
+    /// Number of bytes per pixel, or per pixel of the Y plane for planar formats.
+    pub const fn bytes_per_pixel(self) -> usize {
+        use protocol::server_hello_ack::FrameFormat;
+        match self {
+            FrameFormat::Rgba | FrameFormat::Bgra => 4,
+            FrameFormat::Rgb | FrameFormat::Bgr => 3,
+            FrameFormat::Gray8 | FrameFormat::Yuv420p => 1,
+        }
+    }
+
+    /// Number of bytes per row of a tightly packed frame `width` pixels wide.\
+    /// For planar formats this is the stride of the Y plane.
+    pub const fn row_stride(self, width: usize) -> usize {
+        width * self.bytes_per_pixel()
+    }
+
+    /// Size in bytes of the uncompressed data of a segment or frame of `width` x `height` pixels.
+    pub fn data_len(self, width: u32, height: u32) -> usize {
+        use protocol::server_hello_ack::FrameFormat;
+        let (width, height) = (width as usize, height as usize);
+        match self {
+            FrameFormat::Yuv420p => width * height + 2 * width.div_ceil(2) * height.div_ceil(2),
+            packed => packed.row_stride(width) * height,
+        }
+    }
+}
+
+impl protocol::server_hello_ack::WindowSettings {
+    /// Frame format of the window, its override or the connection's `default`.
+    pub fn frame_format(
+        &self,
+        default: protocol::server_hello_ack::FrameFormat,
+    ) -> protocol::server_hello_ack::FrameFormat {
+        self.format
+            .and_then(|format| format.try_into().ok())
+            .unwrap_or(default)
+    }
+
+    /// Compression of the window's frames, its override or the connection's `default`.
+    pub fn frame_compression(
+        &self,
+        default: Option<&protocol::server_hello_ack::Compression>,
+    ) -> Option<protocol::server_hello_ack::Compression> {
+        use protocol::server_hello_ack::{window_settings, Compression};
+        match &self.compression {
+            Some(window_settings::Compression::Zstd(zstd)) => Some(Compression::Zstd(zstd.clone())),
+            Some(window_settings::Compression::Lz4(lz4)) => Some(Compression::Lz4(*lz4)),
+            Some(window_settings::Compression::Rle(rle)) => Some(Compression::Rle(*rle)),
+            Some(window_settings::Compression::Uncompressed(())) => None,
+            None => default.cloned(),
+        }
+    }
+}
+
+impl protocol::ServerHelloAck {
+    /// The first alternate transport offered by the server that this library's client can migrate to,
+    /// only TLS over TCP is implemented.\
+    /// Migrating resumes the session with the resumption token, so nothing is offered without one.
+    pub fn migration_offer(&self) -> Option<&protocol::server_hello_ack::TransportOffer> {
+        use protocol::server_hello_ack::transport_offer::Transport;
+        if self.resumption_token.is_empty() {
+            return None;
+        }
+        self.transport_offers.iter().find(|offer| {
+            offer.transport() == Transport::Tls
+                && u16::try_from(offer.port).is_ok_and(|port| port != 0)
+        })
+    }
+}
+
+impl protocol::StatusUpdate {
+    /// A status update announcing a graceful disconnect.
+    pub fn exit() -> Self {
+        Self {
+            kind: protocol::status_update::StatusType::Exit as i32,
+            details: None,
+        }
+    }
+
+    /// A handshake rejection of the given kind (e.g. `AuthFailed`) with a message explaining why.\
+    /// Clients report it as `HandshakeError::Rejected`.
+    pub fn rejected(kind: protocol::status_update::StatusType, message: impl Into<String>) -> Self {
+        Self {
+            kind: kind as i32,
+            details: Some(protocol::status_update::Details::Error(
+                protocol::status_update::Error {
+                    message: message.into(),
+                    code: 0,
+                },
+            )),
+        }
+    }
+
+    /// The message of the details, if any.
+    pub fn message(&self) -> Option<&str> {
+        match &self.details {
+            Some(protocol::status_update::Details::Info(info)) => Some(&info.message),
+            Some(protocol::status_update::Details::Warning(warning)) => Some(&warning.message),
+            Some(protocol::status_update::Details::Error(error)) => Some(&error.message),
+            None => None,
+        }
+    }
+
+    /// A keepalive request, answered by the peer with `pong`.
+    pub fn ping() -> Self {
+        Self {
+            kind: protocol::status_update::StatusType::Ping as i32,
+            details: None,
+        }
+    }
+
+    /// A keepalive response to a `ping`.
+    pub fn pong() -> Self {
+        Self {
+            kind: protocol::status_update::StatusType::Pong as i32,
+            details: None,
+        }
+    }
+
+    /// An informational status update with a message.
+    pub fn info(message: impl Into<String>) -> Self {
+        Self {
+            kind: protocol::status_update::StatusType::Info as i32,
+            details: Some(protocol::status_update::Details::Info(
+                protocol::status_update::Info {
+                    message: message.into(),
+                },
+            )),
+        }
+    }
+
+    /// A warning status update with a message and code.
+    pub fn warning(message: impl Into<String>, code: i32) -> Self {
+        Self {
+            kind: protocol::status_update::StatusType::Warning as i32,
+            details: Some(protocol::status_update::Details::Warning(
+                protocol::status_update::Warning {
+                    message: message.into(),
+                    code,
+                },
+            )),
+        }
+    }
+
+    /// An error status update with a message and code.
+    pub fn error(message: impl Into<String>, code: i32) -> Self {
+        Self {
+            kind: protocol::status_update::StatusType::Error as i32,
+            details: Some(protocol::status_update::Details::Error(
+                protocol::status_update::Error {
+                    message: message.into(),
+                    code,
+                },
+            )),
+        }
+    }
+}
+
+impl protocol::AudioChunk {
+    /// A chunk of interleaved signed 16-bit samples, encoded in little-endian byte order.
+    pub fn from_s16(stream_id: u32, sample_rate: u32, channels: u32, samples: &[i16]) -> Self {
+        Self {
+            stream_id,
+            sample_rate,
+            channels,
+            format: protocol::audio_chunk::SampleFormat::S16 as i32,
+            samples: samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+        }
+    }
+
+    /// A chunk of interleaved 32-bit float samples, encoded in little-endian byte order.
+    pub fn from_f32(stream_id: u32, sample_rate: u32, channels: u32, samples: &[f32]) -> Self {
+        Self {
+            stream_id,
+            sample_rate,
+            channels,
+            format: protocol::audio_chunk::SampleFormat::F32 as i32,
+            samples: samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+        }
+    }
+
+    /// Decodes the samples of an `S16` chunk, ignoring a trailing partial sample.
+    pub fn s16_samples(&self) -> Vec<i16> {
+        self.samples
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    /// Decodes the samples of an `F32` chunk, ignoring a trailing partial sample.
+    pub fn f32_samples(&self) -> Vec<f32> {
+        self.samples
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+}
+
+impl protocol::ServerMessage {
+    pub fn frame(frame: protocol::Frame) -> Self {
+        frame.into()
+    }
+
+    pub fn draw_list(draw_list: protocol::DrawList) -> Self {
+        draw_list.into()
+    }
+
+    pub fn audio(chunk: protocol::AudioChunk) -> Self {
+        chunk.into()
+    }
+
+    pub fn set_window_position(window_id: u32, x: i32, y: i32) -> Self {
+        protocol::SetWindowPosition { window_id, x, y }.into()
+    }
+
+    pub fn status_exit() -> Self {
+        protocol::StatusUpdate::exit().into()
+    }
+
+    pub fn status_info(message: impl Into<String>) -> Self {
+        protocol::StatusUpdate::info(message).into()
+    }
+
+    pub fn status_warning(message: impl Into<String>, code: i32) -> Self {
+        protocol::StatusUpdate::warning(message, code).into()
+    }
+
+    pub fn status_error(message: impl Into<String>, code: i32) -> Self {
+        protocol::StatusUpdate::error(message, code).into()
+    }
+}
+
+impl protocol::ClientMessage {
+    pub fn user_input(user_input: protocol::UserInput) -> Self {
+        user_input.into()
+    }
+
+    pub fn frame_ack(window_id: u32, seq: u64) -> Self {
+        protocol::FrameAck { window_id, seq }.into()
+    }
+
+    pub fn status_exit() -> Self {
+        protocol::StatusUpdate::exit().into()
+    }
+}
+
 impl From<protocol::ClientHello> for protocol::ClientMessage {
     fn from(value: protocol::ClientHello) -> Self {
         protocol::ClientMessage {
@@ -68,6 +365,68 @@ impl From<protocol::UserInput> for protocol::ClientMessage {
     }
 }
 
+impl From<protocol::FrameAck> for protocol::ClientMessage {
+    fn from(value: protocol::FrameAck) -> Self {
+        protocol::ClientMessage {
+            client_event: Some(protocol::client_message::ClientEvent::FrameAck(value)),
+        }
+    }
+}
+
+impl From<protocol::NetworkStats> for protocol::ClientMessage {
+    fn from(value: protocol::NetworkStats) -> Self {
+        protocol::ClientMessage {
+            client_event: Some(protocol::client_message::ClientEvent::NetworkStats(value)),
+        }
+    }
+}
+
+impl From<protocol::FileTransferBegin> for protocol::ClientMessage {
+    fn from(value: protocol::FileTransferBegin) -> Self {
+        protocol::ClientMessage {
+            client_event: Some(protocol::client_message::ClientEvent::FileTransferBegin(
+                value,
+            )),
+        }
+    }
+}
+
+impl From<protocol::FileChunk> for protocol::ClientMessage {
+    fn from(value: protocol::FileChunk) -> Self {
+        protocol::ClientMessage {
+            client_event: Some(protocol::client_message::ClientEvent::FileChunk(value)),
+        }
+    }
+}
+
+impl From<protocol::FileTransferEnd> for protocol::ClientMessage {
+    fn from(value: protocol::FileTransferEnd) -> Self {
+        protocol::ClientMessage {
+            client_event: Some(protocol::client_message::ClientEvent::FileTransferEnd(
+                value,
+            )),
+        }
+    }
+}
+
+impl From<protocol::RequestKeyframe> for protocol::ClientMessage {
+    fn from(value: protocol::RequestKeyframe) -> Self {
+        protocol::ClientMessage {
+            client_event: Some(protocol::client_message::ClientEvent::RequestKeyframe(
+                value,
+            )),
+        }
+    }
+}
+
+impl From<protocol::ViewportHint> for protocol::ClientMessage {
+    fn from(value: protocol::ViewportHint) -> Self {
+        protocol::ClientMessage {
+            client_event: Some(protocol::client_message::ClientEvent::ViewportHint(value)),
+        }
+    }
+}
+
 impl From<protocol::ServerHelloAck> for protocol::ServerMessage {
     fn from(value: protocol::ServerHelloAck) -> Self {
         protocol::ServerMessage {
@@ -99,3 +458,339 @@ impl From<protocol::Frame> for protocol::ServerMessage {
         }
     }
 }
+
+impl From<protocol::SetWindowPosition> for protocol::ServerMessage {
+    fn from(value: protocol::SetWindowPosition) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::SetWindowPosition(
+                value,
+            )),
+        }
+    }
+}
+
+impl From<protocol::DrawList> for protocol::ServerMessage {
+    fn from(value: protocol::DrawList) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::DrawList(value)),
+        }
+    }
+}
+
+impl From<protocol::AudioChunk> for protocol::ServerMessage {
+    fn from(value: protocol::AudioChunk) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::Audio(value)),
+        }
+    }
+}
+
+impl From<protocol::server_hello_ack::WindowSettings> for protocol::ServerMessage {
+    fn from(value: protocol::server_hello_ack::WindowSettings) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::CreateWindow(value)),
+        }
+    }
+}
+
+impl From<protocol::DestroyWindow> for protocol::ServerMessage {
+    fn from(value: protocol::DestroyWindow) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::DestroyWindow(value)),
+        }
+    }
+}
+
+impl From<protocol::FileOffer> for protocol::ServerMessage {
+    fn from(value: protocol::FileOffer) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::FileOffer(value)),
+        }
+    }
+}
+
+impl From<protocol::FileChunk> for protocol::ServerMessage {
+    fn from(value: protocol::FileChunk) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::FileChunk(value)),
+        }
+    }
+}
+
+impl From<protocol::FileTransferEnd> for protocol::ServerMessage {
+    fn from(value: protocol::FileTransferEnd) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::FileTransferEnd(
+                value,
+            )),
+        }
+    }
+}
+
+impl From<protocol::WindowCommand> for protocol::ServerMessage {
+    fn from(value: protocol::WindowCommand) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::WindowCommand(value)),
+        }
+    }
+}
+
+impl From<protocol::WarpCursor> for protocol::ServerMessage {
+    fn from(value: protocol::WarpCursor) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::WarpCursor(value)),
+        }
+    }
+}
+
+impl From<protocol::CursorUpdate> for protocol::ServerMessage {
+    fn from(value: protocol::CursorUpdate) -> Self {
+        protocol::ServerMessage {
+            server_event: Some(protocol::server_message::ServerEvent::Cursor(value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::protocol::{
+        client_message::ClientEvent,
+        server_message::ServerEvent,
+        status_update::{Details, StatusType},
+        user_input::{self, InputType},
+        ClientMessage, DrawList, Frame, ServerMessage, StatusUpdate, UserInput,
+    };
+    use super::*;
+    use prost::Message;
+
+    fn status(message: ServerMessage) -> StatusUpdate {
+        match message.server_event {
+            Some(ServerEvent::StatusUpdate(status)) => status,
+            event => panic!("expected a status update, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn server_message_constructors_set_their_variant() {
+        assert!(matches!(
+            ServerMessage::frame(Frame::default()).server_event,
+            Some(ServerEvent::Frame(_))
+        ));
+        assert!(matches!(
+            ServerMessage::draw_list(DrawList::default()).server_event,
+            Some(ServerEvent::DrawList(_))
+        ));
+        assert!(matches!(
+            ServerMessage::audio(protocol::AudioChunk::default()).server_event,
+            Some(ServerEvent::Audio(_))
+        ));
+        assert!(matches!(
+            ServerMessage::set_window_position(1, 10, 20).server_event,
+            Some(ServerEvent::SetWindowPosition(
+                protocol::SetWindowPosition {
+                    window_id: 1,
+                    x: 10,
+                    y: 20
+                }
+            ))
+        ));
+
+        let exit = status(ServerMessage::status_exit());
+        assert_eq!(exit.kind(), StatusType::Exit);
+        assert_eq!(exit.details, None);
+        let info = status(ServerMessage::status_info("hello"));
+        assert_eq!(info.kind(), StatusType::Info);
+        assert!(matches!(info.details, Some(Details::Info(_))));
+        assert_eq!(info.message(), Some("hello"));
+        let warning = status(ServerMessage::status_warning("careful", 2));
+        assert_eq!(warning.kind(), StatusType::Warning);
+        assert!(matches!(
+            warning.details,
+            Some(Details::Warning(protocol::status_update::Warning {
+                code: 2,
+                ..
+            }))
+        ));
+        let error = status(ServerMessage::status_error("failed", 3));
+        assert_eq!(error.kind(), StatusType::Error);
+        assert!(matches!(
+            error.details,
+            Some(Details::Error(protocol::status_update::Error {
+                code: 3,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn client_message_constructors_set_their_variant() {
+        assert!(matches!(
+            ClientMessage::user_input(UserInput::default()).client_event,
+            Some(ClientEvent::UserInput(_))
+        ));
+        assert!(matches!(
+            ClientMessage::frame_ack(1, 42).client_event,
+            Some(ClientEvent::FrameAck(protocol::FrameAck {
+                window_id: 1,
+                seq: 42
+            }))
+        ));
+        match ClientMessage::status_exit().client_event {
+            Some(ClientEvent::StatusUpdate(status)) => assert_eq!(status.kind(), StatusType::Exit),
+            event => panic!("expected a status update, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn multibyte_text_input_round_trips() {
+        let text = "héllo 日本語 🎉";
+        let message = ClientMessage::user_input(UserInput {
+            kind: InputType::TextInput as i32,
+            window_id: 1,
+            input_event: Some(user_input::InputEvent::TextInput(user_input::TextEvent {
+                text: text.to_string(),
+            })),
+            ..Default::default()
+        });
+        let decoded = ClientMessage::decode(message.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, message);
+        match decoded.client_event {
+            Some(ClientEvent::UserInput(input)) => {
+                assert_eq!(input.kind(), InputType::TextInput);
+                match input.input_event {
+                    Some(user_input::InputEvent::TextInput(event)) => assert_eq!(event.text, text),
+                    event => panic!("expected text input, got {:?}", event),
+                }
+            }
+            event => panic!("expected user input, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn audio_chunk_round_trips() {
+        // A few interleaved stereo S16 samples, little-endian on the wire
+        let samples: Vec<u8> = [0i16, -1, i16::MAX, i16::MIN, 1234, -1234]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let message = ServerMessage::audio(protocol::AudioChunk {
+            stream_id: 1,
+            sample_rate: 48000,
+            channels: 2,
+            format: protocol::audio_chunk::SampleFormat::S16 as i32,
+            samples: samples.clone(),
+        });
+        let decoded = ServerMessage::decode(message.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, message);
+        match decoded.server_event {
+            Some(ServerEvent::Audio(chunk)) => {
+                assert_eq!(chunk.format(), protocol::audio_chunk::SampleFormat::S16);
+                assert_eq!((chunk.sample_rate, chunk.channels), (48000, 2));
+                assert_eq!(chunk.samples, samples);
+            }
+            event => panic!("expected audio, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn protocol_version_is_newest_in_both_ranges() {
+        // Same version
+        assert_eq!(negotiate_protocol_version(&(1..=2), 1, 2), Some(2));
+        // Newer client talks to an older server with reduced features
+        assert_eq!(negotiate_protocol_version(&(1..=2), 1, 3), Some(2));
+        // Older client talks to a newer server
+        assert_eq!(negotiate_protocol_version(&(1..=3), 2, 2), Some(2));
+        // Clients without a minimum only support their newest version
+        assert_eq!(negotiate_protocol_version(&(1..=2), 0, 1), Some(1));
+        assert_eq!(negotiate_protocol_version(&(1..=2), 0, 3), None);
+        // Client too new or too old
+        assert_eq!(negotiate_protocol_version(&(1..=2), 3, 4), None);
+        assert_eq!(negotiate_protocol_version(&(2..=3), 1, 1), None);
+    }
+
+    #[test]
+    fn zstd_level_is_clamped_to_the_allowed_range() {
+        assert_eq!(negotiate_zstd_level(3, Some(&(1..=9)), Some(22)), 9);
+        assert_eq!(negotiate_zstd_level(3, Some(&(1..=9)), Some(-7)), 1);
+        assert_eq!(negotiate_zstd_level(3, Some(&(1..=9)), Some(5)), 5);
+        // No preference, no allowed levels or an empty range keep the server's level
+        assert_eq!(negotiate_zstd_level(3, Some(&(1..=9)), None), 3);
+        assert_eq!(negotiate_zstd_level(3, None, Some(5)), 3);
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 9..=1;
+        assert_eq!(negotiate_zstd_level(3, Some(&empty), Some(5)), 3);
+    }
+
+    #[test]
+    fn migration_skips_unsupported_transports() {
+        use protocol::server_hello_ack::{transport_offer::Transport, TransportOffer};
+        let offer = |transport: Transport, port| TransportOffer {
+            transport: transport.into(),
+            host: String::new(),
+            port,
+        };
+        let mut hello = protocol::ServerHelloAck {
+            transport_offers: vec![
+                offer(Transport::Quic, 4433),
+                offer(Transport::Tls, 70000),
+                offer(Transport::Tls, 4434),
+            ],
+            resumption_token: vec![1; 16],
+            ..Default::default()
+        };
+        assert_eq!(hello.migration_offer(), Some(&offer(Transport::Tls, 4434)));
+        // The session can't follow the client without a token
+        hello.resumption_token.clear();
+        assert_eq!(hello.migration_offer(), None);
+    }
+
+    #[test]
+    fn frame_formats_know_their_stride() {
+        use protocol::server_hello_ack::FrameFormat;
+        for (format, bytes_per_pixel) in [
+            (FrameFormat::Rgba, 4),
+            (FrameFormat::Bgra, 4),
+            (FrameFormat::Rgb, 3),
+            (FrameFormat::Bgr, 3),
+            (FrameFormat::Gray8, 1),
+            (FrameFormat::Yuv420p, 1),
+        ] {
+            assert_eq!(format.bytes_per_pixel(), bytes_per_pixel, "{:?}", format);
+            assert_eq!(format.row_stride(0), 0);
+            assert_eq!(format.row_stride(5), 5 * bytes_per_pixel, "{:?}", format);
+        }
+        assert_eq!(FrameFormat::Rgb.data_len(5, 3), 45);
+        // Y plane, followed by U and V planes subsampled by two, rounding up
+        assert_eq!(FrameFormat::Yuv420p.data_len(5, 3), 15 + 2 * 3 * 2);
+    }
+
+    #[test]
+    fn windows_override_connection_format_and_compression() {
+        use protocol::server_hello_ack::{
+            window_settings, Compression, FrameFormat, Lz4Compression, WindowSettings,
+            ZstdCompression,
+        };
+        let zstd = Compression::Zstd(ZstdCompression {
+            level: 3,
+            ..Default::default()
+        });
+        let main = WindowSettings::default();
+        assert_eq!(main.frame_format(FrameFormat::Rgba), FrameFormat::Rgba);
+        assert_eq!(main.frame_compression(Some(&zstd)), Some(zstd.clone()));
+
+        let panel = WindowSettings {
+            format: Some(FrameFormat::Gray8 as i32),
+            compression: Some(window_settings::Compression::Uncompressed(())),
+            ..Default::default()
+        };
+        assert_eq!(panel.frame_format(FrameFormat::Rgba), FrameFormat::Gray8);
+        assert_eq!(panel.frame_compression(Some(&zstd)), None);
+
+        let lz4 = Lz4Compression { level: 1 };
+        let preview = WindowSettings {
+            compression: Some(window_settings::Compression::Lz4(lz4)),
+            ..Default::default()
+        };
+        assert_eq!(preview.frame_compression(None), Some(Compression::Lz4(lz4)));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::shared::protocol::FileChunk;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+/// Size of the chunks files are split into, well below the default message size limit.
+pub const FILE_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Number of transfers a `FileAssembler` receives at once, further ones are rejected until one completes.
+pub const MAX_CONCURRENT_TRANSFERS: usize = 8;
+
+/// Splits file contents into chunks of at most `chunk_size` bytes for a transfer.
+pub fn file_chunks(
+    transfer_id: u64,
+    data: &[u8],
+    chunk_size: usize,
+) -> impl Iterator<Item = FileChunk> + '_ {
+    data.chunks(chunk_size.max(1))
+        .enumerate()
+        .map(move |(i, chunk)| FileChunk {
+            transfer_id,
+            offset: (i * chunk_size.max(1)) as u64,
+            data: chunk.to_vec(),
+        })
+}
+
+/// A file received in full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFile {
+    /// File name as announced by the sender, without any directories
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct IncomingFile {
+    name: String,
+    size: u64,
+    /// Grown as chunks arrive, up to `size`
+    data: Vec<u8>,
+    /// Disjoint byte ranges received so far, as start to end
+    covered: BTreeMap<u64, u64>,
+}
+
+impl IncomingFile {
+    /// Marks `start..end` as received, merging it with the ranges it overlaps or touches.
+    fn cover(&mut self, mut start: u64, mut end: u64) {
+        if start == end {
+            return;
+        }
+        let merged: Vec<u64> = self
+            .covered
+            .range(..=end)
+            .rev()
+            .take_while(|(_, &e)| e >= start)
+            .map(|(&s, _)| s)
+            .collect();
+        for s in merged {
+            let e = self.covered.remove(&s).unwrap_or(s);
+            start = start.min(s);
+            end = end.max(e);
+        }
+        self.covered.insert(start, end);
+    }
+
+    /// Number of distinct bytes received.
+    fn received(&self) -> u64 {
+        self.covered.iter().map(|(start, end)| end - start).sum()
+    }
+}
+
+/// Reassembles files transferred in chunks, which may arrive in any order.\
+/// Feed it the `FileTransferBegin`, `FileChunk` and `FileTransferEnd` messages of a peer,
+/// files are complete once their end is received and every byte was covered by a chunk.
+#[derive(Debug)]
+pub struct FileAssembler {
+    max_size: u64,
+    transfers: HashMap<u64, IncomingFile>,
+}
+
+impl FileAssembler {
+    /// Creates an assembler rejecting files larger than `max_size` bytes, as they are kept in memory.
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            max_size,
+            transfers: HashMap::new(),
+        }
+    }
+
+    /// Starts receiving a file of `size` bytes.\
+    /// At most `MAX_CONCURRENT_TRANSFERS` files are received at once.
+    pub fn begin(&mut self, transfer_id: u64, name: &str, size: u64) -> io::Result<()> {
+        if self.transfers.len() >= MAX_CONCURRENT_TRANSFERS
+            && !self.transfers.contains_key(&transfer_id)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::QuotaExceeded,
+                format!("Too many concurrent file transfers, rejecting {:?}", name),
+            ));
+        }
+        if size > self.max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "File {:?} of {} bytes exceeds the limit of {} bytes",
+                    name, size, self.max_size
+                ),
+            ));
+        }
+        // Never trust the sender with paths, only keep the file name
+        let name = std::path::Path::new(name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.transfers.insert(
+            transfer_id,
+            IncomingFile {
+                name,
+                size,
+                data: Vec::new(),
+                covered: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Writes a chunk into its file at the chunk's offset.
+    pub fn chunk(&mut self, chunk: &FileChunk) -> io::Result<()> {
+        let file = self.transfers.get_mut(&chunk.transfer_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Unknown file transfer {}", chunk.transfer_id),
+            )
+        })?;
+        let start = chunk.offset;
+        let Some(end) = start
+            .checked_add(chunk.data.len() as u64)
+            .filter(|&end| end <= file.size)
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Chunk at offset {} of {} bytes is out of bounds of file transfer {}",
+                    chunk.offset,
+                    chunk.data.len(),
+                    chunk.transfer_id
+                ),
+            ));
+        };
+        if file.data.len() < end as usize {
+            file.data.resize(end as usize, 0);
+        }
+        file.data[start as usize..end as usize].copy_from_slice(&chunk.data);
+        file.cover(start, end);
+        Ok(())
+    }
+
+    /// Completes a transfer, returning the file if all of its bytes were received.
+    pub fn end(&mut self, transfer_id: u64) -> io::Result<ReceivedFile> {
+        let file = self.transfers.remove(&transfer_id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Unknown file transfer {}", transfer_id),
+            )
+        })?;
+        let received = file.received();
+        if received < file.size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "File {:?} incomplete, received {} of {} bytes",
+                    file.name, received, file.size
+                ),
+            ));
+        }
+        Ok(ReceivedFile {
+            name: file.name,
+            data: file.data,
+        })
+    }
+
+    /// Drops a transfer without completing it, e.g. when the sender disconnected.
+    pub fn cancel(&mut self, transfer_id: u64) {
+        self.transfers.remove(&transfer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK_SIZE: usize = 4;
+
+    fn contents() -> Vec<u8> {
+        (0..10).collect()
+    }
+
+    fn receive(chunks: impl IntoIterator<Item = FileChunk>) -> io::Result<ReceivedFile> {
+        let mut assembler = FileAssembler::new(1024);
+        assembler.begin(1, "../notes.txt", contents().len() as u64)?;
+        for chunk in chunks {
+            assembler.chunk(&chunk)?;
+        }
+        assembler.end(1)
+    }
+
+    #[test]
+    fn chunks_in_order_are_reassembled() {
+        let data = contents();
+        let file = receive(file_chunks(1, &data, CHUNK_SIZE)).unwrap();
+        assert_eq!(file.name, "notes.txt");
+        assert_eq!(file.data, data);
+    }
+
+    #[test]
+    fn chunks_out_of_order_are_reassembled() {
+        let data = contents();
+        let mut chunks: Vec<FileChunk> = file_chunks(1, &data, CHUNK_SIZE).collect();
+        chunks.reverse();
+        // A repeated chunk covers no new bytes
+        chunks.push(chunks[0].clone());
+        let file = receive(chunks).unwrap();
+        assert_eq!(file.data, data);
+    }
+
+    #[test]
+    fn missing_chunk_leaves_file_incomplete() {
+        let data = contents();
+        let chunks = file_chunks(1, &data, CHUNK_SIZE).filter(|chunk| chunk.offset != 4);
+        let err = receive(chunks).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}